@@ -0,0 +1,316 @@
+//! Audience of outgoing activities.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitypub/#delivery>
+//! * <https://www.w3.org/TR/activitypub/#public-addressing>
+
+use minicbor::{Decode, Encode};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::model::Object;
+
+const ADDRESSING: [&str; 5] = ["to", "bto", "cc", "bcc", "audience"];
+
+const BLIND: [&str; 2] = ["bto", "bcc"];
+
+/// Ways to name the special public collection, it is never delivered to.
+const PUBLIC: [&str; 3] = [
+    "https://www.w3.org/ns/activitystreams#Public",
+    "as:Public",
+    "Public",
+];
+
+/// Who a post that names no audience goes to, from
+/// `activity_pub.default_visibility` unless the user picked one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, Encode, Decode)]
+#[serde(rename_all = "lowercase")]
+#[cbor(index_only)]
+pub(crate) enum Visibility {
+    /// To the public, copied to the followers.
+    #[default]
+    #[n(0)]
+    Public,
+    /// To the followers and anyone who looks, but kept off public timelines.
+    #[n(1)]
+    Unlisted,
+    /// To the followers only.
+    #[n(2)]
+    Followers,
+}
+
+impl Visibility {
+    /// `to` and `cc` of a post by `actor_iri`.
+    pub(crate) fn addressing(self, actor_iri: &str) -> Vec<(&'static str, Value)> {
+        let public = json!([PUBLIC[0]]);
+        let followers = json!([format!("{actor_iri}/followers")]);
+        match self {
+            Visibility::Public => vec![("to", public), ("cc", followers)],
+            Visibility::Unlisted => vec![("to", followers), ("cc", public)],
+            Visibility::Followers => vec![("to", followers)],
+        }
+    }
+}
+
+/// Whether `object` or the object it embeds names any audience.
+pub(crate) fn has_addressing(object: &Object<'_>) -> bool {
+    let inner = object.get_node_object("object");
+    ADDRESSING.into_iter().any(|prop| {
+        object.get_value(prop).is_some()
+            || inner
+                .as_ref()
+                .is_some_and(|inner| inner.get_value(prop).is_some())
+    })
+}
+
+/// Everyone the activity and the blind recipients recorded with its
+/// delivery are addressed to, excluding the public collection and the
+/// sending actor itself.
+pub(super) fn recipients<'a>(
+    activity: &'a Object<'_>,
+    blind: &'a [String],
+    actor_iri: &str,
+) -> Vec<&'a str> {
+    let mut recipients = vec![];
+    for prop in ADDRESSING {
+        if let Some(iri_array) = activity.get_str_array(prop) {
+            recipients.extend(iri_array);
+        } else if let Some(iri) = activity.get_node_iri(prop) {
+            recipients.push(iri);
+        }
+    }
+    recipients.extend(blind.iter().map(String::as_str));
+    recipients.retain(|&iri| !PUBLIC.contains(&iri) && iri != actor_iri);
+    recipients.sort_unstable();
+    recipients.dedup();
+    recipients
+}
+
+/// `iri` in the form delivery compares, so an actor or inbox named two ways
+/// is only delivered to once: scheme and host lowercase, no default port and
+/// `/` for an empty path. IRIs that are not URLs with a host are kept as
+/// they are.
+pub(super) fn canonical_iri(iri: &str) -> String {
+    match Url::parse(iri) {
+        Ok(url) if url.has_host() => url.into(),
+        _ => iri.to_string(),
+    }
+}
+
+/// Whether `activity` is addressed to the public collection in `to` or
+/// `cc`. Anyone may see such activities.
+pub(super) fn is_public(activity: &Object<'_>) -> bool {
+    ["to", "cc"].into_iter().any(|prop| {
+        let iris = activity
+            .get_str_array(prop)
+            .or_else(|| activity.get_node_iri(prop).map(|iri| vec![iri]));
+        iris.is_some_and(|iris| iris.iter().any(|iri| PUBLIC.contains(iri)))
+    })
+}
+
+/// The owner's uid if `iri` is the followers collection of a local actor.
+pub(super) fn local_followers<'a>(base_url: &str, iri: &'a str) -> Option<&'a str> {
+    iri.strip_prefix(base_url)?
+        .strip_prefix("/users/")?
+        .strip_suffix("/followers")
+        .filter(|uid| !uid.contains('/'))
+}
+
+/// Local users an activity received at the shared inbox is for: those it is
+/// addressed to and the one it acts on, like the followee of a Follow or of
+/// the Follow an Undo takes back.
+pub(crate) fn local_recipients(base_url: &str, activity: &Object<'_>) -> Vec<String> {
+    let mut iris = recipients(activity, &[], "");
+    let object = activity.get_node_object("object");
+    iris.extend(activity.get_node_iri("object"));
+    iris.extend(
+        object
+            .as_ref()
+            .and_then(|object| object.get_node_iri("object")),
+    );
+    let mut uids: Vec<String> = iris
+        .into_iter()
+        .filter_map(|iri| {
+            let path = iri.strip_prefix(base_url)?.strip_prefix("/users/")?;
+            let uid = path.strip_suffix("/followers").unwrap_or(path);
+            (!uid.is_empty() && !uid.contains('/')).then(|| uid.to_string())
+        })
+        .collect();
+    uids.sort_unstable();
+    uids.dedup();
+    uids
+}
+
+/// The `bto` and `bcc` recipients of an activity and its embedded object.
+///
+/// They must be collected before the activity is stored, stored and served
+/// copies never contain them.
+pub(crate) fn blind_recipients(activity: &Object<'_>) -> Vec<String> {
+    let mut blind = vec![];
+    let object = activity.get_node_object("object");
+    for target in [Some(activity), object.as_ref()].into_iter().flatten() {
+        for prop in BLIND {
+            if let Some(iri_array) = target.get_str_array(prop) {
+                blind.extend(iri_array.into_iter().map(str::to_string));
+            } else if let Some(iri) = target.get_node_iri(prop) {
+                blind.push(iri.to_string());
+            }
+        }
+    }
+    blind.sort_unstable();
+    blind.dedup();
+    blind
+}
+
+/// Remove `bto` and `bcc` from an activity and its embedded object, blind
+/// recipients must not be disclosed.
+pub(crate) fn remove_blind_recipients(value: &mut Value) {
+    if let Some(map) = value.as_object_mut() {
+        for prop in BLIND {
+            map.remove(prop);
+        }
+        if let Some(Value::Object(object)) = map.get_mut("object") {
+            for prop in BLIND {
+                object.remove(prop);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::activity_pub::model::Object;
+
+    use super::{
+        blind_recipients, canonical_iri, is_public, local_followers, local_recipients, recipients,
+        remove_blind_recipients,
+    };
+
+    #[test]
+    fn expand_audience() {
+        let activity = Object::from(json!({
+            "type": "Create",
+            "actor": "https://example.com/users/alice",
+            "to": ["https://www.w3.org/ns/activitystreams#Public", "https://remote.example/users/bob"],
+            "cc": "https://example.com/users/alice/followers",
+            "bcc": ["https://remote.example/users/carol", "https://remote.example/users/bob"],
+            "audience": "https://example.com/users/alice",
+            "object": {"type": "Note", "bto": "https://remote.example/users/dave"},
+        }));
+        let blind = blind_recipients(&activity);
+        assert_eq!(
+            blind,
+            vec![
+                "https://remote.example/users/bob",
+                "https://remote.example/users/carol",
+                "https://remote.example/users/dave",
+            ]
+        );
+
+        let mut value = activity.to_value();
+        remove_blind_recipients(&mut value);
+        let stored = Object::from(value);
+        assert!(stored.get_value("bcc").is_none());
+        assert!(stored.get_value("cc").is_some());
+        let note = stored.get_node_object("object").unwrap();
+        assert!(note.get_value("bto").is_none());
+
+        assert_eq!(
+            recipients(&stored, &blind, "https://example.com/users/alice"),
+            vec![
+                "https://example.com/users/alice/followers",
+                "https://remote.example/users/bob",
+                "https://remote.example/users/carol",
+                "https://remote.example/users/dave",
+            ]
+        );
+    }
+
+    #[test]
+    fn canonicalize_iris() {
+        let bob = "https://remote.example/users/bob";
+        for iri in [
+            "HTTPS://Remote.Example/users/bob",
+            "https://remote.example:443/users/bob",
+        ] {
+            assert_eq!(canonical_iri(iri), bob);
+        }
+        assert_eq!(
+            canonical_iri("https://remote.example"),
+            "https://remote.example/"
+        );
+        // Paths are case sensitive.
+        assert_ne!(canonical_iri("https://remote.example/users/Bob"), bob);
+        assert_eq!(canonical_iri("acct:bob"), "acct:bob");
+    }
+
+    #[test]
+    fn tell_public_activities() {
+        let activity = |to: serde_json::Value| {
+            Object::from(json!({
+                "type": "Create",
+                "to": to,
+                "cc": ["https://example.com/users/alice/followers"],
+            }))
+        };
+        assert!(is_public(&activity(json!("as:Public"))));
+        assert!(is_public(&activity(json!([
+            "https://remote.example/users/bob",
+            "https://www.w3.org/ns/activitystreams#Public"
+        ]))));
+        assert!(!is_public(&activity(json!([
+            "https://remote.example/users/bob"
+        ]))));
+        // Blind and audience recipients do not make an activity public.
+        let hidden = Object::from(json!({
+            "type": "Create",
+            "bcc": ["https://www.w3.org/ns/activitystreams#Public"],
+            "audience": "https://www.w3.org/ns/activitystreams#Public",
+        }));
+        assert!(!is_public(&hidden));
+    }
+
+    #[test]
+    fn followers_collection_of_local_actor() {
+        let base_url = "https://example.com";
+        assert_eq!(
+            local_followers(base_url, "https://example.com/users/alice/followers"),
+            Some("alice")
+        );
+        assert_eq!(
+            local_followers(base_url, "https://remote.example/users/alice/followers"),
+            None
+        );
+        assert_eq!(
+            local_followers(base_url, "https://example.com/users/alice/outbox"),
+            None
+        );
+    }
+
+    #[test]
+    fn local_recipients_of_shared_inbox() {
+        let base_url = "https://example.com";
+        let note = Object::from(json!({
+            "type": "Create",
+            "actor": "https://remote.example/users/bob",
+            "to": ["as:Public", "https://example.com/users/alice"],
+            "cc": ["https://remote.example/users/bob/followers", "https://example.com/users/carol/followers"],
+            "object": {"type": "Note", "id": "https://remote.example/notes/1"},
+        }));
+        assert_eq!(local_recipients(base_url, &note), vec!["alice", "carol"]);
+        let undo = Object::from(json!({
+            "type": "Undo",
+            "actor": "https://remote.example/users/bob",
+            "object": {"type": "Follow", "object": "https://example.com/users/alice"},
+        }));
+        assert_eq!(local_recipients(base_url, &undo), vec!["alice"]);
+        let like = Object::from(json!({
+            "type": "Like",
+            "object": "https://example.com/users/alice/notes/1",
+        }));
+        assert!(local_recipients(base_url, &like).is_empty());
+    }
+}
@@ -1,40 +1,121 @@
-use std::time::Duration;
+//! Delivery of queued activities to the inboxes of their recipients.
+//!
+//! Every server runs a [`DeliveryWorker`] taking activities from the
+//! replicated queue one at a time, oldest first, and posting each to all its
+//! inboxes in parallel. Inboxes that fail are retried with the activity
+//! after the visibility timeout of the queue, while later activities go out
+//! in the meantime. A receiver can then see an `Update` or `Delete` before
+//! the `Create` it refers to.
+//!
+//! With `activity_pub.ordered_delivery`, an activity is held back from the
+//! inboxes that activities queued before it still have to post to, and
+//! from every inbox while one of those has not resolved its recipients yet.
+//! Held inboxes are retried with the activity like failed ones. The queue
+//! makes older activities visible again first, so each inbox gets them in
+//! the order they were queued, still in parallel across inboxes. Retries
+//! can still reorder deliveries to an inbox when an activity is abandoned
+//! after too many attempts, a held activity uses up its attempts too, or
+//! when it is redelivered by hand later. Inboxes are compared by IRI, a
+//! personal and a shared inbox of the same server are ordered apart.
 
-use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
 use aws_lc_rs::rsa::KeyPair;
+use jiff::Timestamp;
+use metrics::{counter, gauge, histogram};
 use minicbor::{Decode, Encode};
-use ractor::{Actor, ActorProcessingErr, ActorRef};
+use moka::sync::Cache;
+use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
 use ractor_cluster::RactorMessage;
 use secrecy::ExposeSecret;
+use tokio::sync::watch;
 use tokio::task::{spawn_blocking, JoinSet};
-use tracing::{error, info, warn};
+use tracing::{error, info, info_span, warn, Instrument};
+use uuid::Bytes;
 
 use crate::activity_pub::uuidgen;
+use crate::config::FederationConfig;
 use crate::raft::{get_raft_local_client, ClientResult, LogEntryValue, RaftClientMsg};
 use crate::RuntimeConfig;
 
-use super::machine::ActivityPubCommand;
-use super::mailman::Mailman;
+use super::limiter::DeliveryLimiter;
+use super::machine::{ActivityPubCommand, MAILBOX};
 use super::model::Object;
 use super::simple_queue::{ReceiveResult, SimpleQueue};
-use super::{hs2019, CryptoRepo, ObjectKey, ObjectRepo};
+use super::{
+    addressing, hs2019, ActorResolver, CryptoRepo, KeyMaterial, ModerationRepo, ObjectKey,
+    ObjectRepo, UserIndex,
+};
 
 pub(crate) struct DeliveryWorker;
 
 #[derive(RactorMessage)]
 pub(crate) enum DeliveryWorkerMsg {
     RunLoop,
+    /// Deliver an abandoned activity again, see [`Redelivery`].
+    Redeliver(ObjectKey, RpcReplyPort<Result<Option<Redelivery>>>),
+    /// Tell what a delivery of an activity would send, see [`DeliveryPreview`].
+    Preview(ObjectKey, RpcReplyPort<Result<Option<DeliveryPreview>>>),
+    /// Replies once nothing is in flight anymore, the delivery being made
+    /// when the shutdown signal was raised is handed back to the queue.
+    Shutdown(RpcReplyPort<()>),
+}
+
+/// Outcome of a manual redelivery.
+#[derive(Debug, Default)]
+pub(crate) struct Redelivery {
+    pub(crate) delivered: Vec<String>,
+    pub(crate) failed: Vec<String>,
+}
+
+/// What a delivery of an activity would send, nothing is sent to build it.
+#[derive(Debug)]
+pub(crate) struct DeliveryPreview {
+    pub(crate) body: String,
+    pub(crate) requests: Vec<PreviewRequest>,
 }
 
+#[derive(Debug)]
+pub(crate) struct PreviewRequest {
+    pub(crate) inbox: String,
+    /// Signed headers, with the signature value redacted.
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) last_attempt: Option<DeliveryAttempt>,
+}
+
+/// Outcome of the last POST of an activity to an inbox.
+#[derive(Debug, Clone)]
+pub(crate) struct DeliveryAttempt {
+    pub(crate) at: Timestamp,
+    pub(crate) error: Option<String>,
+}
+
+/// Activity and inbox pairs whose last attempt is remembered.
+const ATTEMPTS_CAPACITY: u64 = 10_000;
+
 pub(crate) struct DeliveryWorkerInit {
     pub(crate) config: RuntimeConfig,
+    /// Raised before the worker is stopped, no new work is pulled after.
+    pub(crate) shutdown: watch::Receiver<bool>,
 }
 
 pub(crate) struct DeliveryWorkerState {
+    base_url: String,
     obj_repo: ObjectRepo,
+    user_index: UserIndex,
     crypto_repo: CryptoRepo,
     queue: SimpleQueue,
-    mailman: Mailman,
+    resolver: ActorResolver,
+    moderation: ModerationRepo,
+    federation: FederationConfig,
+    limiter: DeliveryLimiter,
+    ordered: bool,
+    /// Last attempts made by this server, they are not replicated and are
+    /// lost on restart.
+    attempts: Cache<(ObjectKey, String), DeliveryAttempt>,
+    shutdown: watch::Receiver<bool>,
 }
 
 impl Actor for DeliveryWorker {
@@ -47,19 +128,29 @@ impl Actor for DeliveryWorker {
         _myself: ActorRef<Self::Msg>,
         args: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let DeliveryWorkerInit { config } = args;
+        let DeliveryWorkerInit { config, shutdown } = args;
         let keyspace = config.keyspace.clone();
         spawn_blocking(move || {
             let obj_repo = ObjectRepo::new(keyspace.clone())?;
+            let user_index = UserIndex::new(keyspace.clone())?;
             let crypto_repo = CryptoRepo::new(keyspace.clone())?;
             let queue = SimpleQueue::new(keyspace.clone())?;
-            let mailman = Mailman::new();
+            let resolver = ActorResolver::new(keyspace.clone(), &config.init.cache)?;
+            let moderation = ModerationRepo::new(keyspace.clone())?;
 
             Ok(DeliveryWorkerState {
+                base_url: config.init.activity_pub.base_url.clone(),
                 obj_repo,
+                user_index,
                 crypto_repo,
                 queue,
-                mailman,
+                resolver,
+                moderation,
+                federation: config.init.federation.clone(),
+                limiter: DeliveryLimiter::new(&config.init.activity_pub.delivery),
+                ordered: config.init.activity_pub.ordered_delivery,
+                attempts: Cache::new(ATTEMPTS_CAPACITY),
+                shutdown,
             })
         })
         .await
@@ -70,7 +161,8 @@ impl Actor for DeliveryWorker {
         myself: ActorRef<Self::Msg>,
         _state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        myself.send_after(RETRY_TIMEOUT, || DeliveryWorkerMsg::RunLoop);
+        // Resume what was left in the queue by an earlier run right away.
+        ractor::cast!(myself, DeliveryWorkerMsg::RunLoop)?;
         Ok(())
     }
     async fn handle(
@@ -80,6 +172,7 @@ impl Actor for DeliveryWorker {
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match message {
+            DeliveryWorkerMsg::RunLoop if *state.shutdown.borrow() => {}
             DeliveryWorkerMsg::RunLoop => {
                 match state
                     .handle_delivery()
@@ -101,6 +194,30 @@ impl Actor for DeliveryWorker {
                     }
                 }
             }
+            DeliveryWorkerMsg::Redeliver(act_key, reply) => {
+                let result = state
+                    .redeliver(act_key)
+                    .await
+                    .context("Failed to redeliver activity");
+                if !reply.is_closed() {
+                    reply.send(result)?;
+                }
+            }
+            DeliveryWorkerMsg::Preview(act_key, reply) => {
+                let result = state
+                    .preview(act_key)
+                    .await
+                    .context("Failed to preview delivery");
+                if !reply.is_closed() {
+                    reply.send(result)?;
+                }
+            }
+            DeliveryWorkerMsg::Shutdown(reply) => {
+                info!("delivery worker quiesced");
+                if !reply.is_closed() {
+                    reply.send(())?;
+                }
+            }
         }
         Ok(())
     }
@@ -110,6 +227,7 @@ const RETRY_TIMEOUT: Duration = Duration::from_secs(30);
 
 impl DeliveryWorkerState {
     async fn handle_delivery(&mut self) -> Result<bool> {
+        gauge!("pinka_delivery_queue_depth").set(self.queue.approximate_len() as f64);
         // Sleep if our local replicated queue is empty
         if self.queue.is_empty()? {
             return Ok(false);
@@ -123,7 +241,7 @@ impl DeliveryWorkerState {
             RaftClientMsg::ClientRequest,
             LogEntryValue::from(command)
         )?;
-        let ClientResult::Ok(bytes) = client_result else {
+        let ClientResult::Ok(bytes, _) = client_result else {
             return Ok(false);
         };
         if bytes.is_empty() {
@@ -134,9 +252,12 @@ impl DeliveryWorkerState {
         // Retry limited times
         // TODO: make this configurable
         let retry_count = result.message.approximate_receive_count;
+        histogram!("pinka_delivery_receive_count").record(retry_count as f64);
         if retry_count > 10 {
             warn!("retried {retry_count} times, giving up");
-            let command = ActivityPubCommand::AckDelivery(result.key, receipt_handle);
+            counter!("pinka_delivery_abandoned_total").increment(1);
+            // Kept as a dead letter for manual redelivery.
+            let command = ActivityPubCommand::AbandonDelivery(result.key, receipt_handle);
             let _ = ractor::call!(
                 raft_client,
                 RaftClientMsg::ClientRequest,
@@ -145,9 +266,42 @@ impl DeliveryWorkerState {
             return Ok(false);
         }
 
-        let message = result.message;
+        let ReceiveResult { key, message } = result;
         let item = DeliveryQueueItem::from_bytes(&message.body)?;
 
+        let span = info_span!("delivery", request_id = item.request_id.as_deref());
+        let mut shutdown = self.shutdown.clone();
+        tokio::select! {
+            delivered = self.deliver(key, receipt_handle, item).instrument(span) => delivered,
+            true = async { shutdown.wait_for(|stopping| *stopping).await.is_ok() } => {
+                // Inboxes already posted to get the activity again on the
+                // next attempt, receivers ignore activities they have seen.
+                info!("shutting down, releasing the delivery in flight");
+                let command = ActivityPubCommand::ReleaseDelivery(key, receipt_handle);
+                let released = ractor::call!(
+                    raft_client,
+                    RaftClientMsg::ClientRequest,
+                    LogEntryValue::from(command)
+                )?;
+                if let ClientResult::Err(error) = released {
+                    warn!(
+                        ?error,
+                        "failed to release the delivery in flight, \
+                         it is retried after the visibility timeout"
+                    );
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    async fn deliver(
+        &mut self,
+        key: Bytes,
+        receipt_handle: Bytes,
+        item: DeliveryQueueItem,
+    ) -> Result<bool> {
+        let raft_client = get_raft_local_client()?;
         // Load signing key
         let uid = item.uid.clone();
         let crypto_repo = self.crypto_repo.clone();
@@ -160,7 +314,7 @@ impl DeliveryWorkerState {
             // Get actor IRI
             let Some(actor_iri) = object.get_node_iri("actor") else {
                 warn!(?object, "cannot deliver activity without actor property");
-                let command = ActivityPubCommand::AckDelivery(result.key, receipt_handle);
+                let command = ActivityPubCommand::AckDelivery(key, receipt_handle);
                 let _ = ractor::call!(
                     raft_client,
                     RaftClientMsg::ClientRequest,
@@ -168,114 +322,316 @@ impl DeliveryWorkerState {
                 )?;
                 return Ok(false);
             };
-            // Collect recipients
-            let mut recipients = vec![];
-            for target in ["to", "bto", "cc", "bcc", "audience"] {
-                if let Some(iri_array) = object.get_str_array(target) {
-                    iri_array.iter().for_each(|&iri| recipients.push(iri));
+            let mut inboxes = self.inboxes(&item, &object, actor_iri).await?;
+            let held: Vec<_> = match self.ordered {
+                true => match self.held_inboxes(key).await? {
+                    Some(busy) => inboxes
+                        .extract_if(.., |inbox| busy.contains(inbox))
+                        .collect(),
+                    None => std::mem::take(&mut inboxes),
+                },
+                false => vec![],
+            };
+            if !held.is_empty() {
+                info!(held = held.len(), "holding deliveries behind earlier ones");
+                counter!("pinka_delivery_held_total").increment(held.len() as u64);
+            }
+            let failed = self
+                .post_all(item.act_key, &object, actor_iri, &key_material, inboxes)
+                .await?;
+            if !failed.is_empty() || !held.is_empty() {
+                // Only failures wait for the next loop, held inboxes are
+                // retried with the activity like failed ones.
+                let more = failed.is_empty();
+                // Later attempts only go to the inboxes left.
+                let item = DeliveryQueueItem {
+                    inboxes: Some(failed.into_iter().chain(held).collect()),
+                    ..item
+                };
+                let command = ActivityPubCommand::RetryDelivery(key, receipt_handle, item);
+                let _ = ractor::call!(
+                    raft_client,
+                    RaftClientMsg::ClientRequest,
+                    LogEntryValue::from(command)
+                )?;
+                return Ok(more);
+            }
+        } else {
+            error!(obj_key=%item.act_key, "cannot find object");
+        }
+        // Ack
+        // TODO always ack in case of unrecoverable error
+        let command = ActivityPubCommand::AckDelivery(key, receipt_handle);
+        let _ = ractor::call!(
+            raft_client,
+            RaftClientMsg::ClientRequest,
+            LogEntryValue::from(command)
+        )?;
+
+        Ok(true)
+    }
+
+    /// Inboxes the deliveries queued before `key` still have to post to.
+    async fn held_inboxes(&self, key: Bytes) -> Result<Option<HashSet<String>>> {
+        let queue = self.queue.clone();
+        let bodies = spawn_blocking(move || queue.messages_before(MAILBOX, key)).await??;
+        let earlier = bodies
+            .iter()
+            .map(|body| DeliveryQueueItem::from_bytes(body))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(held_inboxes(earlier))
+    }
+
+    /// Deliver the dead letters of an activity again, to the inboxes that
+    /// failed when they were abandoned. Returns `None` if there are none.
+    async fn redeliver(&mut self, act_key: ObjectKey) -> Result<Option<Redelivery>> {
+        let queue = self.queue.clone();
+        let letters = spawn_blocking(move || queue.dead_letters(MAILBOX)).await??;
+        let mut matching = vec![];
+        for (key, body) in letters {
+            let item = DeliveryQueueItem::from_bytes(&body)?;
+            if item.act_key == act_key {
+                matching.push((key, item));
+            }
+        }
+        let Some((_, item)) = matching.first() else {
+            return Ok(None);
+        };
+
+        let uid = item.uid.clone();
+        let crypto_repo = self.crypto_repo.clone();
+        let Some(key_material) = spawn_blocking(move || crypto_repo.find_one(&uid)).await?? else {
+            bail!("cannot find the signing key of {}", item.uid);
+        };
+        let obj_repo = self.obj_repo.clone();
+        let Some(object) = spawn_blocking(move || obj_repo.find_one(act_key)).await?? else {
+            bail!("cannot find activity {act_key}");
+        };
+        let Some(actor_iri) = object.get_node_iri("actor") else {
+            bail!("cannot deliver activity without actor property");
+        };
+
+        let raft_client = get_raft_local_client()?;
+        let mut report = Redelivery::default();
+        for (key, item) in matching {
+            let inboxes = self.inboxes(&item, &object, actor_iri).await?;
+            let failed = self
+                .post_all(act_key, &object, actor_iri, &key_material, inboxes.clone())
+                .await?;
+            report
+                .delivered
+                .extend(inboxes.into_iter().filter(|inbox| !failed.contains(inbox)));
+            report.failed.extend(failed.iter().cloned());
+            let item = (!failed.is_empty()).then_some(DeliveryQueueItem {
+                inboxes: Some(failed),
+                ..item
+            });
+            let command = ActivityPubCommand::UpdateDeadLetter(key, item);
+            let _ = ractor::call!(
+                raft_client,
+                RaftClientMsg::ClientRequest,
+                LogEntryValue::from(command)
+            )?;
+        }
+        Ok(Some(report))
+    }
+
+    /// Inboxes `item` goes to, the ones left from earlier attempts or those
+    /// of all recipients of `object`.
+    async fn inboxes(
+        &self,
+        item: &DeliveryQueueItem,
+        object: &Object<'_>,
+        actor_iri: &str,
+    ) -> Result<Vec<String>> {
+        let mut inboxes = vec![];
+        if let Some(remaining) = &item.inboxes {
+            inboxes.extend(remaining.iter().cloned());
+        } else {
+            // Convert recipients to inboxes
+            let blind = item.blind_recipients.as_deref().unwrap_or_default();
+            let mut recipients: Vec<String> = addressing::recipients(object, blind, actor_iri)
+                .into_iter()
+                .map(addressing::canonical_iri)
+                .collect();
+            recipients.sort_unstable();
+            recipients.dedup();
+            let mut direct = vec![];
+            for iri in self.unblocked(&item.uid, recipients).await? {
+                let iri = iri.as_str();
+                if let Some(uid) = addressing::local_followers(&self.base_url, iri) {
+                    inboxes.extend(self.follower_inboxes(uid).await?);
                     continue;
                 }
-                if let Some(iri) = object.get_node_iri(target) {
-                    recipients.push(iri);
-                }
-            }
-            // Convert to inbox
-            let mut inboxes = vec![];
-            for iri in recipients {
-                // 5.6 Skip public addressing
-                if iri == "https://www.w3.org/ns/activitystreams#Public"
-                    || iri == "as:Public"
-                    || iri == "Public"
-                {
+                // Don't even fetch actors of domains we don't federate with.
+                if !self.federation.check(iri, "outbound") {
                     continue;
                 }
-                let value = self.mailman.fetch(iri).await?;
-                let object = Object::from(value);
+                let Some(object) = self.resolver.resolve(iri).await? else {
+                    warn!(%iri, "recipient is gone, skipping");
+                    continue;
+                };
                 if object.type_is("Collection") || object.type_is("OrderedCollection") {
                     inboxes.extend(
-                        self.discover_inboxes(&object)
+                        self.discover_inboxes(&item.uid, &object)
                             .await
                             .context("Failed to discover inboxes")?,
                     );
                     continue;
                 }
                 if let Some(inbox) = object.get_str("inbox") {
-                    inboxes.push(inbox.to_string());
+                    let shared = object.get_endpoint("sharedInbox").map(str::to_string);
+                    direct.push((inbox.to_string(), shared));
                 }
             }
+            inboxes = unique_inboxes(inboxes, direct);
+        }
 
-            // De-duplicate the final recipient list
-            inboxes.sort();
-            inboxes.dedup();
-
-            // Remove self and attributedTo and origin actor
-            // TODO
-
-            // Deliver
-            let mut join_set = JoinSet::new();
-            for inbox in inboxes {
-                let body = object.to_string();
-                let actor_iri = actor_iri.to_string();
-                let key_pair = KeyPair::from_pkcs8(key_material.expose_secret())?;
-                let mailman = self.mailman.clone();
-                join_set.spawn(async move {
-                    info!(%actor_iri, %inbox, "delivering activity");
-                    let headers = hs2019::post_headers(&actor_iri, &inbox, &body, &key_pair)
-                        .expect("unable to sign http request");
-                    mailman.post(&inbox, headers, &body).await
-                });
-            }
-            let mut success = true;
-            for result in join_set.join_all().await {
-                if let Err(error) = result {
-                    error!(?error, "failed to deliver activity");
-                    success = false;
-                }
-            }
-            if !success {
-                return Ok(false);
+        // Inboxes may live on another host than the actor itself.
+        inboxes.retain(|inbox| self.federation.check(inbox, "outbound"));
+        Ok(inboxes)
+    }
+
+    /// Tell what delivering the activity at `act_key` to all its recipients
+    /// would send. Returns `None` if there is no such activity.
+    async fn preview(&self, act_key: ObjectKey) -> Result<Option<DeliveryPreview>> {
+        let obj_repo = self.obj_repo.clone();
+        let Some(object) = spawn_blocking(move || obj_repo.find_one(act_key)).await?? else {
+            return Ok(None);
+        };
+        let Some(actor_iri) = object.get_node_iri("actor") else {
+            bail!("cannot deliver activity without actor property");
+        };
+        let users_prefix = format!("{}/users/", self.base_url);
+        let Some(uid) = actor_iri.strip_prefix(&users_prefix) else {
+            bail!("{actor_iri} is not a local actor");
+        };
+        let crypto_repo = self.crypto_repo.clone();
+        let user_id = uid.to_string();
+        let Some(key_material) = spawn_blocking(move || crypto_repo.find_one(&user_id)).await??
+        else {
+            bail!("cannot find the signing key of {uid}");
+        };
+        let key_pair = KeyPair::from_pkcs8(key_material.expose_secret())?;
+
+        // Blind recipients are not stored, only the queued delivery knew them.
+        let item = DeliveryQueueItem {
+            uid: uid.to_string(),
+            act_key,
+            request_id: None,
+            blind_recipients: None,
+            inboxes: None,
+        };
+        let body = object.to_string();
+        let mut requests = vec![];
+        for inbox in self.inboxes(&item, &object, actor_iri).await? {
+            let headers = hs2019::post_headers(actor_iri, &inbox, &body, &key_pair)?;
+            let headers = headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = value.to_str().unwrap_or_default();
+                    (name.to_string(), redact_signature(name.as_str(), value))
+                })
+                .collect();
+            let last_attempt = self.attempts.get(&(act_key, inbox.clone()));
+            requests.push(PreviewRequest {
+                inbox,
+                headers,
+                last_attempt,
+            });
+        }
+        Ok(Some(DeliveryPreview { body, requests }))
+    }
+
+    /// Post `object` to every inbox, returning the inboxes that failed.
+    async fn post_all(
+        &self,
+        act_key: ObjectKey,
+        object: &Object<'_>,
+        actor_iri: &str,
+        key_material: &KeyMaterial,
+        inboxes: Vec<String>,
+    ) -> Result<Vec<String>> {
+        let body = object.to_string();
+        let mut join_set = JoinSet::new();
+        for inbox in inboxes {
+            let body = body.clone();
+            let actor_iri = actor_iri.to_string();
+            let key_pair = KeyPair::from_pkcs8(key_material.expose_secret())?;
+            let mailman = self.resolver.mailman().clone();
+            let limiter = self.limiter.clone();
+            join_set.spawn(async move {
+                let _permit = limiter.acquire(&inbox).await;
+                info!(%actor_iri, %inbox, "delivering activity");
+                let headers = hs2019::post_headers(&actor_iri, &inbox, &body, &key_pair)
+                    .expect("unable to sign http request");
+                counter!("pinka_delivery_attempts_total").increment(1);
+                gauge!("pinka_delivery_in_flight").increment(1);
+                let start = Instant::now();
+                let result = mailman.post(&inbox, headers, &body).await;
+                histogram!("pinka_delivery_duration_seconds").record(start.elapsed());
+                gauge!("pinka_delivery_in_flight").decrement(1);
+                (inbox, result)
+            });
+        }
+        let mut failed = vec![];
+        for (inbox, result) in join_set.join_all().await {
+            let attempt = DeliveryAttempt {
+                at: Timestamp::now(),
+                error: result.as_ref().err().map(|error| format!("{error:#}")),
+            };
+            self.attempts.insert((act_key, inbox.clone()), attempt);
+            if let Err(error) = result {
+                error!(?error, %inbox, "failed to deliver activity");
+                let reason = failure_reason(&error);
+                counter!("pinka_delivery_failures_total", "reason" => reason).increment(1);
+                failed.push(inbox);
+            } else {
+                counter!("pinka_delivery_successes_total").increment(1);
             }
-        } else {
-            error!(obj_key=%item.act_key, "cannot find object");
         }
-        // Ack
-        // TODO always ack in case of unrecoverable error
-        let command = ActivityPubCommand::AckDelivery(result.key, receipt_handle);
-        let _ = ractor::call!(
-            raft_client,
-            RaftClientMsg::ClientRequest,
-            LogEntryValue::from(command)
-        )?;
+        Ok(failed)
+    }
 
-        Ok(true)
+    /// Inboxes of a local actor's followers, read from the follower index
+    /// instead of fetching our own collection over HTTP.
+    async fn follower_inboxes(&self, uid: &str) -> Result<Vec<String>> {
+        let user_index = self.user_index.clone();
+        let uid = uid.to_string();
+        let followers =
+            spawn_blocking(move || user_index.find_followers(&uid, None, None, None, None))
+                .await??;
+        let mut result_set = JoinSet::new();
+        for (_, iri) in followers {
+            let resolver = self.resolver.clone();
+            result_set.spawn(async move {
+                let actor = resolver.resolve(&iri).await;
+                match &actor {
+                    Ok(Some(_)) => {}
+                    Ok(None) => warn!(%iri, "follower is gone, skipping"),
+                    Err(error) => warn!(?error, %iri, "failed to fetch follower, skipping"),
+                }
+                shared_inbox(actor)
+            });
+        }
+        Ok(result_set.join_all().await.into_iter().flatten().collect())
     }
 
-    async fn discover_inboxes(&self, object: &Object<'_>) -> Result<Vec<String>> {
+    async fn discover_inboxes(&self, uid: &str, object: &Object<'_>) -> Result<Vec<String>> {
         let mut next = object.get_str("first").map(str::to_string);
 
         let mut result_set = JoinSet::new();
         while let Some(iri) = next {
-            let value = self.mailman.fetch(&iri).await?;
+            let value = self.resolver.mailman().fetch(&iri).await?;
             let page = Object::from(value);
             let items = page
                 .get_str_array("items")
                 .or_else(|| page.get_str_array("orderedItems"));
             if let Some(items) = items {
-                for item in items {
-                    let mailman = self.mailman.clone();
-                    let iri = item.to_string();
-                    result_set.spawn(async move {
-                        if let Ok(value) = mailman.fetch(&iri).await {
-                            let object = Object::from(value);
-                            // skip nested collections
-                            object
-                                .get_endpoint("sharedInbox")
-                                .or_else(|| object.get_str("inbox"))
-                                .map(str::to_string)
-                        } else {
-                            None
-                        }
-                    });
+                let items = items.into_iter().map(str::to_string).collect();
+                for iri in self.unblocked(uid, items).await? {
+                    let resolver = self.resolver.clone();
+                    result_set.spawn(async move { shared_inbox(resolver.resolve(&iri).await) });
                 }
             }
             next = page.get_str("next").map(str::to_string);
@@ -283,6 +639,96 @@ impl DeliveryWorkerState {
         let result = result_set.join_all().await.into_iter().flatten().collect();
         Ok(result)
     }
+    /// Actors blocked by the sending user never receive its activities.
+    async fn unblocked(&self, uid: &str, iris: Vec<String>) -> Result<Vec<String>> {
+        let moderation = self.moderation.clone();
+        let uid = uid.to_string();
+        spawn_blocking(move || {
+            let mut result = vec![];
+            for iri in iris {
+                if !moderation.is_blocked(&uid, &iri)? {
+                    result.push(iri);
+                }
+            }
+            Ok(result)
+        })
+        .await?
+    }
+}
+
+/// Inboxes `earlier` deliveries still have to post to, `None` if one of them
+/// has not resolved its recipients yet and may post to any inbox.
+fn held_inboxes(earlier: impl IntoIterator<Item = DeliveryQueueItem>) -> Option<HashSet<String>> {
+    let mut held = HashSet::new();
+    for item in earlier {
+        held.extend(item.inboxes?);
+    }
+    Some(held)
+}
+
+/// The inboxes to post to, each once: the `shared` inboxes collections
+/// expanded to and the inboxes of the `direct` recipients, with the shared
+/// inbox each of them has if any.
+///
+/// A shared inbox hands the activity to every actor of its server it is
+/// addressed to, a recipient that is also a follower is reached through it
+/// and its own inbox is left out.
+fn unique_inboxes(shared: Vec<String>, direct: Vec<(String, Option<String>)>) -> Vec<String> {
+    let mut inboxes: Vec<String> = shared
+        .iter()
+        .map(|inbox| addressing::canonical_iri(inbox))
+        .collect();
+    let reached: HashSet<String> = inboxes.iter().cloned().collect();
+    for (inbox, shared_inbox) in direct {
+        let covered = shared_inbox.is_some_and(|shared_inbox| {
+            reached.contains(&addressing::canonical_iri(&shared_inbox))
+        });
+        if !covered {
+            inboxes.push(addressing::canonical_iri(&inbox));
+        }
+    }
+    inboxes.sort_unstable();
+    inboxes.dedup();
+    inboxes
+}
+
+/// Prefer the shared inbox of a collection member, nested collections and
+/// unreachable actors are skipped.
+fn shared_inbox(actor: Result<Option<Object<'static>>>) -> Option<String> {
+    let actor = actor.ok()??;
+    actor
+        .get_endpoint("sharedInbox")
+        .or_else(|| actor.get_str("inbox"))
+        .map(str::to_string)
+}
+
+/// Keep the parameters of a `Signature` header but not the signature itself.
+fn redact_signature(name: &str, value: &str) -> String {
+    if !name.eq_ignore_ascii_case("signature") {
+        return value.to_string();
+    }
+    value
+        .split(',')
+        .map(|param| match param.split_once('=') {
+            Some(("signature", _)) => "signature=\"[redacted]\"".to_string(),
+            _ => param.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Coarse failure class used as the `reason` label of delivery metrics.
+fn failure_reason(error: &anyhow::Error) -> &'static str {
+    let Some(error) = error.downcast_ref::<reqwest::Error>() else {
+        return "other";
+    };
+    match error.status() {
+        Some(status) if status.is_client_error() => "http_4xx",
+        Some(_) => "http_5xx",
+        None if error.is_timeout() => "timeout",
+        None if error.is_connect() => "connect",
+        None => "request",
+    }
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -291,6 +737,16 @@ pub(crate) struct DeliveryQueueItem {
     pub(crate) uid: String,
     #[n(1)]
     pub(crate) act_key: ObjectKey,
+    /// Correlation ID of the HTTP request that queued this delivery.
+    #[n(2)]
+    pub(crate) request_id: Option<String>,
+    /// `bto` and `bcc` recipients, they are stripped from the stored activity.
+    #[n(3)]
+    pub(crate) blind_recipients: Option<Vec<String>>,
+    /// Inboxes left to deliver to after a failed attempt, recipients are
+    /// not resolved again when set.
+    #[n(4)]
+    pub(crate) inboxes: Option<Vec<String>>,
 }
 
 impl DeliveryQueueItem {
@@ -301,3 +757,127 @@ impl DeliveryQueueItem {
         minicbor::decode(bytes).context("Failed to decode DeliveryQueueItem")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use minicbor::{Decode, Encode};
+
+    use crate::activity_pub::mailman::Mailman;
+
+    use super::{
+        failure_reason, held_inboxes, redact_signature, unique_inboxes, DeliveryQueueItem,
+        ObjectKey,
+    };
+
+    #[test]
+    fn queue_item_without_request_id_still_decodes() -> Result<()> {
+        #[derive(Encode, Decode)]
+        struct LegacyItem {
+            #[n(0)]
+            uid: String,
+            #[n(1)]
+            act_key: ObjectKey,
+        }
+        let act_key = ObjectKey::new();
+        let bytes = minicbor::to_vec(LegacyItem {
+            uid: "alice".to_string(),
+            act_key,
+        })?;
+        let item = DeliveryQueueItem::from_bytes(&bytes)?;
+        assert_eq!(item.act_key, act_key);
+        assert_eq!(item.request_id, None);
+
+        let item = DeliveryQueueItem {
+            request_id: Some("req-1".to_string()),
+            ..item
+        };
+        let item = DeliveryQueueItem::from_bytes(&item.to_bytes()?)?;
+        assert_eq!(item.request_id.as_deref(), Some("req-1"));
+        Ok(())
+    }
+
+    #[test]
+    fn hold_inboxes_of_earlier_deliveries() {
+        let item = |inboxes: Option<&[&str]>| DeliveryQueueItem {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            request_id: None,
+            blind_recipients: None,
+            inboxes: inboxes.map(|inboxes| inboxes.iter().map(|s| s.to_string()).collect()),
+        };
+        assert_eq!(held_inboxes([]), Some(Default::default()));
+        let held = held_inboxes([
+            item(Some(&["https://a.example/inbox"])),
+            item(Some(&[
+                "https://a.example/inbox",
+                "https://b.example/inbox",
+            ])),
+        ])
+        .unwrap();
+        assert!(held.contains("https://b.example/inbox"));
+        assert_eq!(held.len(), 2);
+        // Recipients not resolved yet may include any inbox.
+        assert_eq!(
+            held_inboxes([item(Some(&["https://a.example/inbox"])), item(None)]),
+            None
+        );
+    }
+
+    #[test]
+    fn deliver_once_to_follower_also_addressed() {
+        let shared = "https://remote.example/inbox";
+        let bob = (
+            "https://remote.example/users/bob/inbox".to_string(),
+            Some(shared.to_string()),
+        );
+        // Bob follows and is addressed, the shared inbox reaches him.
+        let followers = vec![
+            "https://other.example/inbox".to_string(),
+            shared.to_string(),
+        ];
+        assert_eq!(
+            unique_inboxes(followers.clone(), vec![bob.clone(), bob.clone()]),
+            followers
+        );
+        // Named another way, the shared inbox still covers him.
+        assert_eq!(
+            unique_inboxes(
+                vec!["HTTPS://Remote.Example:443/inbox".to_string()],
+                vec![bob.clone()]
+            ),
+            [shared]
+        );
+        // Only addressed, he gets it in his own inbox.
+        let carol = (
+            "https://elsewhere.example/users/carol/inbox".to_string(),
+            None,
+        );
+        assert_eq!(
+            unique_inboxes(vec![], vec![bob.clone(), carol.clone()]),
+            [carol.0.as_str(), bob.0.as_str()]
+        );
+    }
+
+    #[test]
+    fn redact_signature_value() {
+        let value = r#"keyId="https://example.com/users/alice#main-key",algorithm="rsa-sha256",headers="(request-target) host",signature="c2VjcmV0""#;
+        assert_eq!(
+            redact_signature("signature", value),
+            r#"keyId="https://example.com/users/alice#main-key",algorithm="rsa-sha256",headers="(request-target) host",signature="[redacted]""#
+        );
+        assert_eq!(redact_signature("date", "today"), "today");
+    }
+
+    #[tokio::test]
+    async fn classify_delivery_failures() {
+        assert_eq!(failure_reason(&anyhow::anyhow!("boom")), "other");
+
+        // Nothing listens on port 1, the connection is refused locally.
+        let error = Mailman::new()
+            .post("http://127.0.0.1:1/inbox", Default::default(), "{}")
+            .await
+            .unwrap_err();
+        assert_eq!(failure_reason(&error), "connect");
+    }
+}
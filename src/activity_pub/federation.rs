@@ -0,0 +1,74 @@
+//! Domain allowlist and blocklist enforced on inbound and outbound traffic.
+
+use metrics::counter;
+use reqwest::Url;
+use tracing::info;
+
+use crate::config::FederationConfig;
+
+impl FederationConfig {
+    /// Whether we exchange activities with the host of `iri`.
+    ///
+    /// IRIs without a host are never federated with.
+    pub(crate) fn permits(&self, iri: &str) -> bool {
+        let Some(host) = Url::parse(iri)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        else {
+            return false;
+        };
+        if self.block.iter().any(|rule| matches(rule, &host)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| matches(rule, &host))
+    }
+    /// Like [`FederationConfig::permits`], but log and count rejections in
+    /// the given `direction`, either `inbound` or `outbound`.
+    pub(crate) fn check(&self, iri: &str, direction: &'static str) -> bool {
+        if self.permits(iri) {
+            return true;
+        }
+        info!(%iri, direction, "domain is not federated with");
+        counter!("pinka_federation_rejected_total", "direction" => direction).increment(1);
+        false
+    }
+}
+
+fn matches(rule: &str, host: &str) -> bool {
+    let rule = rule.trim_end_matches('.').to_ascii_lowercase();
+    match rule.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.')),
+        None => host == rule,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::FederationConfig;
+
+    #[test]
+    fn allow_and_block_domains() {
+        let open = FederationConfig::default();
+        assert!(open.permits("https://any.example/users/bob"));
+        assert!(!open.permits("not an iri"));
+
+        let config = FederationConfig {
+            allow: vec![
+                "trusted.example".to_string(),
+                "*.friends.example".to_string(),
+            ],
+            block: vec!["bad.friends.example".to_string()],
+        };
+        assert!(config.permits("https://trusted.example/users/bob"));
+        assert!(config.permits("https://TRUSTED.example:8443/inbox"));
+        assert!(config.permits("https://a.b.friends.example/users/bob"));
+        // Wildcards only cover subdomains.
+        assert!(!config.permits("https://friends.example/users/bob"));
+        assert!(!config.permits("https://notfriends.example/users/bob"));
+        assert!(!config.permits("https://other.example/users/bob"));
+        // Blocks win over allows.
+        assert!(!config.permits("https://bad.friends.example/users/eve"));
+    }
+}
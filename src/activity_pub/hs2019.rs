@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 use std::iter::Peekable;
 use std::str::Chars;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 use aws_lc_rs::rand::SystemRandom;
@@ -11,26 +12,30 @@ use aws_lc_rs::signature::{
     RSA_PKCS1_SHA256, RSA_PSS_2048_8192_SHA256,
 };
 use axum::body::{Body, Bytes};
-use axum::extract::Request;
+use axum::extract::{Extension, Request};
 use axum::http::request::Parts;
 use axum::middleware::Next;
 use axum::response::Response;
 use base64ct::{Base64, Encoding};
 use const_oid::db::rfc5912::{ID_EC_PUBLIC_KEY, RSA_ENCRYPTION};
 use const_oid::db::rfc8410::ID_ED_25519;
-use jiff::Timestamp;
+use jiff::fmt::rfc2822::DateTimeParser;
+use jiff::{SignedDuration, Timestamp};
+use metrics::counter;
+use moka::sync::Cache;
 use reqwest::header::{self, HeaderMap};
 use reqwest::{StatusCode, Url};
 use sha2::{Digest, Sha256, Sha512};
 use spki::SubjectPublicKeyInfoRef;
 use tracing::warn;
 
-use super::mailman::Mailman;
 use super::model::Object;
+use super::ActorResolver;
+use crate::config::{ActivityPubConfig, CacheConfig, FederationConfig};
 
 const HTTP_DATE_FMT: &str = "%a, %d %b %Y %H:%M:%S GMT";
 
-pub(super) fn post_headers(
+pub(crate) fn post_headers(
     actor_iri: &str,
     inbox: &str,
     body: &str,
@@ -79,14 +84,64 @@ fn base64_sha512_string(bytes: &[u8]) -> String {
     Base64::encode_string(hasher.finalize().as_slice())
 }
 
+/// How far the signed time of a request may be from our clock, see
+/// `signature_max_skew_secs`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MaxSkew(SignedDuration);
+
+impl MaxSkew {
+    pub(crate) fn new(config: &ActivityPubConfig) -> Self {
+        let secs = i64::try_from(config.signature_max_skew_secs).unwrap_or(i64::MAX);
+        MaxSkew(SignedDuration::from_secs(secs))
+    }
+    /// The skew of `signed_at` if it is outside the window.
+    fn check(self, signed_at: Timestamp, now: Timestamp) -> Result<(), SignedDuration> {
+        let skew = now.duration_since(signed_at);
+        if skew.abs() > self.0 {
+            return Err(skew);
+        }
+        Ok(())
+    }
+}
+
+/// Signatures of recently accepted requests, so a captured request cannot
+/// be posted again while its date is still inside the skew window.
+#[derive(Clone)]
+pub(crate) struct SeenSignatures {
+    seen: Cache<String, ()>,
+}
+
+impl SeenSignatures {
+    pub(crate) fn new(cache: &CacheConfig, config: &ActivityPubConfig) -> Self {
+        // A request dated at the start of the window stays acceptable until
+        // its end.
+        let ttl = Duration::from_secs(config.signature_max_skew_secs.saturating_mul(2));
+        let seen = Cache::builder()
+            .max_capacity(cache.replay_capacity)
+            .time_to_live(ttl.max(Duration::from_secs(1)))
+            .build();
+        SeenSignatures { seen }
+    }
+    /// Remember `signature`, false if it was seen already.
+    fn insert(&self, signature: &str) -> bool {
+        self.seen.entry_by_ref(signature).or_insert(()).is_fresh()
+    }
+    /// Forget `signature` so the sender can retry the same request.
+    fn forget(&self, signature: &str) {
+        self.seen.invalidate(signature);
+    }
+}
+
 /// Middleware to validate HTTP Signature HS2019
 pub(crate) async fn validate_request(
+    Extension(resolver): Extension<ActorResolver>,
+    Extension(max_skew): Extension<MaxSkew>,
+    Extension(seen): Extension<SeenSignatures>,
+    Extension(federation): Extension<FederationConfig>,
     parts: Parts,
     body: Bytes,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    // TODO reuse mailman
-    let mailman = Mailman::new();
     let headers = &parts.headers;
     let signature_header = headers
         .get("signature")
@@ -101,27 +156,31 @@ pub(crate) async fn validate_request(
             );
         }
     }
-    let signature = Base64::decode_vec(sig_params.get("signature").ok_or(StatusCode::BAD_REQUEST)?)
-        .map_err(bad)?;
+    let encoded_signature = sig_params.get("signature").ok_or(StatusCode::BAD_REQUEST)?;
+    let signature = Base64::decode_vec(encoded_signature).map_err(bad)?;
     let sig_headers =
         parse_headers(sig_params.get("headers").ok_or(StatusCode::BAD_REQUEST)?).map_err(bad)?;
     if sig_headers.is_empty() {
         return Err(StatusCode::BAD_REQUEST);
     }
     let key_id = sig_params.get("keyId").ok_or(StatusCode::BAD_REQUEST)?;
-
-    // Fetch publicKeyPem
-    // TODO cache actor public key
-    let value = mailman.fetch(key_id).await.map_err(bad)?;
-    let object = Object::from(value);
-    let pubkey_pem = if object.type_is("Key") {
-        object.get_str("publicKeyPem").map(str::to_string)
-    } else {
-        object
-            .get_node_object("publicKey")
-            .and_then(|obj| obj.get_str("publicKeyPem").map(str::to_string))
+    // Never fetch keys from servers we do not federate with.
+    if !federation.check(key_id, "inbound") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    // A signature without a signed time could be replayed forever.
+    let Some(signed_at) = signed_at(headers, &sig_params, &sig_headers) else {
+        warn!(key_id, "signature does not cover a valid date");
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if let Err(skew) = max_skew.check(signed_at, Timestamp::now()) {
+        warn!(
+            key_id,
+            skew_secs = skew.as_secs(),
+            "signature date outside the allowed window"
+        );
+        return Err(StatusCode::UNAUTHORIZED);
     }
-    .ok_or(StatusCode::BAD_REQUEST)?;
 
     let mut sig_body = String::new();
     for header in sig_headers {
@@ -166,6 +225,71 @@ pub(crate) async fn validate_request(
     // Remove trailing newline
     let sig_body = sig_body.trim_end();
 
+    // Resolve publicKeyPem through the remote actor cache. A cached key
+    // that fails to verify is refetched once to pick up key rotation.
+    let object = resolver
+        .resolve(key_id)
+        .await
+        .map_err(bad)?
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if !verify_signature(&object, sig_body, &signature)? {
+        resolver.invalidate(key_id).await.map_err(bad)?;
+        let object = resolver
+            .resolve(key_id)
+            .await
+            .map_err(bad)?
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        if !verify_signature(&object, sig_body, &signature)? {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    if !seen.insert(encoded_signature) {
+        warn!(key_id, "replayed signature");
+        counter!("pinka_inbox_replays_total").increment(1);
+        return Err(StatusCode::CONFLICT);
+    }
+    let req = Request::from_parts(parts, Body::from(body));
+    let response = next.run(req).await;
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        seen.forget(encoded_signature);
+    }
+    Ok(response)
+}
+
+/// When the request was signed: the signed `Date` header, or `created` when
+/// only the hs2019 pseudo header is signed.
+fn signed_at(
+    headers: &HeaderMap,
+    sig_params: &BTreeMap<String, String>,
+    sig_headers: &[String],
+) -> Option<Timestamp> {
+    if sig_headers.iter().any(|header| header == "date") {
+        let date = headers.get(header::DATE)?.to_str().ok()?;
+        return DateTimeParser::new().parse_timestamp(date).ok();
+    }
+    if sig_headers.iter().any(|header| header == "(created)") {
+        let created = sig_params.get("created")?.parse().ok()?;
+        return Timestamp::from_second(created).ok();
+    }
+    None
+}
+
+fn verify_signature(
+    object: &Object<'_>,
+    sig_body: &str,
+    signature: &[u8],
+) -> Result<bool, StatusCode> {
+    let pubkey_pem = if object.type_is("Key") {
+        object.get_str("publicKeyPem").map(str::to_string)
+    } else {
+        object
+            .get_node_object("publicKey")
+            .and_then(|obj| obj.get_str("publicKeyPem").map(str::to_string))
+    }
+    .ok_or(StatusCode::BAD_REQUEST)?;
+
     let (label, der) = pem_rfc7468::decode_vec(pubkey_pem.as_bytes()).map_err(bad)?;
     if label != "PUBLIC KEY" {
         return Err(StatusCode::NOT_IMPLEMENTED);
@@ -191,16 +315,11 @@ pub(crate) async fn validate_request(
         ],
         _ => return Err(StatusCode::NOT_IMPLEMENTED),
     };
-    if !algorithms.iter().any(|&alg| {
+    Ok(algorithms.iter().any(|&alg| {
         UnparsedPublicKey::new(alg, spk)
-            .verify(sig_body.as_bytes(), &signature)
+            .verify(sig_body.as_bytes(), signature)
             .is_ok()
-    }) {
-        return Err(StatusCode::BAD_REQUEST);
-    }
-
-    let req = Request::from_parts(parts, Body::from(body));
-    Ok(next.run(req).await)
+    }))
 }
 
 fn bad<T>(_: T) -> StatusCode {
@@ -315,7 +434,11 @@ mod tests {
     use aws_lc_rs::encoding::AsDer;
     use base64ct::{Base64, Encoding};
 
-    use super::{parse_headers, parse_sig_params};
+    use jiff::{SignedDuration, Timestamp};
+    use reqwest::header::HeaderMap;
+
+    use super::{parse_headers, parse_sig_params, signed_at, MaxSkew, SeenSignatures};
+    use crate::config::{ActivityPubConfig, CacheConfig};
 
     #[test]
     fn test_parse_sig_params() {
@@ -337,6 +460,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reject_dates_outside_skew_window() {
+        let config = ActivityPubConfig {
+            signature_max_skew_secs: 300,
+            ..Default::default()
+        };
+        let max_skew = MaxSkew::new(&config);
+        let mut headers = HeaderMap::new();
+        headers.insert("date", "Sat, 07 Jun 2014 20:51:35 GMT".parse().unwrap());
+        let signed = vec!["(request-target)".to_string(), "date".to_string()];
+        let at = signed_at(&headers, &Default::default(), &signed).unwrap();
+        assert_eq!(at, Timestamp::from_second(1402174295).unwrap());
+
+        let late = at + SignedDuration::from_secs(299);
+        assert!(max_skew.check(at, late).is_ok());
+        let early = at - SignedDuration::from_secs(300);
+        assert!(max_skew.check(at, early).is_ok());
+        let stale = at + SignedDuration::from_secs(301);
+        assert_eq!(
+            max_skew.check(at, stale),
+            Err(SignedDuration::from_secs(301))
+        );
+        let ahead = at - SignedDuration::from_secs(301);
+        assert!(max_skew.check(at, ahead).is_err());
+
+        // An unsigned date does not count, `created` does.
+        let unsigned = vec!["(request-target)".to_string(), "digest".to_string()];
+        assert!(signed_at(&headers, &Default::default(), &unsigned).is_none());
+        let params = [("created".to_string(), "1402170695".to_string())].into();
+        let created = vec!["(created)".to_string()];
+        assert_eq!(
+            signed_at(&headers, &params, &created),
+            Timestamp::from_second(1402170695).ok()
+        );
+    }
+
+    #[test]
+    fn remember_seen_signatures() {
+        let seen = SeenSignatures::new(&CacheConfig::default(), &ActivityPubConfig::default());
+        assert!(seen.insert("c2lnbmF0dXJl"));
+        assert!(!seen.insert("c2lnbmF0dXJl"));
+        assert!(seen.insert("b3RoZXI="));
+        seen.forget("c2lnbmF0dXJl");
+        assert!(seen.insert("c2lnbmF0dXJl"));
+    }
+
     #[test]
     fn test_parse_headers() {
         let input =
@@ -0,0 +1,135 @@
+//! Concurrency limits for outbound deliveries.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::DeliveryConfig;
+
+#[derive(Clone)]
+pub(super) struct DeliveryLimiter {
+    global: Arc<Semaphore>,
+    per_host: usize,
+    hosts: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl DeliveryLimiter {
+    pub(super) fn new(config: &DeliveryConfig) -> DeliveryLimiter {
+        DeliveryLimiter {
+            global: Arc::new(Semaphore::new(config.max_concurrency.max(1))),
+            per_host: config.max_concurrency_per_host.max(1),
+            hosts: Default::default(),
+        }
+    }
+    /// Wait for a free slot on the host of `inbox`, then for a free
+    /// delivery slot.
+    ///
+    /// The host comes first, so deliveries queued behind a saturated host
+    /// do not hold delivery slots that other hosts could use.
+    pub(super) async fn acquire(&self, inbox: &str) -> DeliveryPermit {
+        let host = self.acquire_host(inbox).await;
+        let global = self
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("delivery semaphore is never closed");
+        DeliveryPermit {
+            _global: global,
+            _host: host,
+        }
+    }
+    async fn acquire_host(&self, inbox: &str) -> OwnedSemaphorePermit {
+        let host = Url::parse(inbox)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| inbox.to_string());
+        let semaphore = {
+            let mut hosts = self.hosts.lock().unwrap();
+            // Forget hosts nobody holds or waits for, so the map only grows
+            // with the hosts currently being delivered to.
+            hosts.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            hosts
+                .entry(host)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.per_host)))
+                .clone()
+        };
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("delivery semaphore is never closed")
+    }
+}
+
+/// Both slots a delivery holds, released when dropped.
+pub(super) struct DeliveryPermit {
+    _global: OwnedSemaphorePermit,
+    _host: OwnedSemaphorePermit,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use crate::config::DeliveryConfig;
+
+    use super::DeliveryLimiter;
+
+    #[tokio::test]
+    async fn limit_per_host() {
+        let limiter = DeliveryLimiter::new(&DeliveryConfig {
+            max_concurrency: 2,
+            max_concurrency_per_host: 1,
+        });
+        let wait = Duration::from_millis(50);
+
+        let busy = limiter.acquire("https://a.example/inbox").await;
+        assert!(
+            timeout(wait, limiter.acquire("https://a.example/users/bob/inbox"))
+                .await
+                .is_err()
+        );
+        // Other hosts are not held up.
+        let other = limiter.acquire("https://b.example/inbox").await;
+        assert!(timeout(wait, limiter.acquire("https://c.example/inbox"))
+            .await
+            .is_err());
+        drop(busy);
+        let _next = limiter.acquire("https://a.example/inbox").await;
+        drop(other);
+    }
+
+    #[tokio::test]
+    async fn saturated_host_does_not_starve_others() {
+        let limiter = DeliveryLimiter::new(&DeliveryConfig {
+            max_concurrency: 2,
+            max_concurrency_per_host: 1,
+        });
+        let _busy = limiter.acquire("https://a.example/inbox").await;
+        let queued: Vec<_> = (0..4)
+            .map(|n| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    limiter
+                        .acquire(&format!("https://a.example/users/{n}/inbox"))
+                        .await
+                })
+            })
+            .collect();
+        tokio::task::yield_now().await;
+
+        let other = timeout(
+            Duration::from_millis(50),
+            limiter.acquire("https://b.example/inbox"),
+        )
+        .await;
+        assert!(other.is_ok());
+        for task in queued {
+            task.abort();
+        }
+    }
+}
@@ -1,25 +1,87 @@
+//! The ActivityPub state machine, fed with the committed raft log.
+//!
+//! Applies are serialized: the actor handles one entry at a time in log
+//! order and commits each in one batch, so every server ends up with the
+//! same keyspace. Reads do not go through the actor. HTTP handlers open the
+//! repositories on the keyspace and see every batch either fully or not at
+//! all, a slow apply never holds them up. A read of several keys may see
+//! batches applied in between, readers tolerate an index entry whose object
+//! is gone. Readers that need their own write wait on [`AppliedIndex`].
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::{Context, Result};
-use fjall::{Keyspace, PersistMode};
+use fjall::{Keyspace, PartitionCreateOptions, PartitionHandle, PersistMode};
+use metrics::counter;
 use minicbor::{Decode, Encode};
 use ractor::{Actor, ActorProcessingErr, ActorRef};
+use tokio::sync::watch;
 use tokio::task::spawn_blocking;
-use tracing::{error, info, warn};
+use tokio::time::timeout;
+use tracing::{error, info, info_span, warn, Instrument};
 use uuid::Bytes;
 
-use crate::raft::{get_raft_applied, ClientResult, LogEntryValue, RaftAppliedMsg, StateMachineMsg};
+use crate::raft::{
+    get_raft_applied, saved_last_applied, ClientResult, LogEntry, LogEntryValue, RaftAppliedMsg,
+    StateMachineMsg,
+};
+use crate::storage_health::{is_storage_error, StorageHealth};
 use crate::ActivityPubConfig;
 
 use super::delivery::DeliveryQueueItem;
-use super::model::{Actor as AsActor, Create, Object, Update};
-use super::repo::{ContextIndex, CryptoRepo, KeyMaterial, OutboxIndex};
+use super::model::{
+    format_timestamp, Actor as AsActor, Announce, Block, Create, Delete, Move, Object, Question,
+    Update, Vote,
+};
+use super::repo::{
+    transaction, ContextIndex, CryptoRepo, KeyMaterial, ModerationRepo, OutboxIndex, Pruned,
+    RemoteActorRepo, Retention,
+};
 use super::simple_queue::SimpleQueue;
-use super::{IriIndex, ObjectKey, ObjectRepo, UserIndex};
+use super::{
+    required_response, ActorCache, InboxResponse, IriIndex, ObjectKey, ObjectRepo, UserIndex,
+    Visibility,
+};
 
 pub(crate) struct ActivityPubMachine;
 
+/// How long to wait before applying again an entry that failed on degraded
+/// storage.
+const APPLY_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Index of the last log entry the state machine applied on this server.
+///
+/// Readers wait on it to see their own writes, see
+/// [`AppliedIndex::wait_for`].
+#[derive(Clone, Default)]
+pub(crate) struct AppliedIndex(Arc<watch::Sender<u64>>);
+
+impl AppliedIndex {
+    fn set(&self, index: u64) {
+        self.0.send_replace(index);
+    }
+    /// Wait until the entry at `index` is applied, false if `limit` passes
+    /// first.
+    pub(crate) async fn wait_for(&self, index: u64, limit: Duration) -> bool {
+        let mut applied = self.0.subscribe();
+        let reached = timeout(limit, applied.wait_for(|&applied| applied >= index))
+            .await
+            .is_ok_and(|result| result.is_ok());
+        reached
+    }
+}
+
 pub(crate) struct State {
     apub: ActivityPubConfig,
     keyspace: Keyspace,
+    /// Index of the last log entry applied, the authoritative record of
+    /// apply progress.
+    last_applied: u64,
+    /// `last_applied` published to readers.
+    applied_index: AppliedIndex,
+    machine_state: PartitionHandle,
     user_index: UserIndex,
     outbox_index: OutboxIndex,
     ctx_index: ContextIndex,
@@ -27,11 +89,25 @@ pub(crate) struct State {
     obj_repo: ObjectRepo,
     crypto_repo: CryptoRepo,
     queue: SimpleQueue,
+    actor_cache: ActorCache,
+    remote_actors: RemoteActorRepo,
+    moderation: ModerationRepo,
+    retention: Retention,
+    /// Shared with the raft worker, see [`StorageHealth`].
+    storage: StorageHealth,
+    /// An apply failed on degraded storage. Entries other than the next one
+    /// are dropped until raft queues them again after `last_applied`.
+    resume_pending: bool,
+    /// A `ReportApplied` to retry the failed apply is on its way.
+    retry_scheduled: bool,
 }
 
 pub(crate) struct ActivityPubMachineInit {
     pub(crate) apub: ActivityPubConfig,
     pub(crate) keyspace: Keyspace,
+    pub(crate) actor_cache: ActorCache,
+    pub(crate) applied_index: AppliedIndex,
+    pub(crate) storage: StorageHealth,
 }
 
 impl Actor for ActivityPubMachine {
@@ -44,52 +120,74 @@ impl Actor for ActivityPubMachine {
         _myself: ActorRef<Self::Msg>,
         args: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let ActivityPubMachineInit { apub, keyspace } = args;
-        spawn_blocking(move || {
-            let user_index = UserIndex::new(keyspace.clone())?;
-            let outbox_index = OutboxIndex::new(keyspace.clone())?;
-            let ctx_index = ContextIndex::new(keyspace.clone())?;
-            let iri_index = IriIndex::new(keyspace.clone())?;
-            let obj_repo = ObjectRepo::new(keyspace.clone())?;
-            let crypto_repo = CryptoRepo::new(keyspace.clone())?;
-            let queue = SimpleQueue::new(keyspace.clone())?;
-            Ok(State {
-                apub,
-                keyspace,
-                user_index,
-                outbox_index,
-                ctx_index,
-                iri_index,
-                obj_repo,
-                crypto_repo,
-                queue,
-            })
-        })
-        .await
-        .context("Failed to create ActivityPubMachine")?
+        let ActivityPubMachineInit {
+            apub,
+            keyspace,
+            actor_cache,
+            applied_index,
+            storage,
+        } = args;
+        let mut state =
+            spawn_blocking(move || State::new(apub, keyspace, actor_cache, applied_index))
+                .await?
+                .context("Failed to create ActivityPubMachine")?;
+        state.storage = storage;
+        Ok(state)
     }
 
-    async fn handle(
+    async fn post_start(
         &self,
         _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        // Raft resumes applying after our position. If it is not running
+        // yet, it asks with `ReportApplied` once it is.
+        if let Ok(raft) = get_raft_applied() {
+            ractor::cast!(raft, RaftAppliedMsg::Resume(state.last_applied))?;
+        }
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         let reply = get_raft_applied()?;
         match message {
-            StateMachineMsg::Apply(log_entry) => match log_entry.value {
-                LogEntryValue::Command(byte_buf) => {
-                    let command = ActivityPubCommand::from_bytes(&byte_buf)?;
-                    let result = state.handle_command(command).await?;
-                    ractor::cast!(reply, RaftAppliedMsg::Applied(log_entry.index, result))?;
+            StateMachineMsg::Apply(log_entry) => {
+                let index = log_entry.index;
+                if state.resume_pending && index != state.last_applied + 1 {
+                    return Ok(());
                 }
-                LogEntryValue::NewTermStarted | LogEntryValue::ClusterMessage(_) => {
-                    ractor::cast!(
-                        reply,
-                        RaftAppliedMsg::Applied(log_entry.index, ClientResult::ok())
-                    )?;
+                match state.apply(log_entry).await {
+                    Ok(Some(result)) => {
+                        state.storage.succeeded();
+                        state.resume_pending = false;
+                        ractor::cast!(reply, RaftAppliedMsg::Applied(index, result))?;
+                    }
+                    Ok(None) => {}
+                    // Crashing on storage that stays broken would only loop,
+                    // ask raft for the entry again later instead.
+                    Err(error) if is_storage_error(&error) => {
+                        state.storage.failed(&error);
+                        if !state.storage.is_degraded() {
+                            return Err(error.into());
+                        }
+                        state.resume_pending = true;
+                        if !state.retry_scheduled {
+                            state.retry_scheduled = true;
+                            myself.send_after(APPLY_RETRY_DELAY, || StateMachineMsg::ReportApplied);
+                        }
+                    }
+                    Err(error) => return Err(error.into()),
                 }
-            },
+            }
+            StateMachineMsg::ReportApplied => {
+                state.retry_scheduled = false;
+                ractor::cast!(reply, RaftAppliedMsg::Resume(state.last_applied))?;
+            }
         }
         Ok(())
     }
@@ -104,6 +202,18 @@ pub(crate) enum ActivityPubCommand {
     ReceiveDelivery(#[n(0)] Bytes, #[n(1)] u64, #[n(2)] u64),
     #[n(2)]
     AckDelivery(#[n(0)] Bytes, #[n(1)] Bytes),
+    /// Narrow a received delivery down to the inboxes that still failed.
+    #[n(3)]
+    RetryDelivery(#[n(0)] Bytes, #[n(1)] Bytes, #[n(2)] DeliveryQueueItem),
+    /// Give up on a received delivery, keeping it as a dead letter.
+    #[n(4)]
+    AbandonDelivery(#[n(0)] Bytes, #[n(1)] Bytes),
+    /// Replace a dead letter after a manual redelivery, `None` removes it.
+    #[n(5)]
+    UpdateDeadLetter(#[n(0)] Bytes, #[n(1)] Option<DeliveryQueueItem>),
+    /// Hand a received delivery back to the queue when the worker stops.
+    #[n(6)]
+    ReleaseDelivery(#[n(0)] Bytes, #[n(1)] Bytes),
 
     // ===== 10..32 server to server interactions =====
     #[n(10)]
@@ -122,6 +232,14 @@ pub(crate) enum ActivityPubCommand {
     S2sUpdate(#[n(0)] S2sCommand),
     #[n(17)]
     S2sAnnounce(#[n(0)] S2sCommand),
+    /// A followed account moved, `obj_key` is used for the new Follow.
+    #[n(18)]
+    S2sMove(#[n(0)] S2sCommand),
+    #[n(19)]
+    S2sFlag(#[n(0)] S2sCommand),
+    /// A remote actor declined a Follow the user sent.
+    #[n(20)]
+    S2sReject(#[n(0)] S2sCommand),
 
     // ===== 32..100 reserved =====
 
@@ -132,14 +250,53 @@ pub(crate) enum ActivityPubCommand {
         #[n(1)] Object<'static>,
         #[n(2)] Option<KeyMaterial>,
     ),
+    /// Delete remote objects stored before the unix time in seconds, one
+    /// pass after the given key, see [`Retention::prune_remote`]. Replies
+    /// with the key the next pass resumes after, nothing once done.
+    #[n(101)]
+    PruneRemoteObjects(#[n(0)] u64, #[n(1)] Option<ObjectKey>),
+    /// Disable or enable a local user, see [`UserIndex::set_disabled`].
+    #[n(102)]
+    SetUserDisabled(#[n(0)] String, #[n(1)] bool),
+    /// Replace the signing key of a local user.
+    #[n(103)]
+    RotateUserKey(#[n(0)] String, #[n(1)] KeyMaterial),
+    /// Pick the audience of posts by a local user that name none.
+    #[n(104)]
+    SetUserVisibility(#[n(0)] String, #[n(1)] Visibility),
 
     // ===== 200..256 client to server interactions =====
     /// Client to Server - Create Activity
     #[n(200)]
     C2sCreate(#[n(0)] C2sCommand),
-    /// Client to Server - Add Activity
+    /// Client to Server - Accept Activity, `obj_key` is the accepted Follow
     #[n(201)]
     C2sAccept(#[n(0)] C2sCommand),
+    /// Client to Server - Move Activity
+    #[n(202)]
+    C2sMove(#[n(0)] C2sCommand),
+    /// Client to Server - Block Activity
+    #[n(203)]
+    C2sBlock(#[n(0)] C2sCommand),
+    /// Client to Server - Reject Activity, `obj_key` is the rejected Follow
+    #[n(204)]
+    C2sReject(#[n(0)] C2sCommand),
+    /// Client to Server - Add Activity, pins `obj_key` to the featured
+    /// collection
+    #[n(205)]
+    C2sAdd(#[n(0)] C2sCommand),
+    /// Client to Server - Remove Activity, unpins `obj_key`
+    #[n(206)]
+    C2sRemove(#[n(0)] C2sCommand),
+    /// Client to Server - Announce Activity
+    #[n(207)]
+    C2sAnnounce(#[n(0)] C2sCommand),
+    /// Client to Server - Update Activity, edits `obj_key`
+    #[n(208)]
+    C2sUpdate(#[n(0)] C2sCommand),
+    /// Client to Server - Delete Activity, tombstones `obj_key`
+    #[n(209)]
+    C2sDelete(#[n(0)] C2sCommand),
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -152,6 +309,9 @@ pub(crate) struct C2sCommand {
     pub(crate) obj_key: ObjectKey,
     #[n(3)]
     pub(crate) object: Object<'static>,
+    /// Correlation ID of the HTTP request that produced this command.
+    #[n(4)]
+    pub(crate) request_id: Option<String>,
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -162,14 +322,41 @@ pub(crate) struct S2sCommand {
     pub(crate) obj_key: ObjectKey,
     #[n(2)]
     pub(crate) object: Object<'static>,
+    /// Correlation ID of the HTTP request that produced this command.
+    #[n(3)]
+    pub(crate) request_id: Option<String>,
 }
 
 impl ActivityPubCommand {
+    fn request_id(&self) -> Option<&str> {
+        use ActivityPubCommand::*;
+        match self {
+            QueueDelivery(_, item) | RetryDelivery(_, _, item) => item.request_id.as_deref(),
+            S2sCreate(cmd) | S2sDelete(cmd) | S2sLike(cmd) | S2sDislike(cmd) | S2sFollow(cmd)
+            | S2sUndo(cmd) | S2sUpdate(cmd) | S2sAnnounce(cmd) | S2sMove(cmd) | S2sFlag(cmd)
+            | S2sReject(cmd) => cmd.request_id.as_deref(),
+            C2sCreate(cmd) | C2sAccept(cmd) | C2sMove(cmd) | C2sBlock(cmd) | C2sReject(cmd)
+            | C2sAdd(cmd) | C2sRemove(cmd) | C2sAnnounce(cmd) | C2sUpdate(cmd) | C2sDelete(cmd) => {
+                cmd.request_id.as_deref()
+            }
+            ReceiveDelivery(..)
+            | AckDelivery(..)
+            | AbandonDelivery(..)
+            | UpdateDeadLetter(..)
+            | ReleaseDelivery(..)
+            | UpdateUser(..)
+            | PruneRemoteObjects(..)
+            | SetUserDisabled(..)
+            | RotateUserKey(..)
+            | SetUserVisibility(..) => None,
+        }
+    }
+
     fn into_bytes(self) -> Result<Vec<u8>> {
         minicbor::to_vec(&self).context("Unable to serialize apub command")
     }
 
-    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
         minicbor::decode(bytes).context("Unable to deserialize apub command")
     }
 }
@@ -180,10 +367,103 @@ impl From<ActivityPubCommand> for LogEntryValue {
     }
 }
 
-const MAILBOX: &str = "mailbox";
+pub(super) const MAILBOX: &str = "mailbox";
+const LAST_APPLIED: &str = "last_applied";
+
+/// Index of the last log entry applied to the state machine in `keyspace`.
+pub(crate) fn last_applied(keyspace: &Keyspace) -> Result<u64> {
+    let machine_state = keyspace
+        .open_partition("machine_state", PartitionCreateOptions::default())
+        .context("Failed to open machine state")?;
+    saved_applied(keyspace, &machine_state)
+}
+
+fn saved_applied(keyspace: &Keyspace, machine_state: &PartitionHandle) -> Result<u64> {
+    match machine_state.get(LAST_APPLIED)? {
+        Some(value) => Ok(u64::from_be_bytes(value.as_ref().try_into()?)),
+        // Raft kept the position before we did.
+        None => saved_last_applied(keyspace),
+    }
+}
 
 impl State {
+    fn new(
+        apub: ActivityPubConfig,
+        keyspace: Keyspace,
+        actor_cache: ActorCache,
+        applied_index: AppliedIndex,
+    ) -> Result<State> {
+        let machine_state = keyspace
+            .open_partition("machine_state", PartitionCreateOptions::default())
+            .context("Failed to open machine state")?;
+        let last_applied = saved_applied(&keyspace, &machine_state)?;
+        info!(last_applied, "restored apply position");
+        applied_index.set(last_applied);
+        Ok(State {
+            apub,
+            last_applied,
+            applied_index,
+            machine_state,
+            user_index: UserIndex::new(keyspace.clone())?,
+            outbox_index: OutboxIndex::new(keyspace.clone())?,
+            ctx_index: ContextIndex::new(keyspace.clone())?,
+            iri_index: IriIndex::new(keyspace.clone())?,
+            obj_repo: ObjectRepo::new(keyspace.clone())?,
+            crypto_repo: CryptoRepo::new(keyspace.clone())?,
+            queue: SimpleQueue::new(keyspace.clone())?,
+            remote_actors: RemoteActorRepo::new(keyspace.clone())?,
+            moderation: ModerationRepo::new(keyspace.clone())?,
+            retention: Retention::new(keyspace.clone())?,
+            keyspace,
+            actor_cache,
+            storage: StorageHealth::default(),
+            resume_pending: false,
+            retry_scheduled: false,
+        })
+    }
+    /// Apply a committed log entry and make all of its writes durable,
+    /// together with the new `last_applied`.
+    ///
+    /// Entries at or before `last_applied` were applied already, e.g. when
+    /// raft queued them again after a restart, and return `None`.
+    ///
+    /// `last_applied` is written after the command, so it never runs ahead
+    /// of the durable state. Most handlers already commit a synced batch,
+    /// the final persist also covers the plain writes (e.g. remote actor
+    /// cache eviction) in a keyspace opened with manual journal persistence.
+    /// A crash after a handler's own commit but before the final persist
+    /// applies the entry again on restart. Commands must therefore be
+    /// idempotent, which they are because every key is chosen by the leader
+    /// and carried in the command.
+    async fn apply(&mut self, log_entry: LogEntry) -> Result<Option<ClientResult>> {
+        if log_entry.index <= self.last_applied {
+            return Ok(None);
+        }
+        let result = match log_entry.value {
+            LogEntryValue::Command(byte_buf) => {
+                let command = ActivityPubCommand::from_bytes(&byte_buf)?;
+                self.handle_command(command).await?
+            }
+            LogEntryValue::NewTermStarted | LogEntryValue::ClusterMessage(_) => ClientResult::ok(),
+        };
+        let keyspace = self.keyspace.clone();
+        let machine_state = self.machine_state.clone();
+        let index = log_entry.index;
+        spawn_blocking(move || {
+            machine_state.insert(LAST_APPLIED, index.to_be_bytes())?;
+            keyspace.persist(PersistMode::SyncAll)
+        })
+        .await
+        .context("Failed to persist applied command")??;
+        self.last_applied = index;
+        self.applied_index.set(index);
+        Ok(Some(result))
+    }
     async fn handle_command(&mut self, command: ActivityPubCommand) -> Result<ClientResult> {
+        let span = info_span!("command", request_id = command.request_id());
+        self.dispatch_command(command).instrument(span).await
+    }
+    async fn dispatch_command(&mut self, command: ActivityPubCommand) -> Result<ClientResult> {
         // TODO refine logging
         info!(?command, "received command");
 
@@ -193,20 +473,122 @@ impl State {
                     .await
                     .context("Failed to handle UpdateUser command")?;
             }
+            ActivityPubCommand::PruneRemoteObjects(cutoff, after) => {
+                let retention = self.retention.clone();
+                let apub = self.apub.clone();
+                let Pruned { pruned, resume } = spawn_blocking(move || {
+                    retention.prune_remote(cutoff, after, |iri| apub.is_local(iri))
+                })
+                .await
+                .context("Failed to handle PruneRemoteObjects command")??;
+                info!(pruned, cutoff, "pruned remote objects");
+                counter!("pinka_objects_pruned_total").increment(pruned);
+                return Ok(ClientResult::stored(resume));
+            }
+            ActivityPubCommand::SetUserDisabled(uid, disabled) => {
+                let keyspace = self.keyspace.clone();
+                let user_index = self.user_index.clone();
+                spawn_blocking(move || {
+                    transaction(&keyspace, |b| {
+                        user_index.set_disabled(b, &uid, disabled);
+                        Ok(())
+                    })
+                })
+                .await
+                .context("Failed to handle SetUserDisabled command")??;
+            }
+            ActivityPubCommand::SetUserVisibility(uid, visibility) => {
+                let keyspace = self.keyspace.clone();
+                let user_index = self.user_index.clone();
+                spawn_blocking(move || {
+                    transaction(&keyspace, |b| {
+                        user_index.set_visibility(b, &uid, visibility)
+                    })
+                })
+                .await
+                .context("Failed to handle SetUserVisibility command")??;
+            }
+            ActivityPubCommand::RotateUserKey(uid, key_material) => {
+                let keyspace = self.keyspace.clone();
+                let crypto_repo = self.crypto_repo.clone();
+                spawn_blocking(move || {
+                    transaction(&keyspace, |b| {
+                        crypto_repo.insert(b, &uid, &key_material);
+                        Ok(())
+                    })
+                })
+                .await
+                .context("Failed to handle RotateUserKey command")??;
+            }
             ActivityPubCommand::C2sCreate(cmd) => {
-                self.handle_c2s_create(cmd)
+                let stored = self
+                    .handle_c2s_create(cmd)
                     .await
                     .context("Failed to handle C2sCreate command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::C2sAccept(cmd) => {
-                self.handle_c2s_accept(cmd)
+                let stored = self
+                    .handle_c2s_accept(cmd)
                     .await
                     .context("Failed to handle C2sAccept command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sReject(cmd) => {
+                let stored = self
+                    .handle_c2s_reject(cmd)
+                    .await
+                    .context("Failed to handle C2sReject command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sAdd(cmd) => {
+                let stored = self
+                    .handle_c2s_pin(cmd, true)
+                    .await
+                    .context("Failed to handle C2sAdd command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sRemove(cmd) => {
+                let stored = self
+                    .handle_c2s_pin(cmd, false)
+                    .await
+                    .context("Failed to handle C2sRemove command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sAnnounce(cmd) => {
+                let stored = self
+                    .handle_c2s_announce(cmd)
+                    .await
+                    .context("Failed to handle C2sAnnounce command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sUpdate(cmd) => {
+                let stored = self
+                    .handle_c2s_update(cmd)
+                    .await
+                    .context("Failed to handle C2sUpdate command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sDelete(cmd) => {
+                let stored = self
+                    .handle_c2s_delete(cmd)
+                    .await
+                    .context("Failed to handle C2sDelete command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::C2sMove(cmd) => {
+                let stored = self
+                    .handle_c2s_activity(cmd)
+                    .await
+                    .context("Failed to handle C2sMove command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::S2sCreate(cmd) => {
-                self.handle_s2s_create(cmd)
+                let stored = self
+                    .handle_s2s_create(cmd)
                     .await
                     .context("Failed to handle S2sCreate command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::S2sDelete(cmd) => {
                 self.handle_s2s_delete(cmd)
@@ -214,9 +596,11 @@ impl State {
                     .context("Failed to handle S2sDelete command")?;
             }
             ActivityPubCommand::S2sLike(cmd) => {
-                self.handle_s2s_like(cmd)
+                let stored = self
+                    .handle_s2s_like(cmd)
                     .await
                     .context("Failed to handle S2sLike command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::S2sDislike(cmd) => {
                 self.handle_s2s_dislike(cmd)
@@ -224,9 +608,11 @@ impl State {
                     .context("Failed to handle S2sDislike command")?;
             }
             ActivityPubCommand::S2sFollow(cmd) => {
-                self.handle_s2s_follow(cmd)
+                let stored = self
+                    .handle_s2s_follow(cmd)
                     .await
                     .context("Failed to handle S2sFollow command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::S2sUndo(cmd) => {
                 self.handle_s2s_undo(cmd)
@@ -239,9 +625,37 @@ impl State {
                     .context("Failed to handle S2sUpdate command")?;
             }
             ActivityPubCommand::S2sAnnounce(cmd) => {
-                self.handle_s2s_announce(cmd)
+                let stored = self
+                    .handle_s2s_announce(cmd)
                     .await
                     .context("Failed to handle S2sAnnounce command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::S2sMove(cmd) => {
+                let stored = self
+                    .handle_s2s_move(cmd)
+                    .await
+                    .context("Failed to handle S2sMove command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::S2sFlag(cmd) => {
+                let stored = self
+                    .handle_s2s_flag(cmd)
+                    .await
+                    .context("Failed to handle S2sFlag command")?;
+                return Ok(ClientResult::stored(stored));
+            }
+            ActivityPubCommand::S2sReject(cmd) => {
+                self.handle_s2s_reject(cmd)
+                    .await
+                    .context("Failed to handle S2sReject command")?;
+            }
+            ActivityPubCommand::C2sBlock(cmd) => {
+                let stored = self
+                    .handle_c2s_block(cmd)
+                    .await
+                    .context("Failed to handle C2sBlock command")?;
+                return Ok(ClientResult::stored(stored));
             }
             ActivityPubCommand::QueueDelivery(key, item) => {
                 let queue = self.queue.clone();
@@ -258,7 +672,7 @@ impl State {
                 .await
                 .context("Failed to handle ReceiveDelivery command")??
                 {
-                    return Ok(ClientResult::Ok(res.to_bytes()?));
+                    return Ok(ClientResult::from(res.to_bytes()?));
                 }
             }
             ActivityPubCommand::AckDelivery(key, receipt_handle) => {
@@ -267,6 +681,32 @@ impl State {
                     .await
                     .context("Failed to handle AckDelivery command")??;
             }
+            ActivityPubCommand::RetryDelivery(key, receipt_handle, item) => {
+                let queue = self.queue.clone();
+                let bytes = item.to_bytes()?;
+                spawn_blocking(move || queue.update_message(MAILBOX, key, receipt_handle, bytes))
+                    .await
+                    .context("Failed to handle RetryDelivery command")??;
+            }
+            ActivityPubCommand::AbandonDelivery(key, receipt_handle) => {
+                let queue = self.queue.clone();
+                spawn_blocking(move || queue.dead_letter_message(MAILBOX, key, receipt_handle))
+                    .await
+                    .context("Failed to handle AbandonDelivery command")??;
+            }
+            ActivityPubCommand::UpdateDeadLetter(key, item) => {
+                let queue = self.queue.clone();
+                let bytes = item.map(|item| item.to_bytes()).transpose()?;
+                spawn_blocking(move || queue.update_dead_letter(MAILBOX, key, bytes))
+                    .await
+                    .context("Failed to handle UpdateDeadLetter command")??;
+            }
+            ActivityPubCommand::ReleaseDelivery(key, receipt_handle) => {
+                let queue = self.queue.clone();
+                spawn_blocking(move || queue.release_message(MAILBOX, key, receipt_handle))
+                    .await
+                    .context("Failed to handle ReleaseDelivery command")??;
+            }
         }
 
         Ok(ClientResult::ok())
@@ -281,40 +721,46 @@ impl State {
         let keyspace = self.keyspace.clone();
         let user_index = self.user_index.clone();
         let crypto_repo = self.crypto_repo.clone();
+        let actor_cache = self.actor_cache.clone();
 
         spawn_blocking(move || -> Result<()> {
-            let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-            user_index.insert(&mut b, &uid, user)?;
-            if let Some(key_pair) = key_material {
-                crypto_repo.insert(&mut b, &uid, &key_pair);
-            }
-            b.commit()?;
+            transaction(&keyspace, |b| {
+                user_index.insert(b, &uid, user)?;
+                if let Some(key_pair) = key_material {
+                    crypto_repo.insert(b, &uid, &key_pair);
+                }
+                Ok(())
+            })?;
+            // Invalidate after commit so readers can't repopulate a stale copy.
+            actor_cache.invalidate(&uid);
             Ok(())
         })
         .await??;
         Ok(())
     }
-    async fn handle_c2s_create(&mut self, cmd: C2sCommand) -> Result<()> {
+    async fn handle_c2s_create(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
         let C2sCommand {
             uid,
             act_key,
             obj_key,
             object,
+            ..
         } = cmd;
         let create = match Create::try_from(object) {
             Ok(create) => create,
             Err(error) => {
                 error!(?error, "invalid object");
-                return Ok(());
+                return Ok(None);
             }
         };
-        let base_url = self.apub.base_url.clone();
+        let act_iri = self.apub.object_iri(act_key);
+        let actor_iri = self.apub.user_iri(&uid);
         let keyspace = self.keyspace.clone();
         let iri_index = self.iri_index.clone();
         let obj_repo = self.obj_repo.clone();
         let outbox_index = self.outbox_index.clone();
 
-        spawn_blocking(move || -> Result<()> {
+        spawn_blocking(move || -> Result<Option<ObjectKey>> {
             let create: Object = create.into();
             if let Some(iri) = create.get_node_iri("object") {
                 if let Some(object) = iri_index
@@ -324,7 +770,7 @@ impl State {
                 {
                     let Some(update) = create.get_node_object("object") else {
                         error!("activity should have an object");
-                        return Ok(());
+                        return Ok(None);
                     };
                     if update
                         .get_str("updated")
@@ -334,182 +780,606 @@ impl State {
                             .or_else(|| object.get_str("published"))
                     {
                         // skip
-                        return Ok(());
+                        return Ok(None);
                     }
                     // FIXME where should we ensure id and actor?
                     let update = Update::try_from(update)?
-                        .ensure_id(format!("{}/as/objects/{act_key}", base_url))
-                        .with_actor(format!("{}/users/{uid}", base_url));
-                    let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                    outbox_index.insert_update(&mut b, uid, act_key, update.into())?;
-                    b.commit()?;
+                        .ensure_id(act_iri)
+                        .with_actor(actor_iri);
+                    transaction(&keyspace, |b| {
+                        outbox_index.insert_update(b, uid, act_key, update.into())
+                    })?;
                 } else {
-                    let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                    outbox_index.insert_create(&mut b, uid, act_key, obj_key, create)?;
-                    b.commit()?;
+                    let stored = transaction(&keyspace, |b| {
+                        outbox_index.insert_create(b, uid, act_key, obj_key, create)
+                    })?;
+                    if !stored {
+                        return Ok(None);
+                    }
                 }
+                return Ok(Some(act_key));
             }
-            Ok(())
+            Ok(None)
         })
-        .await??;
-
-        Ok(())
+        .await?
     }
-    async fn handle_c2s_accept(&mut self, cmd: C2sCommand) -> Result<()> {
+    /// Store an activity the server generated on behalf of a local user.
+    async fn handle_c2s_activity(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
         let C2sCommand {
             uid: _,
             act_key,
             obj_key: _,
             object,
+            ..
+        } = cmd;
+        let keyspace = self.keyspace.clone();
+        let obj_repo = self.obj_repo.clone();
+        spawn_blocking(move || transaction(&keyspace, |b| obj_repo.insert(b, act_key, object)))
+            .await??;
+        Ok(Some(act_key))
+    }
+    /// Store an Accept and turn the accepted Follow request into a follower.
+    ///
+    /// Follows accepted automatically are followers already.
+    async fn handle_c2s_accept(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            obj_key: follow_key,
+            object,
+            ..
         } = cmd;
-        let mut batch = self.keyspace.batch().durability(Some(PersistMode::SyncAll));
+        let keyspace = self.keyspace.clone();
         let obj_repo = self.obj_repo.clone();
-        spawn_blocking(move || -> Result<()> {
-            obj_repo.insert(&mut batch, act_key, object)?;
-            batch.commit()?;
-            Ok(())
+        let user_index = self.user_index.clone();
+        spawn_blocking(move || {
+            let pending = user_index.has_follow_request(&uid, follow_key)?;
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, act_key, object)?;
+                if pending {
+                    user_index.remove_follow_request(b, &uid, follow_key)?;
+                    user_index.insert_follower(b, &uid, follow_key)?;
+                }
+                Ok(())
+            })
         })
         .await??;
-        Ok(())
+        Ok(Some(act_key))
     }
-    async fn handle_s2s_create(&mut self, cmd: S2sCommand) -> Result<()> {
-        let S2sCommand {
-            obj_key, object, ..
+    /// Store a Reject and drop the rejected Follow, whether it was still
+    /// pending or already accepted.
+    async fn handle_c2s_reject(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            obj_key: follow_key,
+            object,
+            ..
         } = cmd;
-        if object.has_props(&["context"]) {
-            // currently we only care activities mentioning our object
-            // TODO verify context
-            let Some(iri) = object.get_str("context") else {
-                return Ok(());
-            };
-            let iri = iri.to_string();
-            // TODO let create = Create::try_from(object)?;
-            // TODO save the activity and the object
-
-            let keyspace = self.keyspace.clone();
-            let obj_repo = self.obj_repo.clone();
-            let ctx_index = self.ctx_index.clone();
-
-            spawn_blocking(move || -> Result<()> {
-                let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                obj_repo.insert(&mut b, obj_key, object)?;
-                ctx_index.insert(&mut b, &iri, obj_key);
-                b.commit()?;
+        let keyspace = self.keyspace.clone();
+        let obj_repo = self.obj_repo.clone();
+        let user_index = self.user_index.clone();
+        spawn_blocking(move || {
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, act_key, object)?;
+                user_index.remove_follow_request(b, &uid, follow_key)?;
+                user_index.remove_follower(b, &uid, follow_key)?;
                 Ok(())
             })
-            .await??;
-        }
-        Ok(())
-    }
-    async fn handle_s2s_delete(&mut self, cmd: S2sCommand) -> Result<()> {
-        let _ = cmd;
-        // TODO
-        Ok(())
+        })
+        .await??;
+        Ok(Some(act_key))
     }
-    async fn handle_s2s_like(&mut self, cmd: S2sCommand) -> Result<()> {
-        let S2sCommand {
-            obj_key, object, ..
+    /// Store an Add or Remove and pin or unpin its object.
+    async fn handle_c2s_pin(&mut self, cmd: C2sCommand, pin: bool) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            obj_key,
+            object,
+            ..
         } = cmd;
-        if object.has_props(&["object"]) {
-            let Some(iri) = object.get_node_iri("object") else {
-                return Ok(());
-            };
-            let iri = iri.to_string();
-            let keyspace = self.keyspace.clone();
-            let iri_index = self.iri_index.clone();
-            let obj_repo = self.obj_repo.clone();
-            let ctx_index = self.ctx_index.clone();
-
-            spawn_blocking(move || -> Result<()> {
-                let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                if let Some(activity_iri) = object.id() {
-                    iri_index.insert(&mut b, activity_iri, obj_key);
+        let keyspace = self.keyspace.clone();
+        let obj_repo = self.obj_repo.clone();
+        let outbox_index = self.outbox_index.clone();
+        spawn_blocking(move || {
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, act_key, object)?;
+                if pin {
+                    outbox_index.insert_featured(b, &uid, obj_key)?;
+                } else {
+                    outbox_index.remove_featured(b, &uid, obj_key)?;
                 }
-                obj_repo.insert(&mut b, obj_key, object)?;
-                ctx_index.insert_likes(&mut b, &iri, obj_key);
-                b.commit()?;
                 Ok(())
             })
-            .await??;
-        }
-        Ok(())
-    }
-    async fn handle_s2s_dislike(&mut self, cmd: S2sCommand) -> Result<()> {
-        let _ = cmd;
-        Ok(())
+        })
+        .await??;
+        Ok(Some(act_key))
     }
-    async fn handle_s2s_follow(&mut self, cmd: S2sCommand) -> Result<()> {
-        let S2sCommand {
+    /// Store a boost in the outbox and count it as a share of the object.
+    async fn handle_c2s_announce(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
             uid,
-            obj_key,
+            act_key,
             object,
+            ..
         } = cmd;
-        if object.has_props(&["object"]) {
-            // TODO verify object is the actor IRI
-            let keyspace = self.keyspace.clone();
-            let iri_index = self.iri_index.clone();
-            let obj_repo = self.obj_repo.clone();
-            let user_index = self.user_index.clone();
-            spawn_blocking(move || -> Result<()> {
-                let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                if let Some(activity_iri) = object.id() {
-                    iri_index.insert(&mut b, activity_iri, obj_key);
-                }
-                obj_repo.insert(&mut b, obj_key, object)?;
-                user_index.insert_follower(&mut b, &uid, obj_key);
-                b.commit()?;
+        let announce = match Announce::try_from(object) {
+            Ok(announce) => announce,
+            Err(error) => {
+                error!(?error, "invalid Announce");
+                return Ok(None);
+            }
+        };
+        let keyspace = self.keyspace.clone();
+        let outbox_index = self.outbox_index.clone();
+        let ctx_index = self.ctx_index.clone();
+        let actor_iri = self.apub.user_iri(&uid);
+        spawn_blocking(move || {
+            let iri = announce.object().to_string();
+            transaction(&keyspace, |b| {
+                outbox_index.insert_announce(b, &uid, act_key, announce.into())?;
+                ctx_index.insert_shares(b, &iri, Some(&actor_iri), act_key)?;
                 Ok(())
             })
-            .await??;
-            // TODO send Accept or Reject back
-        }
-        Ok(())
+        })
+        .await??;
+        Ok(Some(act_key))
     }
-    /// Undo previous activity.
+    /// Store an edit in the outbox and replace the edited object with it.
     ///
-    /// References:
-    /// * <https://www.w3.org/TR/activitystreams-vocabulary/#inverse>
-    /// * <https://www.w3.org/wiki/ActivityPub/Primer/Referring_to_activities>
-    async fn handle_s2s_undo(&mut self, cmd: S2sCommand) -> Result<()> {
-        let S2sCommand {
-            uid, object: undo, ..
+    /// Only the author can edit an object, other edits store nothing.
+    async fn handle_c2s_update(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            obj_key,
+            object,
+            ..
         } = cmd;
+        let update = match Update::try_from(object) {
+            Ok(update) => update,
+            Err(error) => {
+                error!(?error, "invalid Update");
+                return Ok(None);
+            }
+        };
+        let actor_iri = self.apub.user_iri(&uid);
         let keyspace = self.keyspace.clone();
-        let iri_index = self.iri_index.clone();
         let obj_repo = self.obj_repo.clone();
-        let ctx_index = self.ctx_index.clone();
-        let user_index = self.user_index.clone();
+        let outbox_index = self.outbox_index.clone();
         spawn_blocking(move || {
-            // We can undo Follow and Like
-            // FIXME abstraction
-            // Find the obj_key of the activity we should undo
-            let mut undo_obj_key = None;
-            if let Some(iri) = undo.get_node_iri("object") {
-                // We have an ID, but do we know this ID?
-                if let Some(slice) = iri_index.find_one(iri)? {
-                    undo_obj_key = Some(ObjectKey::try_from(slice.as_ref())?);
-                } else {
-                    warn!("unknown activity id {iri} mentioned in Undo");
-                }
-                if undo_obj_key.is_none() {
-                    // The object does not have a known IRI.
-                    // TODO do our best to find the most recent activity from the actor
-                }
+            let Some(stored) = obj_repo.find_one(obj_key)? else {
+                return Ok(None);
+            };
+            if stored.id() != Some(update.object()) || !stored.is_attributed_to(&actor_iri) {
+                warn!(%uid, iri = update.object(), "rejected Update of an object of someone else");
+                return Ok(None);
             }
-            if let Some(undo_obj_key) = undo_obj_key {
+            transaction(&keyspace, |b| {
+                outbox_index.insert_update(b, uid, act_key, update.into())
+            })?;
+            Ok(Some(act_key))
+        })
+        .await?
+    }
+    async fn handle_c2s_delete(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            obj_key,
+            object,
+            ..
+        } = cmd;
+        let delete = match Delete::try_from(object) {
+            Ok(delete) => delete,
+            Err(error) => {
+                error!(?error, "invalid Delete");
+                return Ok(None);
+            }
+        };
+        let actor_iri = self.apub.user_iri(&uid);
+        let keyspace = self.keyspace.clone();
+        let obj_repo = self.obj_repo.clone();
+        let outbox_index = self.outbox_index.clone();
+        spawn_blocking(move || {
+            let Some(stored) = obj_repo.find_one(obj_key)? else {
+                return Ok(None);
+            };
+            if stored.id() != Some(delete.object()) || !stored.is_attributed_to(&actor_iri) {
+                warn!(%uid, iri = delete.object(), "rejected Delete of an object of someone else");
+                return Ok(None);
+            }
+            transaction(&keyspace, |b| {
+                outbox_index.insert_delete(b, &uid, act_key, obj_key, delete.into())
+            })?;
+            Ok(Some(act_key))
+        })
+        .await?
+    }
+    /// Record a block, drop the blocked actor from the user's followers and
+    /// stop counting its replies, Likes and Announces of the user's objects.
+    /// The inbox refuses new ones from then on.
+    async fn handle_c2s_block(&mut self, cmd: C2sCommand) -> Result<Option<ObjectKey>> {
+        let C2sCommand {
+            uid,
+            act_key,
+            object,
+            ..
+        } = cmd;
+        let block = match Block::try_from(object) {
+            Ok(block) => block,
+            Err(error) => {
+                error!(?error, "invalid Block");
+                return Ok(None);
+            }
+        };
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let user_index = self.user_index.clone();
+        let moderation = self.moderation.clone();
+        let ctx_index = self.ctx_index.clone();
+        let outbox_index = self.outbox_index.clone();
+        let actor_iri = self.apub.user_iri(&uid);
+        spawn_blocking(move || {
+            let blocked = block.blocked().to_string();
+            let follows: Vec<ObjectKey> = user_index
+                .find_followers(&uid, None, None, None, None)?
+                .into_iter()
+                .filter(|(_, actor)| *actor == blocked)
+                .map(|(key, _)| key)
+                .collect();
+            // Replies, Likes and Announces of the blocked actor on objects
+            // of the user, by the IRIs they are counted under.
+            let mut removed = vec![];
+            let mut counted: BTreeMap<String, Vec<ObjectKey>> = BTreeMap::new();
+            for key in ctx_index.find_by_actor(&blocked)? {
+                let Some(activity) = obj_repo.find_one(key)? else {
+                    removed.push(key);
+                    continue;
+                };
+                let (target, context) = if activity.type_is("Create") {
+                    let reply_to = activity
+                        .get_node_object("object")
+                        .and_then(|inner| inner.get_node_iri("inReplyTo").map(str::to_string));
+                    (reply_to, activity.get_str("context").map(str::to_string))
+                } else {
+                    (activity.get_node_iri("object").map(str::to_string), None)
+                };
+                let Some(target) = target else {
+                    continue;
+                };
+                if !outbox_index
+                    .find_object(&target)?
+                    .is_some_and(|object| object.is_attributed_to(&actor_iri))
+                {
+                    continue;
+                }
+                removed.push(key);
+                if let Some(context) = context.filter(|context| *context != target) {
+                    counted.entry(context).or_default().push(key);
+                }
+                counted.entry(target).or_default().push(key);
+            }
+            let block = Object::from(block);
+            transaction(&keyspace, |b| {
+                if let Some(iri) = block.id() {
+                    iri_index.insert(b, iri, act_key);
+                }
+                obj_repo.insert(b, act_key, block)?;
+                moderation.insert_block(b, &uid, &blocked, act_key);
+                ctx_index.remove_interactions(b, &blocked, removed, &counted)?;
+                user_index.remove_followers(b, &uid, follows)
+            })
+        })
+        .await??;
+        Ok(Some(act_key))
+    }
+    async fn handle_s2s_create(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
+        let S2sCommand {
+            obj_key, object, ..
+        } = cmd;
+        let actor = object.get_node_iri("actor").map(str::to_string);
+        if let Some(vote) = object
+            .get_node_object("object")
+            .and_then(|inner| Vote::try_from(inner.into_owned()).ok())
+        {
+            self.handle_vote(actor, vote, obj_key).await?;
+            return Ok(None);
+        }
+        if let Some(question) = object
+            .get_node_object("object")
+            .and_then(|inner| Question::try_from(inner.into_owned()).ok())
+        {
+            let activity_iri = object.id().map(str::to_string);
+            return self
+                .handle_s2s_question(actor, activity_iri, question, obj_key)
+                .await;
+        }
+        // Currently we only care about activities of a conversation and
+        // replies to our objects.
+        // TODO verify context
+        let context = object.get_str("context").map(str::to_string);
+        let reply_to = object
+            .get_node_object("object")
+            .and_then(|inner| inner.get_node_iri("inReplyTo").map(str::to_string))
+            .filter(|iri| self.apub.is_local(iri));
+        if context.is_none() && reply_to.is_none() {
+            return Ok(None);
+        }
+        // TODO let create = Create::try_from(object)?;
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let ctx_index = self.ctx_index.clone();
+        spawn_blocking(move || -> Result<Option<ObjectKey>> {
+            // Retried deliveries and relays bring the same activity again.
+            let activity_iri = object.id().map(str::to_string);
+            if let Some(activity_iri) = &activity_iri {
+                if iri_index.find_one(activity_iri)?.is_some() {
+                    info!(%activity_iri, "ignoring Create delivered before");
+                    return Ok(None);
+                }
+            }
+            transaction(&keyspace, |b| {
+                if let Some(activity_iri) = &activity_iri {
+                    iri_index.insert(b, activity_iri, obj_key);
+                }
+                obj_repo.insert(b, obj_key, object)?;
+                if let Some(context) = &context {
+                    ctx_index.insert(b, context, actor.as_deref(), obj_key)?;
+                }
+                if let Some(reply_to) = &reply_to {
+                    ctx_index.insert_replies(b, reply_to, actor.as_deref(), obj_key)?;
+                }
+                Ok(())
+            })?;
+            Ok(Some(obj_key))
+        })
+        .await?
+    }
+    /// Count a vote in a poll of a local user.
+    async fn handle_vote(
+        &mut self,
+        actor: Option<String>,
+        vote: Vote<'static>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        // Votes are cast by their sender, in polls on this server only.
+        if actor.as_deref() != Some(vote.voter()) || !self.apub.is_local(vote.question()) {
+            return Ok(());
+        }
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let ctx_index = self.ctx_index.clone();
+
+        spawn_blocking(move || -> Result<()> {
+            let Some(key) = iri_index.find_one(vote.question())? else {
+                return Ok(());
+            };
+            let key = ObjectKey::try_from(key.as_ref())?;
+            let Some(Ok(question)) = obj_repo.find_one(key)?.map(Question::try_from) else {
+                return Ok(());
+            };
+            let (iri, voter, name) = (vote.question(), vote.voter(), vote.name());
+            let voted = ctx_index.has_voted(iri, voter, None)?;
+            if question.is_closed()
+                || (voted && !question.is_multiple())
+                || ctx_index.has_voted(iri, voter, Some(name))?
+            {
+                info!(%iri, %voter, "ignoring vote");
+                return Ok(());
+            }
+            let Some(question) = question.vote(name, !voted) else {
+                return Ok(());
+            };
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, key, Object::from(question))?;
+                ctx_index.insert_vote(b, iri, voter, name, obj_key)?;
+                Ok(())
+            })
+        })
+        .await?
+    }
+    /// Keep a poll of a remote actor, it is served with its options and
+    /// tallies rather than as a plain note.
+    async fn handle_s2s_question(
+        &mut self,
+        actor: Option<String>,
+        activity_iri: Option<String>,
+        question: Question<'static>,
+        obj_key: ObjectKey,
+    ) -> Result<Option<ObjectKey>> {
+        if self.apub.is_local(question.id()) || !question.is_by(actor.as_deref()) {
+            return Ok(None);
+        }
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+
+        spawn_blocking(move || -> Result<Option<ObjectKey>> {
+            if iri_index.find_one(question.id())?.is_some() {
+                return Ok(None);
+            }
+            transaction(&keyspace, |b| {
+                if let Some(activity_iri) = &activity_iri {
+                    iri_index.insert(b, activity_iri, obj_key);
+                }
+                iri_index.insert(b, question.id(), obj_key);
+                obj_repo.insert(b, obj_key, Object::from(question))
+            })?;
+            Ok(Some(obj_key))
+        })
+        .await?
+    }
+    /// Take the new options and tallies of a remote poll we keep.
+    async fn handle_s2s_question_update(
+        &mut self,
+        actor: Option<String>,
+        question: Question<'static>,
+    ) -> Result<()> {
+        if self.apub.is_local(question.id()) || !question.is_by(actor.as_deref()) {
+            return Ok(());
+        }
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+
+        spawn_blocking(move || -> Result<()> {
+            let Some(key) = iri_index.find_one(question.id())? else {
+                return Ok(());
+            };
+            let key = ObjectKey::try_from(key.as_ref())?;
+            let Some(Ok(stored)) = obj_repo.find_one(key)?.map(Question::try_from) else {
+                return Ok(());
+            };
+            if stored.authors() != question.authors() {
+                return Ok(());
+            }
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, key, Object::from(question))
+            })
+        })
+        .await?
+    }
+    async fn handle_s2s_delete(&mut self, cmd: S2sCommand) -> Result<()> {
+        let S2sCommand { object: delete, .. } = cmd;
+        if let (Some(actor), Some(object)) =
+            (delete.get_node_iri("actor"), delete.get_node_iri("object"))
+        {
+            if actor == object {
+                // The actor deleted itself, forget its cached document.
+                let remote_actors = self.remote_actors.clone();
+                let actor = actor.to_string();
+                spawn_blocking(move || remote_actors.remove(&actor)).await??;
+            }
+        }
+        // TODO
+        Ok(())
+    }
+    async fn handle_s2s_like(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
+        let S2sCommand {
+            obj_key, object, ..
+        } = cmd;
+        if object.has_props(&["object"]) {
+            let Some(iri) = object.get_node_iri("object") else {
+                return Ok(None);
+            };
+            let iri = iri.to_string();
+            let keyspace = self.keyspace.clone();
+            let iri_index = self.iri_index.clone();
+            let obj_repo = self.obj_repo.clone();
+            let ctx_index = self.ctx_index.clone();
+
+            spawn_blocking(move || -> Result<()> {
+                let actor = object.get_node_iri("actor").map(str::to_string);
+                transaction(&keyspace, |b| {
+                    if let Some(activity_iri) = object.id() {
+                        iri_index.insert(b, activity_iri, obj_key);
+                    }
+                    obj_repo.insert(b, obj_key, object)?;
+                    ctx_index.insert_likes(b, &iri, actor.as_deref(), obj_key)?;
+                    Ok(())
+                })?;
+                Ok(())
+            })
+            .await??;
+            return Ok(Some(obj_key));
+        }
+        Ok(None)
+    }
+    async fn handle_s2s_dislike(&mut self, cmd: S2sCommand) -> Result<()> {
+        let _ = cmd;
+        Ok(())
+    }
+    async fn handle_s2s_follow(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
+        let S2sCommand {
+            uid,
+            obj_key,
+            object,
+            ..
+        } = cmd;
+        if object.has_props(&["object"]) {
+            // TODO verify object is the actor IRI
+            let keyspace = self.keyspace.clone();
+            let iri_index = self.iri_index.clone();
+            let obj_repo = self.obj_repo.clone();
+            let user_index = self.user_index.clone();
+            spawn_blocking(move || -> Result<()> {
+                // Held for the user only when the policy lets them Reject it.
+                let manual = required_response(&object) == InboxResponse::AcceptOrReject
+                    && user_index.approves_followers_manually(&uid)?;
+                transaction(&keyspace, |b| {
+                    if let Some(activity_iri) = object.id() {
+                        iri_index.insert(b, activity_iri, obj_key);
+                    }
+                    obj_repo.insert(b, obj_key, object)?;
+                    if manual {
+                        // Wait for the user to Accept or Reject it.
+                        user_index.insert_follow_request(b, &uid, obj_key)?;
+                    } else {
+                        user_index.insert_follower(b, &uid, obj_key)?;
+                    }
+                    Ok(())
+                })?;
+                Ok(())
+            })
+            .await??;
+            return Ok(Some(obj_key));
+        }
+        Ok(None)
+    }
+    /// Undo previous activity.
+    ///
+    /// References:
+    /// * <https://www.w3.org/TR/activitystreams-vocabulary/#inverse>
+    /// * <https://www.w3.org/wiki/ActivityPub/Primer/Referring_to_activities>
+    async fn handle_s2s_undo(&mut self, cmd: S2sCommand) -> Result<()> {
+        let S2sCommand {
+            uid, object: undo, ..
+        } = cmd;
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let ctx_index = self.ctx_index.clone();
+        let user_index = self.user_index.clone();
+        spawn_blocking(move || {
+            // We can undo Follow and Like
+            // FIXME abstraction
+            // Find the obj_key of the activity we should undo
+            let mut undo_obj_key = None;
+            if let Some(iri) = undo.get_node_iri("object") {
+                // We have an ID, but do we know this ID?
+                if let Some(slice) = iri_index.find_one(iri)? {
+                    undo_obj_key = Some(ObjectKey::try_from(slice.as_ref())?);
+                } else {
+                    warn!("unknown activity id {iri} mentioned in Undo");
+                }
+                if undo_obj_key.is_none() {
+                    // The object does not have a known IRI.
+                    // TODO do our best to find the most recent activity from the actor
+                }
+            }
+            if let Some(undo_obj_key) = undo_obj_key {
                 if let Some(activity) = obj_repo.find_one(undo_obj_key)? {
                     if let Some(object_iri) = activity.get_node_iri("object") {
                         if activity.type_is("Like") {
                             // Undo Like
-                            let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                            ctx_index.remove_likes(&mut b, object_iri, undo_obj_key);
-                            b.commit()?;
+                            transaction(&keyspace, |b| {
+                                let actor = activity.get_node_iri("actor");
+                                ctx_index.remove_likes(b, object_iri, actor, undo_obj_key)?;
+                                Ok(())
+                            })?;
                         }
                         if activity.type_is("Follow") {
                             // Undo Follow
-                            let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                            user_index.remove_follower(&mut b, &uid, undo_obj_key);
-                            b.commit()?;
+                            transaction(&keyspace, |b| {
+                                user_index.remove_follower(b, &uid, undo_obj_key)?;
+                                user_index.remove_follow_request(b, &uid, undo_obj_key)?;
+                                Ok(())
+                            })?;
                         }
                     }
                 } else {
@@ -522,6 +1392,32 @@ impl State {
     }
     async fn handle_s2s_update(&mut self, cmd: S2sCommand) -> Result<()> {
         let S2sCommand { object: update, .. } = cmd;
+        let users_prefix = format!("{}/users/", self.apub.base_url);
+        if let Some(uid) = update
+            .get_node_iri("object")
+            .and_then(|iri| iri.strip_prefix(&users_prefix))
+        {
+            // Never trust a remote copy of a local actor, but drop any cached one.
+            self.actor_cache.invalidate(uid);
+        }
+        if let Some(actor) = update
+            .get_node_object("object")
+            .filter(|object| object.get_str("inbox").is_some())
+        {
+            // Remote actor updated its profile or keys, refetch on next use.
+            if let Some(iri) = actor.id() {
+                let remote_actors = self.remote_actors.clone();
+                let iri = iri.to_string();
+                spawn_blocking(move || remote_actors.remove(&iri)).await??;
+            }
+        }
+        if let Some(question) = update
+            .get_node_object("object")
+            .and_then(|inner| Question::try_from(inner.into_owned()).ok())
+        {
+            let actor = update.get_node_iri("actor").map(str::to_string);
+            self.handle_s2s_question_update(actor, question).await?;
+        }
         if update.has_props(&["object"]) {
             // let Some(iri) = value.object_iri() else {
             //     return Ok(());
@@ -542,7 +1438,7 @@ impl State {
         }
         Ok(())
     }
-    async fn handle_s2s_announce(&mut self, cmd: S2sCommand) -> Result<()> {
+    async fn handle_s2s_announce(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
         let S2sCommand {
             obj_key,
             object: announce,
@@ -550,7 +1446,7 @@ impl State {
         } = cmd;
         if announce.has_props(&["object"]) {
             let Some(iri) = announce.get_node_iri("object") else {
-                return Ok(());
+                return Ok(None);
             };
             let iri = iri.to_string();
             let keyspace = self.keyspace.clone();
@@ -558,14 +1454,966 @@ impl State {
             let ctx_index = self.ctx_index.clone();
 
             spawn_blocking(move || -> Result<()> {
-                let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
-                obj_repo.insert(&mut b, obj_key, announce)?;
-                ctx_index.insert_shares(&mut b, &iri, obj_key);
-                b.commit()?;
+                let actor = announce.get_node_iri("actor").map(str::to_string);
+                transaction(&keyspace, |b| {
+                    obj_repo.insert(b, obj_key, announce)?;
+                    ctx_index.insert_shares(b, &iri, actor.as_deref(), obj_key)?;
+                    Ok(())
+                })?;
                 Ok(())
             })
             .await??;
+            return Ok(Some(obj_key));
+        }
+        Ok(None)
+    }
+    /// Queue a report for admins to review.
+    async fn handle_s2s_flag(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
+        let S2sCommand {
+            obj_key, object, ..
+        } = cmd;
+        let keyspace = self.keyspace.clone();
+        let obj_repo = self.obj_repo.clone();
+        let moderation = self.moderation.clone();
+        spawn_blocking(move || {
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, obj_key, object)?;
+                moderation.insert_report(b, obj_key);
+                Ok(())
+            })
+        })
+        .await??;
+        Ok(Some(obj_key))
+    }
+    /// Follow the new account of a remote actor the user followed.
+    ///
+    /// Both accounts were checked to agree on the migration when the Move
+    /// was received, the stored Follow is delivered by the caller.
+    async fn handle_s2s_move(&mut self, cmd: S2sCommand) -> Result<Option<ObjectKey>> {
+        let S2sCommand {
+            uid,
+            obj_key,
+            object,
+            ..
+        } = cmd;
+        let activity = match Move::try_from(object) {
+            Ok(activity) => activity,
+            Err(error) => {
+                error!(?error, "invalid Move");
+                return Ok(None);
+            }
+        };
+        let origin = activity.origin().to_string();
+        let follow = activity.follow_target(
+            &self.apub.user_iri(&uid),
+            &self.apub.object_iri(obj_key),
+            &format_timestamp(obj_key.minted_at()),
+        );
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let user_index = self.user_index.clone();
+        spawn_blocking(move || {
+            // Only followers move along, anyone else is not told to follow.
+            if !user_index.follows(&uid, &origin)? {
+                info!(%uid, %origin, "ignoring Move of an account the user does not follow");
+                return Ok(None);
+            }
+            transaction(&keyspace, |b| {
+                if let Some(iri) = follow.id() {
+                    iri_index.insert(b, iri, obj_key);
+                }
+                obj_repo.insert(b, obj_key, follow)?;
+                // Optimistically following until the target rejects it.
+                user_index.insert_following(b, &uid, obj_key)?;
+                Ok(())
+            })?;
+            Ok(Some(obj_key))
+        })
+        .await?
+    }
+    /// Stop following an account that rejected the user's Follow.
+    ///
+    /// Only the followed account can reject, a Reject from anyone else is
+    /// ignored.
+    async fn handle_s2s_reject(&mut self, cmd: S2sCommand) -> Result<()> {
+        let S2sCommand {
+            uid,
+            object: reject,
+            ..
+        } = cmd;
+        let (Some(actor), Some(follow_iri)) =
+            (reject.get_node_iri("actor"), reject.get_node_iri("object"))
+        else {
+            warn!("Reject without actor or object");
+            return Ok(());
+        };
+        let actor = actor.to_string();
+        let follow_iri = follow_iri.to_string();
+        let keyspace = self.keyspace.clone();
+        let iri_index = self.iri_index.clone();
+        let obj_repo = self.obj_repo.clone();
+        let user_index = self.user_index.clone();
+        spawn_blocking(move || {
+            let Some(slice) = iri_index.find_one(&follow_iri)? else {
+                warn!("unknown activity id {follow_iri} mentioned in Reject");
+                return Ok(());
+            };
+            let follow_key = ObjectKey::try_from(slice.as_ref())?;
+            let Some(follow) = obj_repo.find_one(follow_key)? else {
+                return Ok(());
+            };
+            if !follow.type_is("Follow") || follow.get_node_iri("object") != Some(&actor) {
+                warn!(%actor, "ignoring Reject of {follow_iri}");
+                return Ok(());
+            }
+            if user_index.is_following(&uid, follow_key)? {
+                transaction(&keyspace, |b| {
+                    user_index.remove_following(b, &uid, follow_key)?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use serde_json::{json, Value};
+    use tempfile::tempdir;
+
+    use crate::activity_pub::{immediate_answer, ActorCache};
+    use crate::config::CacheConfig;
+    use crate::ActivityPubConfig;
+
+    use super::{
+        transaction, ActivityPubCommand, AppliedIndex, C2sCommand, ClientResult, LogEntry, Object,
+        ObjectKey, S2sCommand, State,
+    };
+
+    /// Apply `command` as the next log entry.
+    async fn apply(state: &mut State, command: ActivityPubCommand) -> Result<ClientResult> {
+        let log_entry = LogEntry {
+            index: state.last_applied + 1,
+            term: 1,
+            value: command.into(),
+        };
+        Ok(state.apply(log_entry).await?.expect("entry is new"))
+    }
+
+    /// Deliver `activity` to alice's inbox, return the Follow to Accept right away.
+    async fn answer(
+        state: &mut State,
+        command: fn(S2sCommand) -> ActivityPubCommand,
+        activity: Object<'static>,
+    ) -> Result<Option<ObjectKey>> {
+        let cmd = S2sCommand {
+            uid: "alice".to_string(),
+            obj_key: ObjectKey::new(),
+            object: activity.clone(),
+            request_id: None,
+        };
+        let stored = match apply(state, command(cmd)).await? {
+            ClientResult::Ok(bytes, _) if bytes.is_empty() => None,
+            ClientResult::Ok(bytes, _) => Some(ObjectKey::try_from(bytes.as_slice())?),
+            ClientResult::Err(error) => panic!("{error:?}"),
+        };
+        immediate_answer(&state.user_index, "alice", &activity, stored)
+    }
+
+    /// Record that `uid` follows `actor`, as if it had sent a Follow.
+    fn following(state: &State, uid: &str, actor: &str) -> Result<ObjectKey> {
+        let key = ObjectKey::new();
+        let follow = json!({"type": "Follow", "actor": state.apub.user_iri(uid), "object": actor});
+        transaction(&state.keyspace, |b| {
+            state
+                .obj_repo
+                .insert(b, key, Object::from(follow.clone()))?;
+            state.user_index.insert_following(b, uid, key)
+        })?;
+        Ok(key)
+    }
+
+    fn like(obj_key: ObjectKey) -> ActivityPubCommand {
+        ActivityPubCommand::S2sLike(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key,
+            object: json!({
+                "id": "https://remote.example/likes/1",
+                "type": "Like",
+                "actor": "https://remote.example/users/bob",
+                "object": "https://example.com/notes/1",
+            })
+            .into(),
+            request_id: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn applied_command_survives_reopen_and_replay() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let open = || {
+            Config::new(tmp_dir.path())
+                .manual_journal_persist(true)
+                .open()
+        };
+        let cache = ActorCache::new(&CacheConfig::default());
+        let obj_key = ObjectKey::new();
+
+        let keyspace = open()?;
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache.clone(),
+            AppliedIndex::default(),
+        )?;
+        apply(&mut state, like(obj_key)).await?;
+        drop(state);
+
+        // Raft queues the entry again after a restart, it is skipped.
+        let keyspace: Keyspace = open()?;
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        assert_eq!(state.last_applied, 1);
+        assert!(state.obj_repo.find_one(obj_key)?.is_some());
+        let replayed = LogEntry {
+            index: 1,
+            term: 1,
+            value: like(obj_key).into(),
+        };
+        assert!(state.apply(replayed).await?.is_none());
+
+        // A crash between a handler's commit and the saved position applies
+        // the command again, it must not be counted twice.
+        apply(&mut state, like(obj_key)).await?;
+        assert_eq!(
+            state.ctx_index.count_likes("https://example.com/notes/1")?,
+            1
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn c2s_create_reports_stored_activity() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let create = |act_key| {
+            ActivityPubCommand::C2sCreate(C2sCommand {
+                uid: "alice".to_string(),
+                act_key,
+                obj_key: ObjectKey::new(),
+                object: json!({
+                    "type": "Create",
+                    "id": "https://example.com/as/objects/1",
+                    "object": {
+                        "type": "Note",
+                        "id": "https://example.com/notes/1",
+                        "published": "2025-01-01T00:00:00Z",
+                    }
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+
+        let act_key = ObjectKey::new();
+        let result = apply(&mut state, create(act_key)).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes == act_key.as_ref()));
+
+        // Same object again, nothing new is stored.
+        let result = apply(&mut state, create(ObjectKey::new())).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes.is_empty()));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn s2s_create_delivered_twice_is_stored_once() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let create = |obj_key| {
+            ActivityPubCommand::S2sCreate(S2sCommand {
+                uid: "alice".to_string(),
+                obj_key,
+                object: json!({
+                    "id": "https://remote.example/activities/1",
+                    "type": "Create",
+                    "actor": "https://remote.example/users/bob",
+                    "context": "https://example.com/contexts/1",
+                    "object": {
+                        "id": "https://remote.example/notes/1",
+                        "type": "Note",
+                    },
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+
+        let obj_key = ObjectKey::new();
+        let result = apply(&mut state, create(obj_key)).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes == obj_key.as_ref()));
+
+        let again = ObjectKey::new();
+        let result = apply(&mut state, create(again)).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes.is_empty()));
+        assert!(state.obj_repo.find_one(again)?.is_none());
+        assert_eq!(state.ctx_index.count("https://example.com/contexts/1")?, 1);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn count_votes_in_local_poll() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let apub = ActivityPubConfig {
+            base_url: "https://example.com".to_string(),
+            ..Default::default()
+        };
+        let mut state = State::new(apub, keyspace, cache, AppliedIndex::default())?;
+        let poll = "https://example.com/as/objects/2";
+        let obj_key = ObjectKey::new();
+        let create = ActivityPubCommand::C2sCreate(C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key,
+            object: json!({
+                "type": "Create",
+                "id": "https://example.com/as/objects/1",
+                "object": {
+                    "type": "Question",
+                    "id": poll,
+                    "oneOf": [
+                        {"type": "Note", "name": "tabs"},
+                        {"type": "Note", "name": "spaces"},
+                    ],
+                }
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, create).await?;
+        let vote = |id: &str, voter: &str, name: &str| {
+            ActivityPubCommand::S2sCreate(S2sCommand {
+                uid: "alice".to_string(),
+                obj_key: ObjectKey::new(),
+                object: json!({
+                    "id": format!("{id}/activity"),
+                    "type": "Create",
+                    "actor": voter,
+                    "object": {
+                        "id": id,
+                        "type": "Note",
+                        "name": name,
+                        "inReplyTo": poll,
+                        "attributedTo": voter,
+                    },
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+        let bob = "https://remote.example/users/bob";
+        let carol = "https://remote.example/users/carol";
+        apply(
+            &mut state,
+            vote("https://remote.example/votes/1", bob, "tabs"),
+        )
+        .await?;
+        // Delivered again, and a second pick in a single choice poll.
+        apply(
+            &mut state,
+            vote("https://remote.example/votes/1", bob, "tabs"),
+        )
+        .await?;
+        apply(
+            &mut state,
+            vote("https://remote.example/votes/2", bob, "spaces"),
+        )
+        .await?;
+        apply(
+            &mut state,
+            vote("https://remote.example/votes/3", carol, "tabs"),
+        )
+        .await?;
+        apply(
+            &mut state,
+            vote("https://remote.example/votes/4", carol, "emacs"),
+        )
+        .await?;
+
+        let question = state.obj_repo.find_one(obj_key)?.unwrap();
+        assert_eq!(question.get_value("votersCount"), Some(json!(2)));
+        assert_eq!(
+            question.get_value("oneOf"),
+            Some(json!([
+                {"type": "Note", "name": "tabs", "replies": {"type": "Collection", "totalItems": 2}},
+                {"type": "Note", "name": "spaces"},
+            ]))
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn keep_remote_poll_up_to_date() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let bob = "https://remote.example/users/bob";
+        let question = |votes: u64| {
+            json!({
+                "id": "https://remote.example/polls/1",
+                "type": "Question",
+                "attributedTo": bob,
+                "anyOf": [
+                    {"type": "Note", "name": "tabs", "replies": {"type": "Collection", "totalItems": votes}},
+                ],
+                "votersCount": votes,
+            })
+        };
+        let obj_key = ObjectKey::new();
+        let command = ActivityPubCommand::S2sCreate(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key,
+            object: json!({
+                "id": "https://remote.example/polls/1/activity",
+                "type": "Create",
+                "actor": bob,
+                "object": question(0),
+            })
+            .into(),
+            request_id: None,
+        });
+        let result = apply(&mut state, command).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes == obj_key.as_ref()));
+
+        let update = |actor: &str, votes: u64| {
+            ActivityPubCommand::S2sUpdate(S2sCommand {
+                uid: "alice".to_string(),
+                obj_key: ObjectKey::new(),
+                object: json!({
+                    "id": format!("https://remote.example/polls/1#updates/{votes}"),
+                    "type": "Update",
+                    "actor": actor,
+                    "object": question(votes),
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+        apply(&mut state, update(bob, 3)).await?;
+        // Only the author updates the poll.
+        apply(&mut state, update("https://remote.example/users/carol", 9)).await?;
+        let stored = state.obj_repo.find_one(obj_key)?.unwrap();
+        assert_eq!(stored.to_value(), question(3));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn move_follows_target_account() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        following(&state, "alice", "https://old.example/users/bob")?;
+        let obj_key = ObjectKey::new();
+        let command = ActivityPubCommand::S2sMove(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key,
+            object: json!({
+                "type": "Move",
+                "actor": "https://old.example/users/bob",
+                "object": "https://old.example/users/bob",
+                "target": "https://new.example/users/bob",
+            })
+            .into(),
+            request_id: None,
+        });
+        let result = apply(&mut state, command).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes == obj_key.as_ref()));
+
+        let follow = state.obj_repo.find_one(obj_key)?.unwrap();
+        assert!(follow.type_is("Follow"));
+        assert_eq!(
+            follow.get_node_iri("object"),
+            Some("https://new.example/users/bob")
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn move_is_ignored_by_non_followers() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        following(&state, "alice", "https://other.example/users/carol")?;
+        let obj_key = ObjectKey::new();
+        let command = ActivityPubCommand::S2sMove(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key,
+            object: json!({
+                "type": "Move",
+                "actor": "https://old.example/users/bob",
+                "object": "https://old.example/users/bob",
+                "target": "https://new.example/users/eve",
+            })
+            .into(),
+            request_id: None,
+        });
+        let result = apply(&mut state, command).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes.is_empty()));
+        assert!(state.obj_repo.find_one(obj_key)?.is_none());
+        assert!(!state.user_index.is_following("alice", obj_key)?);
+        assert!(!state
+            .user_index
+            .follows("alice", "https://new.example/users/eve")?);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn reject_stops_following() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        following(&state, "alice", "https://old.example/users/bob")?;
+        let follow_key = ObjectKey::new();
+        let command = ActivityPubCommand::S2sMove(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key: follow_key,
+            object: json!({
+                "type": "Move",
+                "actor": "https://old.example/users/bob",
+                "object": "https://old.example/users/bob",
+                "target": "https://new.example/users/bob",
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, command).await?;
+        assert!(state.user_index.is_following("alice", follow_key)?);
+
+        let follow_iri = state.apub.object_iri(follow_key);
+        let reject = |actor: &str| {
+            ActivityPubCommand::S2sReject(S2sCommand {
+                uid: "alice".to_string(),
+                obj_key: ObjectKey::new(),
+                object: json!({
+                    "type": "Reject",
+                    "actor": actor,
+                    "object": {"id": follow_iri, "type": "Follow"},
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+        // Only the followed account can reject.
+        apply(&mut state, reject("https://spam.example/users/eve")).await?;
+        assert!(state.user_index.is_following("alice", follow_key)?);
+        apply(&mut state, reject("https://new.example/users/bob")).await?;
+        assert!(!state.user_index.is_following("alice", follow_key)?);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn manually_approved_follow() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let user = json!({"type": "Person", "id": "alice", "manuallyApprovesFollowers": true});
+        apply(
+            &mut state,
+            ActivityPubCommand::UpdateUser("alice".to_string(), user.into(), None),
+        )
+        .await?;
+        let follow = |n: u32| {
+            ActivityPubCommand::S2sFollow(S2sCommand {
+                uid: "alice".to_string(),
+                obj_key: ObjectKey::new(),
+                object: json!({
+                    "id": format!("https://remote.example/follows/{n}"),
+                    "type": "Follow",
+                    "actor": format!("https://remote.example/users/{n}"),
+                    "object": "https://example.com/users/alice",
+                })
+                .into(),
+                request_id: None,
+            })
+        };
+        let answer = |follow_key, ty| C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key: follow_key,
+            object: json!({"type": ty}).into(),
+            request_id: None,
+        };
+        let stored = |result| match result {
+            ClientResult::Ok(bytes, _) => ObjectKey::try_from(bytes.as_slice()),
+            ClientResult::Err(error) => panic!("{error:?}"),
+        };
+        let accepted = stored(apply(&mut state, follow(1)).await?)?;
+        let rejected = stored(apply(&mut state, follow(2)).await?)?;
+        assert_eq!(state.user_index.count_followers("alice")?, 0);
+        assert_eq!(state.user_index.find_follow_requests("alice")?.len(), 2);
+
+        let command = ActivityPubCommand::C2sAccept(answer(accepted, "Accept"));
+        apply(&mut state, command).await?;
+        let command = ActivityPubCommand::C2sReject(answer(rejected, "Reject"));
+        apply(&mut state, command).await?;
+        assert!(state.user_index.find_follow_requests("alice")?.is_empty());
+        let followers = state
+            .user_index
+            .find_followers("alice", None, None, None, None)?;
+        assert_eq!(
+            followers,
+            [(accepted, "https://remote.example/users/1".to_string())]
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn only_follow_is_answered() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let bob = "https://remote.example/users/bob";
+        let note = json!({
+            "id": "https://remote.example/notes/1",
+            "type": "Note",
+            "attributedTo": bob,
+            "content": "hello",
+        });
+        let activity = |n: usize, ty: &str, object: Value| {
+            Object::from(json!({
+                "id": format!("https://remote.example/activities/{n}"),
+                "type": ty,
+                "actor": bob,
+                "object": object,
+            }))
+        };
+        let alice = "https://example.com/users/alice";
+        type Inbox = fn(S2sCommand) -> ActivityPubCommand;
+        let commands: [(Inbox, &str, Value); 10] = [
+            (ActivityPubCommand::S2sCreate, "Create", note.clone()),
+            (ActivityPubCommand::S2sUpdate, "Update", note.clone()),
+            (ActivityPubCommand::S2sLike, "Like", json!(note["id"])),
+            (ActivityPubCommand::S2sDislike, "Dislike", json!(note["id"])),
+            (
+                ActivityPubCommand::S2sAnnounce,
+                "Announce",
+                json!(note["id"]),
+            ),
+            (ActivityPubCommand::S2sFlag, "Flag", json!(alice)),
+            (ActivityPubCommand::S2sReject, "Reject", json!(alice)),
+            (ActivityPubCommand::S2sMove, "Move", json!(bob)),
+            (ActivityPubCommand::S2sUndo, "Undo", json!(note["id"])),
+            (ActivityPubCommand::S2sDelete, "Delete", json!(note["id"])),
+        ];
+        for (n, (command, ty, object)) in commands.into_iter().enumerate() {
+            let answer = answer(&mut state, command, activity(n, ty, object)).await?;
+            assert_eq!(answer, None, "{ty}");
+        }
+        let follow = activity(10, "Follow", json!(alice));
+        let answer = answer(&mut state, ActivityPubCommand::S2sFollow, follow).await?;
+        assert!(answer.is_some());
+
+        assert!(state.user_index.find_follow_requests("alice")?.is_empty());
+        assert_eq!(state.user_index.count_followers("alice")?, 1);
+        assert_eq!(state.outbox_index.count("alice", false)?, 0);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn pin_and_unpin() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let obj_key = ObjectKey::new();
+        let create = ActivityPubCommand::C2sCreate(C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key,
+            object: json!({
+                "type": "Create",
+                "id": "https://example.com/as/objects/1",
+                "object": {"type": "Note", "id": "https://example.com/notes/1"}
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, create).await?;
+        let pin = |ty| C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key,
+            object: json!({"type": ty, "object": "https://example.com/notes/1"}).into(),
+            request_id: None,
+        };
+        apply(&mut state, ActivityPubCommand::C2sAdd(pin("Add"))).await?;
+        let featured = state.outbox_index.find_featured("alice")?;
+        assert_eq!(featured.len(), 1);
+        assert_eq!(featured[0].id(), Some("https://example.com/notes/1"));
+
+        apply(&mut state, ActivityPubCommand::C2sRemove(pin("Remove"))).await?;
+        assert!(state.outbox_index.find_featured("alice")?.is_empty());
+        Ok(())
+    }
+    #[tokio::test]
+    async fn only_the_author_edits() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let alice = state.apub.user_iri("alice");
+        let obj_key = ObjectKey::new();
+        let create = ActivityPubCommand::C2sCreate(C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key,
+            object: json!({
+                "type": "Create",
+                "id": "https://example.com/as/objects/1",
+                "object": {
+                    "type": "Note",
+                    "id": "https://example.com/notes/1",
+                    "attributedTo": alice,
+                    "content": "helo",
+                }
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, create).await?;
+        let update = |uid: &str| C2sCommand {
+            uid: uid.to_string(),
+            act_key: ObjectKey::new(),
+            obj_key,
+            object: json!({
+                "type": "Update",
+                "id": "https://example.com/as/objects/2",
+                "object": {
+                    "type": "Note",
+                    "id": "https://example.com/notes/1",
+                    "attributedTo": alice,
+                    "content": "hello",
+                }
+            })
+            .into(),
+            request_id: None,
+        };
+        let result = apply(&mut state, ActivityPubCommand::C2sUpdate(update("bob"))).await?;
+        assert!(result.into_result().unwrap().0.is_empty());
+        let note = state.obj_repo.find_one(obj_key)?.unwrap();
+        assert_eq!(note.get_str("content"), Some("helo"));
+
+        let result = apply(&mut state, ActivityPubCommand::C2sUpdate(update("alice"))).await?;
+        assert!(!result.into_result().unwrap().0.is_empty());
+        let note = state.obj_repo.find_one(obj_key)?.unwrap();
+        assert_eq!(note.get_str("content"), Some("hello"));
+        Ok(())
+    }
+    #[tokio::test]
+    async fn block_drops_follower() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let mut state = State::new(
+            ActivityPubConfig::default(),
+            keyspace,
+            cache,
+            AppliedIndex::default(),
+        )?;
+        let spammer = "https://spam.example/users/eve";
+        let follow = ActivityPubCommand::S2sFollow(S2sCommand {
+            uid: "alice".to_string(),
+            obj_key: ObjectKey::new(),
+            object: json!({
+                "id": "https://spam.example/follows/1",
+                "type": "Follow",
+                "actor": spammer,
+                "object": "https://example.com/users/alice",
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, follow).await?;
+        assert_eq!(state.user_index.count_followers("alice")?, 1);
+
+        let act_key = ObjectKey::new();
+        let block = ActivityPubCommand::C2sBlock(C2sCommand {
+            uid: "alice".to_string(),
+            act_key,
+            obj_key: ObjectKey::new(),
+            object: json!({
+                "id": "https://example.com/as/objects/1",
+                "type": "Block",
+                "actor": "https://example.com/users/alice",
+                "object": spammer,
+            })
+            .into(),
+            request_id: None,
+        });
+        let result = apply(&mut state, block).await?;
+        assert!(matches!(result, ClientResult::Ok(bytes, _) if bytes == act_key.as_ref()));
+        assert!(state.moderation.is_blocked("alice", spammer)?);
+        assert_eq!(state.user_index.count_followers("alice")?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn block_removes_interactions() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let apub = ActivityPubConfig {
+            base_url: "https://example.com".to_string(),
+            ..Default::default()
+        };
+        let mut state = State::new(apub, keyspace, cache, AppliedIndex::default())?;
+        let alice = "https://example.com/users/alice";
+        let note = "https://example.com/notes/1";
+        let create = ActivityPubCommand::C2sCreate(C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key: ObjectKey::new(),
+            object: json!({
+                "type": "Create",
+                "id": "https://example.com/as/objects/1",
+                "actor": alice,
+                "object": {"type": "Note", "id": note, "attributedTo": alice},
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, create).await?;
+        let spammer = "https://spam.example/users/eve";
+        let bob = "https://remote.example/users/bob";
+        let inbox = |n: u32, actor: &str, ty: &str, object: Value| S2sCommand {
+            uid: "alice".to_string(),
+            obj_key: ObjectKey::new(),
+            object: json!({
+                "id": format!("{actor}/activities/{n}"),
+                "type": ty,
+                "actor": actor,
+                "object": object,
+            })
+            .into(),
+            request_id: None,
+        };
+        for actor in [spammer, bob] {
+            let reply = json!({
+                "id": format!("{actor}/notes/1"),
+                "type": "Note",
+                "attributedTo": actor,
+                "inReplyTo": note,
+            });
+            let create = ActivityPubCommand::S2sCreate(inbox(1, actor, "Create", reply));
+            apply(&mut state, create).await?;
+            let like = ActivityPubCommand::S2sLike(inbox(2, actor, "Like", json!(note)));
+            apply(&mut state, like).await?;
+            let announce = inbox(3, actor, "Announce", json!(note));
+            apply(&mut state, ActivityPubCommand::S2sAnnounce(announce)).await?;
         }
+        assert_eq!(state.ctx_index.count_replies(note)?, 2);
+        assert_eq!(state.ctx_index.count_likes(note)?, 2);
+        assert_eq!(state.ctx_index.count_shares(note)?, 2);
+
+        let block = ActivityPubCommand::C2sBlock(C2sCommand {
+            uid: "alice".to_string(),
+            act_key: ObjectKey::new(),
+            obj_key: ObjectKey::new(),
+            object: json!({
+                "id": "https://example.com/as/objects/2",
+                "type": "Block",
+                "actor": alice,
+                "object": spammer,
+            })
+            .into(),
+            request_id: None,
+        });
+        apply(&mut state, block).await?;
+        assert_eq!(state.ctx_index.count_replies(note)?, 1);
+        assert_eq!(state.ctx_index.count_likes(note)?, 1);
+        assert_eq!(state.ctx_index.count_shares(note)?, 1);
+        assert!(state.ctx_index.find_by_actor(spammer)?.is_empty());
+        assert_eq!(state.ctx_index.find_by_actor(bob)?.len(), 3);
         Ok(())
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_applied_index() {
+        let applied_index = AppliedIndex::default();
+        applied_index.set(3);
+        let limit = Duration::from_secs(1);
+        assert!(applied_index.wait_for(3, limit).await);
+        assert!(!applied_index.wait_for(4, limit).await);
+
+        let waiting = applied_index.wait_for(5, limit);
+        let setter = applied_index.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            setter.set(5);
+        });
+        assert!(waiting.await);
+    }
 }
@@ -1,16 +1,29 @@
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{bail, Result};
+use anyhow::{Context, Result};
 use axum::http::HeaderValue;
 use reqwest::header::HeaderMap;
-use reqwest::{header, Client};
+use reqwest::redirect::Policy;
+use reqwest::{header, Client, ClientBuilder, StatusCode};
 use serde_json::Value;
 
+use super::object_serde::normalize;
+use super::repair::PublicResolver;
+
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 const APPLICATION_LD_JSON: HeaderValue = HeaderValue::from_static(
     "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
 );
 
+/// Result of a fetch that keeps the bits needed for caching decisions.
+pub(super) struct Fetched {
+    pub(super) status: StatusCode,
+    /// `max-age` from `Cache-Control`, `Some(0)` if the response must not be stored.
+    pub(super) max_age: Option<u64>,
+    pub(super) value: Option<Value>,
+}
+
 #[derive(Clone)]
 pub(super) struct Mailman {
     client: Client,
@@ -19,13 +32,20 @@ pub(super) struct Mailman {
 impl Mailman {
     pub(super) fn new() -> Mailman {
         Mailman {
-            client: Client::builder()
-                .http1_only()
-                .user_agent(APP_USER_AGENT)
-                .gzip(true)
-                .timeout(Duration::from_secs(10))
-                .build()
-                .unwrap(),
+            client: client().build().unwrap(),
+        }
+    }
+    /// A mailman that does not follow redirects, so a fetch only reaches the
+    /// host that was checked before. With `public_only` it also connects to
+    /// public addresses only, checked as the host name is resolved for the
+    /// connection.
+    pub(super) fn without_redirects(public_only: bool) -> Mailman {
+        let mut client = client().redirect(Policy::none());
+        if public_only {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+        Mailman {
+            client: client.build().unwrap(),
         }
     }
     pub(super) async fn fetch(&self, iri: &str) -> Result<Value> {
@@ -35,7 +55,31 @@ impl Mailman {
             .header(header::ACCEPT, APPLICATION_LD_JSON)
             .send()
             .await?;
-        Ok(response.json().await?)
+        Ok(normalize(response.json().await?))
+    }
+    pub(super) async fn fetch_with_meta(&self, iri: &str) -> Result<Fetched> {
+        let response = self
+            .client
+            .get(iri)
+            .header(header::ACCEPT, APPLICATION_LD_JSON)
+            .send()
+            .await?;
+        let status = response.status();
+        let max_age = response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_max_age);
+        let value = if status.is_success() {
+            Some(normalize(response.json().await?))
+        } else {
+            None
+        };
+        Ok(Fetched {
+            status,
+            max_age,
+            value,
+        })
     }
     pub(super) async fn post(&self, inbox: &str, headers: HeaderMap, body: &str) -> Result<()> {
         let response = self
@@ -46,11 +90,46 @@ impl Mailman {
             .body(body.to_string())
             .send()
             .await?;
-        if response.error_for_status_ref().is_err() {
-            let code = response.status();
+        // Keep the status error as the source so callers can classify it.
+        if let Some(error) = response.error_for_status_ref().err() {
             let text = response.text().await?;
-            bail!("posting to {inbox} failed with error {code} {text}");
+            return Err(error).context(format!("posting to {inbox} failed: {text}"));
         }
         Ok(())
     }
 }
+
+fn client() -> ClientBuilder {
+    Client::builder()
+        .http1_only()
+        .user_agent(APP_USER_AGENT)
+        .gzip(true)
+        .timeout(Duration::from_secs(10))
+}
+
+fn parse_max_age(cache_control: &str) -> Option<u64> {
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-store" || directive == "no-cache" {
+            return Some(0);
+        }
+        if let Some(secs) = directive.strip_prefix("max-age=") {
+            max_age = secs.trim_matches('"').parse().ok();
+        }
+    }
+    max_age
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_max_age;
+
+    #[test]
+    fn cache_control_max_age() {
+        assert_eq!(parse_max_age("max-age=180, public"), Some(180));
+        assert_eq!(parse_max_age("public, Max-Age=\"60\""), Some(60));
+        assert_eq!(parse_max_age("max-age=180, no-store"), Some(0));
+        assert_eq!(parse_max_age("private"), None);
+    }
+}
@@ -1,25 +1,115 @@
 #[macro_use]
 mod object_serde;
+mod addressing;
+mod federation;
 mod hs2019;
+mod limiter;
 mod mailman;
+mod repair;
 mod repo;
+mod resolver;
+mod response;
 mod simple_queue;
 
 pub(crate) mod delivery;
 pub(crate) mod machine;
 pub(crate) mod model;
 
-pub(crate) use hs2019::validate_request;
+pub(crate) use addressing::{
+    blind_recipients, local_recipients, remove_blind_recipients, Visibility,
+};
+#[cfg(test)]
+pub(crate) use hs2019::post_headers;
+pub(crate) use hs2019::{validate_request, MaxSkew, SeenSignatures};
+pub(crate) use object_serde::from_json_slice;
+pub(crate) use repair::ObjectRepairer;
+pub(crate) use repo::ActorCache;
+#[cfg(test)]
+pub(crate) use repo::Batch;
 pub(crate) use repo::ContextIndex;
 pub(crate) use repo::IriIndex;
 pub(crate) use repo::OutboxIndex;
 pub(crate) use repo::UserIndex;
+pub(crate) use repo::{index_options, object_options, ObjectKey, ObjectRepo};
+pub(crate) use repo::{BlockEntry, ModerationRepo};
 pub(crate) use repo::{CryptoRepo, KeyMaterial};
-pub(crate) use repo::{ObjectKey, ObjectRepo};
+pub(crate) use resolver::ActorResolver;
+pub(crate) use response::{immediate_answer, required_response, InboxResponse};
 
 use uuid::Bytes;
 use uuid::Uuid;
 
 pub(crate) fn uuidgen() -> Bytes {
-    Uuid::now_v7().into_bytes()
+    new_uuid().into_bytes()
+}
+
+/// A new time ordered UUID, from [`test_uuids`] when a test asked for
+/// deterministic ones.
+pub(crate) fn new_uuid() -> Uuid {
+    #[cfg(test)]
+    if let Some(uuid) = test_uuids::next() {
+        return uuid;
+    }
+    Uuid::now_v7()
+}
+
+/// Deterministic UUIDs, so tests can assert on stored keys.
+#[cfg(test)]
+pub(crate) mod test_uuids {
+    use std::cell::Cell;
+
+    use uuid::{Builder, Uuid};
+
+    thread_local! {
+        static NEXT: Cell<Option<u64>> = const { Cell::new(None) };
+    }
+
+    /// Until the guard drops, UUIDs minted on this thread are version 7
+    /// UUIDs for `start_millis`, `start_millis + 1`, and so on, so they keep
+    /// sorting in the order they were minted.
+    pub(crate) fn deterministic(start_millis: u64) -> Deterministic {
+        NEXT.set(Some(start_millis));
+        Deterministic(())
+    }
+
+    pub(crate) struct Deterministic(());
+
+    impl Drop for Deterministic {
+        fn drop(&mut self) {
+            NEXT.set(None);
+        }
+    }
+
+    pub(super) fn next() -> Option<Uuid> {
+        let millis = NEXT.get()?;
+        NEXT.set(Some(millis + 1));
+        let mut random = [0; 10];
+        random[2..].copy_from_slice(&millis.to_be_bytes());
+        Some(Builder::from_unix_timestamp_millis(millis, &random).into_uuid())
+    }
+
+    mod tests {
+        use super::deterministic;
+        use crate::activity_pub::ObjectKey;
+
+        #[test]
+        fn mint_the_same_keys_in_order() {
+            let first: Vec<_> = {
+                let _uuids = deterministic(1_700_000_000_000);
+                (0..3).map(|_| ObjectKey::new()).collect()
+            };
+            let again: Vec<_> = {
+                let _uuids = deterministic(1_700_000_000_000);
+                (0..3).map(|_| ObjectKey::new()).collect()
+            };
+            assert_eq!(first, again);
+            assert!(first.is_sorted_by_key(|key| key.as_ref().to_vec()));
+            assert_eq!(first[0].to_string(), "018bcfe5680070008000018bcfe56800");
+            assert!(ObjectKey::min_at(1_700_000_000).as_ref() <= first[0].as_ref());
+            assert!(ObjectKey::min_at(1_700_000_001).as_ref() > first[2].as_ref());
+
+            // Random again once the guard is gone.
+            assert!(!first.contains(&ObjectKey::new()));
+        }
+    }
 }
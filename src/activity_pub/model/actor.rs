@@ -14,10 +14,14 @@ impl<'a> From<Object<'a>> for Actor<'a> {
 }
 
 impl Actor<'_> {
+    /// The account this actor migrated to, announced with a `Move`.
+    pub(crate) fn moved_to(&self) -> Option<&str> {
+        self.0.get_node_iri("movedTo")
+    }
     // TODO
     pub(crate) fn enrich_with(self, config: &ActivityPubConfig, public_key_pem: &str) -> Self {
-        let base_url = &config.base_url;
         let id = self.0.id().expect("Actor should have an IRI id");
+        let iri = config.user_iri(id);
 
         // TODO: correctly update @context
         let Value::Object(properties) = json!({
@@ -28,23 +32,32 @@ impl Actor<'_> {
                     "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
                     "toot": "http://joinmastodon.org/ns#",
                     "discoverable": "toot:discoverable",
-                    "indexable": "toot:indexable"
+                    "indexable": "toot:indexable",
+                    "movedTo": {"@id": "as:movedTo", "@type": "@id"},
+                    "alsoKnownAs": {"@id": "as:alsoKnownAs", "@type": "@id"},
+                    "featured": {"@id": "toot:featured", "@type": "@id"}
                 }
             ],
             "type": "Person",
-            "id": format!("{}/users/{}", base_url, id),
-            "followers": format!("{}/users/{}/followers", base_url, id),
-            "inbox": format!("{}/users/{}/inbox", base_url, id),
-            "outbox": format!("{}/users/{}/outbox", base_url, id),
+            "id": iri,
+            "featured": format!("{iri}/collections/featured"),
+            "followers": format!("{iri}/followers"),
+            "inbox": format!("{iri}/inbox"),
+            "outbox": format!("{iri}/outbox"),
             "publicKey": {
-                "id": format!("{}/users/{}#main-key", base_url, id),
-                "owner": format!("{}/users/{}", base_url, id),
+                "id": format!("{iri}#main-key"),
+                "owner": iri,
                 "publicKeyPem": public_key_pem
             }
         }) else {
             unreachable!()
         };
-        Actor(self.0.augment_with(properties))
+        let mut actor = self.0.augment_with(properties);
+        if config.shared_inbox {
+            let endpoints = json!({"sharedInbox": config.shared_inbox_iri()});
+            actor = actor.augment("endpoints", endpoints);
+        }
+        Actor(actor)
     }
 }
 
@@ -57,7 +70,7 @@ impl From<Actor<'_>> for Value {
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
-    use serde_json::json;
+    use serde_json::{json, Value};
 
     use crate::activity_pub::model::Object;
 
@@ -68,8 +81,9 @@ mod tests {
         let config = ActivityPubConfig {
             base_url: "https://social.example.com".to_string(),
             webfinger_at_host: "@social.example.com".to_string(),
+            ..Default::default()
         };
-        let object = Object::try_from(json!({
+        let object = Object::from(json!({
             "id": "john",
             "name": "John Smith",
             "icon": {
@@ -77,18 +91,28 @@ mod tests {
                 "mediaType": "image/jpeg",
                 "url": "https://objects.social.example.com/493d7fea0a23.jpg"
             }
-        }))?;
-        let actor = Actor::from(object).enrich_with(&config, "PEM");
+        }));
+        let actor = Actor::from(object.clone()).enrich_with(&config, "PEM");
         assert_eq!(
             actor,
             Actor(Object::from(&json!({
                 "@context": [
                     "https://www.w3.org/ns/activitystreams",
-                    "https://w3id.org/security/v1"
+                    "https://w3id.org/security/v1",
+                    {
+                        "alsoKnownAs": {"@id": "as:alsoKnownAs", "@type": "@id"},
+                        "discoverable": "toot:discoverable",
+                        "featured": {"@id": "toot:featured", "@type": "@id"},
+                        "indexable": "toot:indexable",
+                        "manuallyApprovesFollowers": "as:manuallyApprovesFollowers",
+                        "movedTo": {"@id": "as:movedTo", "@type": "@id"},
+                        "toot": "http://joinmastodon.org/ns#"
+                    }
                 ],
                 "type": "Person",
                 "id": "https://social.example.com/users/john",
                 "name": "John Smith",
+                "featured": "https://social.example.com/users/john/collections/featured",
                 "followers": "https://social.example.com/users/john/followers",
                 "inbox": "https://social.example.com/users/john/inbox",
                 "outbox": "https://social.example.com/users/john/outbox",
@@ -104,6 +128,16 @@ mod tests {
                 }
             })))
         );
+
+        let config = ActivityPubConfig {
+            shared_inbox: true,
+            ..config
+        };
+        let actor = Object::from(Value::from(Actor::from(object).enrich_with(&config, "PEM")));
+        assert_eq!(
+            actor.get_endpoint("sharedInbox"),
+            Some("https://social.example.com/inbox")
+        );
         Ok(())
     }
 }
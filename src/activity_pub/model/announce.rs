@@ -0,0 +1,141 @@
+//! Boosting objects to the followers of an actor.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitystreams-vocabulary/#dfn-announce>
+//! * <https://www.w3.org/TR/activitypub/#announce-activity-outbox>
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{timestamp_now, Object};
+
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Announce<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Announce<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        if !object.type_is("Announce") {
+            bail!("activity must be an Announce");
+        }
+        if object.get_node_iri("object").is_none() {
+            bail!("Announce must name the shared object");
+        }
+        Ok(Announce(object))
+    }
+}
+
+impl Announce<'static> {
+    /// Build the activity for an Announce posted to an outbox.
+    ///
+    /// Like [`super::Block::from_outbox`], the activity always gets a server
+    /// generated id and the outbox owner as actor. Only the IRI of the shared
+    /// object is kept. Without an audience the boost is public and goes to
+    /// the followers, the way other servers address theirs.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        act_iri: &str,
+        actor_iri: &str,
+    ) -> Result<Announce<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        let Some(shared) = object.get_node_iri("object").map(str::to_string) else {
+            bail!("Announce must name the shared object");
+        };
+        let audience = object.has_props(&["to"]) || object.has_props(&["cc"]);
+        let mut value = object.to_value();
+        let Some(map) = value.as_object_mut() else {
+            bail!("outbox item must be an object");
+        };
+        map.insert("id".to_string(), Value::String(act_iri.to_string()));
+        map.insert("actor".to_string(), Value::String(actor_iri.to_string()));
+        map.insert("object".to_string(), Value::String(shared));
+        map.insert("published".to_string(), Value::String(timestamp_now()));
+        if !audience {
+            map.insert("to".to_string(), json!([PUBLIC]));
+            map.insert("cc".to_string(), json!([format!("{actor_iri}/followers")]));
+        }
+        Announce::try_from(Object::from(value))
+    }
+}
+
+impl Announce<'_> {
+    /// The boosted object.
+    pub(crate) fn object(&self) -> &str {
+        self.0
+            .get_node_iri("object")
+            .expect("validated in try_from")
+    }
+    /// Also address the author of the boosted object, so they learn about it.
+    pub(crate) fn notify(self, author: &str) -> Announce<'static> {
+        let mut value = self.0.to_value();
+        if let Some(map) = value.as_object_mut() {
+            let cc = map.entry("cc").or_insert_with(|| json!([]));
+            match cc {
+                Value::Array(cc) if !cc.iter().any(|iri| iri == author) => {
+                    cc.push(Value::String(author.to_string()));
+                }
+                Value::String(iri) if iri != author => {
+                    *cc = json!([iri, author]);
+                }
+                _ => {}
+            }
+        }
+        Announce(Object::from(value))
+    }
+}
+
+impl<'a> From<Announce<'a>> for Object<'a> {
+    fn from(value: Announce<'a>) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Announce, Object};
+
+    #[test]
+    fn outbox_announce_goes_to_followers() {
+        let actor = "https://example.com/users/alice";
+        let object = Object::from(json!({
+            "type": "Announce",
+            "object": {"id": "https://remote.example/notes/1", "type": "Note"},
+        }));
+        let announce =
+            Announce::from_outbox(object, "https://example.com/as/objects/1", actor).unwrap();
+        assert_eq!(announce.object(), "https://remote.example/notes/1");
+        let announce = announce.notify("https://remote.example/users/bob");
+        let object = Object::from(announce);
+        assert_eq!(object.id(), Some("https://example.com/as/objects/1"));
+        assert_eq!(
+            object.get_str("object"),
+            Some("https://remote.example/notes/1")
+        );
+        assert_eq!(
+            object.get_str_array("cc"),
+            Some(vec![
+                "https://example.com/users/alice/followers",
+                "https://remote.example/users/bob"
+            ])
+        );
+
+        let impostor = Object::from(json!({
+            "type": "Announce",
+            "actor": "https://example.com/users/bob",
+            "object": "https://remote.example/notes/1",
+        }));
+        assert!(
+            Announce::from_outbox(impostor, "https://example.com/as/objects/2", actor).is_err()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Blocking remote actors.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitypub/#block-activity-outbox>
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::{timestamp_now, Object};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Block<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Block<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        if !object.type_is("Block") {
+            bail!("activity must be a Block");
+        }
+        if object.get_node_iri("object").is_none() {
+            bail!("Block must name the blocked actor");
+        }
+        Ok(Block(object))
+    }
+}
+
+impl Block<'static> {
+    /// Build the activity for a Block posted to an outbox.
+    ///
+    /// Like [`super::Create::from_outbox`], the activity always gets a server
+    /// generated id and the outbox owner as actor.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        act_iri: &str,
+        actor_iri: &str,
+    ) -> Result<Block<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        let mut value = object.to_value();
+        let Some(map) = value.as_object_mut() else {
+            bail!("outbox item must be an object");
+        };
+        map.insert("id".to_string(), Value::String(act_iri.to_string()));
+        map.insert("actor".to_string(), Value::String(actor_iri.to_string()));
+        map.insert("published".to_string(), Value::String(timestamp_now()));
+        Block::try_from(Object::from(value))
+    }
+}
+
+impl Block<'_> {
+    /// The actor being blocked.
+    pub(crate) fn blocked(&self) -> &str {
+        self.0
+            .get_node_iri("object")
+            .expect("validated in try_from")
+    }
+}
+
+impl<'a> From<Block<'a>> for Object<'a> {
+    fn from(value: Block<'a>) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Block, Object};
+
+    #[test]
+    fn outbox_block_gets_server_id() {
+        let object = Object::from(json!({
+            "id": "https://client.example/1",
+            "type": "Block",
+            "object": {"id": "https://spam.example/users/eve", "type": "Person"},
+        }));
+        let block = Block::from_outbox(
+            object,
+            "https://example.com/as/objects/1",
+            "https://example.com/users/alice",
+        )
+        .unwrap();
+        assert_eq!(block.blocked(), "https://spam.example/users/eve");
+        let object = Object::from(block);
+        assert!(object.get_str("published").is_some());
+        assert_eq!(object.id(), Some("https://example.com/as/objects/1"));
+        assert_eq!(
+            object.get_node_iri("actor"),
+            Some("https://example.com/users/alice")
+        );
+
+        let forged = Object::from(json!({
+            "type": "Block",
+            "actor": "https://example.com/users/bob",
+            "object": "https://spam.example/users/eve",
+        }));
+        assert!(Block::from_outbox(
+            forged,
+            "https://example.com/as/objects/2",
+            "https://example.com/users/alice",
+        )
+        .is_err());
+    }
+}
@@ -1,93 +1,170 @@
+//! Builders of collections and their pages.
+//!
+//! Ordered collections list their items as `orderedItems`, unordered ones
+//! as `items`. The builder picks the property and the page type from how it
+//! was created.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitystreams-core/#collections>
+//! * <https://www.w3.org/TR/activitypub/#collections>
+
 use serde_json::{json, Number, Value};
 
-pub(crate) struct OrderedCollection(Value);
+pub(crate) struct Collection {
+    value: Value,
+    ordered: bool,
+}
 
-impl OrderedCollection {
-    pub(crate) fn new() -> OrderedCollection {
-        OrderedCollection(json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
-            "type": "OrderedCollection"
-        }))
-    }
-    pub(crate) fn id(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("id".to_string(), Value::String(link.into()));
-        self
+impl Collection {
+    pub(crate) fn ordered() -> Collection {
+        Collection::new("OrderedCollection", true)
+    }
+    pub(crate) fn unordered() -> Collection {
+        Collection::new("Collection", false)
     }
-    pub(crate) fn part_of(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
+    fn new(kind: &str, ordered: bool) -> Collection {
+        Collection {
+            value: json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "type": kind
+            }),
+            ordered,
+        }
+    }
+    fn insert(mut self, prop: &str, value: Value) -> Collection {
+        self.value
             .as_object_mut()
             .unwrap()
-            .insert("partOf".to_string(), Value::String(link.into()));
+            .insert(prop.to_string(), value);
         self
     }
-    pub(crate) fn with_ordered_items<T>(mut self, items: Vec<T>) -> OrderedCollection
+    pub(crate) fn id(self, link: impl Into<String>) -> Collection {
+        self.insert("id", Value::String(link.into()))
+    }
+    pub(crate) fn part_of(self, link: impl Into<String>) -> Collection {
+        self.insert("partOf", Value::String(link.into()))
+    }
+    pub(crate) fn with_items<T>(self, items: Vec<T>) -> Collection
     where
         T: Into<Value>,
     {
         let items = items.into_iter().map(|it| it.into()).collect();
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("orderedItems".to_string(), Value::Array(items));
-        self
+        let prop = if self.ordered {
+            "orderedItems"
+        } else {
+            "items"
+        };
+        self.insert(prop, Value::Array(items))
     }
-    pub(crate) fn total_items(mut self, total: u64) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("totalItems".to_string(), Value::Number(Number::from(total)));
-        self
+    pub(crate) fn total_items(self, total: u64) -> Collection {
+        self.insert("totalItems", Value::Number(Number::from(total)))
     }
-    pub(crate) fn first(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("first".to_string(), Value::String(link.into()));
-        self
+    pub(crate) fn first(self, link: impl Into<String>) -> Collection {
+        self.insert("first", Value::String(link.into()))
     }
-    pub(crate) fn last(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("last".to_string(), Value::String(link.into()));
-        self
+    /// Embed the first page so simple clients need a single round trip.
+    pub(crate) fn first_page(self, page: CollectionPage) -> Collection {
+        self.insert("first", page.0)
     }
-    pub(crate) fn next(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("next".to_string(), Value::String(link.into()));
-        self
+    pub(crate) fn last(self, link: impl Into<String>) -> Collection {
+        self.insert("last", Value::String(link.into()))
     }
-    pub(crate) fn prev(mut self, link: impl Into<String>) -> OrderedCollection {
-        self.0
-            .as_object_mut()
-            .unwrap()
-            .insert("prev".to_string(), Value::String(link.into()));
-        self
+    pub(crate) fn next(self, link: impl Into<String>) -> Collection {
+        self.insert("next", Value::String(link.into()))
     }
-    pub(crate) fn into_page(mut self) -> OrderedCollectionPage {
-        self.0.as_object_mut().unwrap().insert(
-            "type".to_string(),
-            Value::String("OrderedCollectionPage".to_string()),
-        );
-        OrderedCollectionPage(self.0)
+    pub(crate) fn prev(self, link: impl Into<String>) -> Collection {
+        self.insert("prev", Value::String(link.into()))
+    }
+    pub(crate) fn into_page(self) -> CollectionPage {
+        let kind = if self.ordered {
+            "OrderedCollectionPage"
+        } else {
+            "CollectionPage"
+        };
+        CollectionPage(self.insert("type", Value::String(kind.to_string())).value)
     }
 }
 
-pub(crate) struct OrderedCollectionPage(Value);
+pub(crate) struct CollectionPage(Value);
 
-impl From<OrderedCollection> for Value {
-    fn from(value: OrderedCollection) -> Self {
-        value.0
+impl From<Collection> for Value {
+    fn from(value: Collection) -> Self {
+        value.value
     }
 }
 
-impl From<OrderedCollectionPage> for Value {
-    fn from(value: OrderedCollectionPage) -> Self {
+impl From<CollectionPage> for Value {
+    fn from(value: CollectionPage) -> Self {
         value.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::Collection;
+
+    #[test]
+    fn serialize_ordered_collection() {
+        let page = Collection::ordered()
+            .id("https://example.com/users/alice/outbox?last=2")
+            .part_of("https://example.com/users/alice/outbox")
+            .next("https://example.com/users/alice/outbox?before=1&last=2")
+            .with_items(vec![json!("note 2"), json!("note 1")])
+            .into_page();
+        let collection = Collection::ordered()
+            .id("https://example.com/users/alice/outbox")
+            .total_items(2)
+            .first_page(page);
+        assert_eq!(
+            Value::from(collection),
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "type": "OrderedCollection",
+                "id": "https://example.com/users/alice/outbox",
+                "totalItems": 2,
+                "first": {
+                    "@context": "https://www.w3.org/ns/activitystreams",
+                    "type": "OrderedCollectionPage",
+                    "id": "https://example.com/users/alice/outbox?last=2",
+                    "partOf": "https://example.com/users/alice/outbox",
+                    "next": "https://example.com/users/alice/outbox?before=1&last=2",
+                    "orderedItems": ["note 2", "note 1"],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_unordered_collection() {
+        let collection = Collection::unordered()
+            .id("https://example.com/as/objects/1/likes")
+            .total_items(1);
+        assert_eq!(
+            Value::from(collection),
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "type": "Collection",
+                "id": "https://example.com/as/objects/1/likes",
+                "totalItems": 1,
+            })
+        );
+
+        let page = Collection::unordered()
+            .id("https://example.com/users/alice/tags?page=1")
+            .part_of("https://example.com/users/alice/tags")
+            .with_items(vec![json!({"type": "Hashtag", "name": "#rust"})])
+            .into_page();
+        assert_eq!(
+            Value::from(page),
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "type": "CollectionPage",
+                "id": "https://example.com/users/alice/tags?page=1",
+                "partOf": "https://example.com/users/alice/tags",
+                "items": [{"type": "Hashtag", "name": "#rust"}],
+            })
+        );
+    }
+}
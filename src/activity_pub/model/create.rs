@@ -1,8 +1,8 @@
 use anyhow::{bail, Result};
-use jiff::Timestamp;
 use serde_json::{json, Value};
 
-use super::Object;
+use super::{default_context, timestamp_now, Object};
+use crate::activity_pub::addressing::{has_addressing, Visibility};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Create<'a>(Object<'a>);
@@ -16,7 +16,7 @@ impl TryFrom<Object<'_>> for Create<'static> {
                 bail!("Create activity must have id and type property");
             }
             // TODO validate all required properties
-            return Ok(Create(object.into_owned()));
+            return Ok(Create(Object::from(share_addressing(object.to_value()))));
         }
 
         if !object.has_props(&["type"]) {
@@ -26,20 +26,119 @@ impl TryFrom<Object<'_>> for Create<'static> {
         let object = object.strip_context();
 
         let mut create = json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
+            "@context": default_context(),
             "type": "Create",
-            "published": Timestamp::now().to_string(),
+            "published": timestamp_now(),
         });
 
         let map = create.as_object_mut().unwrap();
-        for prop in ["to", "bto", "cc", "bcc", "audience", "published"] {
-            if let Some(v) = object.get_value(prop) {
-                map.insert(prop.to_string(), v);
-            }
+        if let Some(published) = object.get_value("published") {
+            map.insert("published".to_string(), published);
         }
         map.insert("object".to_string(), object.into());
 
-        Ok(Create(Object::from(create)))
+        Ok(Create(Object::from(share_addressing(create))))
+    }
+}
+
+impl Create<'static> {
+    /// Build the activity for an object or a Create posted to an outbox.
+    ///
+    /// Follows the client to server rules of ActivityPub section 6: the
+    /// activity always gets a server generated id, bare objects are wrapped
+    /// in a Create, and missing object `id` and `published` are filled in. An
+    /// object that already has an id keeps it so posting it again turns
+    /// into an update.
+    ///
+    /// A post that names no audience in the activity or the object goes to
+    /// the audience of `visibility`, the activity and the object share it.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        act_iri: &str,
+        obj_iri: &str,
+        actor_iri: &str,
+        visibility: Visibility,
+    ) -> Result<Create<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        let published = Value::String(timestamp_now());
+        let mut value = object.to_value();
+        let Some(map) = value.as_object_mut() else {
+            bail!("outbox item must be an object");
+        };
+        if !has_addressing(&object) {
+            for (prop, recipients) in visibility.addressing(actor_iri) {
+                map.insert(prop.to_string(), recipients);
+            }
+        }
+        let inner = if object.type_is("Create") {
+            map.insert("id".to_string(), Value::String(act_iri.to_string()));
+            map.entry("published").or_insert(published.clone());
+            match map.get_mut("object") {
+                Some(Value::Object(inner)) => inner,
+                _ => bail!("Create activity must embed its object"),
+            }
+        } else {
+            map
+        };
+        inner
+            .entry("id")
+            .or_insert_with(|| Value::String(obj_iri.to_string()));
+        inner.entry("published").or_insert(published);
+
+        let create = Create::try_from(Object::from(value))?
+            .ensure_id(act_iri)
+            .with_actor(actor_iri);
+        Ok(Create(create.0.with_context()))
+    }
+}
+
+/// Give a Create and its embedded object the union of their recipients.
+///
+/// Servers should copy recipients of the Create to its object and the other
+/// way around so both are delivered and displayed to the same audience.
+fn share_addressing(mut create: Value) -> Value {
+    let Some(map) = create.as_object_mut() else {
+        return create;
+    };
+    for prop in ["to", "bto", "cc", "bcc", "audience"] {
+        let own = map.get(prop).cloned();
+        let inner = match map.get("object") {
+            Some(Value::Object(object)) => object.get(prop).cloned(),
+            _ => None,
+        };
+        let value = match (own, inner) {
+            (None, None) => continue,
+            (Some(value), None) | (None, Some(value)) => value,
+            (Some(own), Some(inner)) if own == inner => continue,
+            (Some(own), Some(inner)) => {
+                let mut recipients = vec![];
+                collect_recipients(own, &mut recipients);
+                collect_recipients(inner, &mut recipients);
+                Value::Array(recipients)
+            }
+        };
+        if let Some(Value::Object(object)) = map.get_mut("object") {
+            object.insert(prop.to_string(), value.clone());
+        }
+        map.insert(prop.to_string(), value);
+    }
+    create
+}
+
+fn collect_recipients(value: Value, recipients: &mut Vec<Value>) {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    for value in values {
+        if !recipients.contains(&value) {
+            recipients.push(value);
+        }
     }
 }
 
@@ -76,7 +175,7 @@ mod tests {
 
     use crate::activity_pub::model::Object;
 
-    use super::Create;
+    use super::{Create, Visibility};
 
     #[test]
     fn object_to_create_activity() -> Result<()> {
@@ -90,7 +189,10 @@ mod tests {
                 "https://www.w3.org/ns/activitystreams#Public"]
         });
         let result = Create(Object::from(json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"sensitive": "as:sensitive"}
+            ],
             "type": "Create",
             "id": "https://example.net/~mallory/87374",
             "actor": "https://example.net/~mallory",
@@ -118,4 +220,178 @@ mod tests {
         assert_eq!(activity, result);
         Ok(())
     }
+
+    #[test]
+    fn addressing_is_shared_with_object() -> Result<()> {
+        let create = Create::try_from(Object::from(json!({
+            "type": "Create",
+            "id": "https://example.com/as/objects/1",
+            "to": "https://www.w3.org/ns/activitystreams#Public",
+            "object": {
+                "type": "Note",
+                "to": ["https://remote.example/users/bob"],
+            }
+        })))?;
+        let create = Object::from(create);
+        let expected = json!([
+            "https://www.w3.org/ns/activitystreams#Public",
+            "https://remote.example/users/bob"
+        ]);
+        assert_eq!(create.get_value("to"), Some(expected.clone()));
+        let note = create.get_node_object("object").unwrap();
+        assert_eq!(note.get_value("to"), Some(expected));
+        Ok(())
+    }
+
+    #[test]
+    fn outbox_keeps_content_warning() -> Result<()> {
+        let create = Object::from(json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"Hashtag": "as:Hashtag"}
+            ],
+            "type": "Create",
+            "object": {
+                "type": "Note",
+                "summary": "spoilers",
+                "sensitive": true,
+                "content": "Hello",
+            }
+        }));
+        let create = Object::from(Create::from_outbox(
+            create,
+            "https://example.com/as/objects/act",
+            "https://example.com/as/objects/obj",
+            "https://example.com/users/alice",
+            Visibility::Public,
+        )?);
+        assert_eq!(
+            create.get_value("@context"),
+            Some(json!([
+                "https://www.w3.org/ns/activitystreams",
+                {"Hashtag": "as:Hashtag"},
+                {"sensitive": "as:sensitive"}
+            ]))
+        );
+        let note = create.get_node_object("object").unwrap();
+        assert_eq!(note.get_str("summary"), Some("spoilers"));
+        assert_eq!(note.get_value("sensitive"), Some(json!(true)));
+        Ok(())
+    }
+
+    #[test]
+    fn outbox_wraps_bare_object() -> Result<()> {
+        let note = Object::from(json!({
+            "type": "Note",
+            "content": "Hello",
+            "cc": "https://example.com/users/alice/followers",
+        }));
+        let create = Object::from(Create::from_outbox(
+            note,
+            "https://example.com/as/objects/act",
+            "https://example.com/as/objects/obj",
+            "https://example.com/users/alice",
+            Visibility::Public,
+        )?);
+        assert!(create.type_is("Create"));
+        assert_eq!(create.id(), Some("https://example.com/as/objects/act"));
+        assert_eq!(
+            create.get_str("actor"),
+            Some("https://example.com/users/alice")
+        );
+        assert_eq!(
+            create.get_str("cc"),
+            Some("https://example.com/users/alice/followers")
+        );
+        let note = create.get_node_object("object").unwrap();
+        assert_eq!(note.id(), Some("https://example.com/as/objects/obj"));
+        assert_eq!(
+            note.get_str("attributedTo"),
+            Some("https://example.com/users/alice")
+        );
+        assert_eq!(note.get_str("published"), create.get_str("published"));
+        Ok(())
+    }
+
+    #[test]
+    fn outbox_defaults_audience() -> Result<()> {
+        let alice = "https://example.com/users/alice";
+        let followers = json!(["https://example.com/users/alice/followers"]);
+        let public = json!(["https://www.w3.org/ns/activitystreams#Public"]);
+        let post = |object: serde_json::Value, visibility| -> Result<Object<'static>> {
+            Ok(Object::from(Create::from_outbox(
+                Object::from(object),
+                "https://example.com/as/objects/act",
+                "https://example.com/as/objects/obj",
+                alice,
+                visibility,
+            )?))
+        };
+        let note = json!({"type": "Note", "content": "Hello"});
+        let create = post(note.clone(), Visibility::Public)?;
+        assert_eq!(create.get_value("to"), Some(public.clone()));
+        assert_eq!(create.get_value("cc"), Some(followers.clone()));
+        let object = create.get_node_object("object").unwrap();
+        assert_eq!(object.get_value("to"), Some(public.clone()));
+        assert_eq!(object.get_value("cc"), Some(followers.clone()));
+
+        let create = post(note.clone(), Visibility::Unlisted)?;
+        assert_eq!(create.get_value("to"), Some(followers.clone()));
+        assert_eq!(create.get_value("cc"), Some(public));
+        let create = post(note, Visibility::Followers)?;
+        assert_eq!(create.get_value("to"), Some(followers));
+        assert!(create.get_value("cc").is_none());
+
+        // Any audience named by the client is kept as is, on either level.
+        let create = post(
+            json!({
+                "type": "Create",
+                "bto": "https://remote.example/users/bob",
+                "object": {"type": "Note"},
+            }),
+            Visibility::Public,
+        )?;
+        assert!(create.get_value("to").is_none());
+        let object = create.get_node_object("object").unwrap();
+        assert_eq!(
+            object.get_str("bto"),
+            Some("https://remote.example/users/bob")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn outbox_replaces_client_activity_id() -> Result<()> {
+        let posted = Object::from(json!({
+            "type": "Create",
+            "id": "https://client.example/activity",
+            "to": "https://remote.example/users/bob",
+            "object": {"type": "Note", "content": "Hello"},
+        }));
+        let create = Object::from(Create::from_outbox(
+            posted.clone(),
+            "https://example.com/as/objects/act",
+            "https://example.com/as/objects/obj",
+            "https://example.com/users/alice",
+            Visibility::Public,
+        )?);
+        assert_eq!(create.id(), Some("https://example.com/as/objects/act"));
+        let note = create.get_node_object("object").unwrap();
+        assert_eq!(note.get_str("to"), Some("https://remote.example/users/bob"));
+
+        let impostor = Object::from(json!({
+            "type": "Create",
+            "actor": "https://example.com/users/mallory",
+            "object": {"type": "Note"},
+        }));
+        assert!(Create::from_outbox(
+            impostor,
+            "https://example.com/as/objects/act",
+            "https://example.com/as/objects/obj",
+            "https://example.com/users/alice",
+            Visibility::Public,
+        )
+        .is_err());
+        Ok(())
+    }
 }
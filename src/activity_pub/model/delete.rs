@@ -0,0 +1,152 @@
+//! Deleting objects with a `Delete`.
+//!
+//! The stored object is replaced with a `Tombstone` that keeps its id, and
+//! the Delete carrying the Tombstone goes to the audience of the object so
+//! other servers remove their copies too.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitypub/#delete-activity-outbox>
+//! * <https://www.w3.org/TR/activitystreams-vocabulary/#dfn-tombstone>
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{default_context, timestamp_now, Object};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Delete<'a>(Object<'a>);
+
+impl TryFrom<Object<'_>> for Delete<'static> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'_>) -> Result<Self> {
+        if !object.type_is("Delete") || object.id().is_none() {
+            bail!("activity must have id and type property");
+        }
+        if !object
+            .get_node_object("object")
+            .is_some_and(|tombstone| tombstone.type_is("Tombstone") && tombstone.id().is_some())
+        {
+            bail!("Delete must embed the Tombstone of the deleted object");
+        }
+        Ok(Delete(object.into_owned()))
+    }
+}
+
+impl Delete<'static> {
+    /// Build the activity for a Delete posted to an outbox.
+    ///
+    /// The object is replaced with a Tombstone and the Delete is addressed to
+    /// the audience of `stored`, so it reaches everyone who got the object.
+    /// The caller checks that the outbox owner wrote `stored`.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        stored: &Object<'_>,
+        act_iri: &str,
+        actor_iri: &str,
+    ) -> Result<Delete<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        if stored.type_is("Tombstone") {
+            bail!("object is deleted already");
+        }
+        let Some(iri) = stored.id() else {
+            bail!("deleted object must have an id");
+        };
+        if object.get_node_iri("object") != Some(iri) {
+            bail!("Delete must name the deleted object");
+        }
+        let now = timestamp_now();
+        let mut tombstone = json!({
+            "id": iri,
+            "type": "Tombstone",
+            "deleted": now,
+        });
+        if let Some(former_type) = stored.get_first_type() {
+            tombstone["formerType"] = Value::String(former_type);
+        }
+        let mut delete = json!({
+            "@context": default_context(),
+            "id": act_iri,
+            "type": "Delete",
+            "actor": actor_iri,
+            "published": now,
+        });
+        let map = delete.as_object_mut().unwrap();
+        for prop in ["to", "cc", "audience"] {
+            if let Some(v) = stored.get_value(prop) {
+                map.insert(prop.to_string(), v);
+            }
+        }
+        map.insert("object".to_string(), tombstone);
+        Delete::try_from(Object::from(delete))
+    }
+}
+
+impl Delete<'_> {
+    /// The deleted object.
+    pub(crate) fn object(&self) -> &str {
+        self.0
+            .get_node_iri("object")
+            .expect("validated in try_from")
+    }
+}
+
+impl<'a> From<Delete<'a>> for Object<'a> {
+    fn from(value: Delete<'a>) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Delete, Object};
+
+    #[test]
+    fn outbox_delete_tombstones_stored_object() {
+        let actor = "https://example.com/users/alice";
+        let stored = Object::from(json!({
+            "id": "https://example.com/as/objects/1",
+            "type": "Note",
+            "attributedTo": actor,
+            "content": "helo",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "cc": ["https://example.com/users/alice/followers"],
+        }));
+        let object = Object::from(json!({
+            "type": "Delete",
+            "object": "https://example.com/as/objects/1",
+        }));
+        let act_iri = "https://example.com/as/objects/2";
+        let delete = Delete::from_outbox(object, &stored, act_iri, actor).unwrap();
+        assert_eq!(delete.object(), "https://example.com/as/objects/1");
+        let delete = Object::from(delete);
+        assert_eq!(delete.id(), Some(act_iri));
+        assert_eq!(delete.get_node_iri("actor"), Some(actor));
+        assert_eq!(delete.get_value("to"), stored.get_value("to"));
+        assert_eq!(delete.get_value("cc"), stored.get_value("cc"));
+        let tombstone = delete.get_node_object("object").unwrap();
+        assert!(tombstone.type_is("Tombstone"));
+        assert_eq!(tombstone.get_str("formerType"), Some("Note"));
+        assert!(tombstone.get_str("deleted").is_some());
+        assert!(tombstone.get_value("content").is_none());
+
+        let other = Object::from(json!({
+            "type": "Delete",
+            "object": "https://example.com/as/objects/3",
+        }));
+        assert!(Delete::from_outbox(other, &stored, act_iri, actor).is_err());
+        let tombstone = Object::from(tombstone.to_value());
+        let again = Object::from(json!({
+            "type": "Delete",
+            "object": "https://example.com/as/objects/1",
+        }));
+        assert!(Delete::from_outbox(again, &tombstone, act_iri, actor).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Pinning objects to the featured collection of an actor.
+//!
+//! Clients pin with an `Add` and unpin with a `Remove` whose target is the
+//! collection.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitystreams-vocabulary/#dfn-add>
+//! * <https://docs.joinmastodon.org/spec/activitypub/#featured>
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::{timestamp_now, Object};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pin<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Pin<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        if !object.type_is("Add") && !object.type_is("Remove") {
+            bail!("activity must be an Add or Remove");
+        }
+        if object.get_node_iri("object").is_none() || object.get_node_iri("target").is_none() {
+            bail!("Add and Remove must have object and target property");
+        }
+        Ok(Pin(object))
+    }
+}
+
+impl Pin<'static> {
+    /// Build the activity for an Add or Remove posted to an outbox.
+    ///
+    /// Like [`super::Block::from_outbox`], the activity always gets a server
+    /// generated id and the outbox owner as actor. Only the featured
+    /// collection can be targeted, it is the only one clients maintain.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        act_iri: &str,
+        actor_iri: &str,
+    ) -> Result<Pin<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        let featured_iri = format!("{actor_iri}/collections/featured");
+        if object.get_node_iri("target") != Some(&featured_iri) {
+            bail!("target must be {featured_iri}");
+        }
+        let audience = object.has_props(&["to"]) || object.has_props(&["cc"]);
+        let mut value = object.to_value();
+        let Some(map) = value.as_object_mut() else {
+            bail!("outbox item must be an object");
+        };
+        map.insert("id".to_string(), Value::String(act_iri.to_string()));
+        map.insert("actor".to_string(), Value::String(actor_iri.to_string()));
+        map.insert("published".to_string(), Value::String(timestamp_now()));
+        if !audience {
+            let followers = format!("{actor_iri}/followers");
+            map.insert("to".to_string(), Value::String(followers));
+        }
+        Pin::try_from(Object::from(value))
+    }
+}
+
+impl Pin<'_> {
+    /// Whether the object is pinned rather than unpinned.
+    pub(crate) fn is_pin(&self) -> bool {
+        self.0.type_is("Add")
+    }
+}
+
+impl<'a> From<Pin<'a>> for Object<'a> {
+    fn from(value: Pin<'a>) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Object, Pin};
+
+    #[test]
+    fn outbox_pin_targets_featured() {
+        let actor = "https://example.com/users/alice";
+        let object = Object::from(json!({
+            "type": "Add",
+            "object": "https://example.com/as/objects/1",
+            "target": "https://example.com/users/alice/collections/featured",
+        }));
+        let pin = Pin::from_outbox(object, "https://example.com/as/objects/2", actor).unwrap();
+        assert!(pin.is_pin());
+        let object = Object::from(pin);
+        assert_eq!(
+            object.get_node_iri("object"),
+            Some("https://example.com/as/objects/1")
+        );
+        assert_eq!(object.id(), Some("https://example.com/as/objects/2"));
+        assert!(object.get_str("published").is_some());
+        assert_eq!(
+            object.get_str("to"),
+            Some("https://example.com/users/alice/followers")
+        );
+
+        let elsewhere = Object::from(json!({
+            "type": "Remove",
+            "object": "https://example.com/as/objects/1",
+            "target": "https://example.com/users/bob/collections/featured",
+        }));
+        assert!(Pin::from_outbox(elsewhere, "https://example.com/as/objects/3", actor).is_err());
+    }
+}
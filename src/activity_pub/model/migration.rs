@@ -0,0 +1,182 @@
+//! Account migration with the `Move` activity.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitystreams-vocabulary/#dfn-move>
+//! * <https://docs.joinmastodon.org/spec/activitypub/#Move>
+
+use anyhow::{bail, Result};
+use serde_json::json;
+
+use super::{timestamp_now, Object};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Move<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Move<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        if !object.type_is("Move") {
+            bail!("activity must be a Move");
+        }
+        let (Some(actor), Some(origin)) =
+            (object.get_node_iri("actor"), object.get_node_iri("object"))
+        else {
+            bail!("Move must have actor and object property");
+        };
+        // An account can only move itself.
+        if actor != origin {
+            bail!("Move actor {actor} does not match moved account {origin}");
+        }
+        if object.get_node_iri("target").is_none() {
+            bail!("Move must have target property");
+        }
+        Ok(Move(object))
+    }
+}
+
+impl Move<'static> {
+    /// A local actor announcing to its followers that it moved to `target`.
+    pub(crate) fn new(actor_iri: &str, target: &str, id: &str) -> Self {
+        Move(Object::from(json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": id,
+            "type": "Move",
+            "actor": actor_iri,
+            "object": actor_iri,
+            "target": target,
+            "to": format!("{actor_iri}/followers"),
+            "published": timestamp_now(),
+        })))
+    }
+}
+
+impl Move<'_> {
+    /// The account that moved.
+    pub(crate) fn origin(&self) -> &str {
+        self.0
+            .get_node_iri("object")
+            .expect("validated in try_from")
+    }
+    /// The account it moved to.
+    pub(crate) fn target(&self) -> &str {
+        self.0
+            .get_node_iri("target")
+            .expect("validated in try_from")
+    }
+    /// Check that both accounts agree on the migration.
+    ///
+    /// The target must list the origin in `alsoKnownAs`, and the origin must
+    /// point back to the target with `movedTo` or `alsoKnownAs`. Anyone can
+    /// claim an alias, only the two accounts together prove who owns both.
+    pub(crate) fn verify(&self, origin: &Object<'_>, target: &Object<'_>) -> Result<()> {
+        if origin.id() != Some(self.origin()) || target.id() != Some(self.target()) {
+            bail!("fetched accounts do not match the Move activity");
+        }
+        if !also_known_as(target, self.origin()) {
+            bail!(
+                "{} does not list {} in alsoKnownAs",
+                self.target(),
+                self.origin()
+            );
+        }
+        let moved_to = origin.get_node_iri("movedTo");
+        if moved_to != Some(self.target()) && !also_known_as(origin, self.target()) {
+            bail!("{} does not point to {}", self.origin(), self.target());
+        }
+        Ok(())
+    }
+    /// A `Follow` from a local actor to the new account, `published` at the
+    /// given time.
+    pub(crate) fn follow_target(
+        &self,
+        actor_iri: &str,
+        id: &str,
+        published: &str,
+    ) -> Object<'static> {
+        Object::from(json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": id,
+            "type": "Follow",
+            "actor": actor_iri,
+            "object": self.target(),
+            "to": self.target(),
+            "published": published,
+        }))
+    }
+}
+
+impl<'a> From<Move<'a>> for Object<'a> {
+    fn from(value: Move<'a>) -> Self {
+        value.0
+    }
+}
+
+fn also_known_as(actor: &Object<'_>, iri: &str) -> bool {
+    actor
+        .get_str_array("alsoKnownAs")
+        .is_some_and(|aliases| aliases.contains(&iri))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Move, Object};
+
+    fn move_activity() -> Move<'static> {
+        Move::try_from(Object::from(json!({
+            "id": "https://old.example/users/bob#moves/1",
+            "type": "Move",
+            "actor": "https://old.example/users/bob",
+            "object": "https://old.example/users/bob",
+            "target": "https://new.example/users/bob",
+        })))
+        .unwrap()
+    }
+
+    #[test]
+    fn verify_aliases_in_both_directions() {
+        let activity = move_activity();
+        let origin = Object::from(json!({
+            "id": "https://old.example/users/bob",
+            "movedTo": "https://new.example/users/bob",
+        }));
+        let target = Object::from(json!({
+            "id": "https://new.example/users/bob",
+            "alsoKnownAs": ["https://old.example/users/bob"],
+        }));
+        assert!(activity.verify(&origin, &target).is_ok());
+
+        // The new account never claimed the old one.
+        let unclaimed = Object::from(json!({"id": "https://new.example/users/bob"}));
+        assert!(activity.verify(&origin, &unclaimed).is_err());
+
+        // The old account does not point to the new one.
+        let stale = Object::from(json!({"id": "https://old.example/users/bob"}));
+        assert!(activity.verify(&stale, &target).is_err());
+
+        let follow = activity.follow_target(
+            "https://example.com/users/alice",
+            "https://example.com/as/objects/1",
+            "2024-05-01T12:00:00Z",
+        );
+        assert!(follow.type_is("Follow"));
+        assert_eq!(follow.get_str("published"), Some("2024-05-01T12:00:00Z"));
+        assert_eq!(
+            follow.get_node_iri("object"),
+            Some("https://new.example/users/bob")
+        );
+    }
+
+    #[test]
+    fn reject_moving_someone_else() {
+        let object = Object::from(json!({
+            "type": "Move",
+            "actor": "https://evil.example/users/eve",
+            "object": "https://old.example/users/bob",
+            "target": "https://evil.example/users/eve",
+        }));
+        assert!(Move::try_from(object).is_err());
+    }
+}
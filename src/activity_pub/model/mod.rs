@@ -2,12 +2,26 @@
 mod object;
 
 mod actor;
+mod announce;
+mod block;
 mod collection;
 mod create;
+mod delete;
+mod featured;
+mod migration;
+mod question;
 mod update;
 
 pub(crate) use actor::Actor;
-pub(crate) use collection::OrderedCollection;
+pub(crate) use announce::Announce;
+pub(crate) use block::Block;
+pub(crate) use collection::{Collection, CollectionPage};
 pub(crate) use create::Create;
-pub(crate) use object::Object;
+pub(crate) use delete::Delete;
+pub(crate) use featured::Pin;
+pub(crate) use migration::Move;
+#[cfg(test)]
+pub(crate) use object::INBOX_ACTIVITY_TYPES;
+pub(crate) use object::{default_context, format_timestamp, timestamp_now, Object};
+pub(crate) use question::{Question, Vote};
 pub(crate) use update::Update;
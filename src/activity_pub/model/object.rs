@@ -3,11 +3,62 @@
 use std::borrow::Cow;
 use std::fmt::Display;
 
+use jiff::Timestamp;
 use serde_json::{Map, Value};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Object<'a>(Cow<'a, Value>);
 
+const ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
+
+/// Terms of objects written by local users that the ActivityStreams context
+/// leaves out, defined like Mastodon does. `sensitive` marks content behind
+/// the warning in `summary`.
+const EXTENSION_TERMS: [(&str, &str); 1] = [("sensitive", "as:sensitive")];
+
+/// Format `at` for `published` and `updated` of what the server writes:
+/// RFC 3339 in UTC, to the second like most servers write it.
+pub(crate) fn format_timestamp(at: Timestamp) -> String {
+    Timestamp::from_second(at.as_second())
+        .unwrap_or(at)
+        .to_string()
+}
+
+/// [`format_timestamp`] of the current time.
+///
+/// Not for the state machine, every replica must write the same bytes there,
+/// derive the time from the object key instead.
+pub(crate) fn timestamp_now() -> String {
+    format_timestamp(Timestamp::now())
+}
+
+/// The `@context` of the activities the server writes.
+pub(crate) fn default_context() -> Value {
+    extend_context(None)
+}
+
+/// `context` with the ActivityStreams vocabulary and the
+/// [`EXTENSION_TERMS`] it does not define yet.
+pub(crate) fn extend_context(context: Option<Value>) -> Value {
+    let mut items = match context {
+        None | Some(Value::Null) => vec![],
+        Some(Value::Array(items)) => items,
+        Some(item) => vec![item],
+    };
+    if !items.iter().any(|item| item == ACTIVITYSTREAMS) {
+        items.insert(0, Value::String(ACTIVITYSTREAMS.to_string()));
+    }
+    let terms: Map<String, Value> = EXTENSION_TERMS
+        .iter()
+        .filter(|(term, _)| !items.iter().any(|item| item.get(term).is_some()))
+        .map(|(term, iri)| (term.to_string(), Value::String(iri.to_string())))
+        .collect();
+    if !terms.is_empty() {
+        items.push(Value::Object(terms));
+    }
+    Value::Array(items)
+}
+
 impl Object<'_> {
     pub(crate) fn id(&self) -> Option<&str> {
         self.get_str("id").or_else(|| self.get_str("@id"))
@@ -18,6 +69,14 @@ impl Object<'_> {
     pub(crate) fn is_inbox_activity(&self) -> bool {
         INBOX_ACTIVITY_TYPES.iter().any(|ty| self.type_is(ty))
     }
+    /// The activity type of the vocabulary the object has, `other` for
+    /// anything else, so it can label metrics without unbounded values.
+    pub(crate) fn activity_type_label(&self) -> &'static str {
+        ACTIVITY_TYPES
+            .into_iter()
+            .find(|ty| self.type_is(ty))
+            .unwrap_or("other")
+    }
     pub(crate) fn type_is(&self, ty: &str) -> bool {
         for prop in ["type", "@type"] {
             if let Some(Value::String(object_type)) = self.0.get(prop) {
@@ -72,7 +131,7 @@ impl Object<'_> {
         }
         None
     }
-    pub(crate) fn get_node_object(&self, prop: &str) -> Option<Object> {
+    pub(crate) fn get_node_object(&self, prop: &str) -> Option<Object<'_>> {
         if let Some(v) = self.0.get(prop) {
             if v.is_object() {
                 return Some(v.into());
@@ -98,6 +157,25 @@ impl Object<'_> {
         }
         None
     }
+    /// IRIs of the actors in `attributedTo`, which may be an IRI, an
+    /// embedded actor or an array of either. Embedded actors without an
+    /// `id` are skipped.
+    pub(crate) fn attributed_to(&self) -> Vec<&str> {
+        fn node_iri(value: &Value) -> Option<&str> {
+            value
+                .as_str()
+                .or_else(|| value.get("id").and_then(Value::as_str))
+        }
+        match self.0.get("attributedTo") {
+            Some(Value::Array(array)) => array.iter().filter_map(node_iri).collect(),
+            Some(value) => node_iri(value).into_iter().collect(),
+            None => vec![],
+        }
+    }
+    /// Whether `actor_iri` is one of the actors the object is attributed to.
+    pub(crate) fn is_attributed_to(&self, actor_iri: &str) -> bool {
+        self.attributed_to().contains(&actor_iri)
+    }
     pub(crate) fn get_endpoint(&self, prop: &str) -> Option<&str> {
         if let Some(value) = self.0.get("endpoints") {
             if let Some(v) = value.get(prop) {
@@ -149,6 +227,15 @@ impl Object<'_> {
         obj_map.extend(map);
         Object(Cow::Owned(obj))
     }
+    /// Define the terms the server writes in `@context`, see
+    /// [`extend_context`].
+    pub(crate) fn with_context(self) -> Self {
+        let mut obj = self.0.into_owned();
+        let obj_map = obj.as_object_mut().unwrap();
+        let context = extend_context(obj_map.remove("@context"));
+        obj_map.insert("@context".to_string(), context);
+        Object(Cow::Owned(obj))
+    }
 }
 
 impl From<Value> for Object<'static> {
@@ -226,6 +313,52 @@ const ACTIVITY_TYPES: [&str; 28] = [
     "View",
 ];
 
-const INBOX_ACTIVITY_TYPES: [&str; 8] = [
-    "Announce", "Create", "Delete", "Dislike", "Follow", "Like", "Update", "Undo",
+pub(crate) const INBOX_ACTIVITY_TYPES: [&str; 11] = [
+    "Announce", "Create", "Delete", "Dislike", "Flag", "Follow", "Like", "Move", "Reject",
+    "Update", "Undo",
 ];
+
+#[cfg(test)]
+mod tests {
+    use jiff::Timestamp;
+    use serde_json::json;
+
+    use super::{timestamp_now, Object};
+
+    #[test]
+    fn timestamps_are_utc_seconds() {
+        let now = timestamp_now();
+        assert!(now.ends_with('Z'), "{now}");
+        assert!(!now.contains('.'), "{now}");
+        assert!(now.parse::<Timestamp>().is_ok(), "{now}");
+    }
+
+    #[test]
+    fn activity_type_labels_are_bounded() {
+        let label = |ty| Object::from(json!({"type": ty})).activity_type_label();
+        assert_eq!(label(json!("Block")), "Block");
+        assert_eq!(label(json!(["Accept", "litepub:Extra"])), "Accept");
+        assert_eq!(label(json!("EmojiReact")), "other");
+        assert_eq!(label(json!("Note")), "other");
+    }
+
+    #[test]
+    fn attributed_to_shapes() {
+        let note =
+            |attributed_to| Object::from(json!({"type": "Note", "attributedTo": attributed_to}));
+        let alice = "https://example.com/users/alice";
+        let bob = "https://example.com/users/bob";
+
+        let by_iri = note(json!(alice));
+        assert_eq!(by_iri.attributed_to(), vec![alice]);
+        let embedded = note(json!({"type": "Person", "id": alice, "name": "Alice"}));
+        assert_eq!(embedded.attributed_to(), vec![alice]);
+        let array = note(json!([{"type": "Person", "id": alice}, bob, {"type": "Person"}]));
+        assert_eq!(array.attributed_to(), vec![alice, bob]);
+        assert!(array.is_attributed_to(bob));
+        assert!(!by_iri.is_attributed_to(bob));
+        assert!(Object::from(json!({"type": "Note"}))
+            .attributed_to()
+            .is_empty());
+    }
+}
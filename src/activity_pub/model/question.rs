@@ -0,0 +1,179 @@
+//! Polls, and the votes cast in them.
+//!
+//! A poll is a `Question` listing its options as `oneOf`, or `anyOf` when
+//! several may be picked. A vote is a `Note` addressed to the author of the
+//! poll, naming the option and replying to the poll.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitystreams-vocabulary/#dfn-question>
+//! * <https://docs.joinmastodon.org/spec/activitypub/#Question>
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::Object;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Question<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Question<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        if !object.type_is("Question") {
+            bail!("object must be a Question");
+        }
+        if object.id().is_none() {
+            bail!("Question must have an id");
+        }
+        Ok(Question(object))
+    }
+}
+
+impl Question<'_> {
+    pub(crate) fn id(&self) -> &str {
+        self.0.id().expect("validated in try_from")
+    }
+    pub(crate) fn authors(&self) -> Vec<&str> {
+        self.0.attributed_to()
+    }
+    /// Whether `actor` is one of the authors of the poll.
+    pub(crate) fn is_by(&self, actor: Option<&str>) -> bool {
+        actor.is_some_and(|actor| self.0.is_attributed_to(actor))
+    }
+    /// Whether voters may pick more than one option.
+    pub(crate) fn is_multiple(&self) -> bool {
+        self.0.has_props(&["anyOf"])
+    }
+    /// Whether the poll stopped taking votes.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.0.has_props(&["closed"])
+    }
+    /// The poll with one more vote for the option `name`, none if there is
+    /// no such option. `new_voter` also counts the voter.
+    pub(crate) fn vote(&self, name: &str, new_voter: bool) -> Option<Question<'static>> {
+        let prop = if self.is_multiple() { "anyOf" } else { "oneOf" };
+        let mut value = self.0.to_value();
+        let option = value
+            .get_mut(prop)?
+            .as_array_mut()?
+            .iter_mut()
+            .find(|option| option.get("name").and_then(Value::as_str) == Some(name))?;
+        let replies = option
+            .as_object_mut()?
+            .entry("replies")
+            .or_insert_with(|| json!({"type": "Collection", "totalItems": 0}));
+        let votes = replies.get("totalItems").and_then(Value::as_u64);
+        replies["totalItems"] = json!(votes.unwrap_or(0) + 1);
+        if new_voter {
+            let voters = value.get("votersCount").and_then(Value::as_u64);
+            value["votersCount"] = json!(voters.unwrap_or(0) + 1);
+        }
+        Some(Question(Object::from(value)))
+    }
+}
+
+impl<'a> From<Question<'a>> for Object<'a> {
+    fn from(value: Question<'a>) -> Self {
+        value.0
+    }
+}
+
+/// A vote in a poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Vote<'a>(Object<'a>);
+
+impl<'a> TryFrom<Object<'a>> for Vote<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(object: Object<'a>) -> Result<Self> {
+        // Replies have content, votes only the name of the option.
+        if !object.type_is("Note") || object.has_props(&["content"]) {
+            bail!("object must be a Note without content");
+        }
+        if object.get_str("name").is_none()
+            || object.get_node_iri("inReplyTo").is_none()
+            || object.attributed_to().is_empty()
+        {
+            bail!("vote must have name, inReplyTo and attributedTo property");
+        }
+        Ok(Vote(object))
+    }
+}
+
+impl Vote<'_> {
+    /// The poll voted in.
+    pub(crate) fn question(&self) -> &str {
+        self.0
+            .get_node_iri("inReplyTo")
+            .expect("validated in try_from")
+    }
+    /// The option voted for.
+    pub(crate) fn name(&self) -> &str {
+        self.0.get_str("name").expect("validated in try_from")
+    }
+    pub(crate) fn voter(&self) -> &str {
+        self.0
+            .attributed_to()
+            .first()
+            .copied()
+            .expect("validated in try_from")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Object, Question, Vote};
+
+    #[test]
+    fn count_votes() {
+        let question = Question::try_from(Object::from(json!({
+            "id": "https://example.com/as/objects/1",
+            "type": "Question",
+            "oneOf": [
+                {"type": "Note", "name": "yes"},
+                {"type": "Note", "name": "no", "replies": {"type": "Collection", "totalItems": 2}},
+            ],
+            "votersCount": 2,
+        })))
+        .unwrap();
+        assert!(!question.is_multiple());
+        assert!(question.vote("maybe", true).is_none());
+
+        let question = question.vote("yes", true).unwrap();
+        let question = question.vote("no", false).unwrap();
+        let object = Object::from(question);
+        assert_eq!(
+            object.get_value("oneOf"),
+            Some(json!([
+                {"type": "Note", "name": "yes", "replies": {"type": "Collection", "totalItems": 1}},
+                {"type": "Note", "name": "no", "replies": {"type": "Collection", "totalItems": 3}},
+            ]))
+        );
+        assert_eq!(object.get_value("votersCount"), Some(json!(3)));
+    }
+
+    #[test]
+    fn tell_votes_from_replies() {
+        let vote = Object::from(json!({
+            "type": "Note",
+            "name": "yes",
+            "inReplyTo": "https://example.com/as/objects/1",
+            "attributedTo": "https://remote.example/users/bob",
+        }));
+        let vote = Vote::try_from(vote).unwrap();
+        assert_eq!(vote.question(), "https://example.com/as/objects/1");
+        assert_eq!(vote.name(), "yes");
+
+        let reply = Object::from(json!({
+            "type": "Note",
+            "name": "yes",
+            "content": "<p>yes</p>",
+            "inReplyTo": "https://example.com/as/objects/1",
+            "attributedTo": "https://remote.example/users/bob",
+        }));
+        assert!(Vote::try_from(reply).is_err());
+    }
+}
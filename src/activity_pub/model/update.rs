@@ -1,8 +1,17 @@
+//! Editing objects with an `Update`.
+//!
+//! An edit replaces the stored object, the earlier versions stay in the
+//! outbox as the objects of the Create and Update activities that carried
+//! them.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitypub/#update-activity-outbox>
+//! * <https://docs.joinmastodon.org/spec/activitypub/#as>
+
 use anyhow::{bail, Result};
-use jiff::Timestamp;
 use serde_json::{json, Value};
 
-use super::Object;
+use super::{default_context, timestamp_now, Object};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Update<'a>(Object<'a>);
@@ -26,9 +35,9 @@ impl TryFrom<Object<'_>> for Update<'static> {
         let object = object.strip_context();
 
         let mut update = json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
+            "@context": default_context(),
             "type": "Update",
-            "published": Timestamp::now().to_string(),
+            "published": timestamp_now(),
         });
 
         let map = update.as_object_mut().unwrap();
@@ -43,7 +52,86 @@ impl TryFrom<Object<'_>> for Update<'static> {
     }
 }
 
+/// Properties of the stored object an edit cannot change.
+const KEPT: [&str; 10] = [
+    "@context",
+    "id",
+    "type",
+    "attributedTo",
+    "published",
+    "to",
+    "cc",
+    "bto",
+    "bcc",
+    "audience",
+];
+
+impl Update<'static> {
+    /// Build the activity for an Update posted to an outbox.
+    ///
+    /// The properties of the embedded object replace those of `stored`,
+    /// except for its identity, author and audience. The edit is stamped
+    /// `updated` and addressed to the audience of the stored object, so it
+    /// reaches everyone who got the original. The caller checks that the
+    /// outbox owner wrote `stored`.
+    pub(crate) fn from_outbox(
+        object: Object<'_>,
+        stored: &Object<'_>,
+        act_iri: &str,
+        actor_iri: &str,
+    ) -> Result<Update<'static>> {
+        if object
+            .get_node_iri("actor")
+            .is_some_and(|actor| actor != actor_iri)
+        {
+            bail!("activity actor must be the outbox owner");
+        }
+        if stored.type_is("Tombstone") {
+            bail!("object is deleted");
+        }
+        let Some(edit) = object.get_node_object("object") else {
+            bail!("Update must embed the edited object");
+        };
+        if edit.id().is_none() || edit.id() != stored.id() {
+            bail!("Update must embed the edited object with its id");
+        }
+        let now = timestamp_now();
+        let mut edited = stored.to_value();
+        let (Some(edited_map), Value::Object(changes)) = (edited.as_object_mut(), edit.to_value())
+        else {
+            bail!("edited object must be an object");
+        };
+        for (prop, value) in changes {
+            if !KEPT.contains(&prop.as_str()) {
+                edited_map.insert(prop, value);
+            }
+        }
+        edited_map.insert("updated".to_string(), Value::String(now.clone()));
+        let mut update = json!({
+            "@context": default_context(),
+            "id": act_iri,
+            "type": "Update",
+            "actor": actor_iri,
+            "published": now,
+        });
+        let map = update.as_object_mut().unwrap();
+        for prop in ["to", "cc", "audience"] {
+            if let Some(v) = stored.get_value(prop) {
+                map.insert(prop.to_string(), v);
+            }
+        }
+        map.insert("object".to_string(), edited);
+        Update::try_from(Object::from(update))
+    }
+}
+
 impl Update<'_> {
+    /// The edited object.
+    pub(crate) fn object(&self) -> &str {
+        self.0
+            .get_node_iri("object")
+            .expect("validated in from_outbox")
+    }
     pub(crate) fn ensure_id(self, iri: impl Into<String>) -> Self {
         Update(self.0.ensure_id(iri))
     }
@@ -68,3 +156,60 @@ impl From<Update<'_>> for Value {
         value.0.to_value()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{Object, Update};
+
+    #[test]
+    fn outbox_update_edits_stored_object() {
+        let actor = "https://example.com/users/alice";
+        let stored = Object::from(json!({
+            "id": "https://example.com/as/objects/1",
+            "type": "Note",
+            "attributedTo": actor,
+            "content": "helo",
+            "published": "2024-01-01T00:00:00Z",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "cc": ["https://example.com/users/alice/followers"],
+        }));
+        let object = Object::from(json!({
+            "type": "Update",
+            "object": {
+                "id": "https://example.com/as/objects/1",
+                "type": "Article",
+                "content": "hello",
+                "attributedTo": "https://example.com/users/bob",
+                "to": [],
+            },
+        }));
+        let act_iri = "https://example.com/as/objects/2";
+        let update = Update::from_outbox(object, &stored, act_iri, actor).unwrap();
+        assert_eq!(update.object(), "https://example.com/as/objects/1");
+        let update = Object::from(update);
+        assert_eq!(update.id(), Some(act_iri));
+        assert_eq!(update.get_node_iri("actor"), Some(actor));
+        assert_eq!(update.get_value("to"), stored.get_value("to"));
+        assert_eq!(update.get_value("cc"), stored.get_value("cc"));
+        let edited = update.get_node_object("object").unwrap();
+        assert_eq!(edited.get_str("content"), Some("hello"));
+        assert!(edited.type_is("Note"));
+        assert_eq!(edited.get_node_iri("attributedTo"), Some(actor));
+        assert_eq!(edited.get_str("published"), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(edited.get_value("to"), stored.get_value("to"));
+        assert!(edited.get_str("updated").is_some());
+
+        let other = Object::from(json!({
+            "type": "Update",
+            "object": {"id": "https://example.com/as/objects/3", "content": "hi"},
+        }));
+        assert!(Update::from_outbox(other, &stored, act_iri, actor).is_err());
+        let reference = Object::from(json!({
+            "type": "Update",
+            "object": "https://example.com/as/objects/1",
+        }));
+        assert!(Update::from_outbox(reference, &stored, act_iri, actor).is_err());
+    }
+}
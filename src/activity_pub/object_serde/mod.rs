@@ -1,13 +1,20 @@
+mod normalize;
 mod symbols;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use minicbor::data::Type;
 use minicbor::{Decode, Encode};
 use serde_json::{Number, Value};
 
+pub(crate) use self::normalize::normalize;
 use self::symbols::activitystreams_symbol_table;
 
+use super::addressing::remove_blind_recipients;
 use super::model::Object;
+use crate::config::ObjectLimits;
+
+/// Integers up to this size are exact in an f64.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
 
 #[derive(Debug, Encode, Decode)]
 enum Envelope {
@@ -15,13 +22,48 @@ enum Envelope {
     V1(#[n(0)] NodeValue),
 }
 
+/// Blind recipients (`bto`, `bcc`) are never persisted, records written
+/// before they were stripped lose them when read back.
 pub(crate) fn to_bytes(object: impl Into<Value>) -> Result<Vec<u8>> {
-    let value = object.into();
+    let mut value = object.into();
+    remove_blind_recipients(&mut value);
     minicbor::to_vec(Envelope::V1(value.into())).context("unable to serialize object")
 }
 pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Object<'static>> {
     let Envelope::V1(value) = minicbor::decode(bytes).context("unable to deserialize object")?;
-    Ok(Object::from(Value::from(value)))
+    let mut value = Value::from(value);
+    remove_blind_recipients(&mut value);
+    Ok(Object::from(value))
+}
+
+/// Parse a JSON document received from a client or peer within `limits`.
+///
+/// The size is checked before parsing, nesting and array lengths right
+/// after, without recursion. Documents that pass are safe to hand to the
+/// recursive conversions used everywhere else, starting with [`normalize`].
+pub(crate) fn from_json_slice(bytes: &[u8], limits: &ObjectLimits) -> Result<Value> {
+    if bytes.len() > limits.max_bytes {
+        bail!("document exceeds {} bytes", limits.max_bytes);
+    }
+    let value: Value = serde_json::from_slice(bytes).context("invalid JSON document")?;
+    let mut stack = vec![(&value, 1)];
+    while let Some((value, depth)) = stack.pop() {
+        let children: Box<dyn Iterator<Item = &Value>> = match value {
+            Value::Array(array) => {
+                if array.len() > limits.max_array_len {
+                    bail!("array exceeds {} elements", limits.max_array_len);
+                }
+                Box::new(array.iter())
+            }
+            Value::Object(map) => Box::new(map.values()),
+            _ => continue,
+        };
+        if depth > limits.max_depth {
+            bail!("document nests deeper than {} levels", limits.max_depth);
+        }
+        stack.extend(children.map(|child| (child, depth + 1)));
+    }
+    Ok(normalize(value))
 }
 
 impl<C> Encode<C> for Object<'_> {
@@ -235,6 +277,11 @@ impl From<NodeValue> for Value {
         match value {
             NodeValue::Null => Value::Null,
             NodeValue::Bool(v) => Value::Bool(v),
+            // Numbers are stored as f64, counts such as `totalItems` and
+            // `votersCount` must come back as integers.
+            NodeValue::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER => {
+                Value::Number(Number::from(n as i64))
+            }
             NodeValue::Number(n) => {
                 Value::Number(Number::from_f64(n).expect("number should be f64 compatible"))
             }
@@ -251,7 +298,26 @@ impl From<NodeValue> for Value {
 mod tests {
     use serde_json::json;
 
-    use super::{NodeValue, Symbol, Value};
+    use crate::config::ObjectLimits;
+
+    use super::{from_json_slice, NodeValue, Symbol, Value};
+
+    #[test]
+    fn enforce_object_limits() {
+        let limits = ObjectLimits {
+            max_bytes: 64,
+            max_depth: 3,
+            max_array_len: 2,
+        };
+        let parse = |json: &str| from_json_slice(json.as_bytes(), &limits);
+        assert!(parse(r#"{"a": {"b": [1, 2]}}"#).is_ok());
+        assert!(parse(r#"{"a": {"b": [[1]]}}"#).is_err());
+        assert!(parse(r#"{"a": [1, 2, 3]}"#).is_err());
+        assert!(parse(&format!(r#"{{"a": "{}"}}"#, "x".repeat(64))).is_err());
+        // Deeper than the parser itself allows, must fail rather than overflow.
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(from_json_slice(deep.as_bytes(), &ObjectLimits::default()).is_err());
+    }
 
     #[test]
     fn convert_mastodon_note() {
@@ -418,4 +484,110 @@ mod tests {
         let node: NodeValue = note.clone().into();
         assert_eq!(note, Value::from(node));
     }
+
+    #[test]
+    fn round_trip_poll() {
+        let question = json!({
+            "id": "https://example.com/statuses/12345",
+            "type": "Question",
+            "content": "<p>tabs or spaces?</p>",
+            "endTime": "2024-11-05T05:12:16Z",
+            "votersCount": 7,
+            "oneOf": [
+                {"type": "Note", "name": "tabs", "replies": {"type": "Collection", "totalItems": 4}},
+                {"type": "Note", "name": "spaces", "replies": {"type": "Collection", "totalItems": 3}},
+            ],
+            "ratio": 0.5,
+        });
+        let bytes = super::to_bytes(question.clone()).unwrap();
+        let object = super::from_bytes(&bytes).unwrap();
+        assert_eq!(object.to_value(), question);
+    }
+
+    #[test]
+    fn round_trip_content_warning() {
+        let note = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"sensitive": "as:sensitive"}
+            ],
+            "id": "https://example.com/notes/1",
+            "type": "Note",
+            "summary": "cw: spoilers",
+            "sensitive": true,
+            "content": "<p>the butler did it</p>",
+        });
+        let parsed =
+            from_json_slice(note.to_string().as_bytes(), &ObjectLimits::default()).unwrap();
+        assert_eq!(parsed, note);
+        let bytes = super::to_bytes(note.clone()).unwrap();
+        let object = super::from_bytes(&bytes).unwrap();
+        assert_eq!(object.to_value(), note);
+    }
+
+    #[test]
+    fn round_trip_url_shapes() {
+        let link = json!({
+            "type": "Link",
+            "mediaType": "text/html",
+            "href": "https://video.example.com/w/1",
+        });
+        // PeerTube lists the page and each rendition of the video.
+        let links = json!([
+            {"type": "Link", "mediaType": "text/html", "href": "https://video.example.com/w/1"},
+            {
+                "type": "Link",
+                "mediaType": "video/mp4",
+                "href": "https://video.example.com/static/1-720.mp4",
+                "height": 720,
+                "size": 31_000_000,
+                "fps": 30,
+            },
+            {
+                "type": "Link",
+                "rel": ["metadata", "video/mp4"],
+                "mediaType": "application/json",
+                "href": "https://video.example.com/api/v1/videos/1/metadata/720",
+            },
+            "https://video.example.com/videos/watch/1",
+        ]);
+        for url in [json!("https://video.example.com/w/1"), link, links] {
+            let video = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": "https://video.example.com/videos/1",
+                "type": "Video",
+                "url": url,
+            });
+            let parsed =
+                from_json_slice(video.to_string().as_bytes(), &ObjectLimits::default()).unwrap();
+            assert_eq!(parsed, video);
+            let bytes = super::to_bytes(video.clone()).unwrap();
+            let object = super::from_bytes(&bytes).unwrap();
+            assert_eq!(object.to_value(), video);
+        }
+    }
+
+    #[test]
+    fn round_trip_attributed_to_shapes() {
+        let actor = json!({
+            "type": "Person",
+            "id": "https://example.com/users/alice",
+            "name": "Alice",
+        });
+        let actors = json!([actor.clone(), "https://example.com/users/bob"]);
+        for attributed_to in [json!("https://example.com/users/alice"), actor, actors] {
+            let note = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": "https://example.com/notes/1",
+                "type": "Note",
+                "attributedTo": attributed_to,
+            });
+            let parsed =
+                from_json_slice(note.to_string().as_bytes(), &ObjectLimits::default()).unwrap();
+            assert_eq!(parsed, note);
+            let bytes = super::to_bytes(note.clone()).unwrap();
+            let object = super::from_bytes(&bytes).unwrap();
+            assert_eq!(object.to_value(), note);
+        }
+    }
 }
@@ -0,0 +1,340 @@
+//! Pragmatic JSON-LD normalization.
+//!
+//! Everything else in the crate reads documents in the compacted form with
+//! the plain ActivityStreams terms, like Mastodon, Pleroma and PeerTube
+//! usually send them. Producers are free to pick other shapes of the same
+//! data though: compact IRIs (`as:object`), their own term aliases, full
+//! IRIs, or the expanded form with `@id`, `@type` and `@value` objects. This
+//! module rewrites those back to the plain terms without running the full
+//! JSON-LD algorithms, terms outside the vocabularies below are left alone.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+const ACTIVITYSTREAMS: &str = "https://www.w3.org/ns/activitystreams";
+
+/// Namespaces whose terms are used without a prefix in compacted documents.
+const NAMESPACES: [&str; 4] = [
+    "https://www.w3.org/ns/activitystreams#",
+    // Some producers still use the old scheme.
+    "http://www.w3.org/ns/activitystreams#",
+    "http://www.w3.org/ns/ldp#",
+    "https://w3id.org/security#",
+];
+
+/// Properties that stay arrays when unwrapping an expanded document.
+const ARRAY_PROPERTIES: [&str; 12] = [
+    "to",
+    "cc",
+    "bto",
+    "bcc",
+    "audience",
+    "tag",
+    "attachment",
+    "items",
+    "orderedItems",
+    "alsoKnownAs",
+    "oneOf",
+    "anyOf",
+];
+
+/// Rewrite `value` to the compacted form with plain ActivityStreams terms.
+pub(crate) fn normalize(value: Value) -> Value {
+    // The expansion algorithm always outputs an array, and leaves neither a
+    // context nor the `id` and `type` aliases behind.
+    let (value, expanded) = match value {
+        Value::Array(mut items) if items.len() == 1 && items[0].is_object() => {
+            (items.pop().unwrap(), true)
+        }
+        Value::Object(map) => {
+            let expanded = !map.contains_key("@context")
+                && (map.contains_key("@id") || map.contains_key("@type"));
+            (Value::Object(map), expanded)
+        }
+        value => return value,
+    };
+    let mut value = Normalizer { expanded }.walk(value, &Context::default());
+    if let (true, Some(map)) = (expanded, value.as_object_mut()) {
+        map.insert(
+            "@context".to_string(),
+            Value::String(ACTIVITYSTREAMS.to_string()),
+        );
+    }
+    value
+}
+
+/// Term definitions in scope, from the `@context` of a node and its parents.
+#[derive(Clone, Default)]
+struct Context {
+    terms: HashMap<String, String>,
+}
+
+impl Context {
+    /// The context with the definitions in `local` added.
+    fn extend(&self, local: &Value) -> Context {
+        let mut context = self.clone();
+        let definitions = match local {
+            Value::Array(items) => items.iter().filter_map(Value::as_object).collect(),
+            Value::Object(map) => vec![map],
+            _ => vec![],
+        };
+        for map in definitions {
+            for (term, definition) in map {
+                let iri = match definition {
+                    Value::String(iri) => iri,
+                    Value::Object(definition) => match definition.get("@id") {
+                        Some(Value::String(iri)) => iri,
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                context.terms.insert(term.clone(), iri.clone());
+            }
+        }
+        // Resolve definitions written as compact IRIs, e.g. `as:sensitive`.
+        let resolved: Vec<_> = context
+            .terms
+            .iter()
+            .map(|(term, iri)| (term.clone(), context.expand(iri).into_owned()))
+            .collect();
+        context.terms.extend(resolved);
+        context
+    }
+    /// The IRI or keyword `name` stands for.
+    fn expand<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if let Some(iri) = self.terms.get(name) {
+            return Cow::Owned(iri.clone());
+        }
+        if let Some((prefix, suffix)) = name.split_once(':') {
+            if !suffix.starts_with("//") {
+                let namespace = self.terms.get(prefix).map(String::as_str).or(match prefix {
+                    "as" => Some(NAMESPACES[0]),
+                    "ldp" => Some(NAMESPACES[2]),
+                    _ => None,
+                });
+                if let Some(namespace) = namespace {
+                    return Cow::Owned(format!("{namespace}{suffix}"));
+                }
+            }
+        }
+        Cow::Borrowed(name)
+    }
+    /// The plain term for `name`, if it differs from `name`.
+    fn compact(&self, name: &str) -> Option<String> {
+        let iri = self.expand(name);
+        let term = match iri.as_ref() {
+            "@id" => "id",
+            "@type" => "type",
+            iri => NAMESPACES
+                .iter()
+                .find_map(|namespace| iri.strip_prefix(namespace))
+                .filter(|term| !term.is_empty() && !term.contains(['/', '#']))?,
+        };
+        (term != name).then(|| term.to_string())
+    }
+}
+
+struct Normalizer {
+    expanded: bool,
+}
+
+impl Normalizer {
+    fn walk(&self, value: Value, context: &Context) -> Value {
+        let mut map = match value {
+            Value::Array(items) => {
+                return Value::Array(
+                    items
+                        .into_iter()
+                        .map(|item| self.walk(item, context))
+                        .collect(),
+                )
+            }
+            Value::Object(map) => map,
+            value => return value,
+        };
+        // Value objects and bare node references.
+        if let Some(value) = map.remove("@value") {
+            return value;
+        }
+        if let Some(list) = map.remove("@list") {
+            return self.walk(list, context);
+        }
+        if map.len() == 1 {
+            if let Some(Value::String(iri)) = map.get("@id") {
+                return Value::String(iri.clone());
+            }
+        }
+        let context = match map.get("@context") {
+            Some(local) => Cow::Owned(context.extend(local)),
+            None => Cow::Borrowed(context),
+        };
+        let mut normalized = Map::new();
+        let mut renamed = vec![];
+        for (key, value) in map {
+            if key == "@context" {
+                normalized.insert(key, value);
+                continue;
+            }
+            let value = self.walk(value, &context);
+            match context.compact(&key) {
+                Some(term) => renamed.push((term, value)),
+                None => {
+                    normalized.insert(key, value);
+                }
+            }
+        }
+        // Terms used as is win over aliases of the same term.
+        for (term, value) in renamed {
+            normalized.entry(term).or_insert(value);
+        }
+        if let Some(types) = normalized.get_mut("type") {
+            compact_types(types, &context);
+        }
+        if self.expanded {
+            for (key, value) in normalized.iter_mut() {
+                if ARRAY_PROPERTIES.contains(&key.as_str()) {
+                    continue;
+                }
+                if let Value::Array(items) = value {
+                    if items.len() == 1 {
+                        *value = items.pop().unwrap();
+                    }
+                }
+            }
+        }
+        Value::Object(normalized)
+    }
+}
+
+/// Types are IRIs too, `as:Note` is a `Note`.
+fn compact_types(types: &mut Value, context: &Context) {
+    match types {
+        Value::String(ty) => {
+            if let Some(term) = context.compact(ty) {
+                *ty = term;
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|ty| compact_types(ty, context)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::normalize;
+    use crate::activity_pub::model::Object;
+
+    #[test]
+    fn keep_compacted_documents() {
+        let note = json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {"toot": "http://joinmastodon.org/ns#", "Emoji": "toot:Emoji"},
+            ],
+            "id": "https://example.com/notes/1",
+            "type": "Note",
+            "to": ["https://www.w3.org/ns/activitystreams#Public"],
+            "tag": [{"type": "Emoji", "name": ":blob:"}],
+            "toot:discoverable": true,
+        });
+        assert_eq!(normalize(note.clone()), note);
+    }
+
+    #[test]
+    fn resolve_prefixes_and_aliases() {
+        let activity = normalize(json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                {
+                    "activitystreams": "https://www.w3.org/ns/activitystreams#",
+                    "obj": "as:object",
+                    "uri": "@id",
+                },
+            ],
+            "uri": "https://example.com/activities/1",
+            "activitystreams:type": "as:Create",
+            "as:actor": "https://example.com/users/bob",
+            "obj": {
+                "https://www.w3.org/ns/activitystreams#content": "hello",
+                "type": "Note",
+            },
+            "object": "kept",
+        }));
+        let object = Object::from(activity.clone());
+        assert_eq!(object.id(), Some("https://example.com/activities/1"));
+        assert!(object.type_is("Create"));
+        assert_eq!(
+            object.get_node_iri("actor"),
+            Some("https://example.com/users/bob")
+        );
+        // The plain term wins over an alias.
+        assert_eq!(object.get_str("object"), Some("kept"));
+        assert_eq!(activity["obj"], json!(null));
+    }
+
+    #[test]
+    fn compact_expanded_documents() {
+        let activity = normalize(json!([{
+            "@id": "https://example.com/activities/1",
+            "@type": ["https://www.w3.org/ns/activitystreams#Create"],
+            "https://www.w3.org/ns/activitystreams#actor": [
+                {"@id": "https://example.com/users/bob"}
+            ],
+            "https://www.w3.org/ns/activitystreams#to": [
+                {"@id": "https://www.w3.org/ns/activitystreams#Public"}
+            ],
+            "https://www.w3.org/ns/activitystreams#object": [{
+                "@id": "https://example.com/notes/1",
+                "@type": ["https://www.w3.org/ns/activitystreams#Note"],
+                "https://www.w3.org/ns/activitystreams#content": [
+                    {"@value": "hello", "@language": "en"}
+                ],
+            }],
+        }]));
+        assert_eq!(
+            activity,
+            json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": "https://example.com/activities/1",
+                "type": "Create",
+                "actor": "https://example.com/users/bob",
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "object": {
+                    "id": "https://example.com/notes/1",
+                    "type": "Note",
+                    "content": "hello",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn keep_expanded_url_links() {
+        let link = |media_type: &str, href: &str| {
+            json!({
+                "@type": ["https://www.w3.org/ns/activitystreams#Link"],
+                "https://www.w3.org/ns/activitystreams#mediaType": [{"@value": media_type}],
+                "https://www.w3.org/ns/activitystreams#href": [{"@id": href}],
+            })
+        };
+        let video = normalize(json!([{
+            "@id": "https://video.example.com/videos/1",
+            "@type": ["https://www.w3.org/ns/activitystreams#Video"],
+            "https://www.w3.org/ns/activitystreams#url": [
+                link("text/html", "https://video.example.com/w/1"),
+                link("video/mp4", "https://video.example.com/1-720.mp4"),
+            ],
+        }]));
+        assert_eq!(
+            video["url"],
+            json!([
+                {"type": "Link", "mediaType": "text/html", "href": "https://video.example.com/w/1"},
+                {"type": "Link", "mediaType": "video/mp4", "href": "https://video.example.com/1-720.mp4"},
+            ])
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Repair of activities stored with their object named by IRI only.
+//!
+//! Remote servers often deliver an Announce, and sometimes a Create, with
+//! just the IRI of its object. Served as stored, clients have to fetch the
+//! object themselves and many render nothing instead. When such an activity
+//! is served the repairer fetches the object from its origin, keeps it in a
+//! node local cache like remote actors, and embeds it.
+//!
+//! Fetches are limited per host and go neither to servers we do not
+//! federate with nor, unless configured, to loopback, private and link-local
+//! addresses. Host names are checked by the resolver of the fetching client,
+//! so the addresses checked are the ones connected to, and redirects are not
+//! followed.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use fjall::Keyspace;
+use metrics::counter;
+use moka::sync::Cache;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{StatusCode, Url};
+use serde_json::Value;
+use tokio::net::lookup_host;
+use tokio::task::spawn_blocking;
+use tracing::{debug, warn};
+
+use crate::config::{ActivityPubConfig, Config, FederationConfig};
+
+use super::mailman::Mailman;
+use super::model::Object;
+use super::repo::{RemoteActorEntry, RemoteActorRepo};
+use super::simple_queue::SimpleQueue;
+
+#[derive(Clone)]
+pub(crate) struct ObjectRepairer {
+    mailman: Mailman,
+    repo: RemoteActorRepo,
+    apub: ActivityPubConfig,
+    federation: FederationConfig,
+    ttl_secs: u64,
+    negative_ttl_secs: u64,
+    private_addresses: bool,
+    /// Fetches per host in the current minute.
+    fetches: Cache<String, Arc<AtomicU32>>,
+    per_minute: u32,
+}
+
+impl ObjectRepairer {
+    pub(crate) fn new(keyspace: Keyspace, config: &Config) -> Result<ObjectRepairer> {
+        Ok(ObjectRepairer {
+            mailman: Mailman::without_redirects(!config.cache.fetch_private_addresses),
+            repo: RemoteActorRepo::objects(keyspace)?,
+            apub: config.activity_pub.clone(),
+            federation: config.federation.clone(),
+            ttl_secs: config.cache.remote_object_ttl_secs,
+            negative_ttl_secs: config.cache.remote_actor_negative_ttl_secs,
+            private_addresses: config.cache.fetch_private_addresses,
+            fetches: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(60))
+                .build(),
+            per_minute: config.cache.remote_object_fetches_per_minute,
+        })
+    }
+
+    /// `activity` with its object embedded if it names a remote object by
+    /// IRI only and the object can be fetched, otherwise as it is.
+    pub(crate) async fn repair(&self, mut activity: Value) -> Value {
+        // Local objects are served on their own, boosts of them name them.
+        let Some(iri) = missing_object(&activity).filter(|iri| !self.apub.is_local(iri)) else {
+            return activity;
+        };
+        let result = self.fetch(&iri).await;
+        let outcome = match &result {
+            Ok(Some(_)) => "repaired",
+            Ok(None) => "skipped",
+            Err(error) => {
+                warn!(?error, %iri, "failed to fetch object to repair activity");
+                "failed"
+            }
+        };
+        counter!("pinka_object_repairs_total", "outcome" => outcome).increment(1);
+        if let Ok(Some(object)) = result {
+            activity["object"] = object.strip_context().to_value();
+        }
+        activity
+    }
+
+    /// The object `iri` from the cache or its origin, `None` if it is gone or
+    /// may not be fetched now.
+    async fn fetch(&self, iri: &str) -> Result<Option<Object<'static>>> {
+        let now = SimpleQueue::now();
+        let repo = self.repo.clone();
+        let key = iri.to_string();
+        if let Some(entry) = spawn_blocking(move || repo.find_fresh(&key, now)).await?? {
+            return Ok(entry.object);
+        }
+        if !self.permits(iri).await {
+            debug!(%iri, "not fetching object to repair activity");
+            return Ok(None);
+        }
+
+        let fetched = self.mailman.fetch_with_meta(iri).await?;
+        let (object, ttl) = match fetched.status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => (None, self.negative_ttl_secs),
+            status if status.is_success() => {
+                // Only the origin speaks for an object.
+                let object = fetched
+                    .value
+                    .map(Object::from)
+                    .filter(|object| object.id() == Some(iri));
+                let ttl = fetched.max_age.unwrap_or(self.ttl_secs).min(self.ttl_secs);
+                (object, ttl)
+            }
+            status => bail!("fetching {iri} failed with status {status}"),
+        };
+        if ttl > 0 {
+            let repo = self.repo.clone();
+            let iri = iri.to_string();
+            let entry = RemoteActorEntry {
+                expires_at: now + ttl,
+                object: object.clone(),
+            };
+            spawn_blocking(move || repo.insert(&iri, &entry)).await??;
+        }
+        Ok(object)
+    }
+
+    /// Whether a fetch of `iri` may go out now, counting it against its host.
+    async fn permits(&self, iri: &str) -> bool {
+        if !self.apub.federation_enabled || !self.federation.check(iri, "outbound") {
+            return false;
+        }
+        let Some(url) = Url::parse(iri)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+        else {
+            return false;
+        };
+        // Host names are left to the resolver of the mailman.
+        if !self.private_addresses && literal_ip(&url).is_some_and(|ip| !is_public(ip)) {
+            warn!(%iri, "refusing to fetch object from a non-public address");
+            return false;
+        }
+        let host = url.host_str().unwrap_or_default().to_string();
+        let fetches = self.fetches.get_with(host, Default::default);
+        fetches.fetch_add(1, Ordering::Relaxed) < self.per_minute
+    }
+}
+
+/// IRI of the object of an Announce or Create that carries no more than
+/// that IRI.
+fn missing_object(activity: &Value) -> Option<String> {
+    let kind = activity.get("type")?.as_str()?;
+    if kind != "Announce" && kind != "Create" {
+        return None;
+    }
+    match activity.get("object")? {
+        Value::String(iri) => Some(iri.clone()),
+        Value::Object(node) if !node.contains_key("type") => {
+            node.get("id")?.as_str().map(str::to_string)
+        }
+        _ => None,
+    }
+}
+
+/// The host of `url` if it is an IP address rather than a name.
+fn literal_ip(url: &Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Resolves host names like the system does, failing unless all their
+/// addresses are public. Used as the resolver of the client that connects,
+/// a name cannot resolve to one address when checked and another when
+/// fetched.
+pub(super) struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(format!("{} has non-public addresses", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the public internet, not loopback, private,
+/// link-local, shared or otherwise reserved for special use.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use reqwest::dns::{Name, Resolve};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use crate::config::Config;
+
+    use super::{is_public, missing_object, ObjectRepairer, PublicResolver};
+
+    #[test]
+    fn public_addresses() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_public_names_only() {
+        let resolver = Arc::new(PublicResolver);
+        let name = Name::from_str("localhost").unwrap();
+        assert!(resolver.resolve(name).await.is_err());
+    }
+
+    #[test]
+    fn find_missing_objects() {
+        let iri = "https://remote.example/notes/1";
+        let announce = |object: Value| json!({"type": "Announce", "object": object});
+        assert_eq!(missing_object(&announce(json!(iri))).as_deref(), Some(iri));
+        assert_eq!(
+            missing_object(&announce(json!({"id": iri}))).as_deref(),
+            Some(iri)
+        );
+        assert!(missing_object(&announce(json!({"id": iri, "type": "Note"}))).is_none());
+        assert!(missing_object(&json!({"type": "Like", "object": iri})).is_none());
+    }
+
+    #[tokio::test]
+    async fn embed_fetched_objects() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        // Not the host of the base URL, or the notes would be local.
+        let origin = format!("http://localhost:{}", listener.local_addr()?.port());
+        let note = format!("{origin}/notes/1");
+        let body = json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": note,
+            "type": "Note",
+            "content": "hello",
+        });
+        let app = Router::new()
+            .route("/notes/1", get(move || async move { Json(body) }))
+            .route("/notes/2", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/notes/3", get(|| async { StatusCode::NOT_FOUND }));
+        let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let tmp_dir = tempfile::tempdir()?;
+        let keyspace = fjall::Config::new(tmp_dir.path()).temporary(true).open()?;
+        let mut config = Config::default();
+        config.activity_pub.base_url = "http://127.0.0.1".to_string();
+        config.cache.remote_object_fetches_per_minute = 2;
+        let announce =
+            |n: u32| json!({"type": "Announce", "object": format!("{origin}/notes/{n}")});
+
+        // Loopback is refused unless asked for, by name or by address.
+        let repairer = ObjectRepairer::new(keyspace.clone(), &config)?;
+        assert_eq!(repairer.repair(announce(1)).await, announce(1));
+        let by_address = origin.replace("localhost", "127.0.0.1");
+        assert!(!repairer.permits(&format!("{by_address}/notes/1")).await);
+
+        config.cache.fetch_private_addresses = true;
+        let repairer = ObjectRepairer::new(keyspace, &config)?;
+        let repaired = repairer.repair(announce(1)).await;
+        assert_eq!(repaired["object"]["content"], "hello");
+        assert!(repaired["object"].get("@context").is_none());
+        assert_eq!(repairer.repair(announce(2)).await, announce(2));
+        // Cached copies are served while the origin is down.
+        server.abort();
+        let repaired = repairer.repair(announce(1)).await;
+        assert_eq!(repaired["object"]["id"], note);
+        // The third fetch within a minute is over the limit.
+        assert!(!repairer.permits(&format!("{origin}/notes/3")).await);
+        Ok(())
+    }
+}
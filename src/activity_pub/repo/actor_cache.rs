@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use metrics::counter;
+use moka::sync::Cache;
+
+use crate::activity_pub::model::Object;
+use crate::config::CacheConfig;
+
+/// Bounded in-memory cache of local actor documents keyed by uid.
+///
+/// Entries expire after the configured TTL and are explicitly invalidated
+/// by the state machine whenever the actor is rewritten.
+#[derive(Clone)]
+pub(crate) struct ActorCache {
+    actors: Cache<String, Object<'static>>,
+}
+
+impl ActorCache {
+    pub(crate) fn new(config: &CacheConfig) -> ActorCache {
+        let actors = Cache::builder()
+            .max_capacity(config.actor_capacity)
+            .time_to_live(Duration::from_secs(config.actor_ttl_secs))
+            .build();
+        ActorCache { actors }
+    }
+    pub(crate) fn get(&self, uid: &str) -> Option<Object<'static>> {
+        let object = self.actors.get(uid);
+        if object.is_some() {
+            counter!("pinka_actor_cache_hits_total").increment(1);
+        } else {
+            counter!("pinka_actor_cache_misses_total").increment(1);
+        }
+        object
+    }
+    pub(crate) fn insert(&self, uid: &str, object: Object<'static>) {
+        self.actors.insert(uid.to_string(), object);
+    }
+    pub(crate) fn invalidate(&self, uid: &str) {
+        self.actors.invalidate(uid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::activity_pub::model::Object;
+    use crate::config::CacheConfig;
+
+    use super::ActorCache;
+
+    #[test]
+    fn insert_get_invalidate() {
+        let cache = ActorCache::new(&CacheConfig::default());
+        assert!(cache.get("alice").is_none());
+
+        let actor = Object::from(json!({"type": "Person", "name": "Alice"}));
+        cache.insert("alice", actor.clone());
+        assert_eq!(cache.get("alice"), Some(actor));
+
+        cache.invalidate("alice");
+        assert!(cache.get("alice").is_none());
+    }
+
+    #[test]
+    fn zero_capacity_never_caches() {
+        let cache = ActorCache::new(&CacheConfig {
+            actor_capacity: 0,
+            ..Default::default()
+        });
+        cache.insert("alice", Object::from(json!({"type": "Person"})));
+        cache.actors.run_pending_tasks();
+        assert!(cache.get("alice").is_none());
+    }
+}
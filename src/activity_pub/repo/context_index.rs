@@ -1,52 +1,206 @@
+use std::collections::BTreeMap;
+
 use anyhow::{Context, Result};
-use fjall::{Batch, Keyspace};
+use fjall::Keyspace;
 
 use super::xindex::IdObjIndex;
-use super::{IdObjIndexKey, ObjectKey};
+use super::{Batch, IdObjIndexKey, ObjectKey};
 
 #[derive(Clone)]
 pub(crate) struct ContextIndex {
     ctx_index: IdObjIndex,
+    /// Replies to local objects, by the IRI of the object.
+    replies_index: IdObjIndex,
     likes_index: IdObjIndex,
     shares_index: IdObjIndex,
+    votes_index: IdObjIndex,
+    /// Replies, Likes and Announces by the IRI of their actor.
+    actors_index: IdObjIndex,
 }
 
 impl ContextIndex {
     pub(crate) fn new(keyspace: Keyspace) -> Result<ContextIndex> {
-        fn open_indexes(keyspace: Keyspace) -> Result<(IdObjIndex, IdObjIndex, IdObjIndex)> {
-            let ctx_index =
-                IdObjIndex::new(keyspace.open_partition("ctx_index", Default::default())?);
-            let likes_index =
-                IdObjIndex::new(keyspace.open_partition("likes_index", Default::default())?);
-            let shares_index =
-                IdObjIndex::new(keyspace.open_partition("shares_index", Default::default())?);
-            Ok((ctx_index, likes_index, shares_index))
-        }
-        let (ctx_index, likes_index, shares_index) =
-            open_indexes(keyspace).context("Failed to open indexes")?;
+        let open = |name| IdObjIndex::open(&keyspace, name).context("Failed to open indexes");
         Ok(ContextIndex {
-            ctx_index,
-            likes_index,
-            shares_index,
+            ctx_index: open("ctx_index")?,
+            replies_index: open("replies_index")?,
+            likes_index: open("likes_index")?,
+            shares_index: open("shares_index")?,
+            votes_index: open("votes_index")?,
+            actors_index: open("actors_index")?,
         })
     }
-    pub(crate) fn insert(&self, b: &mut Batch, iri: &str, obj_key: ObjectKey) {
-        self.ctx_index.insert(b, IdObjIndexKey::new(iri, obj_key));
+    pub(crate) fn insert(
+        &self,
+        b: &mut Batch,
+        iri: &str,
+        actor: Option<&str>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.insert_actor(b, actor, obj_key)?;
+        self.ctx_index.insert(b, IdObjIndexKey::new(iri, obj_key))
+    }
+    /// Count `obj_key` as a reply to the local object `iri`.
+    pub(crate) fn insert_replies(
+        &self,
+        b: &mut Batch,
+        iri: &str,
+        actor: Option<&str>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.insert_actor(b, actor, obj_key)?;
+        self.replies_index
+            .insert(b, IdObjIndexKey::new(iri, obj_key))
+    }
+    fn insert_actor(&self, b: &mut Batch, actor: Option<&str>, obj_key: ObjectKey) -> Result<()> {
+        match actor {
+            Some(actor) => self
+                .actors_index
+                .insert(b, IdObjIndexKey::new(actor, obj_key)),
+            None => Ok(()),
+        }
+    }
+    /// Remove several activities of the context `iri` at once.
+    pub(crate) fn remove_all(&self, b: &mut Batch, iri: &str, keys: Vec<ObjectKey>) -> Result<()> {
+        self.ctx_index.remove_all(b, iri, keys)
+    }
+    /// Activities stored for the context `iri`.
+    #[cfg(test)]
+    pub(crate) fn count(&self, iri: &str) -> Result<u64> {
+        self.ctx_index.count(iri)
+    }
+    /// Replies, Likes and Announces of `actor`.
+    pub(crate) fn find_by_actor(&self, actor: &str) -> Result<Vec<ObjectKey>> {
+        let mut keys = vec![];
+        for key in self.actors_index.find_all(actor, None, None, None, None)? {
+            keys.push(ObjectKey::try_from(key.as_ref())?);
+        }
+        Ok(keys)
+    }
+    /// Stop counting the replies, Likes and Announces `keys` of `actor`,
+    /// each under the IRIs in `counted` it was counted under.
+    pub(crate) fn remove_interactions(
+        &self,
+        b: &mut Batch,
+        actor: &str,
+        keys: Vec<ObjectKey>,
+        counted: &BTreeMap<String, Vec<ObjectKey>>,
+    ) -> Result<()> {
+        for (iri, keys) in counted {
+            for index in [
+                &self.ctx_index,
+                &self.replies_index,
+                &self.likes_index,
+                &self.shares_index,
+            ] {
+                index.remove_all(b, iri, keys.iter().copied())?;
+            }
+        }
+        self.actors_index.remove_all(b, actor, keys)
+    }
+    /// Forget that `actor` sent `keys`, once they are no longer stored.
+    pub(crate) fn remove_actor(
+        &self,
+        b: &mut Batch,
+        actor: &str,
+        keys: Vec<ObjectKey>,
+    ) -> Result<()> {
+        self.actors_index.remove_all(b, actor, keys)
+    }
+    /// Replies to local objects are kept as long as the objects, like likes.
+    pub(super) fn replied_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.replies_index.obj_keys()
+    }
+    /// Replies counted for the local object `iri`.
+    #[cfg(test)]
+    pub(crate) fn count_replies(&self, iri: &str) -> Result<u64> {
+        self.replies_index.count(iri)
+    }
+    /// Likes are kept as long as the objects they count.
+    pub(super) fn liked_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.likes_index.obj_keys()
     }
-    pub(crate) fn insert_likes(&self, b: &mut Batch, iri: &str, obj_key: ObjectKey) {
-        self.likes_index.insert(b, IdObjIndexKey::new(iri, obj_key));
+    /// Announces are kept as long as the objects they count, like likes.
+    pub(super) fn shared_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.shares_index.obj_keys()
     }
-    pub(crate) fn remove_likes(&self, b: &mut Batch, iri: &str, obj_key: ObjectKey) {
-        self.likes_index.remove(b, IdObjIndexKey::new(iri, obj_key));
+    pub(crate) fn insert_likes(
+        &self,
+        b: &mut Batch,
+        iri: &str,
+        actor: Option<&str>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.insert_actor(b, actor, obj_key)?;
+        self.likes_index.insert(b, IdObjIndexKey::new(iri, obj_key))
     }
-    pub(crate) fn insert_shares(&self, b: &mut Batch, iri: &str, obj_key: ObjectKey) {
+    pub(crate) fn remove_likes(
+        &self,
+        b: &mut Batch,
+        iri: &str,
+        actor: Option<&str>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        if let Some(actor) = actor {
+            self.actors_index
+                .remove(b, IdObjIndexKey::new(actor, obj_key))?;
+        }
+        self.likes_index.remove(b, IdObjIndexKey::new(iri, obj_key))
+    }
+    pub(crate) fn insert_shares(
+        &self,
+        b: &mut Batch,
+        iri: &str,
+        actor: Option<&str>,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.insert_actor(b, actor, obj_key)?;
         self.shares_index
-            .insert(b, IdObjIndexKey::new(iri, obj_key));
+            .insert(b, IdObjIndexKey::new(iri, obj_key))
     }
-    pub(crate) fn count_likes(&self, iri: &str) -> u64 {
+    pub(crate) fn count_likes(&self, iri: &str) -> Result<u64> {
         self.likes_index.count(iri)
     }
-    pub(crate) fn count_shares(&self, iri: &str) -> u64 {
+    pub(crate) fn count_shares(&self, iri: &str) -> Result<u64> {
         self.shares_index.count(iri)
     }
+    /// Votes are kept as long as the polls they count.
+    pub(super) fn voted_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.votes_index.obj_keys()
+    }
+    /// Record that `voter` picked the option `name` of the poll `question`.
+    ///
+    /// Votes are recorded once for the voter and once for the option, IRIs
+    /// have no spaces to confuse the two.
+    pub(crate) fn insert_vote(
+        &self,
+        b: &mut Batch,
+        question: &str,
+        voter: &str,
+        name: &str,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.votes_index.insert(
+            b,
+            IdObjIndexKey::new(&format!("{question} {voter}"), obj_key),
+        )?;
+        self.votes_index.insert(
+            b,
+            IdObjIndexKey::new(&format!("{question} {voter} {name}"), obj_key),
+        )
+    }
+    /// Whether `voter` voted in the poll `question`, for the option `name`
+    /// if given.
+    pub(crate) fn has_voted(
+        &self,
+        question: &str,
+        voter: &str,
+        name: Option<&str>,
+    ) -> Result<bool> {
+        let id = match name {
+            Some(name) => format!("{question} {voter} {name}"),
+            None => format!("{question} {voter}"),
+        };
+        Ok(self.votes_index.count(&id)? > 0)
+    }
 }
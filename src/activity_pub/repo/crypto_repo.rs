@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
-use fjall::{Batch, Keyspace, PartitionCreateOptions, PartitionHandle};
+use fjall::{Keyspace, PartitionHandle};
 use minicbor::{Decode, Encode};
 use secrecy::{ExposeSecret, SecretSlice};
 
+use super::options::index_options;
+use super::Batch;
+
 #[derive(Clone)]
 pub(crate) struct CryptoRepo {
     key_pairs: PartitionHandle,
@@ -11,7 +14,7 @@ pub(crate) struct CryptoRepo {
 impl CryptoRepo {
     pub(crate) fn new(keyspace: Keyspace) -> Result<CryptoRepo> {
         let key_pairs = keyspace
-            .open_partition("key_pairs", PartitionCreateOptions::default())
+            .open_partition("key_pairs", index_options())
             .context("Failed top open crypto repo")?;
         Ok(CryptoRepo { key_pairs })
     }
@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
-use fjall::{Batch, Keyspace, PartitionCreateOptions, PartitionHandle, UserKey};
+use fjall::{Keyspace, PartitionHandle, UserKey};
 
-use super::ObjectKey;
+use super::options::index_options;
+use super::{Batch, ObjectKey};
 
 #[derive(Clone)]
 pub(crate) struct IriIndex {
@@ -11,13 +12,16 @@ pub(crate) struct IriIndex {
 impl IriIndex {
     pub(crate) fn new(keyspace: Keyspace) -> Result<IriIndex> {
         let index = keyspace
-            .open_partition("iri_index", PartitionCreateOptions::default())
+            .open_partition("iri_index", index_options())
             .context("Failed to open IRI index")?;
         Ok(IriIndex { index })
     }
     pub(crate) fn insert(&self, b: &mut Batch, iri: &str, obj_key: ObjectKey) {
         b.insert(&self.index, iri, obj_key);
     }
+    pub(crate) fn remove(&self, b: &mut Batch, iri: &str) {
+        b.remove(&self.index, iri);
+    }
     pub(crate) fn find_one(&self, iri: &str) -> Result<Option<UserKey>> {
         self.index.get(iri).context("Failed to read from index")
     }
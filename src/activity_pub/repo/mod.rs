@@ -1,17 +1,29 @@
+mod actor_cache;
 mod context_index;
 mod crypto_repo;
 mod iri_index;
+mod moderation_repo;
 mod object_repo;
+mod options;
 mod outbox_index;
+mod remote_actor_repo;
+mod retention;
+mod transaction;
 mod user_index;
 mod xindex;
 mod xkey;
 
+pub(crate) use actor_cache::ActorCache;
 pub(crate) use context_index::ContextIndex;
 pub(crate) use crypto_repo::{CryptoRepo, KeyMaterial};
 pub(crate) use iri_index::IriIndex;
+pub(crate) use moderation_repo::{BlockEntry, ModerationRepo};
 pub(crate) use object_repo::ObjectRepo;
+pub(crate) use options::{index_options, object_options};
 pub(crate) use outbox_index::OutboxIndex;
+pub(crate) use remote_actor_repo::{RemoteActorEntry, RemoteActorRepo};
+pub(crate) use retention::{Pruned, Retention};
+pub(crate) use transaction::{transaction, Batch};
 pub(crate) use user_index::UserIndex;
 pub(crate) use xkey::ObjectKey;
 
@@ -0,0 +1,148 @@
+//! Actors blocked by local users and reports sent by remote servers.
+
+use anyhow::{Context, Result};
+use fjall::{Keyspace, PartitionHandle};
+
+use crate::activity_pub::model::Object;
+
+use super::options::index_options;
+use super::{Batch, ObjectKey, ObjectRepo};
+
+#[derive(Clone)]
+pub(crate) struct ModerationRepo {
+    object_repo: ObjectRepo,
+    block_index: PartitionHandle,
+    report_index: PartitionHandle,
+}
+
+/// A local user's block, as listed to admins.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BlockEntry {
+    pub(crate) uid: String,
+    pub(crate) actor: String,
+    pub(crate) act_key: ObjectKey,
+}
+
+impl ModerationRepo {
+    pub(crate) fn new(keyspace: Keyspace) -> Result<ModerationRepo> {
+        let object_repo = ObjectRepo::new(keyspace.clone())?;
+        let block_index = keyspace
+            .open_partition("block_index", index_options())
+            .context("Failed to open block index")?;
+        let report_index = keyspace
+            .open_partition("report_index", index_options())
+            .context("Failed to open report index")?;
+        Ok(ModerationRepo {
+            object_repo,
+            block_index,
+            report_index,
+        })
+    }
+    pub(crate) fn insert_block(&self, b: &mut Batch, uid: &str, actor: &str, act_key: ObjectKey) {
+        b.insert(&self.block_index, block_key(uid, actor), act_key);
+    }
+    pub(crate) fn is_blocked(&self, uid: &str, actor: &str) -> Result<bool> {
+        self.block_index
+            .contains_key(block_key(uid, actor))
+            .context("Failed to read from block index")
+    }
+    pub(crate) fn find_blocks(&self) -> Result<Vec<BlockEntry>> {
+        let mut blocks = vec![];
+        for item in self.block_index.iter() {
+            let (key, value) = item?;
+            let mut parts = key.splitn(2, |&b| b == 0);
+            let (Some(uid), Some(actor)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            blocks.push(BlockEntry {
+                uid: String::from_utf8(uid.to_vec())?,
+                actor: String::from_utf8(actor.to_vec())?,
+                act_key: ObjectKey::try_from(value.as_ref())?,
+            });
+        }
+        Ok(blocks)
+    }
+    /// Queue a received Flag for admins, the activity itself is stored
+    /// under `obj_key`.
+    pub(crate) fn insert_report(&self, b: &mut Batch, obj_key: ObjectKey) {
+        b.insert(&self.report_index, obj_key, []);
+    }
+    /// Block activities and received reports.
+    pub(super) fn referenced_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        let blocks = self
+            .block_index
+            .values()
+            .map(|value| Ok(ObjectKey::try_from(value?.as_ref())?));
+        let reports = self
+            .report_index
+            .keys()
+            .map(|key| Ok(ObjectKey::try_from(key?.as_ref())?));
+        blocks.chain(reports)
+    }
+    /// Reports in the order they were received.
+    pub(crate) fn find_reports(&self) -> Result<Vec<(ObjectKey, Object<'static>)>> {
+        let mut reports = vec![];
+        for key in self.report_index.keys() {
+            let obj_key = ObjectKey::try_from(key?.as_ref())?;
+            if let Some(object) = self.object_repo.find_one(obj_key)? {
+                reports.push((obj_key, object));
+            }
+        }
+        Ok(reports)
+    }
+}
+
+fn block_key(uid: &str, actor: &str) -> Vec<u8> {
+    let mut key = vec![];
+    key.extend_from_slice(uid.as_bytes());
+    key.push(0);
+    key.extend_from_slice(actor.as_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::{Batch, BlockEntry, ModerationRepo, ObjectKey, ObjectRepo};
+
+    #[test]
+    fn block_and_report() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let repo = ModerationRepo::new(keyspace.clone())?;
+        let obj_repo = ObjectRepo::new(keyspace.clone())?;
+        let spammer = "https://spam.example/users/eve";
+
+        let act_key = ObjectKey::new();
+        let report_key = ObjectKey::new();
+        let mut b = Batch::new(&keyspace);
+        repo.insert_block(&mut b, "alice", spammer, act_key);
+        obj_repo.insert(
+            &mut b,
+            report_key,
+            json!({"type": "Flag", "object": spammer}),
+        )?;
+        repo.insert_report(&mut b, report_key);
+        b.commit()?;
+
+        assert!(repo.is_blocked("alice", spammer)?);
+        assert!(!repo.is_blocked("bob", spammer)?);
+        assert_eq!(
+            repo.find_blocks()?,
+            vec![BlockEntry {
+                uid: "alice".to_string(),
+                actor: spammer.to_string(),
+                act_key,
+            }]
+        );
+        let reports = repo.find_reports()?;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].0, report_key);
+        assert!(reports[0].1.type_is("Flag"));
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
+use std::ops::Bound;
+
 use anyhow::Result;
-use fjall::{Batch, Keyspace, KvSeparationOptions, PartitionCreateOptions, PartitionHandle};
+use fjall::{Keyspace, PartitionHandle, UserKey};
 use serde_json::Value;
 
 use crate::activity_pub::model::Object;
 use crate::activity_pub::object_serde;
 
-use super::ObjectKey;
+use super::options::object_options;
+use super::{Batch, ObjectKey};
 
 #[derive(Clone)]
 pub(crate) struct ObjectRepo {
@@ -14,10 +17,7 @@ pub(crate) struct ObjectRepo {
 
 impl ObjectRepo {
     pub(crate) fn new(keyspace: Keyspace) -> Result<ObjectRepo> {
-        let objects = keyspace.open_partition(
-            "objects",
-            PartitionCreateOptions::default().with_kv_separation(KvSeparationOptions::default()),
-        )?;
+        let objects = keyspace.open_partition("objects", object_options())?;
         Ok(ObjectRepo { objects })
     }
     pub(crate) fn insert(
@@ -30,6 +30,24 @@ impl ObjectRepo {
         b.insert(&self.objects, key, bytes);
         Ok(())
     }
+    pub(crate) fn remove(&self, b: &mut Batch, key: ObjectKey) {
+        b.remove(&self.objects, key);
+    }
+    /// Objects stored after the key `after`, if any, and before the key
+    /// `end`, oldest first.
+    pub(super) fn find_range(
+        &self,
+        after: Option<ObjectKey>,
+        end: ObjectKey,
+    ) -> impl Iterator<Item = Result<(ObjectKey, Object<'static>)>> + '_ {
+        let start = after.map_or(Bound::Unbounded, |key| Bound::Excluded(UserKey::from(key)));
+        let range = (start, Bound::Excluded(UserKey::from(end)));
+        self.objects.range(range).map(|item| {
+            let (key, bytes) = item?;
+            let object = object_serde::from_bytes(&bytes)?;
+            Ok((ObjectKey::try_from(key.as_ref())?, object))
+        })
+    }
     pub(crate) fn find_one(&self, key: impl AsRef<[u8]>) -> Result<Option<Object<'static>>> {
         if let Some(bytes) = self.objects.get(key)? {
             let object = object_serde::from_bytes(&bytes)?;
@@ -46,14 +64,14 @@ mod tests {
     use serde_json::json;
     use tempfile::tempdir;
 
-    use super::{Object, ObjectKey, ObjectRepo};
+    use super::{Batch, Object, ObjectKey, ObjectRepo};
 
     #[test]
     fn insert_then_find() -> Result<()> {
         let tmp_dir = tempdir()?;
         let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
         let repo = ObjectRepo::new(keyspace.clone())?;
-        let object = Object::try_from(json!({
+        let object = Object::from(json!({
             "@context": "https://www.w3.org/ns/activitystreams",
             "type": "Note",
             "content": "This is a note",
@@ -61,12 +79,38 @@ mod tests {
             "to": ["https://example.org/~john/"],
             "cc": ["https://example.com/~erik/followers",
                 "https://www.w3.org/ns/activitystreams#Public"]
-        }))?;
-        let mut b = keyspace.batch();
+        }));
+        let mut b = Batch::new(&keyspace);
         let obj_key = ObjectKey::new();
         repo.insert(&mut b, obj_key, object.clone())?;
         b.commit()?;
         assert_eq!(Some(object), repo.find_one(obj_key)?);
         Ok(())
     }
+    #[test]
+    fn blind_recipients_are_never_served() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let repo = ObjectRepo::new(keyspace.clone())?;
+        let object = Object::from(json!({
+            "type": "Create",
+            "to": "https://example.org/~john/",
+            "bcc": "https://example.org/~jane/",
+            "object": {
+                "type": "Note",
+                "bto": ["https://example.org/~jane/"],
+            }
+        }));
+        let mut b = Batch::new(&keyspace);
+        let obj_key = ObjectKey::new();
+        repo.insert(&mut b, obj_key, object)?;
+        b.commit()?;
+
+        let served = repo.find_one(obj_key)?.unwrap();
+        assert!(served.get_value("bcc").is_none());
+        assert!(served.get_value("to").is_some());
+        let note = served.get_node_object("object").unwrap();
+        assert!(note.get_value("bto").is_none());
+        Ok(())
+    }
 }
@@ -1,32 +1,50 @@
 use anyhow::{Context, Result};
-use fjall::{Batch, Keyspace, PartitionCreateOptions};
+use fjall::Keyspace;
+use serde_json::json;
 
+use crate::activity_pub::addressing::is_public;
 use crate::activity_pub::model::Object;
 
 use super::iri_index::IriIndex;
 use super::xindex::IdObjIndex;
-use super::{IdObjIndexKey, ObjectKey, ObjectRepo};
+use super::{Batch, IdObjIndexKey, ObjectKey, ObjectRepo};
 
 #[derive(Clone)]
 pub(crate) struct OutboxIndex {
     object_repo: ObjectRepo,
     iri_index: IriIndex,
     outbox_index: IdObjIndex,
+    /// Activities in the outbox addressed to the public, counted apart.
+    public_outbox_index: IdObjIndex,
+    /// Objects pinned by the user.
+    featured_index: IdObjIndex,
 }
 
 impl OutboxIndex {
     pub(crate) fn new(keyspace: Keyspace) -> Result<OutboxIndex> {
         let object_repo = ObjectRepo::new(keyspace.clone())?;
         let iri_index = IriIndex::new(keyspace.clone())?;
-        let outbox_index = IdObjIndex::new(
-            keyspace.open_partition("outbox_index", PartitionCreateOptions::default())?,
-        );
+        let outbox_index = IdObjIndex::open(&keyspace, "outbox_index")?;
+        let public_outbox_index = IdObjIndex::open_subset(
+            &keyspace,
+            "public_outbox_index",
+            Some(&outbox_index),
+            |key| {
+                let obj = object_repo.find_one(key.obj_key())?;
+                Ok(obj.is_none_or(|obj| is_public(&obj) || obj.type_is("Tombstone")))
+            },
+        )?;
+        let featured_index = IdObjIndex::open(&keyspace, "featured_index")?;
         Ok(OutboxIndex {
             object_repo,
             iri_index,
             outbox_index,
+            public_outbox_index,
+            featured_index,
         })
     }
+    /// Store a Create and its object, false if an object with the same IRI
+    /// is stored already.
     pub(crate) fn insert_create(
         &self,
         b: &mut Batch,
@@ -34,7 +52,7 @@ impl OutboxIndex {
         act_key: ObjectKey,
         obj_key: ObjectKey,
         act: Object,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let obj = act
             .get_node_object("object")
             .context("Create activity should have inner object")?;
@@ -42,12 +60,13 @@ impl OutboxIndex {
             .get_node_iri("object")
             .context("obj should have an IRI")?
             .to_string();
+        if self.iri_index.find_one(&obj_iri)?.is_some() {
+            return Ok(false);
+        }
         self.object_repo.insert(b, obj_key, obj)?;
-        self.object_repo.insert(b, act_key, act)?;
+        self.insert_activity(b, &uid, act_key, act)?;
         self.iri_index.insert(b, &obj_iri, obj_key);
-        self.outbox_index
-            .insert(b, IdObjIndexKey::new(&uid, act_key));
-        Ok(())
+        Ok(true)
     }
 
     pub(crate) fn insert_update(
@@ -71,18 +90,130 @@ impl OutboxIndex {
                 .as_ref(),
         )?;
         self.object_repo.insert(b, obj_key, obj)?;
+        self.insert_activity(b, &uid, act_key, act)
+    }
+    /// Store a Delete and replace the deleted object with the Tombstone it
+    /// carries, also where the Create and Update activities in the outbox
+    /// embed the object. A deleted object is no longer pinned.
+    pub(crate) fn insert_delete(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        act_key: ObjectKey,
+        obj_key: ObjectKey,
+        act: Object,
+    ) -> Result<()> {
+        let tombstone = act
+            .get_node_object("object")
+            .context("Delete activity should have inner object")?
+            .to_value();
+        let obj_iri = act
+            .get_node_iri("object")
+            .context("obj should have an IRI")?;
+        for key in self.outbox_index.find_all(uid, None, None, None, None)? {
+            let Some(activity) = self.object_repo.find_one(key.as_ref())? else {
+                continue;
+            };
+            let embeds = activity.get_node_object("object").is_some()
+                && activity.get_node_iri("object") == Some(obj_iri);
+            if embeds && (activity.type_is("Create") || activity.type_is("Update")) {
+                let mut activity = activity.to_value();
+                activity["object"] = tombstone.clone();
+                self.object_repo
+                    .insert(b, ObjectKey::try_from(key.as_ref())?, activity)?;
+            }
+        }
+        self.object_repo.insert(b, obj_key, tombstone)?;
+        self.featured_index
+            .remove(b, IdObjIndexKey::new(uid, obj_key))?;
+        self.insert_activity(b, uid, act_key, act)
+    }
+    /// Store an Announce in the outbox, the boosted object stays where it is.
+    pub(crate) fn insert_announce(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        act_key: ObjectKey,
+        act: Object,
+    ) -> Result<()> {
+        self.insert_activity(b, uid, act_key, act)
+    }
+    fn insert_activity(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        act_key: ObjectKey,
+        act: Object,
+    ) -> Result<()> {
+        if is_public(&act) {
+            self.public_outbox_index
+                .insert(b, IdObjIndexKey::new(uid, act_key))?;
+        }
         self.object_repo.insert(b, act_key, act)?;
         self.outbox_index
-            .insert(b, IdObjIndexKey::new(&uid, act_key));
-        Ok(())
+            .insert(b, IdObjIndexKey::new(uid, act_key))
     }
-    pub(crate) fn count(&self, uid: &str) -> u64 {
-        // FIXME optimize scanning
+    pub(crate) fn insert_featured(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.featured_index
+            .insert(b, IdObjIndexKey::new(uid, obj_key))
+    }
+    pub(crate) fn remove_featured(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        obj_key: ObjectKey,
+    ) -> Result<()> {
+        self.featured_index
+            .remove(b, IdObjIndexKey::new(uid, obj_key))
+    }
+    /// The object `iri` of an activity in the outbox, as stored now.
+    pub(crate) fn find_object(&self, iri: &str) -> Result<Option<Object<'_>>> {
+        let Some(key) = self.iri_index.find_one(iri)? else {
+            return Ok(None);
+        };
+        self.object_repo.find_one(key.as_ref())
+    }
+    /// Objects the user pinned, newest first.
+    pub(crate) fn find_featured(&self, uid: &str) -> Result<Vec<Object<'_>>> {
+        let keys = self.featured_index.find_all(uid, None, None, None, None)?;
+        let mut result = vec![];
+        for key in keys.iter().rev() {
+            if let Some(obj) = self.object_repo.find_one(key.as_ref())? {
+                result.push(obj);
+            }
+        }
+        Ok(result)
+    }
+    /// Activities in the outbox and pinned objects of any local user.
+    pub(super) fn referenced_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.outbox_index
+            .obj_keys()
+            .chain(self.featured_index.obj_keys())
+    }
+    /// Activities in the outbox of `uid`, only those addressed to the public
+    /// with `public_only`.
+    pub(crate) fn count(&self, uid: &str, public_only: bool) -> Result<u64> {
+        if public_only {
+            return self.public_outbox_index.count(uid);
+        }
         self.outbox_index.count(uid)
     }
     /// Based on GraphQL Cursor Connections Specification
     ///
     /// Ref: <https://relay.dev/graphql/connections.htm#sec-Pagination-algorithm>
+    ///
+    /// With `public_only`, activities not addressed to the public are left
+    /// out before `first` and `last` are applied, so pages stay full.
+    ///
+    /// Index entries are never removed, a deleted object is replaced with its
+    /// Tombstone where the activities embed it. An activity that is missing
+    /// all the same is listed as a Tombstone without id, so the items around
+    /// a cursor do not shift.
     pub(crate) fn find_all(
         &self,
         uid: &str,
@@ -90,16 +221,104 @@ impl OutboxIndex {
         after: Option<String>,
         first: Option<u64>,
         last: Option<u64>,
-    ) -> Result<Vec<(ObjectKey, Object)>> {
-        let keys = self
-            .outbox_index
-            .find_all(uid, before, after, first, last)?;
+        public_only: bool,
+    ) -> Result<Vec<(ObjectKey, Object<'_>)>> {
         let mut result = vec![];
-        for key in keys {
-            if let Some(obj) = self.object_repo.find_one(key.as_ref())? {
+        if !public_only {
+            let keys = self
+                .outbox_index
+                .find_all(uid, before, after, first, last)?;
+            for key in keys {
+                let obj = self.find_or_placeholder(key.as_ref())?;
                 result.push((ObjectKey::try_from(key.as_ref())?, obj));
             }
+            return Ok(result);
+        }
+        let keys = self.outbox_index.find_all(uid, before, after, None, None)?;
+        if let (None, Some(last)) = (first, last) {
+            for key in keys.iter().rev() {
+                if result.len() as u64 == last {
+                    break;
+                }
+                result.extend(self.find_public(key.as_ref())?);
+            }
+            result.reverse();
+        } else {
+            for key in &keys {
+                if first.is_some_and(|first| result.len() as u64 == first) {
+                    break;
+                }
+                result.extend(self.find_public(key.as_ref())?);
+            }
+            if let Some(last) = last {
+                result.drain(..result.len().saturating_sub(last as usize));
+            }
         }
         Ok(result)
     }
+    fn find_public(&self, key: &[u8]) -> Result<Option<(ObjectKey, Object<'_>)>> {
+        let obj = self.find_or_placeholder(key)?;
+        if !is_public(&obj) && !obj.type_is("Tombstone") {
+            return Ok(None);
+        }
+        Ok(Some((ObjectKey::try_from(key)?, obj)))
+    }
+    fn find_or_placeholder(&self, key: &[u8]) -> Result<Object<'_>> {
+        let obj = self.object_repo.find_one(key)?;
+        Ok(obj.unwrap_or_else(|| Object::from(json!({"type": "Tombstone"}))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use crate::activity_pub::model::Object;
+
+    use super::{Batch, ObjectKey, ObjectRepo, OutboxIndex};
+
+    #[test]
+    fn missing_activity_keeps_its_place() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let index = OutboxIndex::new(keyspace.clone())?;
+        let mut act_keys = vec![];
+        let mut b = Batch::new(&keyspace);
+        for n in 0..3 {
+            let act_key = ObjectKey::new();
+            let act = Object::from(json!({
+                "type": "Create",
+                "to": ["https://www.w3.org/ns/activitystreams#Public"],
+                "object": {"id": format!("https://example.com/notes/{n}"), "type": "Note"},
+            }));
+            assert!(index.insert_create(
+                &mut b,
+                "alice".to_string(),
+                act_key,
+                ObjectKey::new(),
+                act
+            )?);
+            act_keys.push(act_key);
+        }
+        b.commit()?;
+        let mut b = Batch::new(&keyspace);
+        ObjectRepo::new(keyspace.clone())?.remove(&mut b, act_keys[1]);
+        b.commit()?;
+
+        for public_only in [false, true] {
+            let items = index.find_all("alice", None, None, Some(3), None, public_only)?;
+            let keys: Vec<_> = items.iter().map(|(key, _)| *key).collect();
+            assert_eq!(keys, act_keys);
+            assert!(items[1].1.type_is("Tombstone"));
+            assert_eq!(index.count("alice", public_only)?, 3);
+            // The page after the placeholder starts where it did before.
+            let after = Some(act_keys[1].to_string());
+            let items = index.find_all("alice", None, after, Some(1), None, public_only)?;
+            assert_eq!(items[0].0, act_keys[2]);
+        }
+        Ok(())
+    }
 }
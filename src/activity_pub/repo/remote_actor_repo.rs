@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use fjall::{Keyspace, PartitionHandle};
+use minicbor::{Decode, Encode};
+
+use crate::activity_pub::model::Object;
+
+use super::options::index_options;
+
+/// Node local cache of remote actor documents keyed by actor IRI.
+///
+/// This partition is not replicated. Each node fetches and expires remote
+/// actors independently, so entries are written outside of the Raft log.
+#[derive(Clone)]
+pub(crate) struct RemoteActorRepo {
+    remote_actors: PartitionHandle,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub(crate) struct RemoteActorEntry {
+    /// Unix timestamp in seconds after which the entry is stale.
+    #[n(0)]
+    pub(crate) expires_at: u64,
+    /// The actor document, or `None` if the actor is known to be gone.
+    #[n(1)]
+    pub(crate) object: Option<Object<'static>>,
+}
+
+impl RemoteActorRepo {
+    pub(crate) fn new(keyspace: Keyspace) -> Result<RemoteActorRepo> {
+        let remote_actors = keyspace
+            .open_partition("remote_actors", index_options())
+            .context("Failed to open remote actor repo")?;
+        Ok(RemoteActorRepo { remote_actors })
+    }
+    /// The same cache for remote objects other than actors, kept in a
+    /// partition of its own.
+    pub(crate) fn objects(keyspace: Keyspace) -> Result<RemoteActorRepo> {
+        let remote_actors = keyspace
+            .open_partition("remote_objects", index_options())
+            .context("Failed to open remote object repo")?;
+        Ok(RemoteActorRepo { remote_actors })
+    }
+    pub(crate) fn insert(&self, iri: &str, entry: &RemoteActorEntry) -> Result<()> {
+        let bytes = minicbor::to_vec(entry).context("Failed to encode RemoteActorEntry")?;
+        self.remote_actors.insert(iri, bytes)?;
+        Ok(())
+    }
+    /// Find an entry that is still fresh at `now`.
+    pub(crate) fn find_fresh(&self, iri: &str, now: u64) -> Result<Option<RemoteActorEntry>> {
+        if let Some(bytes) = self.remote_actors.get(iri)? {
+            let entry: RemoteActorEntry =
+                minicbor::decode(&bytes).context("Failed to decode RemoteActorEntry")?;
+            if entry.expires_at > now {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+    pub(crate) fn remove(&self, iri: &str) -> Result<()> {
+        self.remote_actors.remove(iri)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use crate::activity_pub::model::Object;
+
+    use super::{RemoteActorEntry, RemoteActorRepo};
+
+    #[test]
+    fn fresh_and_expired_entries() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let repo = RemoteActorRepo::new(keyspace)?;
+        let iri = "https://remote.example/users/bob";
+        let actor = Object::from(json!({"id": iri, "type": "Person"}));
+
+        repo.insert(
+            iri,
+            &RemoteActorEntry {
+                expires_at: 100,
+                object: Some(actor.clone()),
+            },
+        )?;
+        let entry = repo.find_fresh(iri, 99)?.expect("entry should be fresh");
+        assert_eq!(entry.object, Some(actor));
+        assert!(repo.find_fresh(iri, 100)?.is_none());
+
+        // Negative entries are fresh too, they just carry no document.
+        repo.insert(
+            iri,
+            &RemoteActorEntry {
+                expires_at: 200,
+                object: None,
+            },
+        )?;
+        assert!(repo.find_fresh(iri, 150)?.unwrap().object.is_none());
+
+        repo.remove(iri)?;
+        assert!(repo.find_fresh(iri, 150)?.is_none());
+        Ok(())
+    }
+}
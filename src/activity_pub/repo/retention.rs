@@ -0,0 +1,362 @@
+//! Pruning of objects received from other servers.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use fjall::Keyspace;
+
+use super::{
+    transaction, ContextIndex, IriIndex, ModerationRepo, ObjectKey, ObjectRepo, OutboxIndex,
+    UserIndex,
+};
+
+/// Objects looked at per pass, a pass deletes in one write batch.
+const CHUNK_SIZE: usize = 1000;
+
+/// What one pass of [`Retention::prune_remote`] did.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Pruned {
+    pub(crate) pruned: u64,
+    /// The last object looked at, the next pass resumes after it. `None`
+    /// once every object stored before the cutoff was looked at.
+    pub(crate) resume: Option<ObjectKey>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Retention {
+    keyspace: Keyspace,
+    object_repo: ObjectRepo,
+    iri_index: IriIndex,
+    ctx_index: ContextIndex,
+    outbox_index: OutboxIndex,
+    user_index: UserIndex,
+    moderation: ModerationRepo,
+}
+
+impl Retention {
+    pub(crate) fn new(keyspace: Keyspace) -> Result<Retention> {
+        Ok(Retention {
+            object_repo: ObjectRepo::new(keyspace.clone())?,
+            iri_index: IriIndex::new(keyspace.clone())?,
+            ctx_index: ContextIndex::new(keyspace.clone())?,
+            outbox_index: OutboxIndex::new(keyspace.clone())?,
+            user_index: UserIndex::new(keyspace.clone())?,
+            moderation: ModerationRepo::new(keyspace.clone())?,
+            keyspace,
+        })
+    }
+    /// Delete remote objects stored before `cutoff`, in unix seconds.
+    ///
+    /// A pass looks at a bounded number of objects stored after `after`,
+    /// oldest first, and tells where the next pass resumes. Objects in a
+    /// local collection (outboxes, followers, replies, likes, shares,
+    /// votes, blocks and reports) are kept whatever their age, as are
+    /// objects without an id. Only the object key tells the age, so the
+    /// outcome depends on the stored data and the arguments alone and is
+    /// the same on every server.
+    pub(crate) fn prune_remote(
+        &self,
+        cutoff: u64,
+        after: Option<ObjectKey>,
+        is_local: impl Fn(&str) -> bool,
+    ) -> Result<Pruned> {
+        self.prune_chunk(cutoff, after, CHUNK_SIZE, is_local)
+    }
+    fn prune_chunk(
+        &self,
+        cutoff: u64,
+        after: Option<ObjectKey>,
+        chunk_size: usize,
+        is_local: impl Fn(&str) -> bool,
+    ) -> Result<Pruned> {
+        let mut scanned = 0;
+        let mut last = None;
+        let mut expired = HashMap::new();
+        let objects = self
+            .object_repo
+            .find_range(after, ObjectKey::min_at(cutoff));
+        for item in objects.take(chunk_size) {
+            let (obj_key, object) = item?;
+            scanned += 1;
+            last = Some(obj_key);
+            let Some(iri) = object.id().filter(|iri| !is_local(iri)) else {
+                continue;
+            };
+            let context = object.get_str("context").map(str::to_string);
+            let actor = object.get_node_iri("actor").map(str::to_string);
+            expired.insert(obj_key, (iri.to_string(), context, actor));
+        }
+        let resume = if scanned == chunk_size { last } else { None };
+
+        // Only the keys of this chunk are held, however large the
+        // collections are.
+        for key in self.referenced_keys() {
+            if expired.is_empty() {
+                break;
+            }
+            expired.remove(&key?);
+        }
+
+        if expired.is_empty() {
+            return Ok(Pruned { pruned: 0, resume });
+        }
+        transaction(&self.keyspace, |b| {
+            let mut contexts: HashMap<&str, Vec<ObjectKey>> = HashMap::new();
+            let mut actors: HashMap<&str, Vec<ObjectKey>> = HashMap::new();
+            for (obj_key, (iri, context, actor)) in &expired {
+                // The IRI may have been taken over by a newer copy.
+                if self
+                    .iri_index
+                    .find_one(iri)?
+                    .is_some_and(|key| key.as_ref() == obj_key.as_ref())
+                {
+                    self.iri_index.remove(b, iri);
+                }
+                if let Some(context) = context {
+                    contexts.entry(context).or_default().push(*obj_key);
+                }
+                if let Some(actor) = actor {
+                    actors.entry(actor).or_default().push(*obj_key);
+                }
+                self.object_repo.remove(b, *obj_key);
+            }
+            for (context, keys) in contexts {
+                self.ctx_index.remove_all(b, context, keys)?;
+            }
+            for (actor, keys) in actors {
+                self.ctx_index.remove_actor(b, actor, keys)?;
+            }
+            Ok(())
+        })?;
+        Ok(Pruned {
+            pruned: expired.len() as u64,
+            resume,
+        })
+    }
+    /// Objects some local collection holds.
+    fn referenced_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.outbox_index
+            .referenced_keys()
+            .chain(self.user_index.referenced_keys())
+            .chain(self.ctx_index.replied_keys())
+            .chain(self.ctx_index.liked_keys())
+            .chain(self.ctx_index.shared_keys())
+            .chain(self.ctx_index.voted_keys())
+            .chain(self.moderation.referenced_keys())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use super::{ObjectKey, Pruned, Retention};
+    use crate::activity_pub::model::Object;
+    use crate::activity_pub::repo::{
+        transaction, Batch, ContextIndex, IriIndex, ModerationRepo, ObjectRepo, OutboxIndex,
+        UserIndex,
+    };
+
+    fn after_now() -> Result<u64> {
+        Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + 1)
+    }
+
+    #[test]
+    fn prune_unreferenced_remote_objects() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let obj_repo = ObjectRepo::new(keyspace.clone())?;
+        let iri_index = IriIndex::new(keyspace.clone())?;
+        let ctx_index = ContextIndex::new(keyspace.clone())?;
+        let user_index = UserIndex::new(keyspace.clone())?;
+        let retention = Retention::new(keyspace.clone())?;
+        let is_local = |iri: &str| iri.starts_with("https://example.com/");
+
+        let reply = ObjectKey::new();
+        let follow = ObjectKey::new();
+        let local = ObjectKey::new();
+        let anonymous = ObjectKey::new();
+        let announce = ObjectKey::new();
+        let vote = ObjectKey::new();
+        let context = "https://example.com/as/objects/1";
+        transaction(&keyspace, |b| {
+            let reply_iri = "https://remote.example/notes/1";
+            obj_repo.insert(b, reply, json!({"id": reply_iri, "context": context}))?;
+            iri_index.insert(b, reply_iri, reply);
+            ctx_index.insert(b, context, None, reply)?;
+            let follow_iri = "https://remote.example/follows/1";
+            obj_repo.insert(b, follow, json!({"id": follow_iri, "type": "Follow"}))?;
+            user_index.insert_follower(b, "alice", follow)?;
+            obj_repo.insert(b, local, json!({"id": "https://example.com/as/objects/2"}))?;
+            obj_repo.insert(b, anonymous, json!({"type": "Note"}))?;
+            let announce_iri = "https://remote.example/announces/1";
+            let announce_obj = json!({"id": announce_iri, "type": "Announce", "object": context});
+            obj_repo.insert(b, announce, announce_obj)?;
+            iri_index.insert(b, announce_iri, announce);
+            ctx_index.insert_shares(b, context, None, announce)?;
+            let voter = "https://remote.example/users/bob";
+            let vote_iri = "https://remote.example/votes/1";
+            let vote_obj =
+                json!({"id": vote_iri, "type": "Note", "name": "yes", "inReplyTo": context});
+            obj_repo.insert(b, vote, vote_obj)?;
+            iri_index.insert(b, vote_iri, vote);
+            ctx_index.insert_vote(b, context, voter, "yes", vote)?;
+            Ok(())
+        })?;
+
+        // Nothing was stored before the cutoff yet.
+        let done = Pruned {
+            pruned: 0,
+            resume: None,
+        };
+        assert_eq!(retention.prune_remote(0, None, is_local)?, done);
+
+        let cutoff = after_now()?;
+        assert_eq!(retention.prune_remote(cutoff, None, is_local)?.pruned, 1);
+        assert!(obj_repo.find_one(reply)?.is_none());
+        assert!(iri_index
+            .find_one("https://remote.example/notes/1")?
+            .is_none());
+        for kept in [follow, local, anonymous, announce, vote] {
+            assert!(obj_repo.find_one(kept)?.is_some());
+        }
+        assert_eq!(ctx_index.count_shares(context)?, 1);
+        assert_eq!(retention.prune_remote(cutoff, None, is_local)?, done);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_objects_of_every_collection() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let obj_repo = ObjectRepo::new(keyspace.clone())?;
+        let outbox_index = OutboxIndex::new(keyspace.clone())?;
+        let user_index = UserIndex::new(keyspace.clone())?;
+        let ctx_index = ContextIndex::new(keyspace.clone())?;
+        let moderation = ModerationRepo::new(keyspace.clone())?;
+        let retention = Retention::new(keyspace.clone())?;
+
+        let iri = "https://example.com/notes/1";
+        let actor = "https://remote.example/users/bob";
+        type Collect<'a> = Box<dyn Fn(&mut Batch, ObjectKey) -> Result<()> + 'a>;
+        let collections: Vec<(&str, Collect)> = vec![
+            (
+                "outbox",
+                Box::new(|b, key| {
+                    let act = Object::from(json!({"id": "https://remote.example/outbox"}));
+                    outbox_index.insert_announce(b, "alice", key, act)
+                }),
+            ),
+            (
+                "featured",
+                Box::new(|b, key| outbox_index.insert_featured(b, "alice", key)),
+            ),
+            (
+                "followers",
+                Box::new(|b, key| user_index.insert_follower(b, "alice", key)),
+            ),
+            (
+                "follow requests",
+                Box::new(|b, key| user_index.insert_follow_request(b, "alice", key)),
+            ),
+            (
+                "following",
+                Box::new(|b, key| user_index.insert_following(b, "alice", key)),
+            ),
+            (
+                "replies",
+                Box::new(|b, key| ctx_index.insert_replies(b, iri, Some(actor), key)),
+            ),
+            (
+                "likes",
+                Box::new(|b, key| ctx_index.insert_likes(b, iri, Some(actor), key)),
+            ),
+            (
+                "shares",
+                Box::new(|b, key| ctx_index.insert_shares(b, iri, Some(actor), key)),
+            ),
+            (
+                "votes",
+                Box::new(|b, key| ctx_index.insert_vote(b, iri, actor, "yes", key)),
+            ),
+            (
+                "blocks",
+                Box::new(|b, key| {
+                    moderation.insert_block(b, "alice", actor, key);
+                    Ok(())
+                }),
+            ),
+            (
+                "reports",
+                Box::new(|b, key| {
+                    moderation.insert_report(b, key);
+                    Ok(())
+                }),
+            ),
+        ];
+
+        let mut kept = vec![];
+        for (n, (name, collect)) in collections.iter().enumerate() {
+            let key = ObjectKey::new();
+            let object = json!({"id": format!("https://remote.example/objects/{n}")});
+            transaction(&keyspace, |b| {
+                obj_repo.insert(b, key, object)?;
+                collect(b, key)
+            })?;
+            kept.push((name, key));
+        }
+        let expired = ObjectKey::new();
+        let object = json!({"id": "https://remote.example/objects/expired"});
+        transaction(&keyspace, |b| obj_repo.insert(b, expired, object))?;
+
+        let pruned = retention.prune_remote(after_now()?, None, |_| false)?;
+        for (name, key) in kept {
+            assert!(obj_repo.find_one(key)?.is_some(), "pruned from {name}");
+        }
+        assert!(obj_repo.find_one(expired)?.is_none());
+        assert_eq!(pruned.pruned, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn prune_in_resumable_chunks() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let obj_repo = ObjectRepo::new(keyspace.clone())?;
+        let retention = Retention::new(keyspace.clone())?;
+        let keys: Vec<ObjectKey> = (0..3).map(|_| ObjectKey::new()).collect();
+        transaction(&keyspace, |b| {
+            for (n, key) in keys.iter().enumerate() {
+                let object = json!({"id": format!("https://remote.example/notes/{n}")});
+                obj_repo.insert(b, *key, object)?;
+            }
+            Ok(())
+        })?;
+
+        let cutoff = after_now()?;
+        let first = retention.prune_chunk(cutoff, None, 2, |_| false)?;
+        assert_eq!(
+            first,
+            Pruned {
+                pruned: 2,
+                resume: Some(keys[1]),
+            }
+        );
+        assert!(obj_repo.find_one(keys[2])?.is_some());
+        let second = retention.prune_chunk(cutoff, first.resume, 2, |_| false)?;
+        assert_eq!(
+            second,
+            Pruned {
+                pruned: 1,
+                resume: None,
+            }
+        );
+        assert!(obj_repo.find_one(keys[2])?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use anyhow::Result;
+use fjall::{Keyspace, PartitionHandle, PersistMode, UserKey};
+
+/// A write batch that also remembers the index entries and counts staged in
+/// it, as a fjall batch cannot read its own writes.
+///
+/// [`IdObjIndex`](super::xindex::IdObjIndex) reads them back, so several
+/// changes to the entries of one id in a batch add up. Counts are written
+/// once, when the batch is committed.
+pub(crate) struct Batch {
+    batch: fjall::Batch,
+    entries: HashMap<(String, UserKey), bool>,
+    counts: HashMap<(String, String), (PartitionHandle, u64)>,
+}
+
+impl Batch {
+    pub(crate) fn new(keyspace: &Keyspace) -> Batch {
+        Batch {
+            batch: keyspace.batch(),
+            entries: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+    pub(crate) fn durability(self, mode: Option<PersistMode>) -> Batch {
+        Batch {
+            batch: self.batch.durability(mode),
+            ..self
+        }
+    }
+    /// Whether this batch adds or removes `key` of `partition`, if it does.
+    pub(super) fn staged_entry(&self, partition: &PartitionHandle, key: &UserKey) -> Option<bool> {
+        let key = (partition.name.to_string(), key.clone());
+        self.entries.get(&key).copied()
+    }
+    pub(super) fn insert_entry(&mut self, partition: &PartitionHandle, key: UserKey) {
+        self.batch.insert(partition, key.clone(), []);
+        self.entries.insert((partition.name.to_string(), key), true);
+    }
+    pub(super) fn remove_entry(&mut self, partition: &PartitionHandle, key: UserKey) {
+        self.batch.remove(partition, key.clone());
+        self.entries
+            .insert((partition.name.to_string(), key), false);
+    }
+    /// The count of `id` in `partition` as this batch leaves it, if it
+    /// changes it.
+    pub(super) fn staged_count(&self, partition: &PartitionHandle, id: &str) -> Option<u64> {
+        let key = (partition.name.to_string(), id.to_string());
+        self.counts.get(&key).map(|(_, count)| *count)
+    }
+    pub(super) fn set_count(&mut self, partition: &PartitionHandle, id: &str, count: u64) {
+        let key = (partition.name.to_string(), id.to_string());
+        self.counts.insert(key, (partition.clone(), count));
+    }
+    pub(crate) fn commit(mut self) -> Result<()> {
+        for ((_, id), (partition, count)) in self.counts.drain() {
+            self.batch.insert(&partition, id, count.to_be_bytes());
+        }
+        self.batch.commit()?;
+        Ok(())
+    }
+}
+
+impl Deref for Batch {
+    type Target = fjall::Batch;
+
+    fn deref(&self) -> &fjall::Batch {
+        &self.batch
+    }
+}
+
+impl DerefMut for Batch {
+    fn deref_mut(&mut self) -> &mut fjall::Batch {
+        &mut self.batch
+    }
+}
+
+/// Run `f` against a fresh write batch and commit it durably if `f` succeeds.
+///
+/// Repo and index methods take `&mut Batch`, so everything `f` stages
+/// through them lands atomically. If `f` returns an error the batch is
+/// dropped and nothing is written, a crash can never leave objects and
+/// their indexes half applied.
+pub(crate) fn transaction<T>(
+    keyspace: &Keyspace,
+    f: impl FnOnce(&mut Batch) -> Result<T>,
+) -> Result<T> {
+    let mut b = Batch::new(keyspace).durability(Some(PersistMode::SyncAll));
+    let value = f(&mut b)?;
+    b.commit()?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{bail, Result};
+    use fjall::{Config, Keyspace};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    use crate::activity_pub::repo::{IriIndex, ObjectKey, ObjectRepo};
+
+    use super::transaction;
+
+    #[test]
+    fn commit_all_or_nothing() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let obj_repo = ObjectRepo::new(keyspace.clone())?;
+        let iri_index = IriIndex::new(keyspace.clone())?;
+        let iri = "https://example.com/notes/1";
+
+        let failed_key = ObjectKey::new();
+        let result: Result<()> = transaction(&keyspace, |b| {
+            obj_repo.insert(b, failed_key, json!({"id": iri}))?;
+            iri_index.insert(b, iri, failed_key);
+            bail!("rejected");
+        });
+        assert!(result.is_err());
+        assert!(obj_repo.find_one(failed_key)?.is_none());
+        assert!(iri_index.find_one(iri)?.is_none());
+
+        let obj_key = ObjectKey::new();
+        transaction(&keyspace, |b| {
+            obj_repo.insert(b, obj_key, json!({"id": iri}))?;
+            iri_index.insert(b, iri, obj_key);
+            Ok(())
+        })?;
+        assert!(obj_repo.find_one(obj_key)?.is_some());
+        assert!(iri_index.find_one(iri)?.is_some());
+        Ok(())
+    }
+}
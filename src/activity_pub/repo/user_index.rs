@@ -1,32 +1,55 @@
+use std::ops::Bound;
+
 use anyhow::Result;
-use fjall::{Batch, Keyspace, PartitionCreateOptions, PartitionHandle};
+use fjall::{Keyspace, PartitionHandle, UserKey};
 
+use crate::activity_pub::addressing::Visibility;
 use crate::activity_pub::model::{Actor, Object};
 
+use super::options::index_options;
 use super::xindex::IdObjIndex;
-use super::{IdObjIndexKey, ObjectKey, ObjectRepo};
+use super::{ActorCache, Batch, IdObjIndexKey, ObjectKey, ObjectRepo};
 
 #[derive(Clone)]
 pub(crate) struct UserIndex {
     object_repo: ObjectRepo,
     user_index: PartitionHandle,
+    disabled_index: PartitionHandle,
+    /// Audience of posts naming none, for users who picked one.
+    visibility_index: PartitionHandle,
     follower_index: IdObjIndex,
+    /// Follows waiting for the user to approve them.
+    follow_request_index: IdObjIndex,
+    /// Follows the user sent.
+    following_index: IdObjIndex,
+    cache: Option<ActorCache>,
 }
 
 impl UserIndex {
     pub(crate) fn new(keyspace: Keyspace) -> Result<UserIndex> {
         let object_repo = ObjectRepo::new(keyspace.clone())?;
-        let user_index =
-            keyspace.open_partition("user_index", PartitionCreateOptions::default())?;
-        let follower_index = IdObjIndex::new(
-            keyspace.open_partition("follower_index", PartitionCreateOptions::default())?,
-        );
+        let user_index = keyspace.open_partition("user_index", index_options())?;
+        let disabled_index = keyspace.open_partition("disabled_users", index_options())?;
+        let visibility_index = keyspace.open_partition("user_visibility", index_options())?;
+        let follower_index = IdObjIndex::open(&keyspace, "follower_index")?;
+        let follow_request_index = IdObjIndex::open(&keyspace, "follow_request_index")?;
+        let following_index = IdObjIndex::open(&keyspace, "following_index")?;
         Ok(UserIndex {
             object_repo,
             user_index,
+            disabled_index,
+            visibility_index,
             follower_index,
+            follow_request_index,
+            following_index,
+            cache: None,
         })
     }
+    /// Serve actor lookups through the shared in-memory cache.
+    pub(crate) fn with_cache(mut self, cache: ActorCache) -> UserIndex {
+        self.cache = Some(cache);
+        self
+    }
     pub(crate) fn insert(&self, b: &mut Batch, uid: &str, user: Actor) -> Result<()> {
         // FIXME
         let obj_key = ObjectKey::new();
@@ -34,19 +57,167 @@ impl UserIndex {
         b.insert(&self.user_index, uid, obj_key);
         Ok(())
     }
-    pub(crate) fn insert_follower(&self, b: &mut Batch, uid: &str, key: ObjectKey) {
+    /// Disabled users keep their data but are neither served nor accept
+    /// activities.
+    pub(crate) fn set_disabled(&self, b: &mut Batch, uid: &str, disabled: bool) {
+        if disabled {
+            b.insert(&self.disabled_index, uid, []);
+        } else {
+            b.remove(&self.disabled_index, uid);
+        }
+    }
+    pub(crate) fn is_disabled(&self, uid: &str) -> Result<bool> {
+        Ok(self.disabled_index.contains_key(uid)?)
+    }
+    pub(crate) fn set_visibility(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        visibility: Visibility,
+    ) -> Result<()> {
+        b.insert(&self.visibility_index, uid, minicbor::to_vec(visibility)?);
+        Ok(())
+    }
+    /// The audience the user picked for posts naming none, if any.
+    pub(crate) fn visibility(&self, uid: &str) -> Result<Option<Visibility>> {
+        let Some(value) = self.visibility_index.get(uid)? else {
+            return Ok(None);
+        };
+        Ok(Some(minicbor::decode(&value)?))
+    }
+    /// Up to `first` local users, in uid order, after the uid `after`.
+    pub(crate) fn list_users(&self, after: Option<&str>, first: u64) -> Result<Vec<String>> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        let mut uids = vec![];
+        for key in self
+            .user_index
+            .range::<&str, _>((start, Bound::Unbounded))
+            .take(first as usize)
+        {
+            let (uid, _) = key?;
+            uids.push(String::from_utf8(uid.to_vec())?);
+        }
+        Ok(uids)
+    }
+    pub(crate) fn insert_follower(&self, b: &mut Batch, uid: &str, key: ObjectKey) -> Result<()> {
         self.follower_index.insert(b, IdObjIndexKey::new(uid, key))
     }
-    pub(crate) fn remove_follower(&self, b: &mut Batch, uid: &str, key: ObjectKey) {
+    pub(crate) fn remove_follower(&self, b: &mut Batch, uid: &str, key: ObjectKey) -> Result<()> {
         self.follower_index.remove(b, IdObjIndexKey::new(uid, key))
     }
-    pub(crate) fn find_one(&self, uid: &str) -> Result<Option<Object>> {
+    /// Remove several followers of `uid` at once.
+    pub(crate) fn remove_followers(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        keys: Vec<ObjectKey>,
+    ) -> Result<()> {
+        self.follower_index.remove_all(b, uid, keys)
+    }
+    pub(crate) fn insert_follow_request(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        key: ObjectKey,
+    ) -> Result<()> {
+        self.follow_request_index
+            .insert(b, IdObjIndexKey::new(uid, key))
+    }
+    pub(crate) fn remove_follow_request(
+        &self,
+        b: &mut Batch,
+        uid: &str,
+        key: ObjectKey,
+    ) -> Result<()> {
+        self.follow_request_index
+            .remove(b, IdObjIndexKey::new(uid, key))
+    }
+    pub(crate) fn has_follow_request(&self, uid: &str, key: ObjectKey) -> Result<bool> {
+        self.follow_request_index
+            .contains(IdObjIndexKey::new(uid, key))
+    }
+    pub(crate) fn insert_following(&self, b: &mut Batch, uid: &str, key: ObjectKey) -> Result<()> {
+        self.following_index.insert(b, IdObjIndexKey::new(uid, key))
+    }
+    pub(crate) fn remove_following(&self, b: &mut Batch, uid: &str, key: ObjectKey) -> Result<()> {
+        self.following_index.remove(b, IdObjIndexKey::new(uid, key))
+    }
+    pub(crate) fn is_following(&self, uid: &str, key: ObjectKey) -> Result<bool> {
+        self.following_index.contains(IdObjIndexKey::new(uid, key))
+    }
+    /// Whether the user sent a Follow of `actor` that still stands.
+    pub(crate) fn follows(&self, uid: &str, actor: &str) -> Result<bool> {
+        for key in self.following_index.find_all(uid, None, None, None, None)? {
+            if self.followed_actor(key.as_ref())?.as_deref() == Some(actor) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    /// Local users following `actor`.
+    ///
+    /// Local users only follow the few accounts they moved with, so the
+    /// Follows they sent are read rather than kept in a reverse index.
+    pub(crate) fn local_followers_of(&self, actor: &str) -> Result<Vec<String>> {
+        let mut uids = vec![];
+        for entry in self.following_index.entries()? {
+            if uids.last().is_some_and(|uid| uid == entry.id()) {
+                continue;
+            }
+            if self.followed_actor(entry.obj_key().as_ref())?.as_deref() == Some(actor) {
+                uids.push(entry.id().to_string());
+            }
+        }
+        Ok(uids)
+    }
+    fn followed_actor(&self, follow_key: &[u8]) -> Result<Option<String>> {
+        let follow = self.object_repo.find_one(follow_key)?;
+        Ok(follow.and_then(|follow| follow.get_node_iri("object").map(str::to_string)))
+    }
+    /// Whether the user reviews Follows before accepting them, as announced
+    /// with `manuallyApprovesFollowers`.
+    pub(crate) fn approves_followers_manually(&self, uid: &str) -> Result<bool> {
+        let approves = self
+            .find_one(uid)?
+            .and_then(|user| user.get_value("manuallyApprovesFollowers"))
+            .and_then(|value| value.as_bool());
+        Ok(approves.unwrap_or(false))
+    }
+    pub(crate) fn find_one(&self, uid: &str) -> Result<Option<Object<'static>>> {
+        if let Some(object) = self.cache.as_ref().and_then(|cache| cache.get(uid)) {
+            return Ok(Some(object));
+        }
         if let Some(key) = self.user_index.get(uid)? {
-            return self.object_repo.find_one(key);
+            let object = self.object_repo.find_one(key)?;
+            if let (Some(cache), Some(object)) = (&self.cache, &object) {
+                cache.insert(uid, object.clone());
+            }
+            return Ok(object);
         }
         Ok(None)
     }
-    pub(crate) fn count_followers(&self, uid: &str) -> u64 {
+    /// Actors of local users and the Follow activities from and to them.
+    pub(super) fn referenced_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        let users = self
+            .user_index
+            .values()
+            .map(|value| Ok(ObjectKey::try_from(value?.as_ref())?));
+        self.follower_index
+            .obj_keys()
+            .chain(self.follow_request_index.obj_keys())
+            .chain(self.following_index.obj_keys())
+            .chain(users)
+    }
+    /// Local users that are not disabled.
+    pub(crate) fn count_users(&self) -> Result<u64> {
+        let users = self.user_index.len()?;
+        let disabled = self.disabled_index.len()?;
+        Ok(users.saturating_sub(disabled) as u64)
+    }
+    pub(crate) fn count_followers(&self, uid: &str) -> Result<u64> {
         self.follower_index.count(uid)
     }
     pub(crate) fn find_followers(
@@ -60,6 +231,16 @@ impl UserIndex {
         let keys = self
             .follower_index
             .find_all(uid, before, after, first, last)?;
+        self.follow_actors(keys)
+    }
+    /// Pending Follows of the user, oldest first, with the requesting actor.
+    pub(crate) fn find_follow_requests(&self, uid: &str) -> Result<Vec<(ObjectKey, String)>> {
+        let keys = self
+            .follow_request_index
+            .find_all(uid, None, None, None, None)?;
+        self.follow_actors(keys)
+    }
+    fn follow_actors(&self, keys: Vec<UserKey>) -> Result<Vec<(ObjectKey, String)>> {
         let mut items = vec![];
         for key in keys {
             if let Some(obj) = self.object_repo.find_one(key.as_ref())? {
@@ -84,15 +265,16 @@ mod tests {
 
     use crate::activity_pub::model::Object;
 
-    use super::{Actor, UserIndex};
+    use super::{Actor, ActorCache, Batch, UserIndex, Visibility};
+    use crate::config::CacheConfig;
 
     #[test]
     fn insert_then_find() -> Result<()> {
         let tmp_dir = tempdir()?;
         let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
-        let mut b = keyspace.batch();
+        let mut b = Batch::new(&keyspace);
         let repo = UserIndex::new(keyspace)?;
-        let obj = Object::try_from(json!(
+        let obj = Object::from(json!(
             {
                 "@context": ["https://www.w3.org/ns/activitystreams",
                              {"@language": "ja"}],
@@ -105,11 +287,66 @@ mod tests {
                   "https://kenzoishii.example.com/image/165987aklre4"
                 ]
               }
-        ))?;
-        let actor = Actor::try_from(obj.clone())?;
+        ));
+        let actor = Actor::from(obj.clone());
         repo.insert(&mut b, "kenzoishii", actor.clone())?;
         b.commit()?;
         assert_eq!(Some(obj), repo.find_one("kenzoishii")?);
         Ok(())
     }
+    #[test]
+    fn list_and_disable_users() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let repo = UserIndex::new(keyspace.clone())?;
+        let mut b = Batch::new(&keyspace);
+        for uid in ["carol", "alice", "bob"] {
+            let actor = Actor::from(Object::from(json!({"type": "Person", "id": uid})));
+            repo.insert(&mut b, uid, actor)?;
+        }
+        repo.set_disabled(&mut b, "bob", true);
+        b.commit()?;
+
+        assert_eq!(repo.list_users(None, 2)?, ["alice", "bob"]);
+        assert_eq!(repo.list_users(Some("bob"), 2)?, ["carol"]);
+        assert!(repo.list_users(Some("carol"), 2)?.is_empty());
+        assert!(repo.is_disabled("bob")?);
+        assert!(!repo.is_disabled("alice")?);
+
+        let mut b = Batch::new(&keyspace);
+        repo.set_disabled(&mut b, "bob", false);
+        b.commit()?;
+        assert!(!repo.is_disabled("bob")?);
+
+        assert_eq!(repo.visibility("bob")?, None);
+        let mut b = Batch::new(&keyspace);
+        repo.set_visibility(&mut b, "bob", Visibility::Unlisted)?;
+        b.commit()?;
+        assert_eq!(repo.visibility("bob")?, Some(Visibility::Unlisted));
+        Ok(())
+    }
+
+    #[test]
+    fn find_through_cache() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let cache = ActorCache::new(&CacheConfig::default());
+        let repo = UserIndex::new(keyspace.clone())?.with_cache(cache.clone());
+        let obj = Object::from(json!({"type": "Person", "name": "Alice"}));
+
+        let mut b = Batch::new(&keyspace);
+        repo.insert(&mut b, "alice", Actor::from(obj.clone()))?;
+        b.commit()?;
+        assert_eq!(Some(obj.clone()), repo.find_one("alice")?);
+        assert_eq!(Some(obj), cache.get("alice"));
+
+        // Rewrites are only visible once the stale entry is invalidated.
+        let renamed = Object::from(json!({"type": "Person", "name": "Alicia"}));
+        let mut b = Batch::new(&keyspace);
+        repo.insert(&mut b, "alice", Actor::from(renamed.clone()))?;
+        b.commit()?;
+        cache.invalidate("alice");
+        assert_eq!(Some(renamed), repo.find_one("alice")?);
+        Ok(())
+    }
 }
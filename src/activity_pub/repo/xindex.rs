@@ -1,31 +1,155 @@
 use std::ops::Bound;
 use std::str::FromStr;
 
-use anyhow::Result;
-use fjall::{Batch, PartitionHandle, UserKey};
+use anyhow::{Context, Result};
+use fjall::{Keyspace, PartitionHandle, PersistMode, UserKey};
 
-use super::{IdObjIndexKey, ObjectKey};
+use super::options::index_options;
+use super::{Batch, IdObjIndexKey, ObjectKey};
 
+/// Key in the counts partition marking that every id has its count. Ids are
+/// never empty nor hold NUL, see [`IdObjIndexKey`].
+const COUNTED: &[u8] = b"\0counted";
+
+/// Object keys grouped by id, with the number of entries of each id kept in
+/// a partition of its own so collections know their size without a scan.
+///
+/// Entries and counts changed earlier in the same [`Batch`] are read back
+/// from it, so a batch may change the entries of an id any number of times.
 #[derive(Clone)]
 pub(super) struct IdObjIndex {
     index: PartitionHandle,
+    counts: PartitionHandle,
 }
 
 impl IdObjIndex {
-    pub(super) fn new(index: PartitionHandle) -> IdObjIndex {
-        IdObjIndex { index }
+    /// Open the index `name`, counting the entries of an index written by an
+    /// older version. That happens when the state machine opens it, before
+    /// it takes writes.
+    pub(super) fn open(keyspace: &Keyspace, name: &str) -> Result<IdObjIndex> {
+        IdObjIndex::open_subset(keyspace, name, None, |_| Ok(true))
+    }
+    /// Open the index `name` holding the entries of `source` that `keep`
+    /// picks, filled from `source` when it is new.
+    pub(super) fn open_subset(
+        keyspace: &Keyspace,
+        name: &str,
+        source: Option<&IdObjIndex>,
+        keep: impl Fn(&IdObjIndexKey) -> Result<bool>,
+    ) -> Result<IdObjIndex> {
+        let index = keyspace.open_partition(name, index_options())?;
+        let counts = keyspace.open_partition(&format!("{name}_counts"), index_options())?;
+        let index = IdObjIndex { index, counts };
+        if !index.counts.contains_key(COUNTED)? {
+            index
+                .fill(keyspace, source, keep)
+                .with_context(|| format!("Failed to count entries of {name}"))?;
+        }
+        Ok(index)
     }
-    pub(super) fn insert(&self, b: &mut Batch, id_obj_key: IdObjIndexKey) {
-        b.insert(&self.index, id_obj_key, []);
+    fn fill(
+        &self,
+        keyspace: &Keyspace,
+        source: Option<&IdObjIndex>,
+        keep: impl Fn(&IdObjIndexKey) -> Result<bool>,
+    ) -> Result<()> {
+        let mut b = keyspace.batch().durability(Some(PersistMode::SyncAll));
+        let mut counts: Vec<(String, u64)> = vec![];
+        for key in source.unwrap_or(self).index.keys() {
+            let key = IdObjIndexKey::from(key?.as_ref());
+            if !keep(&key)? {
+                continue;
+            }
+            match counts.last_mut() {
+                Some((id, count)) if id == key.id() => *count += 1,
+                _ => counts.push((key.id().to_string(), 1)),
+            }
+            if source.is_some() {
+                b.insert(&self.index, key, []);
+            }
+        }
+        for (id, count) in counts {
+            b.insert(&self.counts, id, count.to_be_bytes());
+        }
+        b.insert(&self.counts, COUNTED, []);
+        b.commit()?;
+        Ok(())
+    }
+    /// Add an entry, a no-op when it is there already.
+    pub(super) fn insert(&self, b: &mut Batch, id_obj_key: IdObjIndexKey) -> Result<()> {
+        if self.staged_contains(b, id_obj_key.clone())? {
+            return Ok(());
+        }
+        let count = self.staged_count(b, id_obj_key.id())? + 1;
+        b.set_count(&self.counts, id_obj_key.id(), count);
+        b.insert_entry(&self.index, id_obj_key.into());
+        Ok(())
     }
-    pub(super) fn remove(&self, b: &mut Batch, id_obj_key: IdObjIndexKey) {
-        b.remove(&self.index, id_obj_key);
+    pub(super) fn remove(&self, b: &mut Batch, id_obj_key: IdObjIndexKey) -> Result<()> {
+        let obj_key = ObjectKey::try_from(id_obj_key.obj_key().as_ref())?;
+        self.remove_all(b, id_obj_key.id(), [obj_key])
     }
-    pub(super) fn count(&self, id: &str) -> u64 {
-        // FIXME optimize scanning
-        let mut prefix = id.as_bytes().to_vec();
-        prefix.push(0);
-        self.index.prefix(prefix).count() as u64
+    /// Remove the entries of `id` for each of `obj_keys`.
+    pub(super) fn remove_all(
+        &self,
+        b: &mut Batch,
+        id: &str,
+        obj_keys: impl IntoIterator<Item = ObjectKey>,
+    ) -> Result<()> {
+        let mut removed = 0;
+        for obj_key in obj_keys {
+            let key = IdObjIndexKey::new(id, obj_key);
+            if self.staged_contains(b, key.clone())? {
+                removed += 1;
+                b.remove_entry(&self.index, key.into());
+            }
+        }
+        if removed > 0 {
+            let count = self.staged_count(b, id)?.saturating_sub(removed);
+            b.set_count(&self.counts, id, count);
+        }
+        Ok(())
+    }
+    pub(super) fn contains(&self, id_obj_key: IdObjIndexKey) -> Result<bool> {
+        let key: UserKey = id_obj_key.into();
+        Ok(self.index.contains_key(key)?)
+    }
+    /// Whether the entry is there once `b` is committed.
+    fn staged_contains(&self, b: &Batch, id_obj_key: IdObjIndexKey) -> Result<bool> {
+        let key: UserKey = id_obj_key.into();
+        match b.staged_entry(&self.index, &key) {
+            Some(staged) => Ok(staged),
+            None => Ok(self.index.contains_key(key)?),
+        }
+    }
+    /// Entries of `id` once `b` is committed.
+    fn staged_count(&self, b: &Batch, id: &str) -> Result<u64> {
+        match b.staged_count(&self.counts, id) {
+            Some(count) => Ok(count),
+            None => self.count(id),
+        }
+    }
+    /// Every entry, ordered by id.
+    pub(super) fn entries(&self) -> Result<Vec<IdObjIndexKey>> {
+        let mut entries = vec![];
+        for key in self.index.keys() {
+            entries.push(IdObjIndexKey::from(key?.as_ref()));
+        }
+        Ok(entries)
+    }
+    /// Object keys of every entry, whatever their id.
+    pub(super) fn obj_keys(&self) -> impl Iterator<Item = Result<ObjectKey>> + '_ {
+        self.index.keys().map(|key| {
+            let key = IdObjIndexKey::from(key?.as_ref());
+            Ok(ObjectKey::try_from(key.obj_key().as_ref())?)
+        })
+    }
+    pub(super) fn count(&self, id: &str) -> Result<u64> {
+        let Some(count) = self.counts.get(id)? else {
+            return Ok(0);
+        };
+        let count = count.as_ref().try_into().context("count should be a u64")?;
+        Ok(u64::from_be_bytes(count))
     }
     /// Based on GraphQL Cursor Connections Specification
     ///
@@ -91,3 +215,87 @@ impl IdObjIndex {
         Ok(keys)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::{Config, Keyspace};
+    use tempfile::tempdir;
+
+    use super::super::options::index_options;
+    use super::{Batch, IdObjIndex, IdObjIndexKey, ObjectKey};
+
+    #[test]
+    fn count_follows_inserts_and_removes() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let index = IdObjIndex::open(&keyspace, "test_index")?;
+        let keys: Vec<ObjectKey> = (0..3).map(|_| ObjectKey::new()).collect();
+        for key in &keys {
+            let mut b = Batch::new(&keyspace);
+            index.insert(&mut b, IdObjIndexKey::new("alice", *key))?;
+            b.commit()?;
+        }
+        // Applying an insert again does not count the entry twice.
+        let mut b = Batch::new(&keyspace);
+        index.insert(&mut b, IdObjIndexKey::new("alice", keys[0]))?;
+        index.insert(&mut b, IdObjIndexKey::new("bob", keys[0]))?;
+        b.commit()?;
+        assert_eq!(index.count("alice")?, 3);
+        assert_eq!(index.count("bob")?, 1);
+        assert_eq!(index.count("ali")?, 0);
+
+        let mut b = Batch::new(&keyspace);
+        index.remove_all(&mut b, "alice", [keys[0], keys[1], ObjectKey::new()])?;
+        b.commit()?;
+        let mut b = Batch::new(&keyspace);
+        index.remove(&mut b, IdObjIndexKey::new("alice", keys[1]))?;
+        b.commit()?;
+        assert_eq!(index.count("alice")?, 1);
+        assert_eq!(index.find_all("alice", None, None, None, None)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn count_changes_within_one_batch() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let index = IdObjIndex::open(&keyspace, "test_index")?;
+        let keys: Vec<ObjectKey> = (0..3).map(|_| ObjectKey::new()).collect();
+        let mut b = Batch::new(&keyspace);
+        for key in &keys {
+            index.insert(&mut b, IdObjIndexKey::new("alice", *key))?;
+        }
+        index.insert(&mut b, IdObjIndexKey::new("alice", keys[2]))?;
+        index.remove_all(&mut b, "alice", [keys[0]])?;
+        index.remove_all(&mut b, "alice", [keys[0], keys[1]])?;
+        index.insert(&mut b, IdObjIndexKey::new("alice", keys[0]))?;
+        b.commit()?;
+        assert_eq!(index.count("alice")?, 2);
+        assert_eq!(index.find_all("alice", None, None, None, None)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn count_entries_written_without_counts() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+        let partition = keyspace.open_partition("test_index", index_options())?;
+        let keys: Vec<ObjectKey> = (0..3).map(|_| ObjectKey::new()).collect();
+        for (id, key) in [("alice", keys[0]), ("alice", keys[1]), ("bob", keys[2])] {
+            partition.insert(IdObjIndexKey::new(id, key), [])?;
+        }
+
+        let index = IdObjIndex::open(&keyspace, "test_index")?;
+        assert_eq!(index.count("alice")?, 2);
+        assert_eq!(index.count("bob")?, 1);
+
+        let subset = IdObjIndex::open_subset(&keyspace, "test_subset", Some(&index), |key| {
+            Ok(key.id() == "alice")
+        })?;
+        assert_eq!(subset.count("alice")?, 2);
+        assert_eq!(subset.count("bob")?, 0);
+        assert!(subset.contains(IdObjIndexKey::new("alice", keys[1]))?);
+        Ok(())
+    }
+}
@@ -2,15 +2,27 @@ use std::fmt::Display;
 use std::str::{self, FromStr};
 
 use fjall::{Slice, UserKey};
+use jiff::Timestamp;
 use minicbor::{Decode, Encode};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) struct ObjectKey(Uuid);
 
 impl ObjectKey {
     pub(crate) fn new() -> ObjectKey {
-        ObjectKey(uuid::Uuid::now_v7())
+        ObjectKey(crate::activity_pub::new_uuid())
+    }
+    /// Smallest key minted at `unix_secs`, keys sort by the time they were
+    /// minted.
+    pub(crate) fn min_at(unix_secs: u64) -> ObjectKey {
+        let millis = u128::from(unix_secs) * 1000;
+        ObjectKey(Uuid::from_u128(millis << 80))
+    }
+    /// When the key was minted, to the millisecond.
+    pub(crate) fn minted_at(&self) -> Timestamp {
+        let millis = (self.0.as_u128() >> 80) as i64;
+        Timestamp::from_millisecond(millis).unwrap_or(Timestamp::UNIX_EPOCH)
     }
 }
 
@@ -94,8 +106,9 @@ impl IdObjIndexKey {
         str::from_utf8(id_bytes).expect("id should be valid UTF-8 string")
     }
     pub(super) fn obj_key(&self) -> UserKey {
+        // The object key is binary and may contain NUL itself.
         self.0
-            .split(|&b| b == 0)
+            .splitn(2, |&b| b == 0)
             .nth(1)
             .expect("IdObjIndexKey should be NUL delimited")
             .into()
@@ -113,3 +126,76 @@ impl From<&[u8]> for IdObjIndexKey {
         IdObjIndexKey(Slice::new(value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use fjall::UserKey;
+
+    use super::{IdObjIndexKey, ObjectKey};
+
+    fn bytes(key: impl Into<UserKey>) -> Vec<u8> {
+        key.into().to_vec()
+    }
+
+    #[test]
+    fn split_id_obj_index_key() {
+        let obj_key = ObjectKey::from_str("0193b5a6-0000-7000-8000-00000000ff00").unwrap();
+        let key = IdObjIndexKey::new("alice", obj_key);
+        assert_eq!(key.id(), "alice");
+        assert_eq!(key.obj_key(), UserKey::from(obj_key));
+    }
+
+    /// Pagination cursors and retention cut-offs compare object keys as
+    /// bytes, in the partitions, and as strings, in page links.
+    #[test]
+    fn object_keys_sort_by_mint_time() {
+        let minted: Vec<ObjectKey> = (0..100).map(|_| ObjectKey::new()).collect();
+        assert!(minted.windows(2).all(|w| bytes(w[0]) < bytes(w[1])));
+        assert!(minted
+            .windows(2)
+            .all(|w| w[0].to_string() < w[1].to_string()));
+
+        let now = jiff::Timestamp::now().as_second() as u64;
+        let (earlier, cut_off) = (ObjectKey::min_at(now - 1), ObjectKey::min_at(now));
+        assert!(bytes(earlier) < bytes(cut_off));
+        assert!(bytes(cut_off) <= bytes(ObjectKey::new()));
+        let parsed = ObjectKey::from_str(&cut_off.to_string()).unwrap();
+        assert_eq!(parsed, cut_off);
+    }
+
+    /// Index scans take the entries of one id by its prefix up to the NUL,
+    /// the entries of an id must sort by object key and never interleave
+    /// with those of an id it is a prefix of.
+    #[test]
+    fn index_keys_sort_by_id_then_object() {
+        let first = ObjectKey::from_str("00000000-0000-7000-8000-000000000000").unwrap();
+        let last = ObjectKey::from_str("ffffffff-ffff-7fff-bfff-ffffffffffff").unwrap();
+        let mut keys = [
+            bytes(IdObjIndexKey::new("alice", last)),
+            bytes(IdObjIndexKey::new("ali", last)),
+            bytes(IdObjIndexKey::new("alice", first)),
+            bytes(IdObjIndexKey::new("alice2", first)),
+            bytes(IdObjIndexKey::new("ali", first)),
+        ];
+        keys.sort();
+        let order: Vec<(String, UserKey)> = keys
+            .iter()
+            .map(|key| {
+                let key = IdObjIndexKey::from(key.as_slice());
+                (key.id().to_string(), key.obj_key())
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("ali".to_string(), UserKey::from(first)),
+                ("ali".to_string(), UserKey::from(last)),
+                ("alice".to_string(), UserKey::from(first)),
+                ("alice".to_string(), UserKey::from(last)),
+                ("alice2".to_string(), UserKey::from(first)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Remote actor resolution backed by a persistent, TTL'd cache.
+
+use anyhow::{bail, Result};
+use fjall::Keyspace;
+use metrics::counter;
+use reqwest::StatusCode;
+use tokio::task::spawn_blocking;
+use tracing::debug;
+
+use crate::config::CacheConfig;
+
+use super::mailman::Mailman;
+use super::model::Object;
+use super::repo::{RemoteActorEntry, RemoteActorRepo};
+use super::simple_queue::SimpleQueue;
+
+#[derive(Clone)]
+pub(crate) struct ActorResolver {
+    mailman: Mailman,
+    repo: RemoteActorRepo,
+    ttl_secs: u64,
+    negative_ttl_secs: u64,
+}
+
+impl ActorResolver {
+    pub(crate) fn new(keyspace: Keyspace, config: &CacheConfig) -> Result<ActorResolver> {
+        Ok(ActorResolver {
+            mailman: Mailman::new(),
+            repo: RemoteActorRepo::new(keyspace)?,
+            ttl_secs: config.remote_actor_ttl_secs,
+            negative_ttl_secs: config.remote_actor_negative_ttl_secs,
+        })
+    }
+
+    /// Resolve a remote IRI, consulting the cache before the network.
+    ///
+    /// Returns `None` if the remote server answered 404 or 410. Only
+    /// documents that look like actors (they have an inbox) are cached,
+    /// collections are always fetched fresh.
+    pub(crate) async fn resolve(&self, iri: &str) -> Result<Option<Object<'static>>> {
+        let iri = strip_fragment(iri).to_string();
+        let now = SimpleQueue::now();
+
+        let repo = self.repo.clone();
+        let key = iri.clone();
+        if let Some(entry) = spawn_blocking(move || repo.find_fresh(&key, now)).await?? {
+            counter!("pinka_remote_actor_cache_hits_total").increment(1);
+            return Ok(entry.object);
+        }
+        counter!("pinka_remote_actor_cache_misses_total").increment(1);
+
+        let fetched = self.mailman.fetch_with_meta(&iri).await?;
+        let (object, ttl) = match fetched.status {
+            StatusCode::NOT_FOUND | StatusCode::GONE => (None, self.negative_ttl_secs),
+            status if status.is_success() => {
+                let object = fetched.value.map(Object::from);
+                let ttl = fetched.max_age.unwrap_or(self.ttl_secs).min(self.ttl_secs);
+                if object.as_ref().is_none_or(|o| o.get_str("inbox").is_none()) {
+                    return Ok(object);
+                }
+                (object, ttl)
+            }
+            status => bail!("fetching {iri} failed with status {status}"),
+        };
+        if ttl > 0 {
+            debug!(%iri, ttl, negative = object.is_none(), "caching remote actor");
+            let repo = self.repo.clone();
+            let entry = RemoteActorEntry {
+                expires_at: now + ttl,
+                object: object.clone(),
+            };
+            spawn_blocking(move || repo.insert(&iri, &entry)).await??;
+        }
+        Ok(object)
+    }
+
+    /// Drop the cached copy so the next resolve goes to the network.
+    pub(crate) async fn invalidate(&self, iri: &str) -> Result<()> {
+        let repo = self.repo.clone();
+        let iri = strip_fragment(iri).to_string();
+        spawn_blocking(move || repo.remove(&iri)).await?
+    }
+
+    pub(super) fn mailman(&self) -> &Mailman {
+        &self.mailman
+    }
+}
+
+fn strip_fragment(iri: &str) -> &str {
+    iri.split_once('#').map_or(iri, |(iri, _)| iri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_fragment;
+
+    #[test]
+    fn key_id_resolves_to_actor() {
+        assert_eq!(
+            strip_fragment("https://remote.example/users/bob#main-key"),
+            "https://remote.example/users/bob"
+        );
+        assert_eq!(
+            strip_fragment("https://remote.example/users/bob"),
+            "https://remote.example/users/bob"
+        );
+    }
+}
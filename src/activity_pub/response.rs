@@ -0,0 +1,114 @@
+//! What the sender of an inbox activity gets back besides the HTTP status.
+//!
+//! ActivityPub only expects an Accept or Reject for a Follow. Likes,
+//! Announces and the rest are side effects on our copy of the object, some
+//! servers send Accepts for them anyway but nobody waits for one and
+//! answering them would only fan out more deliveries. Every activity type
+//! the inbox handles has a row in [`POLICY`], a new type gets one when it
+//! is added to the inbox.
+//!
+//! References:
+//! * <https://www.w3.org/TR/activitypub/#follow-activity-inbox>
+
+use anyhow::Result;
+
+use super::model::Object;
+use super::{ObjectKey, UserIndex};
+
+/// Answer owed for an inbox activity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InboxResponse {
+    /// Nothing is sent back.
+    None,
+    /// Accept it, or Reject it when the user approves them manually and
+    /// decides so.
+    AcceptOrReject,
+}
+
+/// Inbox activity types and the answer each one gets.
+const POLICY: [(&str, InboxResponse); 11] = [
+    ("Announce", InboxResponse::None),
+    ("Create", InboxResponse::None),
+    ("Delete", InboxResponse::None),
+    ("Dislike", InboxResponse::None),
+    ("Flag", InboxResponse::None),
+    ("Follow", InboxResponse::AcceptOrReject),
+    ("Like", InboxResponse::None),
+    // We follow the new account, which is not an answer to the sender.
+    ("Move", InboxResponse::None),
+    ("Reject", InboxResponse::None),
+    ("Undo", InboxResponse::None),
+    ("Update", InboxResponse::None),
+];
+
+/// Answer owed for `activity`, none for types not in the policy.
+pub(crate) fn required_response(activity: &Object<'_>) -> InboxResponse {
+    POLICY
+        .into_iter()
+        .find(|(ty, _)| activity.type_is(ty))
+        .map_or(InboxResponse::None, |(_, response)| response)
+}
+
+/// Follow to Accept right away for the inbox `activity` the state machine
+/// stored as `stored`.
+///
+/// None when the policy owes no answer, or when the user approves followers
+/// manually and answers the request later.
+pub(crate) fn immediate_answer(
+    user_index: &UserIndex,
+    uid: &str,
+    activity: &Object<'_>,
+    stored: Option<ObjectKey>,
+) -> Result<Option<ObjectKey>> {
+    let Some(follow_key) = stored else {
+        return Ok(None);
+    };
+    match required_response(activity) {
+        InboxResponse::None => Ok(None),
+        InboxResponse::AcceptOrReject => {
+            if user_index.has_follow_request(uid, follow_key)? {
+                return Ok(None);
+            }
+            Ok(Some(follow_key))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{required_response, InboxResponse, POLICY};
+    use crate::activity_pub::model::{Object, INBOX_ACTIVITY_TYPES};
+
+    fn response(ty: &str) -> InboxResponse {
+        required_response(&Object::from(
+            json!({"type": ty, "actor": "https://remote.example/bob"}),
+        ))
+    }
+
+    #[test]
+    fn every_inbox_activity_has_a_policy() {
+        let mut types: Vec<&str> = POLICY.iter().map(|(ty, _)| *ty).collect();
+        types.sort();
+        let mut inbox = INBOX_ACTIVITY_TYPES.to_vec();
+        inbox.sort();
+        assert_eq!(types, inbox);
+    }
+
+    #[test]
+    fn only_follow_is_answered() {
+        assert_eq!(response("Follow"), InboxResponse::AcceptOrReject);
+        for ty in [
+            "Like", "Announce", "Create", "Undo", "Update", "Delete", "Reject",
+        ] {
+            assert_eq!(response(ty), InboxResponse::None, "{ty}");
+        }
+        // Nor are types the inbox ignores.
+        assert_eq!(response("Offer"), InboxResponse::None);
+        assert_eq!(
+            required_response(&Object::from(json!({"type": ["Follow", "litepub:Extra"]}))),
+            InboxResponse::AcceptOrReject
+        );
+    }
+}
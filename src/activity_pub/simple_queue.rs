@@ -41,16 +41,19 @@ pub(super) struct SimpleQueue {
     keyspace: Keyspace,
     messages: Partition,
     visibility: Partition,
+    dead_letters: Partition,
 }
 
 impl SimpleQueue {
     pub(super) fn new(keyspace: Keyspace) -> Result<SimpleQueue> {
         let messages = keyspace.open_partition("sq_messages", Default::default())?;
         let visibility = keyspace.open_partition("sq_visibility", Default::default())?;
+        let dead_letters = keyspace.open_partition("sq_dead_letters", Default::default())?;
         Ok(SimpleQueue {
             keyspace,
             messages,
             visibility,
+            dead_letters,
         })
     }
 
@@ -65,6 +68,11 @@ impl SimpleQueue {
             .is_empty()
             .context("Unable to read from queue messages")
     }
+    /// Approximate number of messages across all queues, including
+    /// in-flight ones.
+    pub(super) fn approximate_len(&self) -> usize {
+        self.messages.approximate_len()
+    }
     pub(super) fn send_message(
         &self,
         queue_name: &str,
@@ -140,6 +148,20 @@ impl SimpleQueue {
 
         Ok(None)
     }
+    /// Bodies of the messages queued before `key`, in flight or not,
+    /// oldest first.
+    pub(super) fn messages_before(&self, queue_name: &str, key: Bytes) -> Result<Vec<Vec<u8>>> {
+        let mut bodies = vec![];
+        for item in self
+            .messages
+            .range(q_key(queue_name, [0; 16])..q_key(queue_name, key))
+        {
+            let (_, value_bytes) = item?;
+            let message: QueueMessage = minicbor::decode(&value_bytes)?;
+            bodies.push(message.body);
+        }
+        Ok(bodies)
+    }
     pub(super) fn delete_message(
         &self,
         queue_name: &str,
@@ -163,6 +185,109 @@ impl SimpleQueue {
         batch.commit()?;
         Ok(true)
     }
+    /// Replace the body of a received message, it keeps its visibility and
+    /// receive count.
+    pub(super) fn update_message(
+        &self,
+        queue_name: &str,
+        key: Bytes,
+        receipt_handle: Bytes,
+        body: impl Into<Vec<u8>>,
+    ) -> Result<bool> {
+        let q_key = q_key(queue_name, key);
+        let Some(message) = self.messages.get(&q_key)? else {
+            return Ok(false);
+        };
+        let mut message: QueueMessage = minicbor::decode(&message)?;
+        if message.receipt_handle != receipt_handle {
+            return Ok(false);
+        }
+        message.body = body.into();
+        debug!(queue_name, ?key, ?message, "update message");
+
+        let mut batch = self.keyspace.batch().durability(Some(PersistMode::SyncAll));
+        batch.insert(&self.messages, q_key, minicbor::to_vec(&message)?);
+        batch.commit()?;
+        Ok(true)
+    }
+    /// Hand a received message back before its visibility timeout, it can
+    /// be received again right away and the attempt is not counted.
+    pub(super) fn release_message(
+        &self,
+        queue_name: &str,
+        key: Bytes,
+        receipt_handle: Bytes,
+    ) -> Result<bool> {
+        let q_key = q_key(queue_name, key);
+        let Some(message) = self.messages.get(&q_key)? else {
+            return Ok(false);
+        };
+        let mut message: QueueMessage = minicbor::decode(&message)?;
+        if message.receipt_handle != receipt_handle {
+            return Ok(false);
+        }
+        message.approximate_receive_count = message.approximate_receive_count.saturating_sub(1);
+        debug!(queue_name, ?key, ?message, "release message");
+
+        let mut batch = self.keyspace.batch().durability(Some(PersistMode::SyncAll));
+        batch.insert(&self.messages, q_key.clone(), minicbor::to_vec(&message)?);
+        batch.remove(&self.visibility, q_key);
+        batch.commit()?;
+        Ok(true)
+    }
+    /// Give up on a received message, its body is kept as a dead letter
+    /// until it is replaced or removed.
+    pub(super) fn dead_letter_message(
+        &self,
+        queue_name: &str,
+        key: Bytes,
+        receipt_handle: Bytes,
+    ) -> Result<bool> {
+        let q_key = q_key(queue_name, key);
+        let Some(message) = self.messages.get(&q_key)? else {
+            return Ok(false);
+        };
+        let message: QueueMessage = minicbor::decode(&message)?;
+        if message.receipt_handle != receipt_handle {
+            return Ok(false);
+        }
+        debug!(queue_name, ?key, ?message, "dead letter message");
+
+        let mut batch = self.keyspace.batch().durability(Some(PersistMode::SyncAll));
+        batch.insert(&self.dead_letters, q_key.clone(), message.body);
+        batch.remove(&self.messages, q_key.clone());
+        batch.remove(&self.visibility, q_key);
+        batch.commit()?;
+        Ok(true)
+    }
+    /// Bodies of the dead letters of a queue, oldest first.
+    pub(super) fn dead_letters(&self, queue_name: &str) -> Result<Vec<(Bytes, Vec<u8>)>> {
+        let mut letters = vec![];
+        for item in self.dead_letters.prefix(queue_name) {
+            let (key, body) = item?;
+            let key = key
+                .strip_prefix(queue_name.as_bytes())
+                .expect("key should be prefixed with the queue name");
+            letters.push((key.try_into()?, body.to_vec()));
+        }
+        Ok(letters)
+    }
+    /// Replace the body of a dead letter, or remove it with `None`.
+    pub(super) fn update_dead_letter(
+        &self,
+        queue_name: &str,
+        key: Bytes,
+        body: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let q_key = q_key(queue_name, key);
+        let mut batch = self.keyspace.batch().durability(Some(PersistMode::SyncAll));
+        match body {
+            Some(body) => batch.insert(&self.dead_letters, q_key, body),
+            None => batch.remove(&self.dead_letters, q_key),
+        }
+        batch.commit()?;
+        Ok(())
+    }
 }
 
 fn q_key(queue_name: &str, key: [u8; 16]) -> UserKey {
@@ -278,6 +403,87 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_release_in_flight_message() -> Result<()> {
+        let dir = tempdir()?;
+        let keyspace = fjall::Config::new(dir.path()).temporary(true).open()?;
+        let queue = SimpleQueue::new(keyspace)?;
+
+        for body in [b"msg0", b"msg1", b"msg2"] {
+            queue.send_message(QUEUE_NAME, uuidgen(), body)?;
+        }
+        // The worker is stopped while the first message is in flight.
+        let handle = uuidgen();
+        let ReceiveResult { key, .. } = queue.receive_message(QUEUE_NAME, handle, 1, 30)?.unwrap();
+        assert!(!queue.release_message(QUEUE_NAME, key, uuidgen())?);
+        assert!(queue.release_message(QUEUE_NAME, key, handle)?);
+
+        // A restarted worker still gets every message, the released one
+        // without waiting for its visibility timeout.
+        let mut bodies = vec![];
+        while let Some(ReceiveResult { key, message }) =
+            queue.receive_message(QUEUE_NAME, uuidgen(), 2, 30)?
+        {
+            assert_eq!(message.approximate_receive_count, 1);
+            bodies.push(message.body);
+            assert!(queue.delete_message(QUEUE_NAME, key, message.receipt_handle)?);
+        }
+        assert_eq!(bodies, [b"msg0", b"msg1", b"msg2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_messages_before() -> Result<()> {
+        let dir = tempdir()?;
+        let keyspace = fjall::Config::new(dir.path()).temporary(true).open()?;
+        let queue = SimpleQueue::new(keyspace)?;
+
+        let keys = [uuidgen(), uuidgen(), uuidgen()];
+        for (key, body) in keys.iter().zip([b"msg0", b"msg1", b"msg2"]) {
+            queue.send_message(QUEUE_NAME, *key, body)?;
+        }
+        queue.send_message("other_queue", uuidgen(), b"other")?;
+        // In flight messages are still queued.
+        queue
+            .receive_message(QUEUE_NAME, uuidgen(), 1, 30)?
+            .unwrap();
+
+        assert!(queue.messages_before(QUEUE_NAME, keys[0])?.is_empty());
+        assert_eq!(
+            queue.messages_before(QUEUE_NAME, keys[2])?,
+            [b"msg0", b"msg1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dead_letters() -> Result<()> {
+        let dir = tempdir()?;
+        let keyspace = fjall::Config::new(dir.path()).temporary(true).open()?;
+        let queue = SimpleQueue::new(keyspace)?;
+
+        queue.send_message(QUEUE_NAME, uuidgen(), b"test4")?;
+        let handle = uuidgen();
+        let ReceiveResult { key, .. } = queue.receive_message(QUEUE_NAME, handle, 1, 0)?.unwrap();
+        assert!(queue.update_message(QUEUE_NAME, key, handle, b"test5")?);
+        assert!(!queue.dead_letter_message(QUEUE_NAME, key, uuidgen())?);
+        assert!(queue.dead_letter_message(QUEUE_NAME, key, handle)?);
+
+        // Dead letters are never received again.
+        assert!(queue
+            .receive_message(QUEUE_NAME, uuidgen(), 2, 0)?
+            .is_none());
+        assert_eq!(
+            queue.dead_letters(QUEUE_NAME)?,
+            vec![(key, b"test5".to_vec())]
+        );
+        assert!(queue.dead_letters("other_queue")?.is_empty());
+
+        queue.update_dead_letter(QUEUE_NAME, key, None)?;
+        assert!(queue.dead_letters(QUEUE_NAME)?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_concurrent_access() -> Result<()> {
         let dir = tempdir()?;
@@ -294,6 +500,12 @@ mod tests {
             }));
         }
 
+        // Producers finish first so consumers never observe a transiently
+        // empty queue and exit early.
+        for handle in handles.drain(..) {
+            handle.join().unwrap();
+        }
+
         for _ in 0..5 {
             let q = queue.clone();
             handles.push(std::thread::spawn(move || {
@@ -338,6 +550,10 @@ mod tests {
             }
         }
 
+        for handle in handles.drain(..) {
+            handle.join().unwrap();
+        }
+
         // Concurrent consumers for different queues
         for queue_name in ["queue_a", "queue_b"] {
             for _ in 0..2 {
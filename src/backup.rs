@@ -0,0 +1,116 @@
+//! Offline backup and restore of a server's keyspace.
+//!
+//! Both commands run while `main` holds the database lock, so the server is
+//! not running and nothing writes concurrently. All partitions are still
+//! read at a single keyspace instant, which keeps the Raft `raft_saved`
+//! state, the log and the state machine partitions consistent with each
+//! other.
+//!
+//! A backup is itself a fjall keyspace, so it can be inspected or restored
+//! with the same code that copies it.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use fjall::{Keyspace, PartitionCreateOptions, PersistMode};
+use tracing::info;
+
+use crate::activity_pub::{index_options, object_options};
+
+const BATCH_SIZE: usize = 1000;
+
+pub(crate) fn backup(keyspace: &Keyspace, out: &Path) -> Result<()> {
+    if out.exists() {
+        bail!("backup directory {} already exists", out.display());
+    }
+    let target = fjall::Config::new(out)
+        .open()
+        .context("Failed to create backup keyspace")?;
+    copy_keyspace(keyspace, &target)?;
+    info!(out = %out.display(), "backup completed");
+    Ok(())
+}
+
+pub(crate) fn restore(keyspace: &Keyspace, from: &Path) -> Result<()> {
+    if !from.is_dir() {
+        bail!("backup directory {} does not exist", from.display());
+    }
+    if keyspace.partition_count() > 0 {
+        bail!("refusing to restore into a database that already has data");
+    }
+    let source = fjall::Config::new(from)
+        .open()
+        .context("Failed to open backup keyspace")?;
+    copy_keyspace(&source, keyspace)?;
+    info!(from = %from.display(), "restore completed");
+    Ok(())
+}
+
+/// Options a partition is created with, they are not recorded in the data.
+fn create_options(partition: &str) -> PartitionCreateOptions {
+    match partition {
+        "raft_log" | "objects" => object_options(),
+        _ => index_options(),
+    }
+}
+
+fn copy_keyspace(source: &Keyspace, target: &Keyspace) -> Result<()> {
+    let instant = source.instant();
+    for name in source.list_partitions() {
+        let from = source.open_partition(&name, Default::default())?;
+        let to = target
+            .open_partition(&name, create_options(&name))
+            .with_context(|| format!("Failed to create partition {name}"))?;
+        let mut count = 0;
+        let mut b = target.batch();
+        for item in from.snapshot_at(instant).iter() {
+            let (key, value) = item?;
+            b.insert(&to, key, value);
+            count += 1;
+            if count % BATCH_SIZE == 0 {
+                b.commit()?;
+                b = target.batch();
+            }
+        }
+        b.commit()?;
+        info!(partition = %name, count, "copied partition");
+    }
+    target.persist(PersistMode::SyncAll)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::Config;
+    use tempfile::tempdir;
+
+    use super::{backup, restore};
+
+    #[test]
+    fn backup_then_restore() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Config::new(tmp_dir.path().join("s1")).open()?;
+        let restore_ks = keyspace.open_partition("raft_restore", Default::default())?;
+        restore_ks.insert("raft_saved", "state")?;
+        let objects = keyspace.open_partition("objects", Default::default())?;
+        for i in 0..2500u32 {
+            objects.insert(i.to_be_bytes(), "object")?;
+        }
+
+        let out = tmp_dir.path().join("backup");
+        backup(&keyspace, &out)?;
+        assert!(backup(&keyspace, &out).is_err());
+
+        let restored = Config::new(tmp_dir.path().join("s2")).open()?;
+        restore(&restored, &out)?;
+        let objects = restored.open_partition("objects", Default::default())?;
+        assert_eq!(objects.len()?, 2500);
+        let raft_restore = restored.open_partition("raft_restore", Default::default())?;
+        assert!(raft_restore.get("raft_saved")?.is_some());
+
+        // A database with data is never overwritten.
+        assert!(restore(&restored, &out).is_err());
+        Ok(())
+    }
+}
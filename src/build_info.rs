@@ -0,0 +1,34 @@
+//! Which build of pinka is running, captured by `build.rs`.
+
+use jiff::Timestamp;
+use serde_json::{json, Value};
+
+pub(crate) const NAME: &str = env!("CARGO_PKG_NAME");
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Abbreviated commit hash, `unknown` when built outside a git checkout.
+pub(crate) const GIT_COMMIT: &str = env!("PINKA_GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("PINKA_BUILD_TIMESTAMP");
+
+/// When the binary was built.
+pub(crate) fn build_timestamp() -> Option<Timestamp> {
+    let seconds = BUILD_TIMESTAMP.parse().ok()?;
+    Timestamp::from_second(seconds).ok()
+}
+
+/// The version with the commit as semver build metadata, e.g.
+/// `0.1.0+1a2b3c4d5e6f`.
+pub(crate) fn full_version() -> String {
+    match GIT_COMMIT {
+        "unknown" => VERSION.to_string(),
+        commit => format!("{VERSION}+{commit}"),
+    }
+}
+
+pub(crate) fn to_json() -> Value {
+    json!({
+        "name": NAME,
+        "version": VERSION,
+        "git_commit": GIT_COMMIT,
+        "build_timestamp": build_timestamp().map(|timestamp| timestamp.to_string()),
+    })
+}
@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::fmt::{self, Display};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -19,12 +20,30 @@ use tokio_rustls::{TlsAcceptor, TlsConnector};
 use tracing::{error, info, warn};
 
 use crate::config::{RuntimeConfig, ServerConfig};
+use crate::raft::{get_raft_members, set_wire_encoding, wire_cookie};
 
 pub(super) struct ClusterMaint;
 
+/// No quorum of raft workers was reachable in time while
+/// `cluster.exit_on_bootstrap_timeout` is set. `cluster_maint` fails with
+/// it and the supervisor stops the server instead of restarting it.
+#[derive(Debug)]
+pub(crate) struct BootstrapFailed;
+
+impl Display for BootstrapFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no quorum of raft workers is reachable")
+    }
+}
+
+impl std::error::Error for BootstrapFailed {}
+
 #[derive(RactorMessage, Debug)]
 pub(super) enum ClusterMaintMsg {
     CheckConnection,
+    /// Check that a quorum of raft workers is reachable, see
+    /// [`ClusterState::check_bootstrap`].
+    BootstrapTimeout,
     ServerConnected(String),
     ServerDisconnected(String),
 }
@@ -35,6 +54,8 @@ pub(super) struct ClusterState {
     config: RuntimeConfig,
     server_status: BTreeMap<String, ServerStatus>,
     myself: ActorRef<ClusterMaintMsg>,
+    /// Whether a quorum of raft workers was reachable since startup.
+    bootstrapped: bool,
 }
 
 #[derive(Debug)]
@@ -68,6 +89,7 @@ impl Actor for ClusterMaint {
             config,
             server_status,
             myself,
+            bootstrapped: false,
         })
     }
 
@@ -88,6 +110,16 @@ impl Actor for ClusterMaint {
             ),
             || ClusterMaintMsg::CheckConnection,
         );
+        if state.config.init.cluster.bootstrap_timeout_ms == 0 {
+            state.bootstrapped = true;
+        } else {
+            let timeout = state.bootstrap_timeout();
+            info!(
+                timeout_ms = timeout.as_millis(),
+                "waiting for a quorum of raft workers"
+            );
+            myself.send_after(timeout, || ClusterMaintMsg::BootstrapTimeout);
+        }
         state.spawn_node_server().await?;
         state.connect_peers().await?;
         Ok(())
@@ -104,9 +136,24 @@ impl Actor for ClusterMaint {
             ClusterMaintMsg::CheckConnection => {
                 state.connect_peers().await?;
             }
+            ClusterMaintMsg::BootstrapTimeout => {
+                state.check_bootstrap()?;
+            }
             ClusterMaintMsg::ServerConnected(name) => {
                 info!(name, "server connected");
                 state.server_status.insert(name, ServerStatus::Connected);
+                if !state.bootstrapped {
+                    let connected = state
+                        .server_status
+                        .values()
+                        .filter(|status| matches!(status, ServerStatus::Connected))
+                        .count();
+                    info!(
+                        connected,
+                        peers = state.server_status.len(),
+                        "bootstrap in progress"
+                    );
+                }
             }
             ClusterMaintMsg::ServerDisconnected(name) => {
                 info!(name, "server disconnected");
@@ -139,6 +186,60 @@ impl Actor for ClusterMaint {
 }
 
 impl ClusterState {
+    fn bootstrap_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.init.cluster.bootstrap_timeout_ms)
+    }
+
+    /// Report the peers that are missing if no quorum of raft workers is
+    /// reachable yet.
+    ///
+    /// Without a quorum this server runs for election forever and never
+    /// learns about a leader, nothing else tells the operator why.
+    fn check_bootstrap(&mut self) -> Result<(), BootstrapFailed> {
+        if self.bootstrapped {
+            return Ok(());
+        }
+        let members = get_raft_members();
+        let (reachable, quorum) = reachable_quorum(&self.config.init.cluster.servers, &members);
+        if reachable >= quorum {
+            info!(reachable, quorum, "quorum of raft workers is reachable");
+            self.bootstrapped = true;
+            return Ok(());
+        }
+        error!(
+            reachable,
+            quorum,
+            timeout_ms = self.config.init.cluster.bootstrap_timeout_ms,
+            "no quorum of raft workers is reachable, this server cannot join the cluster"
+        );
+        for peer in &self.config.init.cluster.servers {
+            if peer.name == self.server.name {
+                continue;
+            }
+            let status = match (
+                self.server_status.get(&peer.name),
+                members.contains(&peer.name),
+            ) {
+                (_, true) => "raft worker joined",
+                (Some(ServerStatus::Connected), false) => "connected, raft worker not running",
+                _ => "not connected",
+            };
+            error!("  {}@{}:{}: {status}", peer.name, peer.hostname, peer.port);
+        }
+        error!(
+            "check that the peers are running and reachable at these addresses, \
+             and that they share cluster.auth_cookie, cluster.wire_encoding, \
+             the mTLS certificates and a compatible pinka version"
+        );
+        if self.config.init.cluster.exit_on_bootstrap_timeout {
+            return Err(BootstrapFailed);
+        }
+        self.myself.send_after(self.bootstrap_timeout(), || {
+            ClusterMaintMsg::BootstrapTimeout
+        });
+        Ok(())
+    }
+
     async fn spawn_node_server(&self) -> Result<ActorRef<NodeServerMessage>> {
         // TODO: should we stop the program if we can't read the cert files?
         //       or should we just log a warning and continue?
@@ -150,9 +251,11 @@ impl ClusterState {
         } else {
             IncomingEncryptionMode::Raw
         };
+        let cluster = &self.config.init.cluster;
+        set_wire_encoding(cluster.wire_encoding);
         let node = NodeServer::new(
             self.server.port,
-            self.config.init.cluster.auth_cookie.clone(),
+            wire_cookie(&cluster.auth_cookie, cluster.wire_encoding),
             self.server.name.clone(),
             self.server.hostname.clone(),
             Some(encryption_mode),
@@ -352,6 +455,13 @@ impl ClusterState {
     }
 }
 
+/// Raft workers among `members` that can vote, and how many make a quorum.
+fn reachable_quorum(servers: &[ServerConfig], members: &[String]) -> (usize, usize) {
+    let voters: Vec<&ServerConfig> = servers.iter().filter(|s| !s.readonly_replica).collect();
+    let reachable = voters.iter().filter(|s| members.contains(&s.name)).count();
+    (reachable, voters.len() / 2 + 1)
+}
+
 struct NodeEventListener;
 
 impl NodeEventSubscription for NodeEventListener {
@@ -387,3 +497,38 @@ impl NodeEventSubscription for NodeEventListener {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ServerConfig;
+
+    use super::reachable_quorum;
+
+    fn server(name: &str, readonly_replica: bool) -> ServerConfig {
+        ServerConfig {
+            name: name.to_string(),
+            readonly_replica,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn count_only_voters_towards_quorum() {
+        let servers = [
+            server("s1", false),
+            server("s2", false),
+            server("s3", false),
+            server("r1", true),
+        ];
+        let members = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(reachable_quorum(&servers, &members(&["s1"])), (1, 2));
+        assert_eq!(reachable_quorum(&servers, &members(&["s1", "r1"])), (1, 2));
+        assert_eq!(reachable_quorum(&servers, &members(&["s1", "s3"])), (2, 2));
+        assert_eq!(reachable_quorum(&servers[..1], &members(&["s1"])), (1, 1));
+    }
+}
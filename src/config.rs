@@ -1,13 +1,22 @@
-use std::fmt::Debug;
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
 use std::fs;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
 use fjall::Keyspace;
+use reqwest::Url;
 use secrecy::SecretString;
 use serde::Deserialize;
+use tracing::warn;
+use tracing_subscriber::filter::Targets;
 use uuid::Uuid;
 
+use crate::activity_pub::machine::AppliedIndex;
+use crate::activity_pub::{ActorCache, Visibility};
+use crate::storage_health::StorageHealth;
+
 #[derive(Clone, Default, Debug, Deserialize)]
 #[serde(default)]
 pub(crate) struct Config {
@@ -16,6 +25,11 @@ pub(crate) struct Config {
     pub(crate) cluster: ClusterConfig,
     pub(crate) database: DatabaseConfig,
     pub(crate) activity_pub: ActivityPubConfig,
+    pub(crate) cache: CacheConfig,
+    pub(crate) feed_slurp: FeedSlurpConfig,
+    pub(crate) federation: FederationConfig,
+    pub(crate) instance: InstanceConfig,
+    pub(crate) log: LogConfig,
 }
 
 impl Config {
@@ -24,7 +38,15 @@ impl Config {
         P: AsRef<Path>,
     {
         let config_text = fs::read_to_string(path)?;
-        Ok(toml::from_str(&config_text)?)
+        let config: Config = toml::from_str(&config_text)?;
+        config.raft.check()?;
+        config.cluster.check()?;
+        for server in &config.cluster.servers {
+            server.http.check(&config.raft)?;
+        }
+        config.activity_pub.check()?;
+        config.log.targets()?;
+        Ok(config)
     }
 }
 
@@ -47,9 +69,42 @@ pub(crate) struct RaftConfig {
     pub(crate) heartbeat_ms: u64,
     pub(crate) min_election_ms: u64,
     pub(crate) max_election_ms: u64,
+    /// Entries a follower may lag behind the commit index before the leader
+    /// replicates to it in catch-up mode.
+    pub(crate) catch_up_threshold: u64,
+    /// Entries per append_entries in catch-up mode.
+    pub(crate) catch_up_batch: u64,
+    /// How long a follower waits for the leader to answer a client request
+    /// it forwarded.
+    pub(crate) client_timeout_ms: u64,
+    /// Largest serialized entry a client may append, bigger requests are
+    /// refused before they reach the log.
+    pub(crate) max_entry_bytes: usize,
+    /// How long a leader asked to step down lets the client requests in
+    /// flight commit, refusing new ones. Requests still in flight after are
+    /// failed with `NotLeader`, 0 fails them right away.
+    pub(crate) step_down_drain_ms: u64,
+    /// How long a leader collects client requests to append them with a
+    /// single write, 0 appends each on its own.
+    pub(crate) max_batch_delay_ms: u64,
+    /// Client requests appended in one write at most, a full batch is
+    /// appended without waiting for `max_batch_delay_ms`.
+    pub(crate) max_batch_entries: usize,
+    /// Committed entries queued to the state machine at most. The rest wait
+    /// in the raft log and are queued as the state machine catches up, so a
+    /// slow apply does not grow its mailbox without bound.
+    pub(crate) max_apply_queue: u64,
+    /// How often the raft worker is polled for progress, 0 disables the
+    /// watchdog.
+    pub(crate) watchdog_interval_ms: u64,
+    /// How long the worker may go without answering or, with committed
+    /// entries waiting, without applying any before it is reported.
+    pub(crate) watchdog_stall_ms: u64,
+    /// Restart a worker reported by the watchdog.
+    pub(crate) watchdog_restart: bool,
 }
 
-#[derive(Clone, Default, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 #[serde(default)]
 pub(crate) struct ClusterConfig {
     pub(crate) auth_cookie: String,
@@ -58,6 +113,94 @@ pub(crate) struct ClusterConfig {
     pub(crate) ca_certs: Vec<PathBuf>,
     pub(crate) servers: Vec<ServerConfig>,
     pub(crate) reconnect_timeout_ms: u64,
+    /// How long after startup a quorum of raft workers must be reachable
+    /// before the missing peers are reported, 0 disables the check.
+    pub(crate) bootstrap_timeout_ms: u64,
+    /// Stop the server when no quorum is reachable after
+    /// `bootstrap_timeout_ms`, instead of reporting again every
+    /// `bootstrap_timeout_ms`. The process exits with an error.
+    pub(crate) exit_on_bootstrap_timeout: bool,
+    /// How raft messages are encoded between nodes. Every node of a cluster
+    /// must use the same encoding.
+    pub(crate) wire_encoding: WireEncoding,
+}
+
+/// Encoding of raft messages between nodes. JSON is larger and slower, but
+/// readable in a packet capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WireEncoding {
+    #[default]
+    Cbor,
+    Json,
+}
+
+impl Display for WireEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireEncoding::Cbor => write!(f, "cbor"),
+            WireEncoding::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            auth_cookie: String::new(),
+            use_mtls: false,
+            pem_dir: None,
+            ca_certs: vec![],
+            servers: vec![],
+            reconnect_timeout_ms: 0,
+            bootstrap_timeout_ms: 60_000,
+            exit_on_bootstrap_timeout: false,
+            wire_encoding: WireEncoding::default(),
+        }
+    }
+}
+
+impl ClusterConfig {
+    /// Every server must be listed once, under one name and one address,
+    /// otherwise peers disagree on the members and the quorum size.
+    fn check(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut addresses = HashSet::new();
+        for server in &self.servers {
+            ensure!(!server.name.is_empty(), "cluster.servers must have a name");
+            ensure!(
+                names.insert(server.name.as_str()),
+                "cluster.servers lists {} more than once",
+                server.name
+            );
+            let address = server.canonical_address();
+            ensure!(
+                addresses.insert(address.clone()),
+                "cluster.servers {} has the address {}:{} of another server",
+                server.name,
+                address.0,
+                address.1
+            );
+        }
+        // Any voter may lead, reads redirected to it need its public URL.
+        if self.servers.iter().any(|s| !s.http.follower_reads) {
+            for server in self.servers.iter().filter(|s| !s.readonly_replica) {
+                ensure!(
+                    !server.http.public_url.is_empty(),
+                    "cluster.servers {} needs http.public_url while reads are redirected to the leader",
+                    server.name
+                );
+            }
+        }
+        let voters = self.servers.iter().filter(|s| !s.readonly_replica).count();
+        if voters > 0 && voters % 2 == 0 {
+            warn!(
+                voters,
+                "an even number of voting servers tolerates no more failures than one less"
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
@@ -76,12 +219,84 @@ pub(crate) struct ServerConfig {
     pub(crate) http: HttpConfig,
 }
 
+impl ServerConfig {
+    /// Host and port in a form that compares equal for the same address,
+    /// e.g. `Example.COM.` and `example.com`, or `::1` and `0::1`.
+    fn canonical_address(&self) -> (String, u16) {
+        let host = self.hostname.trim_end_matches('.').to_ascii_lowercase();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = match host.parse::<IpAddr>() {
+            Ok(ip) => ip.to_canonical().to_string(),
+            Err(_) => host.to_string(),
+        };
+        (host, self.port)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(default)]
 pub(crate) struct HttpConfig {
     pub(crate) listen: bool,
     pub(crate) address: String,
     pub(crate) port: u16,
+    /// Page size used by paginated collections when the client asks for none.
+    pub(crate) collection_page_default: u64,
+    /// Upper bound for the page size a client may request.
+    pub(crate) collection_page_max: u64,
+    /// Embed the first page in collections instead of only linking to it.
+    pub(crate) collection_inline_first_page: bool,
+    /// Embed the objects of Create activities in outbox pages instead of
+    /// only naming their IRI, as Mastodon expects.
+    pub(crate) outbox_embed_objects: bool,
+    /// Writing requests other than inbox deliveries in progress at once,
+    /// more are rejected with 503.
+    pub(crate) max_concurrent_writes: usize,
+    /// Reading requests in progress at once, more wait for their turn. Reads
+    /// and state machine applies share the blocking thread pool, the limit
+    /// keeps a burst of reads from taking all of it.
+    pub(crate) max_concurrent_reads: usize,
+    /// Inbox deliveries in progress at once, apart from other writes. More
+    /// wait for their turn.
+    pub(crate) max_concurrent_inbox: usize,
+    /// Inbox deliveries waiting for their turn at most, more are rejected
+    /// with 503 and sent again later by the remote server.
+    pub(crate) max_queued_inbox: usize,
+    /// How long a read with `min_index` waits for this server to apply
+    /// that entry before it is rejected with 503.
+    pub(crate) min_index_timeout_ms: u64,
+    /// Compress responses for clients that accept brotli or gzip.
+    pub(crate) compression: bool,
+    /// Smaller bodies are sent as is, compressing them saves nothing.
+    pub(crate) compression_min_bytes: u32,
+    /// Inbox activities accepted from one remote actor per minute, 0 for no
+    /// limit. More are rejected with 429.
+    pub(crate) inbox_actor_per_minute: u32,
+    /// Activities one remote actor may send at once before the rate applies.
+    pub(crate) inbox_actor_burst: u32,
+    /// Like `inbox_actor_per_minute`, for all actors of one host together.
+    pub(crate) inbox_host_per_minute: u32,
+    pub(crate) inbox_host_burst: u32,
+    /// Actors and hosts whose rate is tracked, the least recently seen are
+    /// forgotten first.
+    pub(crate) inbox_rate_tracked: u64,
+    /// Serve reads from the local replica when not leading. Otherwise they
+    /// are redirected to the `public_url` of the leader, or rejected with
+    /// 421 while there is none. Admin endpoints are always served.
+    ///
+    /// On by default: a replica answers reads as soon as it applied the
+    /// writes they ask for with `min_index`, and clients need not reach
+    /// every server directly.
+    pub(crate) follower_reads: bool,
+    /// Base URL clients reach this server at, like `https://s1.example.com`.
+    /// Reads are redirected here when the server leads and the others do
+    /// not serve them, see `follower_reads`.
+    pub(crate) public_url: String,
+    /// How long a reading request may take before it is answered with 504.
+    pub(crate) read_timeout_ms: u64,
+    /// Like `read_timeout_ms` for writing requests. It must exceed
+    /// `raft.client_timeout_ms`, so a forwarded request fails with the
+    /// reason raft gives rather than a bare 504.
+    pub(crate) write_timeout_ms: u64,
 }
 
 impl Default for HttpConfig {
@@ -90,7 +305,48 @@ impl Default for HttpConfig {
             listen: true,
             address: "[::1]".to_string(),
             port: 8080,
+            collection_page_default: 10,
+            collection_page_max: 50,
+            collection_inline_first_page: false,
+            outbox_embed_objects: true,
+            max_concurrent_writes: 256,
+            max_concurrent_reads: 256,
+            max_concurrent_inbox: 64,
+            max_queued_inbox: 256,
+            min_index_timeout_ms: 5_000,
+            compression: true,
+            compression_min_bytes: 1024,
+            inbox_actor_per_minute: 60,
+            inbox_actor_burst: 30,
+            inbox_host_per_minute: 600,
+            inbox_host_burst: 300,
+            inbox_rate_tracked: 10_000,
+            follower_reads: true,
+            public_url: String::new(),
+            read_timeout_ms: 15_000,
+            write_timeout_ms: 30_000,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn check(&self, raft: &RaftConfig) -> Result<()> {
+        ensure!(
+            self.read_timeout_ms > 0,
+            "http.read_timeout_ms must be greater than 0"
+        );
+        ensure!(
+            self.write_timeout_ms > raft.client_timeout_ms,
+            "http.write_timeout_ms must be greater than raft.client_timeout_ms"
+        );
+        if !self.public_url.is_empty() {
+            Url::parse(&self.public_url).context("http.public_url must be a URL")?;
+            ensure!(
+                !self.public_url.ends_with('/'),
+                "http.public_url must not end with a slash"
+            );
         }
+        Ok(())
     }
 }
 
@@ -100,10 +356,239 @@ pub(crate) struct DatabaseConfig {
     pub(crate) path: PathBuf,
 }
 
-#[derive(Clone, Default, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub(crate) struct ActivityPubConfig {
+    /// Externally visible URL every local IRI is minted from, for example
+    /// `https://social.example.com` without a trailing slash.
     pub(crate) base_url: String,
     pub(crate) webfinger_at_host: String,
+    #[serde(default)]
+    pub(crate) delivery: DeliveryConfig,
+    #[serde(default)]
+    pub(crate) limits: ObjectLimits,
+    #[serde(default)]
+    pub(crate) retention: RetentionConfig,
+    /// Deliver activities to each inbox in the order they were queued, see
+    /// [`crate::activity_pub::delivery`].
+    #[serde(default)]
+    pub(crate) ordered_delivery: bool,
+    /// Take activities for any local user at `/inbox` and advertise it as
+    /// `endpoints.sharedInbox` on actors, so remote servers deliver an
+    /// activity once instead of to every addressed inbox.
+    #[serde(default)]
+    pub(crate) shared_inbox: bool,
+    /// How far the signed `Date` of an inbox request may be from our clock
+    /// before it is rejected as a possible replay.
+    #[serde(default = "default_signature_max_skew_secs")]
+    pub(crate) signature_max_skew_secs: u64,
+    /// Audience of outbox posts that name none, for users who did not pick
+    /// their own: `public`, `unlisted` or `followers`.
+    #[serde(default)]
+    pub(crate) default_visibility: Visibility,
+    /// Talk to other servers at all. When false nothing is delivered and
+    /// inbox requests are refused, leaving a local-only store.
+    #[serde(default = "default_federation_enabled")]
+    pub(crate) federation_enabled: bool,
+}
+
+fn default_signature_max_skew_secs() -> u64 {
+    300
+}
+
+fn default_federation_enabled() -> bool {
+    true
+}
+
+impl Default for ActivityPubConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::new(),
+            webfinger_at_host: String::new(),
+            delivery: DeliveryConfig::default(),
+            limits: ObjectLimits::default(),
+            retention: RetentionConfig::default(),
+            ordered_delivery: false,
+            shared_inbox: false,
+            signature_max_skew_secs: default_signature_max_skew_secs(),
+            default_visibility: Visibility::default(),
+            federation_enabled: default_federation_enabled(),
+        }
+    }
+}
+
+/// Bounds on documents received from clients and peers.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct ObjectLimits {
+    /// Maximum size of a request body in bytes.
+    pub(crate) max_bytes: usize,
+    /// Maximum nesting of objects and arrays, capped by the JSON parser at 128.
+    pub(crate) max_depth: usize,
+    /// Maximum number of elements in any single array.
+    pub(crate) max_array_len: usize,
+}
+
+impl Default for ObjectLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024,
+            max_depth: 32,
+            max_array_len: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct DeliveryConfig {
+    /// Maximum number of inbox POSTs in flight at once.
+    pub(crate) max_concurrency: usize,
+    /// Maximum number of inbox POSTs in flight to a single host.
+    pub(crate) max_concurrency_per_host: usize,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 64,
+            max_concurrency_per_host: 4,
+        }
+    }
+}
+
+/// How long objects received from other servers are kept.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct RetentionConfig {
+    /// Seconds after which remote objects no local collection refers to are
+    /// pruned, 0 keeps them forever.
+    pub(crate) remote_object_ttl_secs: u64,
+    /// Seconds between two pruning runs.
+    pub(crate) prune_interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            remote_object_ttl_secs: 0,
+            prune_interval_secs: 60 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct CacheConfig {
+    /// Maximum number of local actor documents kept in memory.
+    pub(crate) actor_capacity: u64,
+    /// Seconds before a cached actor document expires.
+    pub(crate) actor_ttl_secs: u64,
+    /// Upper bound in seconds for caching remote actor documents.
+    pub(crate) remote_actor_ttl_secs: u64,
+    /// Seconds to remember that a remote actor or object answered 404 or
+    /// 410.
+    pub(crate) remote_actor_negative_ttl_secs: u64,
+    /// Upper bound in seconds for caching remote objects fetched to repair
+    /// activities that name them by IRI only.
+    pub(crate) remote_object_ttl_secs: u64,
+    /// Remote objects fetched per host and minute, past that activities are
+    /// served as stored.
+    pub(crate) remote_object_fetches_per_minute: u32,
+    /// Also fetch remote objects from loopback, private and link-local
+    /// addresses, only meant for tests and closed networks.
+    pub(crate) fetch_private_addresses: bool,
+    /// Signatures of inbox requests remembered to reject replays, enough for
+    /// the deliveries within twice `signature_max_skew_secs`.
+    pub(crate) replay_capacity: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            actor_capacity: 1000,
+            actor_ttl_secs: 300,
+            remote_actor_ttl_secs: 24 * 60 * 60,
+            remote_actor_negative_ttl_secs: 60 * 60,
+            remote_object_ttl_secs: 24 * 60 * 60,
+            remote_object_fetches_per_minute: 30,
+            fetch_private_addresses: false,
+            replay_capacity: 100_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct FeedSlurpConfig {
+    /// Milliseconds between two fetches of feeds on the same host.
+    pub(crate) politeness_delay_ms: u64,
+}
+
+impl Default for FeedSlurpConfig {
+    fn default() -> Self {
+        Self {
+            politeness_delay_ms: 1000,
+        }
+    }
+}
+
+/// Domains we exchange activities with.
+///
+/// Rules are either a host name such as `example.com` or a wildcard such as
+/// `*.example.com` which matches every subdomain but not the domain itself.
+#[derive(Clone, Default, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct FederationConfig {
+    /// If not empty, only matching domains are federated with.
+    pub(crate) allow: Vec<String>,
+    /// Matching domains are never federated with, even if allowed.
+    pub(crate) block: Vec<String>,
+}
+
+/// Verbosity of log output, see [`crate::logging`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct LogConfig {
+    /// Level per tracing target like `info,raft=debug,http=warn`, a bare
+    /// level applies to the targets not named. A target without `::` also
+    /// names the module of pinka, `raft` covers `pinka::raft`.
+    pub(crate) filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+        }
+    }
+}
+
+impl LogConfig {
+    pub(crate) fn targets(&self) -> Result<Targets> {
+        let mut directives = vec![];
+        for directive in self.filter.split(',').map(str::trim) {
+            directives.push(directive.to_string());
+            if directive.contains('=') && !directive.contains("::") {
+                directives.push(format!("pinka::{directive}"));
+            }
+        }
+        directives
+            .join(",")
+            .parse()
+            .with_context(|| format!("log.filter {:?} is not a valid filter", self.filter))
+    }
+}
+
+/// How the server presents itself to directories and people about to join,
+/// empty values are left out.
+#[derive(Clone, Default, Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct InstanceConfig {
+    pub(crate) title: String,
+    pub(crate) description: String,
+    /// Who runs the server.
+    pub(crate) contact_name: String,
+    pub(crate) contact_email: String,
 }
 
 #[derive(Clone)]
@@ -111,6 +596,9 @@ pub(crate) struct RuntimeConfig {
     pub(crate) init: Config,
     pub(crate) server: ServerConfig,
     pub(crate) keyspace: Keyspace,
+    pub(crate) actor_cache: ActorCache,
+    pub(crate) applied_index: AppliedIndex,
+    pub(crate) storage: StorageHealth,
 }
 
 impl Default for RaftConfig {
@@ -119,10 +607,94 @@ impl Default for RaftConfig {
             heartbeat_ms: 100,
             min_election_ms: 1000,
             max_election_ms: 2000,
+            catch_up_threshold: 100,
+            catch_up_batch: 500,
+            client_timeout_ms: 10_000,
+            max_entry_bytes: 4 * 1024 * 1024,
+            step_down_drain_ms: 0,
+            max_batch_delay_ms: 0,
+            max_batch_entries: 64,
+            max_apply_queue: 256,
+            watchdog_interval_ms: 5_000,
+            watchdog_stall_ms: 60_000,
+            watchdog_restart: false,
         }
     }
 }
 
+impl RaftConfig {
+    fn check(&self) -> Result<()> {
+        // Only randomized election timeouts break up split votes.
+        ensure!(
+            self.min_election_ms < self.max_election_ms,
+            "raft.min_election_ms must be less than raft.max_election_ms"
+        );
+        ensure!(
+            self.heartbeat_ms < self.min_election_ms,
+            "raft.heartbeat_ms must be less than raft.min_election_ms"
+        );
+        ensure!(
+            self.max_entry_bytes > 0,
+            "raft.max_entry_bytes must be greater than 0"
+        );
+        ensure!(
+            self.max_batch_entries > 0,
+            "raft.max_batch_entries must be greater than 0"
+        );
+        ensure!(
+            self.max_apply_queue > 0,
+            "raft.max_apply_queue must be greater than 0"
+        );
+        Ok(())
+    }
+}
+
+impl ActivityPubConfig {
+    /// IRI of a local actor, its collections live below it.
+    pub(crate) fn user_iri(&self, uid: &str) -> String {
+        format!("{}/users/{uid}", self.base_url)
+    }
+    /// Whether `iri` lives on this server rather than a remote one.
+    pub(crate) fn is_local(&self, iri: &str) -> bool {
+        let host = |url: &str| Url::parse(url).ok()?.host_str().map(str::to_string);
+        host(iri).is_some_and(|host_str| Some(host_str) == host(&self.base_url))
+    }
+    /// IRI of the inbox for all local users, see `shared_inbox`.
+    pub(crate) fn shared_inbox_iri(&self) -> String {
+        format!("{}/inbox", self.base_url)
+    }
+    /// IRI of a stored object or activity.
+    pub(crate) fn object_iri(&self, obj_key: impl Display) -> String {
+        format!("{}/as/objects/{obj_key}", self.base_url)
+    }
+    fn check(&self) -> Result<()> {
+        let url = Url::parse(&self.base_url).context("activity_pub.base_url must be a URL")?;
+        let Some(host) = url.host_str() else {
+            bail!("activity_pub.base_url must have a host");
+        };
+        // Remote servers only dereference https IRIs, plain http is left for
+        // development on the loopback interface.
+        let loopback = matches!(host, "localhost" | "127.0.0.1" | "[::1]");
+        ensure!(
+            url.scheme() == "https" || url.scheme() == "http" && loopback,
+            "activity_pub.base_url must be an https URL"
+        );
+        ensure!(
+            url.query().is_none() && url.fragment().is_none(),
+            "activity_pub.base_url must not have a query or fragment"
+        );
+        ensure!(
+            !self.base_url.ends_with('/'),
+            "activity_pub.base_url must not end with a slash"
+        );
+        ensure!(
+            self.retention.prune_interval_secs > 0,
+            "activity_pub.retention.prune_interval_secs must be positive"
+        );
+        Ok(())
+    }
+}
+
 impl Debug for RuntimeConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RuntimeConfig")
@@ -131,3 +703,131 @@ impl Debug for RuntimeConfig {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tracing::Level;
+
+    use super::{
+        ActivityPubConfig, ClusterConfig, HttpConfig, LogConfig, RaftConfig, ServerConfig,
+    };
+
+    fn with_base_url(base_url: &str) -> ActivityPubConfig {
+        ActivityPubConfig {
+            base_url: base_url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn server(name: &str, hostname: &str, port: u16) -> ServerConfig {
+        ServerConfig {
+            name: name.to_string(),
+            hostname: hostname.to_string(),
+            port,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_cluster_servers() {
+        let cluster = |servers| ClusterConfig {
+            servers,
+            ..Default::default()
+        };
+        assert!(cluster(vec![
+            server("s1", "10.0.0.1", 8000),
+            server("s2", "10.0.0.1", 8001),
+            server("s3", "s3.example.com", 8000),
+        ])
+        .check()
+        .is_ok());
+
+        let duplicate_name = cluster(vec![
+            server("s1", "10.0.0.1", 8000),
+            server("s1", "10.0.0.2", 8000),
+        ]);
+        assert!(duplicate_name.check().is_err());
+        let duplicate_host = cluster(vec![
+            server("s1", "S1.example.com.", 8000),
+            server("s2", "s1.example.com", 8000),
+        ]);
+        assert!(duplicate_host.check().is_err());
+        let duplicate_ip = cluster(vec![
+            server("s1", "::1", 8000),
+            server("s2", "[0::1]", 8000),
+        ]);
+        assert!(duplicate_ip.check().is_err());
+        assert!(cluster(vec![server("", "10.0.0.1", 8000)]).check().is_err());
+
+        // Redirected reads need the public URL of every server that may lead.
+        let mut s1 = server("s1", "10.0.0.1", 8000);
+        s1.http.follower_reads = false;
+        s1.http.public_url = "https://s1.example.com".to_string();
+        let mut s2 = server("s2", "10.0.0.2", 8000);
+        assert!(cluster(vec![s1.clone(), s2.clone()]).check().is_err());
+        s2.readonly_replica = true;
+        assert!(cluster(vec![s1, s2]).check().is_ok());
+    }
+
+    #[test]
+    fn log_levels_per_target() {
+        let log = LogConfig {
+            filter: "info,raft=debug,rpc=warn".to_string(),
+        };
+        let targets = log.targets().unwrap();
+        assert!(targets.would_enable("raft", &Level::DEBUG));
+        assert!(targets.would_enable("pinka::raft", &Level::DEBUG));
+        assert!(targets.would_enable("pinka::raft::replication", &Level::DEBUG));
+        assert!(!targets.would_enable("pinka::http", &Level::DEBUG));
+        assert!(!targets.would_enable("rpc", &Level::INFO));
+        assert!(targets.would_enable("http", &Level::INFO));
+        assert!(!targets.would_enable("http", &Level::DEBUG));
+        let log = LogConfig {
+            filter: "raft=loud".to_string(),
+        };
+        assert!(log.targets().is_err());
+    }
+
+    #[test]
+    fn check_http_timeouts() {
+        let raft = RaftConfig::default();
+        assert!(HttpConfig::default().check(&raft).is_ok());
+        let http = HttpConfig {
+            write_timeout_ms: raft.client_timeout_ms,
+            ..Default::default()
+        };
+        assert!(http.check(&raft).is_err());
+        let http = HttpConfig {
+            read_timeout_ms: 0,
+            ..Default::default()
+        };
+        assert!(http.check(&raft).is_err());
+    }
+
+    #[test]
+    fn check_base_url() {
+        assert!(with_base_url("https://social.example.com").check().is_ok());
+        assert!(with_base_url("https://example.com/pinka").check().is_ok());
+        assert!(with_base_url("http://localhost:8080").check().is_ok());
+
+        assert!(with_base_url("").check().is_err());
+        assert!(with_base_url("social.example.com").check().is_err());
+        assert!(with_base_url("http://social.example.com").check().is_err());
+        assert!(with_base_url("https://social.example.com/")
+            .check()
+            .is_err());
+        assert!(with_base_url("https://social.example.com?x=1")
+            .check()
+            .is_err());
+
+        let apub = with_base_url("https://example.com/pinka");
+        assert_eq!(
+            apub.user_iri("alice"),
+            "https://example.com/pinka/users/alice"
+        );
+        assert_eq!(apub.object_iri(1), "https://example.com/pinka/as/objects/1");
+        assert!(apub.is_local("https://example.com/users/alice"));
+        assert!(!apub.is_local("https://remote.example/users/bob"));
+        assert!(!apub.is_local("urn:uuid:0193b5a6"));
+    }
+}
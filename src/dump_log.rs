@@ -0,0 +1,132 @@
+//! Offline dump of a server's Raft log, to look into consensus problems
+//! after the fact.
+//!
+//! Like backup and restore, the command runs while `main` holds the
+//! database lock and only reads. It prints JSON lines: first the state raft
+//! saved, then one line per log entry. The commit index is only known in
+//! memory, every entry up to `last_applied` was committed.
+
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use fjall::Keyspace;
+use serde_json::{json, Value};
+
+use crate::activity_pub::machine::{last_applied, ActivityPubCommand};
+use crate::raft::{saved_state, LogEntry, LogEntryValue};
+
+pub(crate) fn dump_log(
+    keyspace: &Keyspace,
+    from: Option<u64>,
+    to: Option<u64>,
+    out: &mut impl Write,
+) -> Result<()> {
+    if !keyspace.partition_exists("raft_log") {
+        bail!("the database has no raft log");
+    }
+    let log = keyspace.open_partition("raft_log", Default::default())?;
+    let index = |key: &[u8]| -> Result<u64> { Ok(u64::from_be_bytes(key.try_into()?)) };
+    let first_index = log
+        .first_key_value()?
+        .map(|(key, _)| index(&key))
+        .transpose()?;
+    let last_index = log
+        .last_key_value()?
+        .map(|(key, _)| index(&key))
+        .transpose()?;
+    let saved = saved_state(keyspace)?;
+    let state = json!({
+        "current_term": saved.current_term,
+        "voted_for": saved.voted_for,
+        "last_applied": last_applied(keyspace)?,
+        "first_index": first_index,
+        "last_index": last_index,
+    });
+    writeln!(out, "{state}")?;
+
+    let range = from.unwrap_or(0).to_be_bytes()..=to.unwrap_or(u64::MAX).to_be_bytes();
+    for item in log.range(range) {
+        let (key, value) = item?;
+        let index = index(&key)?;
+        let entry: LogEntry = minicbor::decode(&value)
+            .with_context(|| format!("Failed to decode log entry {index}"))?;
+        writeln!(out, "{}", entry_json(entry))?;
+    }
+    Ok(())
+}
+
+fn entry_json(entry: LogEntry) -> Value {
+    let value = match entry.value {
+        LogEntryValue::NewTermStarted => json!({"type": "NewTermStarted"}),
+        LogEntryValue::ClusterMessage(message) => {
+            json!({"type": "ClusterMessage", "message": message})
+        }
+        // Commands have no JSON form, their debug output keeps key material
+        // redacted.
+        LogEntryValue::Command(bytes) => match ActivityPubCommand::from_bytes(&bytes) {
+            Ok(command) => json!({"type": "Command", "command": format!("{command:?}")}),
+            Err(error) => json!({
+                "type": "Command",
+                "error": format!("{error:#}"),
+                "len": bytes.len(),
+            }),
+        },
+    };
+    json!({"index": entry.index, "term": entry.term, "value": value})
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::Config;
+    use serde_json::{json, Value};
+    use tempfile::tempdir;
+
+    use super::dump_log;
+    use crate::raft::{LogEntry, LogEntryValue};
+
+    #[test]
+    fn dump_entries_in_range() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Config::new(tmp_dir.path()).open()?;
+        let log = keyspace.open_partition("raft_log", Default::default())?;
+        let values = [
+            LogEntryValue::NewTermStarted,
+            LogEntryValue::ClusterMessage("hello".to_string()),
+            LogEntryValue::Command(vec![0xff]),
+        ];
+        for (index, value) in (1u64..).zip(values) {
+            let entry = LogEntry {
+                index,
+                term: 2,
+                value,
+            };
+            log.insert(index.to_be_bytes(), minicbor::to_vec(&entry)?)?;
+        }
+
+        let mut out = vec![];
+        dump_log(&keyspace, Some(2), None, &mut out)?;
+        let lines = String::from_utf8(out)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            json!({
+                "current_term": 0,
+                "voted_for": null,
+                "last_applied": 0,
+                "first_index": 1,
+                "last_index": 3,
+            })
+        );
+        assert_eq!(
+            lines[1],
+            json!({"index": 2, "term": 2, "value": {"type": "ClusterMessage", "message": "hello"}})
+        );
+        assert_eq!(lines[2]["value"]["len"], 1);
+        assert!(lines[2]["value"]["error"].is_string());
+        Ok(())
+    }
+}
@@ -1,24 +1,48 @@
-use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
 use feed_rs::model::Entry;
+use jiff::fmt::rfc2822::DateTimeParser;
+use jiff::Timestamp;
+use metrics::{counter, gauge};
 use ractor::{Actor, ActorProcessingErr, ActorRef};
 use ractor_cluster::RactorMessage;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{StatusCode, Url};
 use serde_json::json;
+use tokio::time::Instant;
+use tracing::{error, info};
 
 use crate::activity_pub::delivery::DeliveryQueueItem;
 use crate::activity_pub::machine::{ActivityPubCommand, C2sCommand};
 use crate::activity_pub::model::Object;
 use crate::activity_pub::{uuidgen, ObjectKey};
+use crate::config::FeedSlurpConfig;
 use crate::raft::{get_raft_local_client, LogEntryValue, RaftClientMsg};
 use crate::ActivityPubConfig;
 
+/// Longest wait a feed server can ask for with `Retry-After`.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
 pub(crate) struct FeedSlurpWorker;
 
 pub(crate) struct FeedSlurpWorkerInit {
     pub(crate) apub: ActivityPubConfig,
+    pub(crate) feed_slurp: FeedSlurpConfig,
 }
 
 pub(crate) struct FeedSlurpWorkerState {
     apub: ActivityPubConfig,
+    politeness_delay: Duration,
+    /// Failed polls in a row, by feed URL.
+    failures: HashMap<String, u64>,
+    /// Earliest time the next fetch may start, by feed host.
+    host_ready: HashMap<String, Instant>,
+    /// Feeds whose server answered with `Retry-After`, by feed URL. A poll
+    /// is already scheduled for when the time is up.
+    retry_at: HashMap<String, Instant>,
 }
 
 #[derive(RactorMessage)]
@@ -28,8 +52,26 @@ pub(crate) enum FeedSlurpMsg {
         base_url: String,
         feed_url: String,
     },
+    /// Poll in the slot an earlier `IngestFeed` reserved on the feed host.
+    Fetch {
+        uid: String,
+        base_url: String,
+        feed_url: String,
+    },
 }
 
+/// The feed server asked to be polled again later.
+#[derive(Debug)]
+struct RetryLater(Duration);
+
+impl Display for RetryLater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "feed server asked to retry in {:?}", self.0)
+    }
+}
+
+impl std::error::Error for RetryLater {}
+
 impl Actor for FeedSlurpWorker {
     type Msg = FeedSlurpMsg;
     type State = FeedSlurpWorkerState;
@@ -40,12 +82,18 @@ impl Actor for FeedSlurpWorker {
         _myself: ActorRef<Self::Msg>,
         args: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let FeedSlurpWorkerInit { apub } = args;
-        Ok(FeedSlurpWorkerState { apub })
+        let FeedSlurpWorkerInit { apub, feed_slurp } = args;
+        Ok(FeedSlurpWorkerState {
+            apub,
+            politeness_delay: Duration::from_millis(feed_slurp.politeness_delay_ms),
+            failures: HashMap::new(),
+            host_ready: HashMap::new(),
+            retry_at: HashMap::new(),
+        })
     }
     async fn handle(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
@@ -54,19 +102,134 @@ impl Actor for FeedSlurpWorker {
                 uid,
                 base_url,
                 feed_url,
-            } => state
-                .handle_ingest_feed(&uid, &base_url, &feed_url)
-                .await
-                .context("Failed to ingest feed")?,
+            } => {
+                if state.retry_pending(&feed_url) {
+                    info!(
+                        feed_url,
+                        "feed server asked to retry later, poll is scheduled"
+                    );
+                    return Ok(());
+                }
+                let wait = state.reserve_host(&feed_url, Instant::now());
+                if wait.is_zero() {
+                    state.poll(&myself, uid, base_url, feed_url).await;
+                } else {
+                    myself.send_after(wait, move || FeedSlurpMsg::Fetch {
+                        uid,
+                        base_url,
+                        feed_url,
+                    });
+                }
+            }
+            FeedSlurpMsg::Fetch {
+                uid,
+                base_url,
+                feed_url,
+            } => {
+                if !state.retry_pending(&feed_url) {
+                    state.poll(&myself, uid, base_url, feed_url).await;
+                }
+            }
         }
         Ok(())
     }
 }
 
 impl FeedSlurpWorkerState {
-    async fn handle_ingest_feed(&self, uid: &str, base_url: &str, feed_url: &str) -> Result<()> {
+    /// Whether the feed server asked us to wait and the time is not up.
+    fn retry_pending(&mut self, feed_url: &str) -> bool {
+        match self.retry_at.get(feed_url) {
+            Some(&at) if at > Instant::now() => true,
+            Some(_) => {
+                self.retry_at.remove(feed_url);
+                false
+            }
+            None => false,
+        }
+    }
+    /// Reserve the next fetch slot on the host of `feed_url`, returns how
+    /// long to wait for it.
+    ///
+    /// Slots are `politeness_delay` apart, so feeds on the same host are
+    /// never fetched at the same time.
+    fn reserve_host(&mut self, feed_url: &str, now: Instant) -> Duration {
+        let host = Url::parse(feed_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| feed_url.to_string());
+        let ready = self.host_ready.entry(host).or_insert(now);
+        let slot = (*ready).max(now);
+        *ready = slot + self.politeness_delay;
+        slot - now
+    }
+    /// Poll the feed now, and schedule it again when the server asks to
+    /// retry later.
+    async fn poll(
+        &mut self,
+        myself: &ActorRef<FeedSlurpMsg>,
+        uid: String,
+        base_url: String,
+        feed_url: String,
+    ) {
+        let result = self.handle_ingest_feed(&uid, &base_url, &feed_url).await;
+        if let Some(RetryLater(wait)) = result.as_ref().err().and_then(|e| e.downcast_ref()) {
+            let wait = *wait;
+            info!(feed_url, ?wait, "rescheduling feed as asked by Retry-After");
+            self.retry_at
+                .insert(feed_url.clone(), Instant::now() + wait);
+            let feed = feed_url.clone();
+            myself.send_after(wait, move || FeedSlurpMsg::IngestFeed {
+                uid,
+                base_url,
+                feed_url: feed,
+            });
+        }
+        self.record_poll(&feed_url, result);
+    }
+    /// Export the outcome of a poll so operators can alert on stale feeds.
+    ///
+    /// A broken feed must not stop the worker, the error is only logged.
+    fn record_poll(&mut self, feed_url: &str, result: Result<u64>) {
+        let feed = feed_url.to_string();
+        let failures = self.failures.entry(feed.clone()).or_default();
+        match result {
+            Ok(ingested) => {
+                *failures = 0;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                gauge!("pinka_feed_last_success_timestamp_seconds", "feed" => feed.clone())
+                    .set(now.as_secs_f64());
+                counter!("pinka_feed_items_ingested_total", "feed" => feed.clone())
+                    .increment(ingested);
+            }
+            Err(error) => {
+                *failures += 1;
+                error!(?error, feed_url, "Failed to ingest feed");
+            }
+        }
+        gauge!("pinka_feed_consecutive_failures", "feed" => feed).set(*failures as f64);
+    }
+    /// Publish new entries of the feed, returns how many were stored.
+    async fn handle_ingest_feed(&self, uid: &str, base_url: &str, feed_url: &str) -> Result<u64> {
         let response = reqwest::get(feed_url).await?;
-        let feed_text = response.bytes().await?;
+        let status = response.status();
+        gauge!("pinka_feed_last_http_status", "feed" => feed_url.to_string())
+            .set(status.as_u16() as f64);
+        if matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        ) {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, Timestamp::now()));
+            if let Some(wait) = retry_after {
+                return Err(RetryLater(wait).into());
+            }
+        }
+        let feed_text = response.error_for_status()?.bytes().await?;
         let feed = {
             let feed_parser = feed_rs::parser::Builder::new()
                 .base_uri(Some(base_url))
@@ -74,8 +237,9 @@ impl FeedSlurpWorkerState {
             feed_parser.parse(feed_text.as_ref())?
         };
         let client = get_raft_local_client()?;
+        let mut ingested = 0;
         for entry in feed.entries.iter().rev() {
-            let object = object_from_feed_entry(&self.apub.base_url, uid, entry);
+            let object = object_from_feed_entry(&self.apub.user_iri(uid), entry);
             let act_key = ObjectKey::new();
             let obj_key = ObjectKey::new();
             let command = ActivityPubCommand::C2sCreate(C2sCommand {
@@ -83,35 +247,61 @@ impl FeedSlurpWorkerState {
                 act_key,
                 obj_key,
                 object,
+                request_id: None,
             });
-            ractor::call!(
+            let (stored, _) = ractor::call!(
                 client,
                 RaftClientMsg::ClientRequest,
                 LogEntryValue::from(command)
-            )?;
+            )?
+            .into_result()?;
+            if stored.is_empty() {
+                // Seen in an earlier poll.
+                continue;
+            }
+            ingested += 1;
+            if !self.apub.federation_enabled {
+                continue;
+            }
             let command = ActivityPubCommand::QueueDelivery(
                 uuidgen(),
                 DeliveryQueueItem {
                     uid: uid.to_string(),
                     act_key,
+                    request_id: None,
+                    blind_recipients: None,
+                    inboxes: None,
                 },
             );
             ractor::call!(
                 client,
                 RaftClientMsg::ClientRequest,
                 LogEntryValue::from(command)
-            )?;
+            )?
+            .into_result()?;
         }
-        Ok(())
+        Ok(ingested)
     }
 }
 
-fn object_from_feed_entry(apub_base_url: &str, uid: &str, entry: &Entry) -> Object<'static> {
+/// How long `Retry-After` asks to wait, given as seconds or as an HTTP date.
+fn parse_retry_after(value: &str, now: Timestamp) -> Option<Duration> {
+    let wait = match value.trim().parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => {
+            let at = DateTimeParser::new().parse_timestamp(value).ok()?;
+            Duration::try_from(now.duration_until(at)).unwrap_or_default()
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+fn object_from_feed_entry(actor_iri: &str, entry: &Entry) -> Object<'static> {
     let mut object = Object::from(json!({
         "@context": "https://www.w3.org/ns/activitystreams",
         // TODO config as Note or Article
         "type": "Note",
-        "actor": format!("{apub_base_url}/users/{uid}")
+        "actor": actor_iri
     }));
 
     if entry.id.starts_with("https") {
@@ -160,7 +350,7 @@ fn object_from_feed_entry(apub_base_url: &str, uid: &str, entry: &Entry) -> Obje
     object = object.augment(
         "to",
         vec![
-            format!("{apub_base_url}/users/{uid}/followers"),
+            format!("{actor_iri}/followers"),
             "https://www.w3.org/ns/activitystreams#Public".to_string(),
         ]
         .into(),
@@ -168,3 +358,68 @@ fn object_from_feed_entry(apub_base_url: &str, uid: &str, entry: &Entry) -> Obje
 
     object
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use jiff::Timestamp;
+    use tokio::time::Instant;
+
+    use crate::ActivityPubConfig;
+
+    use super::{parse_retry_after, FeedSlurpWorkerState, MAX_RETRY_AFTER};
+
+    #[test]
+    fn retry_after_as_seconds_or_date() {
+        let now: Timestamp = "2025-01-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 01 Jan 2025 00:01:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        // A date in the past means right away.
+        assert_eq!(
+            parse_retry_after("Tue, 31 Dec 2024 23:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("999999999", now), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn space_fetches_from_the_same_host() {
+        let mut state = FeedSlurpWorkerState {
+            apub: ActivityPubConfig::default(),
+            politeness_delay: Duration::from_secs(1),
+            failures: HashMap::new(),
+            host_ready: HashMap::new(),
+            retry_at: HashMap::new(),
+        };
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            state.reserve_host("https://a.example/feed1", now),
+            Duration::ZERO
+        );
+        assert_eq!(state.reserve_host("https://a.example/feed2", now), second);
+        assert_eq!(
+            state.reserve_host("https://a.example/feed3", now),
+            2 * second
+        );
+        assert_eq!(
+            state.reserve_host("https://b.example/feed", now),
+            Duration::ZERO
+        );
+        // Once the reserved slots passed, the host is ready right away.
+        let later = now + 5 * second;
+        assert_eq!(
+            state.reserve_host("https://a.example/feed1", later),
+            Duration::ZERO
+        );
+    }
+}
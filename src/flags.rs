@@ -12,7 +12,57 @@ xflags::xflags! {
         optional -s, --server N: usize
 
         /// Run the server and start listen for HTTP requests.
-        cmd serve run {}
+        cmd serve run {
+            /// Wait up to SECS for a raft quorum with a known leader before
+            /// listening for HTTP requests, exit if none forms. The last
+            /// check is printed as JSON.
+            optional --wait-for-quorum SECS: u64
+        }
+
+        /// Write a consistent copy of the server's database to a new directory.
+        cmd backup {
+            /// Backup directory, must not exist yet.
+            required out: PathBuf
+        }
+
+        /// Restore the server's database from a backup directory.
+        ///
+        /// The server's database must be empty.
+        cmd restore {
+            /// Backup directory created by the backup command.
+            required from: PathBuf
+        }
+
+        /// Print the server's Raft log and saved state as JSON lines.
+        ///
+        /// The server must not be running.
+        cmd dump-log {
+            /// First log index to print, default from the start of the log.
+            optional --from N: u64
+            /// Last log index to print, default to the end of the log.
+            optional --to M: u64
+        }
+
+        /// Print the term and vote the server's Raft saved, or overwrite them
+        /// to recover a server stuck on a corrupted vote.
+        ///
+        /// The server must not be running. Overwriting is a last resort, back
+        /// up the database first. Raft is only safe while no server votes
+        /// twice in a term and no term goes back: a vote the server did not
+        /// cast, or a term at or below one it already voted in, can elect two
+        /// leaders and lose committed entries. Prefer a term above the term of
+        /// every server in the cluster and no vote.
+        cmd raft-state {
+            /// Overwrite the current term, not below the term of the last log
+            /// entry. The vote is cleared unless set again.
+            optional --set-term TERM: u32
+            /// Overwrite the vote in the current term with a configured server.
+            optional --set-voted-for NAME: String
+            /// Forget the vote in the current term.
+            optional --clear-vote
+            /// Confirm an overwrite by repeating the name of the server.
+            optional --confirm NAME: String
+        }
     }
 }
 
@@ -29,10 +79,40 @@ pub struct Pinka {
 #[derive(Debug)]
 pub enum PinkaCmd {
     Serve(Serve),
+    Backup(Backup),
+    Restore(Restore),
+    DumpLog(DumpLog),
+    RaftState(RaftState),
 }
 
 #[derive(Debug)]
-pub struct Serve;
+pub struct Serve {
+    pub wait_for_quorum: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Backup {
+    pub out: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Restore {
+    pub from: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct DumpLog {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct RaftState {
+    pub set_term: Option<u32>,
+    pub set_voted_for: Option<String>,
+    pub clear_vote: bool,
+    pub confirm: Option<String>,
+}
 
 impl Pinka {
     #[allow(dead_code)]
@@ -0,0 +1,754 @@
+//! The ActivityPub endpoints served by a single-node cluster.
+//!
+//! The state machine and the raft worker register under fixed names, so a
+//! process can run only one server. A single test walks through all the
+//! scenarios against it, each step building on the ones before.
+
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use aws_lc_rs::rsa::KeyPair;
+use ractor::{Actor, ActorRef};
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, LOCATION};
+use reqwest::{Client, StatusCode};
+use secrecy::ExposeSecret;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio::sync::MutexGuard;
+
+use super::router;
+use crate::activity_pub::machine::{ActivityPubMachine, ActivityPubMachineInit, AppliedIndex};
+use crate::activity_pub::model::Object;
+use crate::activity_pub::{
+    post_headers, ActorCache, Batch, CryptoRepo, ObjectKey, ObjectRepo, UserIndex,
+};
+use crate::config::{
+    ActivityPubConfig, CacheConfig, ClusterConfig, Config, FederationConfig, RaftConfig,
+    RuntimeConfig, ServerConfig,
+};
+use crate::raft::{RaftServer, RaftServerMsg, StateMachineMsg};
+use crate::storage_health::StorageHealth;
+use crate::supervisor::NAMED_ACTORS;
+
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+const AS_JSON: &str = "application/activity+json";
+
+struct TestServer {
+    base_url: String,
+    config: RuntimeConfig,
+    client: Client,
+    machine: ActorRef<StateMachineMsg>,
+    raft: ActorRef<RaftServerMsg>,
+    _dir: TempDir,
+    _actors: MutexGuard<'static, ()>,
+}
+
+impl TestServer {
+    /// Boot the state machine, a raft server that is its own cluster and the
+    /// HTTP API on a free port, then wait for the server to lead.
+    async fn start() -> Result<TestServer> {
+        let actors = NAMED_ACTORS.lock().await;
+        let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let base_url = format!("http://127.0.0.1:{port}");
+        let dir = tempfile::tempdir()?;
+        let server = ServerConfig {
+            name: "single".to_string(),
+            hostname: "127.0.0.1".to_string(),
+            port,
+            ..Default::default()
+        };
+        let init = Config {
+            raft: RaftConfig {
+                heartbeat_ms: 20,
+                min_election_ms: 50,
+                max_election_ms: 100,
+                ..Default::default()
+            },
+            cluster: ClusterConfig {
+                servers: vec![server.clone()],
+                ..Default::default()
+            },
+            activity_pub: ActivityPubConfig {
+                base_url: base_url.clone(),
+                webfinger_at_host: "127.0.0.1".to_string(),
+                shared_inbox: true,
+                ..Default::default()
+            },
+            federation: FederationConfig {
+                block: vec!["blocked.example".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let config = RuntimeConfig {
+            init,
+            server,
+            keyspace: fjall::Config::new(dir.path().join("single")).open()?,
+            actor_cache: ActorCache::new(&CacheConfig::default()),
+            applied_index: AppliedIndex::default(),
+            storage: StorageHealth::default(),
+        };
+        let (machine, _) = Actor::spawn(
+            Some("state_machine".into()),
+            ActivityPubMachine,
+            ActivityPubMachineInit {
+                apub: config.init.activity_pub.clone(),
+                keyspace: config.keyspace.clone(),
+                actor_cache: config.actor_cache.clone(),
+                applied_index: config.applied_index.clone(),
+                storage: config.storage.clone(),
+            },
+        )
+        .await?;
+        let (raft, _) = Actor::spawn(None, RaftServer, config.clone()).await?;
+        // A new leader appends an entry before it takes requests.
+        ensure!(
+            config
+                .applied_index
+                .wait_for(1, Duration::from_secs(10))
+                .await,
+            "no leader was elected"
+        );
+        let app = router(&config)?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(TestServer {
+            base_url,
+            config,
+            client: Client::new(),
+            machine,
+            raft,
+            _dir: dir,
+            _actors: actors,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    async fn get(&self, url: &str) -> Result<Value> {
+        let response = self
+            .client
+            .get(url)
+            .header(reqwest::header::ACCEPT, AS_JSON)
+            .send()
+            .await?;
+        ensure!(
+            response.status() == StatusCode::OK,
+            "GET {url}: {}",
+            response.status()
+        );
+        Ok(response.json().await?)
+    }
+
+    /// Content of the objects on the pages from `start` on, following `link`
+    /// until a page has none.
+    async fn walk(&self, start: String, link: &str) -> Result<Vec<Value>> {
+        let mut contents = vec![];
+        let mut url = Some(start);
+        while let Some(page_url) = url {
+            let page = self.get(&page_url).await?;
+            for item in page["orderedItems"].as_array().context("no items")? {
+                contents.push(item["object"]["content"].clone());
+            }
+            url = page[link].as_str().map(str::to_string);
+        }
+        Ok(contents)
+    }
+
+    async fn admin_get(&self, path: &str) -> Result<Value> {
+        let password = self.config.init.admin.password.expose_secret();
+        let response = self
+            .client
+            .get(self.url(path))
+            .basic_auth("pinka", Some(password))
+            .send()
+            .await?;
+        ensure!(
+            response.status() == StatusCode::OK,
+            "GET {path}: {}",
+            response.status()
+        );
+        Ok(response.json().await?)
+    }
+
+    async fn admin_post(&self, path: &str, body: Value) -> Result<reqwest::Response> {
+        let password = self.config.init.admin.password.expose_secret();
+        Ok(self
+            .client
+            .post(self.url(path))
+            .basic_auth("pinka", Some(password))
+            .json(&body)
+            .send()
+            .await?)
+    }
+
+    /// Create a local user with a fresh signing key.
+    async fn create_user(&self, uid: &str) -> Result<()> {
+        let person = json!({"type": "Person", "id": uid, "preferredUsername": uid});
+        let response = self
+            .admin_post(&format!("/users/{uid}?gen_rsa=true"), person)
+            .await?;
+        ensure!(
+            response.status().is_success(),
+            "create {uid}: {}",
+            response.status()
+        );
+        Ok(())
+    }
+
+    /// The signing key of a local user, to pose as a remote actor with.
+    fn key_pair(&self, uid: &str) -> Result<KeyPair> {
+        let key_material = CryptoRepo::new(self.config.keyspace.clone())?
+            .find_one(uid)?
+            .context("user has no key")?;
+        Ok(KeyPair::from_pkcs8(key_material.expose_secret())?)
+    }
+
+    /// Record that `uid` follows `actor_iri`, like a Move leaves it.
+    fn follow(&self, uid: &str, actor_iri: &str) -> Result<()> {
+        let keyspace = &self.config.keyspace;
+        let follow_key = ObjectKey::new();
+        let follow = json!({"type": "Follow", "actor": self.url(&format!("/users/{uid}")), "object": actor_iri});
+        let mut b = Batch::new(keyspace);
+        ObjectRepo::new(keyspace.clone())?.insert(&mut b, follow_key, Object::from(follow))?;
+        UserIndex::new(keyspace.clone())?.insert_following(&mut b, uid, follow_key)?;
+        b.commit()?;
+        Ok(())
+    }
+
+    /// Post `activity` to the inbox of `uid`, signed by `actor_iri`.
+    async fn deliver(
+        &self,
+        uid: &str,
+        actor_iri: &str,
+        key_pair: &KeyPair,
+        activity: Value,
+    ) -> Result<StatusCode> {
+        let inbox = format!("/users/{uid}/inbox");
+        self.deliver_to(&inbox, actor_iri, key_pair, activity).await
+    }
+
+    async fn deliver_to(
+        &self,
+        path: &str,
+        actor_iri: &str,
+        key_pair: &KeyPair,
+        activity: Value,
+    ) -> Result<StatusCode> {
+        let inbox = self.url(path);
+        let body = activity.to_string();
+        let headers = post_headers(actor_iri, &inbox, &body, key_pair)?;
+        let response = self
+            .client
+            .post(&inbox)
+            .header(CONTENT_TYPE, AS_JSON)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await?;
+        Ok(response.status())
+    }
+
+    /// `Content-Encoding` of the response to a GET of `url` that accepts
+    /// the encodings `accept`.
+    async fn content_encoding(&self, url: &str, accept: &str) -> Result<Option<String>> {
+        // This client would ask for gzip itself and decode the body.
+        let client = Client::builder().no_gzip().build()?;
+        let response = client
+            .get(url)
+            .header(ACCEPT_ENCODING, accept)
+            .send()
+            .await?;
+        ensure!(
+            response.status() == StatusCode::OK,
+            "GET {url}: {}",
+            response.status()
+        );
+        let encoding = response.headers().get(CONTENT_ENCODING);
+        Ok(encoding
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string))
+    }
+
+    async fn shutdown(self) -> Result<()> {
+        self.raft.stop_and_wait(None, None).await?;
+        self.machine.stop_and_wait(None, None).await?;
+        Ok(())
+    }
+}
+
+/// `totalItems` of the `likes` or `shares` of the note in an outbox page.
+fn count_of(page: &Value, note: &str, prop: &str) -> Option<u64> {
+    page["orderedItems"]
+        .as_array()?
+        .iter()
+        .find(|item| item["object"]["id"] == note)?["object"][prop]["totalItems"]
+        .as_u64()
+}
+
+#[tokio::test]
+async fn activity_pub_endpoints() -> Result<()> {
+    let server = TestServer::start().await?;
+    let alice = server.url("/users/alice");
+    let bob = server.url("/users/bob");
+
+    // The actor document links its collections and publishes its key.
+    server.create_user("alice").await?;
+    let response = server.client.get(&alice).send().await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\""
+    );
+    let actor: Value = response.json().await?;
+    assert_eq!(actor["type"], "Person");
+    assert_eq!(actor["id"], alice);
+    assert_eq!(actor["inbox"], format!("{alice}/inbox"));
+    assert_eq!(actor["outbox"], format!("{alice}/outbox"));
+    assert_eq!(actor["followers"], format!("{alice}/followers"));
+    assert_eq!(actor["endpoints"]["sharedInbox"], server.url("/inbox"));
+    assert_eq!(actor["publicKey"]["owner"], alice);
+    assert!(actor["publicKey"]["publicKeyPem"]
+        .as_str()
+        .is_some_and(|pem| pem.starts_with("-----BEGIN PUBLIC KEY-----")));
+
+    // Posting to the outbox wraps each note in a Create.
+    for n in 0..3 {
+        let note = json!({"type": "Note", "content": format!("note {n}"), "to": [PUBLIC]});
+        let response = server.admin_post("/users/alice/outbox", note).await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[LOCATION].to_str()?;
+        assert!(
+            location.starts_with(&server.url("/as/objects/")),
+            "{location}"
+        );
+    }
+    // Bodies far past the size limit are cut off while reading, which is
+    // still a limit violation and not a malformed request.
+    let max_bytes = server.config.init.activity_pub.limits.max_bytes;
+    let huge = json!({"type": "Note", "content": "x".repeat(10 * max_bytes), "to": [PUBLIC]});
+    let response = server.admin_post("/users/alice/outbox", huge).await?;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let outbox = server
+        .get(&server.url("/users/alice/outbox?inline=false"))
+        .await?;
+    assert_eq!(outbox["type"], "OrderedCollection");
+    assert_eq!(outbox["id"], format!("{alice}/outbox"));
+    assert_eq!(outbox["totalItems"], 3);
+    let first = outbox["first"]
+        .as_str()
+        .context("outbox has no first page")?;
+    assert!(outbox["last"].is_string());
+
+    // Pages run newest first and link to the older ones.
+    let page = server.get(&format!("{first}&last=2")).await?;
+    assert_eq!(page["type"], "OrderedCollectionPage");
+    assert_eq!(page["partOf"], format!("{alice}/outbox"));
+    let items = page["orderedItems"].as_array().context("no items")?;
+    assert_eq!(items.len(), 2);
+    assert!(items.iter().all(|item| item["type"] == "Create"
+        && item["actor"] == alice
+        && item["object"]["type"] == "Note"
+        && item["object"]["attributedTo"] == alice));
+    assert_eq!(items[0]["object"]["content"], "note 2");
+    assert_eq!(items[1]["object"]["content"], "note 1");
+    let note = items[0]["object"]["id"]
+        .as_str()
+        .context("note has no id")?
+        .to_string();
+    assert_eq!(count_of(&page, &note, "likes"), Some(0));
+    assert!(page["prev"].is_null());
+    let next = page["next"].as_str().context("page has no next")?;
+    let older = server.get(next).await?;
+    let items = older["orderedItems"].as_array().context("no items")?;
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["object"]["content"], "note 0");
+    assert!(older["next"].is_null());
+
+    // Walking the links either way visits every item exactly once.
+    let newest_first = server.walk(format!("{first}&last=1"), "next").await?;
+    assert_eq!(newest_first, ["note 2", "note 1", "note 0"]);
+    let last = outbox["last"].as_str().context("outbox has no last page")?;
+    let oldest_first = server.walk(format!("{last}&first=1"), "prev").await?;
+    assert_eq!(oldest_first, ["note 0", "note 1", "note 2"]);
+
+    // A page in the middle links to itself, its collection and both
+    // neighbours by the key of its item.
+    let newest = server.get(&format!("{first}&last=1")).await?;
+    let middle_url = newest["next"].as_str().context("page has no next")?;
+    let middle = server.get(middle_url).await?;
+    let create = middle["orderedItems"][0]["id"]
+        .as_str()
+        .context("activity has no id")?;
+    let key = create
+        .rsplit('/')
+        .next()
+        .context("activity id has no key")?;
+    let outbox_iri = format!("{alice}/outbox");
+    assert_eq!(middle["type"], "OrderedCollectionPage");
+    assert_eq!(middle["id"], middle_url);
+    assert_eq!(middle["partOf"], outbox_iri);
+    assert_eq!(middle["next"], format!("{outbox_iri}?before={key}&last=1"));
+    assert_eq!(middle["prev"], format!("{outbox_iri}?after={key}&first=1"));
+    assert_eq!(middle["first"], outbox["first"]);
+    assert_eq!(middle["last"], outbox["last"]);
+
+    // Followers-only activities are left out, except for the admin.
+    let private =
+        json!({"type": "Note", "content": "private", "to": [format!("{alice}/followers")]});
+    let response = server.admin_post("/users/alice/outbox", private).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let newest_first = server.walk(format!("{first}&last=2"), "next").await?;
+    assert_eq!(newest_first, ["note 2", "note 1", "note 0"]);
+    let outbox = server
+        .get(&server.url("/users/alice/outbox?inline=false"))
+        .await?;
+    assert_eq!(outbox["totalItems"], 3);
+    let outbox = server.admin_get("/users/alice/outbox?inline=true").await?;
+    assert_eq!(outbox["totalItems"], 4);
+    assert_eq!(
+        outbox["first"]["orderedItems"][0]["object"]["content"],
+        "private"
+    );
+
+    // Bob poses as a remote actor with a key our resolver can fetch.
+    server.create_user("bob").await?;
+    let key = server.key_pair("bob")?;
+
+    // Posts naming no audience go to the one the user picked.
+    let followers_only = json!({"visibility": "followers"});
+    let response = server
+        .admin_post("/as/admin/users/bob/visibility", followers_only.clone())
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = server
+        .admin_post("/as/admin/users/nobody/visibility", followers_only)
+        .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let quiet = json!({"type": "Note", "content": "quiet"});
+    let response = server.admin_post("/users/bob/outbox", quiet).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let outbox = server.admin_get("/users/bob/outbox?inline=true").await?;
+    let create = &outbox["first"]["orderedItems"][0];
+    assert_eq!(create["to"], json!([format!("{bob}/followers")]));
+    assert_eq!(create["object"]["to"], create["to"]);
+    assert!(create["cc"].is_null());
+    let latest = format!("{first}&last=1");
+    let activity = |n: u32, kind: &str, object: Value| {
+        json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{bob}/activities/{n}"),
+            "type": kind,
+            "actor": bob,
+            "object": object,
+        })
+    };
+
+    let unsigned = server
+        .client
+        .post(server.url("/users/alice/inbox"))
+        .header(CONTENT_TYPE, AS_JSON)
+        .body(activity(0, "Like", json!(note)).to_string())
+        .send()
+        .await?;
+    assert_eq!(unsigned.status(), StatusCode::BAD_REQUEST);
+    // Keys on blocked servers are not even fetched.
+    let eve = "https://blocked.example/users/eve";
+    let status = server
+        .deliver("alice", eve, &key, activity(0, "Like", json!(note)))
+        .await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    // Without an actor there is no sender to admit.
+    let mut anonymous = activity(0, "Like", json!(note));
+    anonymous
+        .as_object_mut()
+        .context("not an object")?
+        .remove("actor");
+    let status = server.deliver("alice", &bob, &key, anonymous).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let follow = activity(1, "Follow", json!(alice));
+    let status = server.deliver("alice", &bob, &key, follow.clone()).await?;
+    assert_eq!(status, StatusCode::CREATED);
+    let followers = server.get(&format!("{alice}/followers")).await?;
+    assert_eq!(followers["totalItems"], 1);
+
+    let like = activity(2, "Like", json!(note));
+    let inbox = server.url("/users/alice/inbox");
+    let body = like.to_string();
+    let headers = post_headers(&bob, &inbox, &body, &key)?;
+    let signed = || {
+        server
+            .client
+            .post(&inbox)
+            .header(CONTENT_TYPE, AS_JSON)
+            .headers(headers.clone())
+            .body(body.clone())
+            .send()
+    };
+    let status = signed().await?.status();
+    assert!(status.is_success(), "Like: {status}");
+    // The captured request cannot be posted again.
+    assert_eq!(signed().await?.status(), StatusCode::CONFLICT);
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "likes"), Some(1));
+
+    let announce = activity(3, "Announce", json!(note));
+    let status = server.deliver("alice", &bob, &key, announce).await?;
+    assert!(status.is_success(), "Announce: {status}");
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "shares"), Some(1));
+
+    let reply_iri = format!("{bob}/notes/1");
+    let reply = json!({
+        "id": reply_iri,
+        "type": "Note",
+        "attributedTo": bob,
+        "inReplyTo": note,
+        "content": "reply",
+        "to": [alice],
+    });
+    let mut create = activity(4, "Create", reply.clone());
+    create["to"] = json!([alice]);
+    let status = server.deliver("alice", &bob, &key, create).await?;
+    assert!(status.is_success(), "Create: {status}");
+
+    let mut edited = reply;
+    edited["content"] = json!("edited reply");
+    let status = server
+        .deliver("alice", &bob, &key, activity(5, "Update", edited))
+        .await?;
+    assert!(status.is_success(), "Update: {status}");
+
+    let status = server
+        .deliver("alice", &bob, &key, activity(6, "Delete", json!(reply_iri)))
+        .await?;
+    assert!(status.is_success(), "Delete: {status}");
+
+    let status = server
+        .deliver("alice", &bob, &key, activity(7, "Dislike", json!(note)))
+        .await?;
+    assert!(status.is_success(), "Dislike: {status}");
+
+    // Reports only show up for moderators.
+    let mut flag = activity(8, "Flag", json!([alice, note]));
+    flag["content"] = json!("spam");
+    let status = server.deliver("alice", &bob, &key, flag).await?;
+    assert!(status.is_success(), "Flag: {status}");
+    let reports = server.admin_get("/as/admin/reports").await?;
+    assert_eq!(reports.as_array().map(Vec::len), Some(1));
+    assert_eq!(reports[0]["report"]["content"], "spam");
+
+    // Alice never followed bob, there is nothing to reject.
+    let rejected = json!({"id": format!("{alice}/follows/1"), "type": "Follow", "actor": alice, "object": bob});
+    let status = server
+        .deliver("alice", &bob, &key, activity(9, "Reject", rejected))
+        .await?;
+    assert!(status.is_success(), "Reject: {status}");
+
+    // The target does not exist and does not list bob as an alias.
+    let mut moved = activity(10, "Move", json!(bob));
+    moved["target"] = json!(server.url("/users/carol"));
+    let status = server.deliver("alice", &bob, &key, moved).await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let status = server
+        .deliver("alice", &bob, &key, activity(11, "Listen", json!(note)))
+        .await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let status = server
+        .deliver("alice", &bob, &key, activity(12, "Undo", like))
+        .await?;
+    assert!(status.is_success(), "Undo Like: {status}");
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "likes"), Some(0));
+
+    let status = server
+        .deliver("alice", &bob, &key, activity(13, "Undo", follow))
+        .await?;
+    assert!(status.is_success(), "Undo Follow: {status}");
+    let followers = server.get(&format!("{alice}/followers")).await?;
+    assert_eq!(followers["totalItems"], 0);
+
+    // The shared inbox hands activities to the local users they are for.
+    let follow = activity(14, "Follow", json!(alice));
+    let status = server
+        .deliver_to("/inbox", &bob, &key, follow.clone())
+        .await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let followers = server.get(&format!("{alice}/followers")).await?;
+    assert_eq!(followers["totalItems"], 1);
+    let status = server
+        .deliver_to("/inbox", &bob, &key, activity(15, "Undo", follow))
+        .await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let followers = server.get(&format!("{alice}/followers")).await?;
+    assert_eq!(followers["totalItems"], 0);
+
+    // Posts to the followers of the sender go to whoever here follows it.
+    // Bob is local, his followers collection would name him, so the boost
+    // is only public.
+    let mut boost = activity(16, "Announce", json!(note));
+    boost["to"] = json!([PUBLIC]);
+    let status = server
+        .deliver_to("/inbox", &bob, &key, boost.clone())
+        .await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "shares"), Some(1));
+    server.follow("alice", &bob)?;
+    boost["id"] = json!(format!("{bob}/activities/17"));
+    let status = server.deliver_to("/inbox", &bob, &key, boost).await?;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "shares"), Some(2));
+
+    // Blocking bob takes back what he did to the note.
+    let status = server
+        .deliver("alice", &bob, &key, activity(18, "Like", json!(note)))
+        .await?;
+    assert!(status.is_success(), "Like: {status}");
+    let likes = server.get(&format!("{note}/likes")).await?;
+    assert_eq!(likes["totalItems"], 1);
+    let block = json!({"type": "Block", "object": bob});
+    let response = server.admin_post("/users/alice/outbox", block).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let likes = server.get(&format!("{note}/likes")).await?;
+    assert_eq!(likes["totalItems"], 0);
+    let page = server.get(&latest).await?;
+    assert_eq!(count_of(&page, &note, "likes"), Some(0));
+    assert_eq!(count_of(&page, &note, "shares"), Some(0));
+
+    // A boost goes to the followers and shows up in the outbox.
+    let boost = json!({"type": "Announce", "object": note});
+    let response = server.admin_post("/users/alice/outbox", boost).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let page = server.get(&latest).await?;
+    let boost = &page["orderedItems"][0];
+    assert_eq!(boost["type"], "Announce");
+    assert_eq!(boost["actor"], alice);
+    assert_eq!(boost["object"], note);
+    assert_eq!(boost["cc"], json!([format!("{alice}/followers")]));
+
+    // The author edits the note, the edit keeps its audience.
+    let edit = json!({"type": "Update", "object": {"id": note, "content": "edited"}});
+    let response = server.admin_post("/users/bob/outbox", edit.clone()).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = server.admin_post("/users/alice/outbox", edit).await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let edited = server.get(&note).await?;
+    assert_eq!(edited["content"], "edited");
+    assert_eq!(edited["to"], json!([PUBLIC]));
+    assert!(edited["updated"].is_string());
+    let page = server.get(&latest).await?;
+    assert_eq!(page["orderedItems"][0]["type"], "Update");
+    // Creates embed the object as it is now, or name it when asked to.
+    let create_of = |page: &Value| {
+        page["orderedItems"]
+            .as_array()
+            .and_then(|items| {
+                items.iter().find(|item| {
+                    item["type"] == "Create"
+                        && (item["object"] == note || item["object"]["id"] == note)
+                })
+            })
+            .cloned()
+    };
+    let page = server.get(&format!("{first}&first=50")).await?;
+    let create = create_of(&page).context("no Create of the note")?;
+    assert_eq!(create["object"]["content"], "edited");
+    let page = server.get(&format!("{first}&first=50&embed=false")).await?;
+    let create = create_of(&page).context("no Create of the note")?;
+    assert_eq!(create["object"], note);
+    let page = server.get(&format!("{first}&last=1&embed=false")).await?;
+    let next = page["next"].as_str().context("page has no next")?;
+    assert!(next.ends_with("&embed=false"), "{next}");
+
+    // A page of older items, taken before the delete.
+    let newest = server.get(&format!("{first}&last=2")).await?;
+    let older_url = newest["next"].as_str().context("page has no next")?;
+    let older = server.get(older_url).await?;
+    assert_eq!(older["orderedItems"][0]["object"]["id"], note);
+
+    // Deleting the note leaves a Tombstone, the Delete goes to its audience.
+    let delete = json!({"type": "Delete", "object": note});
+    let response = server
+        .admin_post("/users/bob/outbox", delete.clone())
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = server
+        .admin_post("/users/alice/outbox", delete.clone())
+        .await?;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[LOCATION].to_str()?.to_string();
+    let activity = server.get(&location).await?;
+    assert_eq!(activity["type"], "Delete");
+    assert_eq!(activity["to"], json!([PUBLIC]));
+    assert_eq!(activity["object"]["type"], "Tombstone");
+    assert_eq!(activity["object"]["id"], note);
+    assert_eq!(activity["object"]["formerType"], "Note");
+    let response = server.client.get(&note).send().await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let response = server.admin_post("/users/alice/outbox", delete).await?;
+    assert_eq!(response.status(), StatusCode::GONE);
+    let page = server.get(&latest).await?;
+    assert_eq!(page["orderedItems"][0]["type"], "Delete");
+    // The cursor still pages over the same items, the note as a Tombstone.
+    let again = server.get(older_url).await?;
+    let ids = |page: &Value| {
+        page["orderedItems"].as_array().map(|items| {
+            items
+                .iter()
+                .map(|item| item["id"].clone())
+                .collect::<Vec<_>>()
+        })
+    };
+    assert_eq!(ids(&again), ids(&older));
+    assert_eq!(again["orderedItems"][0]["object"]["type"], "Tombstone");
+    assert_eq!(again["next"], older["next"]);
+    // The Create and Update in the outbox no longer carry the content.
+    let page = server.get(&format!("{first}&first=50")).await?;
+    assert!(!page.to_string().contains("\"edited\""), "{page}");
+    // Every activity the server wrote says when.
+    for item in page["orderedItems"].as_array().context("no items")? {
+        let published = item["published"].as_str().unwrap_or_default();
+        assert!(published.parse::<jiff::Timestamp>().is_ok(), "{item}");
+    }
+
+    // Other servers find the NodeInfo document through the well-known link.
+    let links = server.get(&server.url("/.well-known/nodeinfo")).await?;
+    let href = links["links"][0]["href"].as_str().context("no nodeinfo")?;
+    assert_eq!(href, server.url("/nodeinfo/2.1"));
+    let nodeinfo = server.get(href).await?;
+    assert_eq!(nodeinfo["software"]["name"], "pinka");
+    let version = nodeinfo["software"]["version"].as_str().unwrap_or_default();
+    assert!(version.starts_with(env!("CARGO_PKG_VERSION")), "{version}");
+    assert_eq!(nodeinfo["usage"]["users"]["total"], 2);
+    let build = server.get(&server.url("/version")).await?;
+    assert_eq!(build["version"], env!("CARGO_PKG_VERSION"));
+    assert!(build["git_commit"].is_string());
+
+    // Responses are compressed the way the client prefers, unless small.
+    let page = format!("{first}&first=50");
+    for (accept, expected) in [
+        ("br", Some("br")),
+        ("gzip", Some("gzip")),
+        ("gzip;q=0.5, br", Some("br")),
+        ("br;q=0.5, gzip", Some("gzip")),
+        ("identity", None),
+    ] {
+        let encoding = server.content_encoding(&page, accept).await?;
+        assert_eq!(encoding.as_deref(), expected, "{accept}");
+    }
+    let small = server
+        .content_encoding(&server.url("/version"), "br, gzip")
+        .await?;
+    assert_eq!(small, None);
+
+    server.shutdown().await
+}
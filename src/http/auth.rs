@@ -1,6 +1,7 @@
 use std::str;
 
 use axum::extract::Request;
+use axum::http::HeaderMap;
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use axum::Extension;
@@ -15,33 +16,28 @@ pub(super) async fn admin_basic_auth(
     req: Request,
     next: Next,
 ) -> Response {
-    fn need_auth() -> Response {
+    if !is_admin(req.headers(), &admin) {
         let authn = [("www-authenticate", "Basic realm=\"admin\"")];
-        (StatusCode::UNAUTHORIZED, authn).into_response()
+        return (StatusCode::UNAUTHORIZED, authn).into_response();
     }
-    let Some(authz) = req.headers().get("authorization") else {
-        return need_auth();
-    };
+    next.run(req).await
+}
 
-    let Ok(cred) = authz.to_str() else {
-        return need_auth();
-    };
-    let Some(cred) = cred
-        .strip_prefix("Basic ")
+/// Whether the request carries the admin credentials, for endpoints that
+/// also serve anonymous clients but show the admin more.
+pub(super) fn is_admin(headers: &HeaderMap, admin: &AdminConfig) -> bool {
+    let Some(cred) = headers
+        .get("authorization")
+        .and_then(|authz| authz.to_str().ok())
+        .and_then(|cred| cred.strip_prefix("Basic "))
         .map(str::trim)
         .and_then(|b64| Base64::decode_vec(b64).ok())
         .and_then(|b| String::from_utf8(b).ok())
     else {
-        return need_auth();
+        return false;
     };
-
     let Some((user, password)) = cred.split_once(':') else {
-        return need_auth();
+        return false;
     };
-
-    if user != "pinka" || password != admin.password.expose_secret() {
-        return need_auth();
-    }
-
-    next.run(req).await
+    user == "pinka" && password == admin.password.expose_secret()
 }
@@ -0,0 +1,359 @@
+//! Backpressure between HTTP handlers, the raft log and the state machine.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::extract::Request;
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use metrics::{counter, gauge};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::HttpConfig;
+use crate::storage_health::StorageHealth;
+
+/// Seconds a rejected client is asked to wait before trying again.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Seconds a client is asked to wait while the storage is degraded, which
+/// takes an operator to fix.
+const DEGRADED_RETRY_AFTER_SECS: u64 = 60;
+
+/// Bounds the number of writing requests in progress.
+///
+/// A writing request submits one or more commands to the raft log, one
+/// after another. The limit is taken once for the whole request, so an
+/// accepted request never fails halfway because the log is busy.
+#[derive(Clone)]
+pub(super) struct WriteLimiter {
+    permits: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl WriteLimiter {
+    pub(super) fn new(config: &HttpConfig) -> WriteLimiter {
+        let limit = config.max_concurrent_writes.max(1);
+        WriteLimiter {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+    fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+    fn depth(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+}
+
+/// Reject writing requests with 503 while the storage of this server is
+/// degraded and could not keep them. Layered on every write route.
+pub(super) async fn reject_degraded(
+    Extension(storage): Extension<StorageHealth>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if storage.is_degraded() {
+        counter!("pinka_http_writes_rejected_total", "reason" => "degraded").increment(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, DEGRADED_RETRY_AFTER_SECS.to_string())],
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Reject writing requests with 503 while the limit is reached, instead of
+/// queueing unbounded work in front of the raft log. Layered on the write
+/// routes other than inbox deliveries, see [`admit_inbox`].
+pub(super) async fn limit_writes(
+    Extension(limiter): Extension<WriteLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(permit) = limiter.try_acquire() else {
+        counter!("pinka_http_writes_rejected_total").increment(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+        )
+            .into_response();
+    };
+    gauge!("pinka_http_writes_in_flight").set(limiter.depth() as f64);
+    let response = next.run(request).await;
+    drop(permit);
+    gauge!("pinka_http_writes_in_flight").set(limiter.depth() as f64);
+    response
+}
+
+/// Bounds the number of reading requests in progress.
+///
+/// Reads query the keyspace on the blocking thread pool, where the state
+/// machine also applies commands. They never go through the state machine,
+/// so a slow apply does not hold them up, but without a bound a burst of
+/// reads could hold up applies.
+#[derive(Clone)]
+pub(super) struct ReadLimiter {
+    permits: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl ReadLimiter {
+    pub(super) fn new(config: &HttpConfig) -> ReadLimiter {
+        let limit = config.max_concurrent_reads.max(1);
+        ReadLimiter {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+    fn depth(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+}
+
+/// Queue reading requests while the limit is reached, they are cheap to
+/// hold and clients retry them anyway when they time out.
+pub(super) async fn limit_reads(
+    Extension(limiter): Extension<ReadLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let permit = limiter.acquire().await;
+    gauge!("pinka_http_reads_in_flight").set(limiter.depth() as f64);
+    let response = next.run(request).await;
+    drop(permit);
+    gauge!("pinka_http_reads_in_flight").set(limiter.depth() as f64);
+    response
+}
+
+/// Bounds the inbox deliveries in progress, apart from the other writes.
+///
+/// Verifying the signature of a delivery and submitting its activity is
+/// the most expensive work remote servers make us do, and a widely boosted
+/// post brings a flood of them. Deliveries beyond the limit wait for their
+/// turn, with too many waiting they are rejected and retried by their
+/// senders later. The per actor rate limits of the inbox apply on top.
+#[derive(Clone)]
+pub(super) struct InboxAdmission {
+    permits: Arc<Semaphore>,
+    limit: usize,
+    waiting: Arc<AtomicUsize>,
+    max_waiting: usize,
+}
+
+impl InboxAdmission {
+    pub(super) fn new(config: &HttpConfig) -> InboxAdmission {
+        let limit = config.max_concurrent_inbox.max(1);
+        InboxAdmission {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+            waiting: Arc::default(),
+            max_waiting: config.max_queued_inbox,
+        }
+    }
+    /// A permit once it is our turn, `None` if too many wait already.
+    async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        let waiting = Waiting::enter(&self.waiting);
+        if waiting.position >= self.max_waiting {
+            return None;
+        }
+        gauge!("pinka_http_inbox_queued").set(self.queued() as f64);
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        drop(waiting);
+        gauge!("pinka_http_inbox_queued").set(self.queued() as f64);
+        Some(permit)
+    }
+    fn depth(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+    fn queued(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+/// A delivery counted as waiting until dropped, also when its request is
+/// cancelled while it waits.
+struct Waiting<'a> {
+    waiting: &'a AtomicUsize,
+    /// Deliveries that waited before this one.
+    position: usize,
+}
+
+impl<'a> Waiting<'a> {
+    fn enter(waiting: &'a AtomicUsize) -> Waiting<'a> {
+        let position = waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting { waiting, position }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Admit inbox deliveries one limit at a time, rejecting them with 503
+/// while too many wait.
+pub(super) async fn admit_inbox(
+    Extension(admission): Extension<InboxAdmission>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(permit) = admission.admit().await else {
+        counter!("pinka_http_writes_rejected_total", "reason" => "inbox").increment(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+        )
+            .into_response();
+    };
+    gauge!("pinka_http_inbox_in_flight").set(admission.depth() as f64);
+    let response = next.run(request).await;
+    drop(permit);
+    gauge!("pinka_http_inbox_in_flight").set(admission.depth() as f64);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn;
+    use axum::routing::post;
+    use axum::{Extension, Router};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    use crate::config::HttpConfig;
+    use crate::storage_health::StorageHealth;
+
+    use super::{
+        admit_inbox, limit_writes, reject_degraded, InboxAdmission, ReadLimiter, WriteLimiter,
+    };
+
+    #[test]
+    fn reject_writes_beyond_limit() {
+        let limiter = WriteLimiter::new(&HttpConfig {
+            max_concurrent_writes: 2,
+            ..Default::default()
+        });
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert_eq!(limiter.depth(), 2);
+        assert!(limiter.try_acquire().is_none());
+        drop(first);
+        assert_eq!(limiter.depth(), 1);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_reads_beyond_limit() {
+        let limiter = ReadLimiter::new(&HttpConfig {
+            max_concurrent_reads: 1,
+            ..Default::default()
+        });
+        let first = limiter.acquire().await;
+        let wait = Duration::from_secs(1);
+        assert!(timeout(wait, limiter.acquire()).await.is_err());
+        drop(first);
+        let _second = timeout(wait, limiter.acquire()).await.unwrap();
+        assert_eq!(limiter.depth(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_then_shed_inbox_deliveries() {
+        let admission = InboxAdmission::new(&HttpConfig {
+            max_concurrent_inbox: 1,
+            max_queued_inbox: 1,
+            ..Default::default()
+        });
+        let first = admission.admit().await.unwrap();
+        let queued = tokio::spawn({
+            let admission = admission.clone();
+            async move { admission.admit().await.is_some() }
+        });
+        while admission.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        // The queue is full.
+        assert!(admission.admit().await.is_none());
+        assert_eq!(admission.queued(), 1);
+        drop(first);
+        assert!(queued.await.unwrap());
+        assert_eq!(admission.queued(), 0);
+
+        // A cancelled wait leaves the queue.
+        let _second = admission.admit().await.unwrap();
+        let wait = Duration::from_secs(1);
+        assert!(timeout(wait, admission.admit()).await.is_err());
+        assert_eq!(admission.queued(), 0);
+        assert_eq!(admission.depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn inbox_deliveries_leave_writes_alone() {
+        let config = HttpConfig {
+            max_concurrent_writes: 1,
+            max_concurrent_inbox: 1,
+            max_queued_inbox: 0,
+            ..Default::default()
+        };
+        let app = Router::new()
+            .route("/users/{id}/outbox", post(|| async { StatusCode::CREATED }))
+            .layer(from_fn(limit_writes))
+            .route(
+                "/inbox",
+                post(std::future::pending::<StatusCode>).layer(from_fn(admit_inbox)),
+            )
+            .layer(from_fn(reject_degraded))
+            .layer(Extension(WriteLimiter::new(&config)))
+            .layer(Extension(StorageHealth::default()))
+            .layer(Extension(InboxAdmission::new(&config)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // One delivery never finishes, the next ones are shed.
+        let client = reqwest::Client::new();
+        let inbox = format!("{base_url}/inbox");
+        tokio::spawn(client.post(&inbox).send());
+        loop {
+            let probe = client
+                .post(&inbox)
+                .timeout(Duration::from_millis(100))
+                .send()
+                .await;
+            if probe.is_ok_and(|r| r.status() == StatusCode::SERVICE_UNAVAILABLE) {
+                break;
+            }
+        }
+        let response = client
+            .post(format!("{base_url}/users/alice/outbox"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,246 @@
+//! Read-your-writes between HTTP requests.
+//!
+//! Writes are applied on every server, but not on all of them at the same
+//! time. A writing request responds with the index of the last log entry it
+//! committed in the `Pinka-Log-Index` header. A reading request that passes
+//! it back, in the `Pinka-Min-Index` header or the `min_index` query
+//! parameter, waits until this server applied that entry.
+//!
+//! Followers serve reads from their replica unless `follower_reads` is off,
+//! then reads are redirected to the `public_url` of the leader.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use axum::extract::Request;
+use axum::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use metrics::counter;
+
+use crate::activity_pub::machine::AppliedIndex;
+use crate::config::{HttpConfig, ServerConfig};
+use crate::raft::{get_raft_local_client, ClientError, ClientResult, RaftClientMsg};
+
+const LOG_INDEX: HeaderName = HeaderName::from_static("pinka-log-index");
+const MIN_INDEX: HeaderName = HeaderName::from_static("pinka-min-index");
+
+/// Seconds a client is asked to wait before reading again.
+const RETRY_AFTER_SECS: u64 = 1;
+
+tokio::task_local! {
+    /// Index of the last log entry the current request committed.
+    static WRITE_INDEX: Cell<u64>;
+}
+
+/// Note that the current request committed the log entry at `index`.
+pub(super) fn record_write(index: u64) {
+    let _ = WRITE_INDEX.try_with(|last| last.set(last.get().max(index)));
+}
+
+#[derive(Clone)]
+pub(super) struct ReadYourWrites {
+    applied_index: AppliedIndex,
+    timeout: Duration,
+}
+
+impl ReadYourWrites {
+    pub(super) fn new(config: &HttpConfig, applied_index: AppliedIndex) -> ReadYourWrites {
+        ReadYourWrites {
+            applied_index,
+            timeout: Duration::from_millis(config.min_index_timeout_ms),
+        }
+    }
+}
+
+/// Tag writes with their log index and hold reads until the index they ask
+/// for is applied.
+pub(super) async fn read_your_writes(
+    Extension(consistency): Extension<ReadYourWrites>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        let (mut response, index) = WRITE_INDEX
+            .scope(Cell::new(0), async {
+                let response = next.run(request).await;
+                (response, WRITE_INDEX.with(Cell::get))
+            })
+            .await;
+        if index > 0 {
+            response
+                .headers_mut()
+                .insert(LOG_INDEX, HeaderValue::from(index));
+        }
+        return response;
+    }
+    let Ok(min_index) = min_index(&request) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if let Some(min_index) = min_index {
+        if !consistency
+            .applied_index
+            .wait_for(min_index, consistency.timeout)
+            .await
+        {
+            counter!("pinka_http_min_index_timeouts_total").increment(1);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Where reads go when this server does not lead.
+#[derive(Clone)]
+pub(super) struct FollowerReads {
+    serve: bool,
+    /// Name and public URL of every server of the cluster that has one.
+    servers: Vec<(String, String)>,
+}
+
+impl FollowerReads {
+    pub(super) fn new(config: &HttpConfig, servers: &[ServerConfig]) -> FollowerReads {
+        let servers = servers
+            .iter()
+            .filter(|server| !server.http.public_url.is_empty())
+            .map(|server| (server.name.clone(), server.http.public_url.clone()))
+            .collect();
+        FollowerReads {
+            serve: config.follower_reads,
+            servers,
+        }
+    }
+    /// The same request sent to the server `leader`.
+    fn redirect(&self, leader: &str, request: &Request) -> Option<String> {
+        let (_, base_url) = self.servers.iter().find(|(name, _)| name == leader)?;
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        Some(format!("{base_url}{path}"))
+    }
+}
+
+/// Redirect reads to the leader when followers do not serve them.
+pub(super) async fn route_reads(
+    Extension(reads): Extension<FollowerReads>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if reads.serve
+        || !matches!(*request.method(), Method::GET | Method::HEAD)
+        || request.uri().path().starts_with("/as/admin/")
+    {
+        return next.run(request).await;
+    }
+    let Err(leader) = check_leader().await else {
+        return next.run(request).await;
+    };
+    match leader.and_then(|leader| reads.redirect(&leader, &request)) {
+        Some(location) => {
+            counter!("pinka_http_reads_redirected_total").increment(1);
+            (
+                StatusCode::TEMPORARY_REDIRECT,
+                [(header::LOCATION, location)],
+            )
+                .into_response()
+        }
+        None => StatusCode::MISDIRECTED_REQUEST.into_response(),
+    }
+}
+
+/// `Ok` if this server leads, else the leader if one is known.
+async fn check_leader() -> Result<(), Option<String>> {
+    let Ok(client) = get_raft_local_client() else {
+        return Err(None);
+    };
+    match ractor::call!(client, RaftClientMsg::CheckLeader).map(ClientResult::into_result) {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(ClientError::NotLeader { leader })) => Err(leader),
+        _ => Err(None),
+    }
+}
+
+/// The index a read asks for, from the header or else the query.
+fn min_index(request: &Request) -> Result<Option<u64>, ()> {
+    let value = match request.headers().get(MIN_INDEX) {
+        Some(value) => value.to_str().map_err(|_| ())?,
+        None => match request.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("min_index="))
+        }) {
+            Some(value) => value,
+            None => return Ok(None),
+        },
+    };
+    value.parse().map(Some).map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::extract::Request;
+
+    use crate::config::{HttpConfig, ServerConfig};
+
+    use super::{min_index, FollowerReads};
+
+    fn request(uri: &str, header: Option<&str>) -> Request {
+        let mut builder = Request::get(uri);
+        if let Some(value) = header {
+            builder = builder.header("pinka-min-index", value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn parse_min_index() {
+        assert_eq!(min_index(&request("/users/alice", None)), Ok(None));
+        assert_eq!(
+            min_index(&request("/users/alice/outbox?after=x&min_index=42", None)),
+            Ok(Some(42))
+        );
+        assert_eq!(
+            min_index(&request("/users/alice?min_index=1", Some("7"))),
+            Ok(Some(7))
+        );
+        assert!(min_index(&request("/users/alice?min_index=soon", None)).is_err());
+    }
+
+    #[test]
+    fn redirect_reads_to_leader() {
+        let server = |name: &str, public_url: &str| {
+            let mut server = ServerConfig {
+                name: name.to_string(),
+                hostname: format!("{name}.internal"),
+                ..Default::default()
+            };
+            server.http.public_url = public_url.to_string();
+            server
+        };
+        let reads = FollowerReads::new(
+            &HttpConfig {
+                follower_reads: false,
+                ..Default::default()
+            },
+            &[
+                server("s1", "https://s1.example.com"),
+                server("s2", "https://s2.example.com"),
+                server("s3", ""),
+            ],
+        );
+        let request = request("/users/alice/outbox?last=2", None);
+        assert_eq!(
+            reads.redirect("s2", &request).as_deref(),
+            Some("https://s2.example.com/users/alice/outbox?last=2")
+        );
+        assert_eq!(reads.redirect("s3", &request), None);
+        assert_eq!(reads.redirect("s4", &request), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! Request body extractors.
+
+use axum::body::to_bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use http_body_util::LengthLimitError;
+use serde_json::Value;
+
+use crate::activity_pub::from_json_slice;
+use crate::config::RuntimeConfig;
+
+/// A JSON document posted by a client or peer.
+///
+/// Unlike [`axum::Json`] the document must stay within the configured
+/// [`crate::config::ObjectLimits`], violations are rejected with 422.
+pub(super) struct ObjectJson(pub(super) Value);
+
+impl FromRequest<RuntimeConfig> for ObjectJson {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &RuntimeConfig) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+        }
+        let limits = &state.init.activity_pub.limits;
+        let bytes = match to_bytes(req.into_body(), limits.max_bytes).await {
+            Ok(bytes) => bytes,
+            // Oversized bodies fail the same way as other limit violations.
+            Err(error) if is_length_limit(&error) => {
+                let message = format!("document exceeds {} bytes", limits.max_bytes);
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, message).into_response());
+            }
+            Err(_) => return Err(StatusCode::BAD_REQUEST.into_response()),
+        };
+        match from_json_slice(&bytes, limits) {
+            Ok(value) => Ok(ObjectJson(value)),
+            Err(error) => {
+                Err((StatusCode::UNPROCESSABLE_ENTITY, format!("{error:#}")).into_response())
+            }
+        }
+    }
+}
+
+/// Whether reading the body stopped at the size limit rather than failed.
+fn is_length_limit(error: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if error.is::<LengthLimitError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// `application/json` or any `+json` type such as `application/activity+json`.
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
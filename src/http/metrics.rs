@@ -0,0 +1,41 @@
+//! Request counters and latency histograms for the HTTP API.
+
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use metrics::{counter, histogram};
+
+use crate::telemetry;
+
+/// Record every request by route template, method and status.
+///
+/// The route is the matched path template (e.g. `/users/{id}`) so label
+/// cardinality stays bounded; requests handled by the IRI fallback are
+/// grouped under `fallback`.
+pub(super) async fn track_metrics(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("fallback", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().to_string();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [("method", method), ("route", route), ("status", status)];
+    counter!("pinka_http_requests_total", &labels).increment(1);
+    histogram!("pinka_http_request_duration_seconds", &labels).record(start.elapsed());
+    response
+}
+
+pub(super) async fn get_metrics() -> Response {
+    match telemetry::render() {
+        Some(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -1,42 +1,78 @@
+#[cfg(test)]
+mod api_tests;
 mod auth;
+mod backpressure;
+mod consistency;
 mod content_type;
+mod extract;
+mod metrics;
+mod nodeinfo;
+mod rate_limit;
+mod request_id;
 
 use std::str::FromStr;
+use std::time::Duration;
 
+use ::metrics::counter;
 use anyhow::{Context, Result};
 use aws_lc_rs::encoding::AsDer;
 use aws_lc_rs::rsa::{KeySize, PrivateDecryptingKey};
-use axum::extract::{Path, Query, State};
-use axum::http::{Method, StatusCode, Uri};
-use axum::middleware::from_fn;
-use axum::response::IntoResponse;
+use axum::body::HttpBody;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{self, header, HeaderMap, Method, StatusCode, Uri};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
 use pem_rfc7468::{encode_string as pem_encode, LineEnding};
-use ractor::ActorRef;
+use ractor::{ActorRef, DerivedActorRef};
 use secrecy::ExposeSecret;
 use serde::Deserialize;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use tokio::net::TcpListener;
 use tokio::task::spawn_blocking;
-use tracing::info;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::request_id::{
+    MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer,
+};
+use tower_http::timeout::TimeoutLayer;
+use tower_http::trace::TraceLayer;
+use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::activity_pub::delivery::DeliveryQueueItem;
+use crate::activity_pub::delivery::{DeliveryQueueItem, DeliveryWorkerMsg};
 use crate::activity_pub::machine::{ActivityPubCommand, C2sCommand, S2sCommand};
-use crate::activity_pub::model::{Actor, Create, Object, OrderedCollection};
+use crate::activity_pub::model::{
+    timestamp_now, Actor, Announce, Block, Collection, CollectionPage, Create, Delete, Move,
+    Object, Pin, Update,
+};
 use crate::activity_pub::{
-    uuidgen, validate_request, ContextIndex, CryptoRepo, IriIndex, KeyMaterial, ObjectKey,
-    ObjectRepo, OutboxIndex, UserIndex,
+    blind_recipients, immediate_answer, local_recipients, remove_blind_recipients, uuidgen,
+    validate_request, ActorResolver, BlockEntry, ContextIndex, CryptoRepo, IriIndex, KeyMaterial,
+    MaxSkew, ModerationRepo, ObjectKey, ObjectRepairer, ObjectRepo, OutboxIndex, SeenSignatures,
+    UserIndex, Visibility,
 };
-use crate::config::RuntimeConfig;
+use crate::config::{AdminConfig, HttpConfig, RuntimeConfig};
 use crate::feed_slurp::FeedSlurpMsg;
-use crate::raft::{get_raft_local_client, LogEntryValue, RaftClientMsg};
+use crate::raft::{check_quorum, get_raft_local_client, ClientError, LogEntryValue, RaftClientMsg};
+use crate::supervisor::gc_keyspace;
 
-use self::auth::admin_basic_auth;
+use self::auth::{admin_basic_auth, is_admin};
+use self::backpressure::{
+    admit_inbox, limit_reads, limit_writes, reject_degraded, InboxAdmission, ReadLimiter,
+    WriteLimiter,
+};
+use self::consistency::{
+    read_your_writes, record_write, route_reads, FollowerReads, ReadYourWrites,
+};
 use self::content_type::ActivityStreamsJson;
+use self::extract::ObjectJson;
+use self::metrics::{get_metrics, track_metrics};
+use self::nodeinfo::{get_nodeinfo, get_nodeinfo_links, get_version};
+use self::rate_limit::InboxLimiter;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct PageParams {
     before: Option<String>,
     after: Option<String>,
@@ -48,6 +84,19 @@ impl PageParams {
     fn has_page(&self) -> bool {
         self.after.is_some() || self.before.is_some()
     }
+    /// Page sizes for `first` and `last`, defaulted and clamped per config.
+    fn limits(&self, http: &HttpConfig) -> (Option<u64>, Option<u64>) {
+        let limit = |n: u64| n.clamp(0, http.collection_page_max);
+        let first = self
+            .first
+            .or_else(|| self.after.as_ref().map(|_| http.collection_page_default))
+            .map(limit);
+        let last = self
+            .last
+            .or_else(|| self.before.as_ref().map(|_| http.collection_page_default))
+            .map(limit);
+        (first, last)
+    }
     fn to_query(&self) -> String {
         let mut query = vec![];
         if let Some(before) = &self.before {
@@ -71,47 +120,224 @@ pub(crate) async fn serve(config: &RuntimeConfig) -> Result<()> {
         info!(target: "http", "http API server is disabled");
         return Ok(());
     }
-    let app = Router::new()
+    let app = router(config)?;
+    let listener = TcpListener::bind(format!(
+        "{}:{}",
+        config.server.http.address, config.server.http.port
+    ))
+    .await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// All routes of the API with their middleware.
+fn router(config: &RuntimeConfig) -> Result<Router> {
+    let resolver = ActorResolver::new(config.keyspace.clone(), &config.init.cache)?;
+    let repairer = ObjectRepairer::new(config.keyspace.clone(), &config.init)?;
+    let http = &config.server.http;
+    let reads = Router::new()
         .route("/.well-known/webfinger", get(get_webfinger))
+        .route("/.well-known/nodeinfo", get(get_nodeinfo_links))
+        .route("/nodeinfo/2.1", get(get_nodeinfo))
+        .route("/version", get(get_version))
         .route("/users/{id}", get(get_actor))
+        .route("/users/{id}/outbox", get(get_outbox))
+        .route("/users/{id}/followers", get(get_followers))
+        .route("/users/{id}/collections/featured", get(get_featured))
+        .route("/as/objects/{obj_key}", get(get_object_by_id))
+        .route("/as/objects/{obj_key}/{prop}", get(get_object_likes_shares))
+        .route(
+            "/as/admin/blocks",
+            get(get_blocks).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/reports",
+            get(get_reports).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/delivery_preview",
+            get(get_delivery_preview).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users",
+            get(get_users).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/follow_requests",
+            get(get_follow_requests).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/raft/quorum",
+            get(get_quorum).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/metrics",
+            get(get_metrics).layer(from_fn(admin_basic_auth)),
+        )
+        .fallback(get_object_by_iri)
+        .layer(timeout(http.read_timeout_ms));
+    let writes = Router::new()
         .route(
             "/users/{id}",
             post(post_actor).layer(from_fn(admin_basic_auth)),
         )
-        .route("/users/{id}/outbox", get(get_outbox))
         .route(
             "/users/{id}/outbox",
             post(post_outbox).layer(from_fn(admin_basic_auth)),
         )
+        .route(
+            "/as/admin/ingest_feed",
+            post(post_ingest_feed).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/gc",
+            post(post_gc).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/redeliver",
+            post(post_redeliver).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users",
+            post(post_users).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/disable",
+            post(post_user_disable).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/enable",
+            post(post_user_enable).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/visibility",
+            post(post_user_visibility).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/rotate_key",
+            post(post_user_rotate_key).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/follow_requests/{key}/accept",
+            post(post_follow_request_accept).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/users/{id}/follow_requests/{key}/reject",
+            post(post_follow_request_reject).layer(from_fn(admin_basic_auth)),
+        )
+        .route(
+            "/as/admin/raft/step_down",
+            post(post_step_down).layer(from_fn(admin_basic_auth)),
+        )
+        .layer(from_fn(limit_writes))
+        // Inbox deliveries are bounded by `admit_inbox` instead of the write
+        // limit, so a flood of them cannot starve local writes.
         .route(
             "/users/{id}/inbox",
-            post(post_inbox).layer(from_fn(validate_request)),
+            post(post_inbox)
+                .layer(from_fn(validate_request))
+                .layer(from_fn(admit_inbox))
+                .layer(from_fn_with_state(config.clone(), federated)),
         )
-        .route("/users/{id}/followers", get(get_followers))
-        .route("/as/objects/{obj_key}", get(get_object_by_id))
-        .route("/as/objects/{obj_key}/{prop}", get(get_object_likes_shares))
         .route(
-            "/as/admin/ingest_feed",
-            post(post_ingest_feed).layer(from_fn(admin_basic_auth)),
+            "/inbox",
+            post(post_shared_inbox)
+                .layer(from_fn(validate_request))
+                .layer(from_fn(admit_inbox))
+                .layer(from_fn_with_state(config.clone(), federated)),
         )
-        .fallback(get_object_by_iri)
+        .layer(from_fn(reject_degraded))
+        .layer(timeout(http.write_timeout_ms));
+    let app = reads
+        .merge(writes)
+        .layer(from_fn(read_your_writes))
+        .layer(from_fn(route_reads))
+        .layer(from_fn(limit_reads))
+        .layer(from_fn(track_metrics))
+        .layer(Extension(WriteLimiter::new(&config.server.http)))
+        .layer(Extension(config.storage.clone()))
+        .layer(Extension(ReadLimiter::new(&config.server.http)))
+        .layer(Extension(InboxLimiter::new(&config.server.http)))
+        .layer(Extension(InboxAdmission::new(&config.server.http)))
+        .layer(Extension(FollowerReads::new(
+            &config.server.http,
+            &config.init.cluster.servers,
+        )))
+        .layer(Extension(ReadYourWrites::new(
+            &config.server.http,
+            config.applied_index.clone(),
+        )))
         .layer(Extension(config.init.admin.clone()))
+        .layer(Extension(config.init.federation.clone()))
+        .layer(Extension(MaxSkew::new(&config.init.activity_pub)))
+        .layer(Extension(SeenSignatures::new(
+            &config.init.cache,
+            &config.init.activity_pub,
+        )))
+        .layer(Extension(resolver))
+        .layer(Extension(repairer))
+        .layer(compression(&config.server.http))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(request_id::make_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .with_state(config.clone());
-    let listener = TcpListener::bind(format!(
-        "{}:{}",
-        config.server.http.address, config.server.http.port
-    ))
-    .await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+    Ok(app)
+}
+
+/// Refuse inbox requests with 403 when federation is disabled, before
+/// their signatures send us fetching remote keys.
+async fn federated(State(config): State<RuntimeConfig>, req: Request, next: Next) -> Response {
+    if !config.init.activity_pub.federation_enabled {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(req).await
+}
+
+/// Answer requests still running after `ms` with 504, so none holds its
+/// connection forever.
+fn timeout(ms: u64) -> TimeoutLayer {
+    TimeoutLayer::with_status_code(StatusCode::GATEWAY_TIMEOUT, Duration::from_millis(ms))
+}
+
+/// Compress responses above the configured size with brotli or gzip,
+/// whichever the client prefers, when enabled.
+///
+/// zstd is left out, its codec is C code built through `zstd-sys` and the
+/// clients we serve accept gzip or brotli anyway.
+fn compression(http: &HttpConfig) -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .br(http.compression)
+        .gzip(http.compression)
+        .compress_when(DefaultPredicate::new().and(MinSize(http.compression_min_bytes)))
+}
+
+/// Compress bodies of at least this many bytes, and those of unknown size.
+///
+/// Like [`SizeAbove`](tower_http::compression::predicate::SizeAbove) without
+/// its `u16` cap.
+#[derive(Clone, Copy)]
+struct MinSize(u32);
+
+impl Predicate for MinSize {
+    fn should_compress<B: HttpBody>(&self, response: &http::Response<B>) -> bool {
+        let size = response.body().size_hint().exact().or_else(|| {
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        });
+        size.is_none_or(|size| size >= u64::from(self.0))
+    }
 }
 
 async fn get_object_by_id(
     State(config): State<RuntimeConfig>,
+    Extension(repairer): Extension<ObjectRepairer>,
     Path(obj_key): Path<String>,
 ) -> Result<ActivityStreamsJson<Value>, StatusCode> {
     info!(%obj_key, "handle get object by ID request");
-    spawn_blocking(move || {
+    let ActivityStreamsJson(Json(object)) = spawn_blocking(move || {
         let obj_key = ObjectKey::from_str(&obj_key)
             .context("invalid UUID")
             .map_err(invalid)?;
@@ -119,11 +345,13 @@ async fn get_object_by_id(
     })
     .await
     .context("task failed")
-    .map_err(ise)?
+    .map_err(ise)??;
+    Ok(ActivityStreamsJson(Json(repairer.repair(object).await)))
 }
 
 async fn get_object_by_iri(
     State(config): State<RuntimeConfig>,
+    Extension(repairer): Extension<ObjectRepairer>,
     method: Method,
     uri: Uri,
 ) -> Result<ActivityStreamsJson<Value>, StatusCode> {
@@ -131,7 +359,7 @@ async fn get_object_by_iri(
     if !matches!(method, Method::GET) {
         return Err(StatusCode::METHOD_NOT_ALLOWED);
     }
-    spawn_blocking(move || {
+    let ActivityStreamsJson(Json(object)) = spawn_blocking(move || {
         let iri_index = IriIndex::new(config.keyspace.clone()).map_err(ise)?;
         let iri = format!("{}{}", config.init.activity_pub.base_url, uri.path());
         let obj_key = iri_index
@@ -146,7 +374,8 @@ async fn get_object_by_iri(
     })
     .await
     .context("task failed")
-    .map_err(ise)?
+    .map_err(ise)??;
+    Ok(ActivityStreamsJson(Json(repairer.repair(object).await)))
 }
 
 fn blocking_get_object(
@@ -157,24 +386,40 @@ fn blocking_get_object(
     let obj_repo = ObjectRepo::new(config.keyspace.clone()).map_err(ise)?;
     info!(%obj_key, "loading object");
     if let Some(object) = obj_repo.find_one(obj_key).map_err(ise)? {
+        if object.type_is("Tombstone") {
+            return Err(StatusCode::GONE);
+        }
+        // Ours embedded in a Create are stored without the context, remote
+        // ones are served with the context their server gave them.
+        let object = if object
+            .id()
+            .is_some_and(|iri| config.init.activity_pub.is_local(iri))
+        {
+            object.with_context()
+        } else {
+            object
+        };
         if let Some(iri) = object.id() {
-            let likes = ctx_index.count_likes(iri);
-            let shares = ctx_index.count_shares(iri);
-            let object = object.augment(
-                "likes",
-                json!({
-                    "id": format!("{}/as/objects/{obj_key}/likes", config.init.activity_pub.base_url),
-                    "type": "Collection",
-                    "totalItems": likes
-                }),
-            ).augment(
-                "shares",
-                json!({
-                    "id": format!("{}/as/objects/{obj_key}/shares", config.init.activity_pub.base_url),
-                    "type": "Collection",
-                    "totalItems": shares
-                }),
-            );
+            let likes = ctx_index.count_likes(iri).map_err(ise)?;
+            let shares = ctx_index.count_shares(iri).map_err(ise)?;
+            let obj_iri = config.init.activity_pub.object_iri(obj_key);
+            let object = object
+                .augment(
+                    "likes",
+                    json!({
+                        "id": format!("{obj_iri}/likes"),
+                        "type": "Collection",
+                        "totalItems": likes
+                    }),
+                )
+                .augment(
+                    "shares",
+                    json!({
+                        "id": format!("{obj_iri}/shares"),
+                        "type": "Collection",
+                        "totalItems": shares
+                    }),
+                );
             return Ok(ActivityStreamsJson(Json(object.into())));
         }
         return Ok(ActivityStreamsJson(Json(object.into())));
@@ -195,18 +440,17 @@ async fn get_object_likes_shares(
         let obj_key = ObjectKey::from_str(&obj_key)
             .context("invalid UUID")
             .map_err(invalid)?;
-        let iri = format!("{}/as/objects/{obj_key}", config.init.activity_pub.base_url);
+        let iri = config.init.activity_pub.object_iri(obj_key);
         let count = match prop.as_str() {
             "likes" => ctx_index.count_likes(&iri),
             "shares" => ctx_index.count_shares(&iri),
             _ => unreachable!(),
-        };
-        Ok(ActivityStreamsJson(Json(json!({
-            "@context": "https://www.w3.org/ns/activitystreams",
-            "id": format!("{}/as/objects/{obj_key}/{prop}", config.init.activity_pub.base_url),
-            "type": "Collection",
-            "totalItems": count
-        }))))
+        }
+        .map_err(ise)?;
+        let collection = Collection::unordered()
+            .id(format!("{iri}/{prop}"))
+            .total_items(count);
+        Ok(ActivityStreamsJson(Json(collection.into())))
     })
     .await
     .context("task failed")
@@ -231,7 +475,12 @@ async fn get_webfinger(
         let Some(uid) = subject.strip_suffix(&config.init.activity_pub.webfinger_at_host) else {
             return Err(StatusCode::BAD_REQUEST);
         };
-        let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let user_index = UserIndex::new(config.keyspace.clone())
+            .map_err(ise)?
+            .with_cache(config.actor_cache.clone());
+        if user_index.is_disabled(uid).map_err(ise)? {
+            return Err(StatusCode::NOT_FOUND);
+        }
         if user_index.find_one(uid).map_err(ise)?.is_some() {
             let jrd = json!({
                 "subject": subject,
@@ -239,7 +488,7 @@ async fn get_webfinger(
                     {
                         "rel": "self",
                         "type": "application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
-                        "href": format!("{}/users/{}", config.init.activity_pub.base_url, uid)
+                        "href": config.init.activity_pub.user_iri(uid)
                     }
                 ]
             });
@@ -264,8 +513,13 @@ async fn get_actor(
 ) -> Result<ActivityStreamsJson<Value>, StatusCode> {
     info!(%uid, "handle get actor request");
     spawn_blocking(move || {
-        let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let user_index = UserIndex::new(config.keyspace.clone())
+            .map_err(ise)?
+            .with_cache(config.actor_cache.clone());
         let crypto_repo = CryptoRepo::new(config.keyspace.clone()).map_err(ise)?;
+        if user_index.is_disabled(&uid).map_err(ise)? {
+            return Err(StatusCode::GONE);
+        }
         if let Some(object) = user_index.find_one(&uid).map_err(ise)? {
             let raw_actor = Actor::from(object);
             // TODO store public key separately?
@@ -302,146 +556,153 @@ struct PostActorParams {
 }
 
 async fn post_actor(
+    State(config): State<RuntimeConfig>,
     Path(uid): Path<String>,
     Query(params): Query<PostActorParams>,
-    Json(value): Json<Value>,
+    Extension(request_id): Extension<RequestId>,
+    ObjectJson(value): ObjectJson,
 ) -> Result<(), StatusCode> {
     info!(%uid, "handle post actor request");
     let object = Object::from(value);
     if object.type_is("Person") {
-        let key_bytes = {
-            if params.gen_rsa {
-                let private_key = PrivateDecryptingKey::generate(KeySize::Rsa2048)
-                    .context("generate private key failed")
-                    .map_err(ise)?;
-                let private_key_der = private_key
-                    .as_der()
-                    .context("failed to serialize private key")
-                    .map_err(ise)?;
-                Some(KeyMaterial::from(private_key_der.as_ref().to_vec()))
-            } else {
-                None
-            }
+        // Followers are told about a migration once, when movedTo changes.
+        let moved_to = Actor::from(object.clone()).moved_to().map(str::to_string);
+        let keyspace = config.keyspace.clone();
+        let user_id = uid.clone();
+        let previous = spawn_blocking(move || UserIndex::new(keyspace)?.find_one(&user_id))
+            .await
+            .context("task failed")
+            .map_err(ise)?
+            .map_err(ise)?
+            .map(Actor::from);
+        let moved = moved_to
+            .filter(|target| previous.as_ref().and_then(Actor::moved_to) != Some(target.as_str()));
+        let key_bytes = if params.gen_rsa {
+            Some(generate_key().map_err(ise)?)
+        } else {
+            None
         };
         let client = get_raft_local_client().map_err(ise)?;
-        let command = ActivityPubCommand::UpdateUser(uid, object, key_bytes);
-        ractor::call!(
-            client,
-            RaftClientMsg::ClientRequest,
-            LogEntryValue::from(command)
-        )
-        .context("RPC call failed")
-        .map_err(ise)?;
+        let command = ActivityPubCommand::UpdateUser(uid.clone(), object, key_bytes);
+        submit(&client, command).await?;
+        if let Some(target) = moved {
+            let apub = &config.init.activity_pub;
+            let act_key = ObjectKey::new();
+            let activity = Move::new(&apub.user_iri(&uid), &target, &apub.object_iri(act_key));
+            let command = ActivityPubCommand::C2sMove(C2sCommand {
+                uid: uid.clone(),
+                act_key,
+                obj_key: ObjectKey::new(), // not used
+                object: activity.into(),
+                request_id: request_id::to_string(&request_id),
+            });
+            submit(&client, command).await?;
+            let item = DeliveryQueueItem {
+                uid,
+                act_key,
+                request_id: request_id::to_string(&request_id),
+                blind_recipients: None,
+                inboxes: None,
+            };
+            queue_delivery(&config, &client, item).await?;
+        }
         return Ok(());
     }
     Err(StatusCode::BAD_REQUEST)
 }
 
+fn generate_key() -> Result<KeyMaterial> {
+    let private_key =
+        PrivateDecryptingKey::generate(KeySize::Rsa2048).context("generate private key failed")?;
+    let private_key_der = private_key
+        .as_der()
+        .context("failed to serialize private key")?;
+    Ok(KeyMaterial::from(private_key_der.as_ref().to_vec()))
+}
+
+/// Whether `uid` was disabled by an admin.
+async fn is_disabled(config: &RuntimeConfig, uid: &str) -> Result<bool, StatusCode> {
+    let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+    let uid = uid.to_string();
+    spawn_blocking(move || user_index.is_disabled(&uid))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)
+}
+
+/// Audience of posts by `uid` that name none.
+async fn visibility(config: &RuntimeConfig, uid: &str) -> Result<Visibility, StatusCode> {
+    let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+    let uid = uid.to_string();
+    let picked = spawn_blocking(move || user_index.visibility(&uid))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?;
+    Ok(picked.unwrap_or(config.init.activity_pub.default_visibility))
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CollectionParams {
+    /// Embed the first page instead of linking to it, overrides
+    /// `collection_inline_first_page`.
+    inline: Option<bool>,
+    /// Embed the objects of Create activities instead of naming their IRI,
+    /// overrides `outbox_embed_objects`.
+    embed: Option<bool>,
+}
+
+/// Anyone sees the activities addressed to the public, the admin also sees
+/// followers-only and direct ones.
 async fn get_outbox(
     State(config): State<RuntimeConfig>,
+    Extension(admin): Extension<AdminConfig>,
+    headers: HeaderMap,
     Path(uid): Path<String>,
     Query(params): Query<PageParams>,
+    Query(collection): Query<CollectionParams>,
 ) -> Result<ActivityStreamsJson<Value>, StatusCode> {
     info!(%uid, "handle get outbox request");
+    let public_only = !is_admin(&headers, &admin);
     spawn_blocking(move || {
         let index = OutboxIndex::new(config.keyspace.clone()).map_err(ise)?;
         let ctx_index = ContextIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let outbox_page = |params| {
+            let embed = collection.embed;
+            outbox_page(
+                &config,
+                &index,
+                &ctx_index,
+                &uid,
+                params,
+                public_only,
+                embed,
+            )
+        };
         if params.has_page() {
-            let query = params.to_query();
-            let PageParams { before, after, .. } = params;
-            let first = params
-                .first
-                .or_else(|| after.as_ref().map(|_| 10))
-                .map(|first| first.clamp(0, 50));
-            let last = params
-                .last
-                .or_else(|| before.as_ref().map(|_| 10))
-                .map(|last| last.clamp(0, 50));
-            let items: Vec<(ObjectKey, Object)> = index
-                .find_all(&uid, before, after, first, last)
-                .map_err(invalid)?;
-            let (next, prev) = if !items.is_empty() {
-                (Some(items[0].0), Some(items.last().unwrap().0))
+            let outbox = outbox_page(params)?;
+            Ok(ActivityStreamsJson(Json(outbox.into())))
+        } else {
+            let outbox_iri = format!("{}/outbox", config.init.activity_pub.user_iri(&uid));
+            let outbox = Collection::ordered()
+                .id(outbox_iri.clone())
+                .last(format!("{outbox_iri}?after={}", Uuid::nil().simple()))
+                .first(format!("{outbox_iri}?before={}", Uuid::max().simple()))
+                .total_items(index.count(&uid, public_only).map_err(ise)?);
+            let inline = collection
+                .inline
+                .unwrap_or(config.server.http.collection_inline_first_page);
+            let outbox = if inline {
+                let first = PageParams {
+                    before: Some(Uuid::max().simple().to_string()),
+                    ..Default::default()
+                };
+                outbox.first_page(outbox_page(first)?)
             } else {
-                (None, None)
+                outbox
             };
-            let items = items
-                .into_iter()
-                // NB: outbox collection is displayed in reverse chronological order
-                .rev()
-                .map(|it| {
-                    let (obj_key, activity) = it;
-                    // FIXME abstraction
-                    let object = activity.get_node_object("object").unwrap();
-                    let iri = object.id().expect("stored object should have IRI");
-                    let likes = ctx_index.count_likes(iri);
-                    let shares = ctx_index.count_shares(iri);
-                    let activity = activity.augment_node("object", "likes",
-                        json!({
-                            "id": format!("{}/as/objects/{obj_key}/likes", config.init.activity_pub.base_url),
-                            "type": "Collection",
-                            "totalItems": likes
-                        }),
-                    ).augment_node("object", "shares", 
-                        json!({
-                            "id": format!("{}/as/objects/{obj_key}/shares", config.init.activity_pub.base_url),
-                            "type": "Collection",
-                            "totalItems": shares
-                        }),
-                    );
-                    activity
-                })
-                .collect();
-            let mut outbox = OrderedCollection::new()
-                .id(format!(
-                    "{}/users/{uid}/outbox?{query}",
-                    config.init.activity_pub.base_url,
-                ))
-                .part_of(format!(
-                    "{}/users/{uid}/outbox",
-                    config.init.activity_pub.base_url
-                ))
-                .last(format!(
-                    "{}/users/{uid}/outbox?after={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::nil().simple()
-                ))
-                .first(format!(
-                    "{}/users/{uid}/outbox?before={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::max().simple()
-                ))
-                .with_ordered_items(items);
-            if let Some(id) = next {
-                outbox = outbox.next(format!(
-                    "{}/users/{uid}/outbox?before={id}",
-                    config.init.activity_pub.base_url
-                ));
-            }
-            if let Some(id) = prev {
-                outbox = outbox.prev(format!(
-                    "{}/users/{uid}/outbox?after={id}",
-                    config.init.activity_pub.base_url
-                ));
-            }
-            Ok(ActivityStreamsJson(Json(outbox.into_page().into())))
-        } else {
-            let outbox = OrderedCollection::new()
-                .id(format!(
-                    "{}/users/{uid}/outbox",
-                    config.init.activity_pub.base_url
-                ))
-                .last(format!(
-                    "{}/users/{uid}/outbox?after={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::nil().simple()
-                ))
-                .first(format!(
-                    "{}/users/{uid}/outbox?before={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::max().simple()
-                ))
-                .total_items(index.count(&uid));
             Ok(ActivityStreamsJson(Json(outbox.into())))
         }
     })
@@ -450,74 +711,569 @@ async fn get_outbox(
     .map_err(ise)?
 }
 
+fn outbox_page(
+    config: &RuntimeConfig,
+    index: &OutboxIndex,
+    ctx_index: &ContextIndex,
+    uid: &str,
+    params: PageParams,
+    public_only: bool,
+    embed: Option<bool>,
+) -> Result<CollectionPage, StatusCode> {
+    // Pages link to pages embedding like they do when asked for.
+    let embed_query = embed.map(|embed| format!("&embed={embed}"));
+    let embed_query = embed_query.unwrap_or_default();
+    let embed = embed.unwrap_or(config.server.http.outbox_embed_objects);
+    let query = params.to_query();
+    let (first, last) = params.limits(&config.server.http);
+    let PageParams { before, after, .. } = params;
+    let items: Vec<(ObjectKey, Object)> = index
+        .find_all(uid, before, after, first, last, public_only)
+        .map_err(invalid)?;
+    let keys: Vec<ObjectKey> = items.iter().map(|it| it.0).collect();
+    let size = last
+        .or(first)
+        .unwrap_or(config.server.http.collection_page_default);
+    let (next, prev) = page_links(&keys, size, |params| {
+        let PageParams {
+            before,
+            after,
+            first,
+            last,
+        } = params;
+        Ok(!index
+            .find_all(uid, before, after, first, last, public_only)?
+            .is_empty())
+    })?;
+    let items = items
+        .into_iter()
+        // NB: outbox collection is displayed in reverse chronological order
+        .rev()
+        .map(|it| {
+            let (obj_key, activity) = it;
+            if activity.type_is("Tombstone") {
+                let iri = config.init.activity_pub.object_iri(obj_key);
+                return Ok(activity.augment("id", Value::String(iri)));
+            }
+            let activity = if activity.type_is("Create") {
+                create_object(index, activity, embed).map_err(ise)?
+            } else {
+                activity
+            };
+            // FIXME abstraction
+            // Boosts only name the object, there is nothing to augment.
+            // A deleted object has no likes or shares to count.
+            let Some(object) = activity
+                .get_node_object("object")
+                .filter(|object| !object.type_is("Tombstone"))
+            else {
+                return Ok(activity);
+            };
+            let iri = object.id().expect("stored object should have IRI");
+            let likes = ctx_index.count_likes(iri).map_err(ise)?;
+            let shares = ctx_index.count_shares(iri).map_err(ise)?;
+            let obj_iri = config.init.activity_pub.object_iri(obj_key);
+            Ok(activity
+                .augment_node(
+                    "object",
+                    "likes",
+                    json!({
+                        "id": format!("{obj_iri}/likes"),
+                        "type": "Collection",
+                        "totalItems": likes
+                    }),
+                )
+                .augment_node(
+                    "object",
+                    "shares",
+                    json!({
+                        "id": format!("{obj_iri}/shares"),
+                        "type": "Collection",
+                        "totalItems": shares
+                    }),
+                ))
+        })
+        .collect::<Result<_, StatusCode>>()?;
+    let outbox_iri = format!("{}/outbox", config.init.activity_pub.user_iri(uid));
+    let mut outbox = Collection::ordered()
+        .id(format!("{outbox_iri}?{query}{embed_query}"))
+        .part_of(outbox_iri.clone())
+        .last(format!("{outbox_iri}?after={}", Uuid::nil().simple()))
+        .first(format!("{outbox_iri}?before={}", Uuid::max().simple()))
+        .with_items(items);
+    if let Some(query) = next {
+        outbox = outbox.next(format!("{outbox_iri}?{query}{embed_query}"));
+    }
+    if let Some(query) = prev {
+        outbox = outbox.prev(format!("{outbox_iri}?{query}{embed_query}"));
+    }
+    Ok(outbox.into_page())
+}
+
+/// The Create with its object as stored now when embedding, an Update since
+/// may have changed it, or with only the IRI of the object otherwise.
+fn create_object<'a>(index: &OutboxIndex, create: Object<'a>, embed: bool) -> Result<Object<'a>> {
+    let Some(iri) = create.get_node_iri("object").map(str::to_string) else {
+        return Ok(create);
+    };
+    let object = if embed {
+        match index.find_object(&iri)? {
+            Some(object) => object.to_value(),
+            None => return Ok(create),
+        }
+    } else {
+        Value::String(iri)
+    };
+    let mut create = create.to_value();
+    create["object"] = object;
+    Ok(Object::from(create))
+}
+
+/// Queries of the pages around `keys`, a page in ascending key order that is
+/// shown newest first.
+///
+/// `next` pages on to older items and `prev` back to newer ones, both keep
+/// the page size. The cursor is the key of the item at the page boundary,
+/// the one `before` and `after` take. `probe` tells if a page has any items,
+/// there is no link to an empty page so clients know when to stop.
+fn page_links<F>(
+    keys: &[ObjectKey],
+    size: u64,
+    probe: F,
+) -> Result<(Option<String>, Option<String>), StatusCode>
+where
+    F: Fn(PageParams) -> Result<bool>,
+{
+    let (Some(oldest), Some(newest)) = (keys.first(), keys.last()) else {
+        return Ok((None, None));
+    };
+    let older = |last| PageParams {
+        before: Some(oldest.to_string()),
+        last: Some(last),
+        ..Default::default()
+    };
+    let newer = |first| PageParams {
+        after: Some(newest.to_string()),
+        first: Some(first),
+        ..Default::default()
+    };
+    let next = probe(older(1))
+        .map_err(ise)?
+        .then(|| older(size).to_query());
+    let prev = probe(newer(1))
+        .map_err(ise)?
+        .then(|| newer(size).to_query());
+    Ok((next, prev))
+}
+
 async fn post_outbox(
     State(config): State<RuntimeConfig>,
     Path(uid): Path<String>,
-    Json(value): Json<Value>,
-) -> Result<(), StatusCode> {
+    Extension(request_id): Extension<RequestId>,
+    ObjectJson(value): ObjectJson,
+) -> Result<Response, StatusCode> {
     info!(%uid, "handle post outbox request");
+    if is_disabled(&config, &uid).await? {
+        return Err(StatusCode::FORBIDDEN);
+    }
     let object = Object::from(value);
-    if !object.is_activity() {
-        // Add actor info
-        let act_key = ObjectKey::new();
-        let obj_key = ObjectKey::new();
-        let object = object.ensure_id(format!(
-            "{}/as/objects/{obj_key}",
-            config.init.activity_pub.base_url
-        ));
-        let create = Create::try_from(object)
-            .map_err(invalid)?
-            .ensure_id(format!(
-                "{}/as/objects/{act_key}",
-                config.init.activity_pub.base_url
-            ))
-            .with_actor(format!("{}/users/{uid}", config.init.activity_pub.base_url));
-        let client = get_raft_local_client().map_err(ise)?;
-        let scoped_cmd = C2sCommand {
-            uid: uid.clone(),
-            act_key,
-            obj_key,
-            object: Value::from(create).into(),
+    if object.type_is("Block") {
+        return post_block(&config, uid, object, request_id::to_string(&request_id)).await;
+    }
+    if object.type_is("Add") || object.type_is("Remove") {
+        return post_pin(&config, uid, object, request_id::to_string(&request_id)).await;
+    }
+    if object.type_is("Announce") {
+        return post_announce(&config, uid, object, request_id::to_string(&request_id)).await;
+    }
+    if object.type_is("Update") {
+        return post_update(&config, uid, object, request_id::to_string(&request_id)).await;
+    }
+    if object.type_is("Delete") {
+        return post_delete(&config, uid, object, request_id::to_string(&request_id)).await;
+    }
+    // A poll is an activity to the vocabulary, but it is posted like a note.
+    if object.is_activity() && !object.type_is("Create") && !object.type_is("Question") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let obj_key = ObjectKey::new();
+    let create = Create::from_outbox(
+        object,
+        &apub.object_iri(act_key),
+        &apub.object_iri(obj_key),
+        &apub.user_iri(&uid),
+        visibility(&config, &uid).await?,
+    )
+    .map_err(invalid)?;
+    // Blind recipients are only kept for delivery, never stored or replicated
+    // as part of the activity.
+    let create = Object::from(create);
+    let blind_recipients = blind_recipients(&create);
+    let mut value = create.to_value();
+    remove_blind_recipients(&mut value);
+    let client = get_raft_local_client().map_err(ise)?;
+    let scoped_cmd = C2sCommand {
+        uid: uid.clone(),
+        act_key,
+        obj_key,
+        object: value.into(),
+        request_id: request_id::to_string(&request_id),
+    };
+    let command = ActivityPubCommand::C2sCreate(scoped_cmd);
+    // Nothing is stored or delivered if the object did not change.
+    let Some(act_key) = submit(&client, command).await? else {
+        return Ok(StatusCode::OK.into_response());
+    };
+    let item = DeliveryQueueItem {
+        uid,
+        act_key,
+        request_id: request_id::to_string(&request_id),
+        blind_recipients: Some(blind_recipients),
+        inboxes: None,
+    };
+    queue_delivery(&config, &client, item).await?;
+    let act_iri = apub.object_iri(act_key);
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// Record a block from the outbox.
+///
+/// Blocks are never delivered, the blocked actor is not told about them.
+async fn post_block(
+    config: &RuntimeConfig,
+    uid: String,
+    object: Object<'_>,
+    request_id: Option<String>,
+) -> Result<Response, StatusCode> {
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let act_iri = apub.object_iri(act_key);
+    let block = Block::from_outbox(object, &act_iri, &apub.user_iri(&uid)).map_err(invalid)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::C2sBlock(C2sCommand {
+        uid,
+        act_key,
+        obj_key: ObjectKey::new(), // not used
+        object: block.into(),
+        request_id,
+    });
+    if submit(&client, command).await?.is_none() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// Pin an object of the user to the featured collection with an Add, or
+/// unpin it with a Remove, and tell the followers.
+async fn post_pin(
+    config: &RuntimeConfig,
+    uid: String,
+    object: Object<'_>,
+    request_id: Option<String>,
+) -> Result<Response, StatusCode> {
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let act_iri = apub.object_iri(act_key);
+    let actor_iri = apub.user_iri(&uid);
+    let pin = Pin::from_outbox(object, &act_iri, &actor_iri).map_err(invalid)?;
+    let is_pin = pin.is_pin();
+    let activity = Object::from(pin);
+    let (obj_key, _) = find_own_object(config, &activity, &actor_iri).await?;
+    let cmd = C2sCommand {
+        uid: uid.clone(),
+        act_key,
+        obj_key,
+        object: activity,
+        request_id: request_id.clone(),
+    };
+    let command = if is_pin {
+        ActivityPubCommand::C2sAdd(cmd)
+    } else {
+        ActivityPubCommand::C2sRemove(cmd)
+    };
+    let client = get_raft_local_client().map_err(ise)?;
+    submit(&client, command).await?;
+    let item = DeliveryQueueItem {
+        uid,
+        act_key,
+        request_id,
+        blind_recipients: None,
+        inboxes: None,
+    };
+    queue_delivery(config, &client, item).await?;
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// Boost an object to the user's followers.
+///
+/// The author is addressed too when the object is stored here, remote
+/// objects are not fetched just to find out who wrote them.
+async fn post_announce(
+    config: &RuntimeConfig,
+    uid: String,
+    object: Object<'_>,
+    request_id: Option<String>,
+) -> Result<Response, StatusCode> {
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let act_iri = apub.object_iri(act_key);
+    let actor_iri = apub.user_iri(&uid);
+    let announce = Announce::from_outbox(object, &act_iri, &actor_iri).map_err(invalid)?;
+    let keyspace = config.keyspace.clone();
+    let iri = announce.object().to_string();
+    let authors = spawn_blocking(move || -> Result<Vec<String>> {
+        let Some(key) = IriIndex::new(keyspace.clone())?.find_one(&iri)? else {
+            return Ok(vec![]);
         };
-        let command = ActivityPubCommand::C2sCreate(scoped_cmd);
-        ractor::call!(
-            client,
-            RaftClientMsg::ClientRequest,
-            LogEntryValue::from(command)
-        )
-        .context("RPC call failed")
-        .map_err(ise)?;
-        // XXX: in case of update, the `obj_key` is not used, so this
-        // queue_delivery will be unable to find the item for delivery.
-        let command =
-            ActivityPubCommand::QueueDelivery(uuidgen(), DeliveryQueueItem { uid, act_key });
-        ractor::call!(
-            client,
-            RaftClientMsg::ClientRequest,
-            LogEntryValue::from(command)
-        )
-        .context("RPC call failed")
-        .map_err(ise)?;
-        return Ok(());
+        let object = ObjectRepo::new(keyspace)?.find_one(key)?;
+        Ok(object
+            .map(|object| {
+                let authors = object.attributed_to();
+                authors.into_iter().map(str::to_string).collect()
+            })
+            .unwrap_or_default())
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+    .map_err(ise)?;
+    let announce = authors
+        .iter()
+        .filter(|author| **author != actor_iri)
+        .fold(announce, |announce, author| announce.notify(author));
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::C2sAnnounce(C2sCommand {
+        uid: uid.clone(),
+        act_key,
+        obj_key: ObjectKey::new(), // not used
+        object: announce.into(),
+        request_id: request_id.clone(),
+    });
+    submit(&client, command).await?;
+    let item = DeliveryQueueItem {
+        uid,
+        act_key,
+        request_id,
+        blind_recipients: None,
+        inboxes: None,
+    };
+    queue_delivery(config, &client, item).await?;
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// Edit an object of the user with an Update and deliver the edit to the
+/// audience of the object.
+async fn post_update(
+    config: &RuntimeConfig,
+    uid: String,
+    object: Object<'_>,
+    request_id: Option<String>,
+) -> Result<Response, StatusCode> {
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let act_iri = apub.object_iri(act_key);
+    let actor_iri = apub.user_iri(&uid);
+    let (obj_key, stored) = find_own_object(config, &object, &actor_iri).await?;
+    let update = Update::from_outbox(object, &stored, &act_iri, &actor_iri).map_err(invalid)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::C2sUpdate(C2sCommand {
+        uid: uid.clone(),
+        act_key,
+        obj_key,
+        object: update.into(),
+        request_id: request_id.clone(),
+    });
+    if submit(&client, command).await?.is_none() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
     }
-    Err(StatusCode::BAD_REQUEST)
+    let item = DeliveryQueueItem {
+        uid,
+        act_key,
+        request_id,
+        blind_recipients: None,
+        inboxes: None,
+    };
+    queue_delivery(config, &client, item).await?;
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// Replace an object of the user with a Tombstone and deliver the Delete to
+/// the audience of the object.
+async fn post_delete(
+    config: &RuntimeConfig,
+    uid: String,
+    object: Object<'_>,
+    request_id: Option<String>,
+) -> Result<Response, StatusCode> {
+    let apub = &config.init.activity_pub;
+    let act_key = ObjectKey::new();
+    let act_iri = apub.object_iri(act_key);
+    let actor_iri = apub.user_iri(&uid);
+    let (obj_key, stored) = find_own_object(config, &object, &actor_iri).await?;
+    let delete = Delete::from_outbox(object, &stored, &act_iri, &actor_iri).map_err(invalid)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::C2sDelete(C2sCommand {
+        uid: uid.clone(),
+        act_key,
+        obj_key,
+        object: delete.into(),
+        request_id: request_id.clone(),
+    });
+    if submit(&client, command).await?.is_none() {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let item = DeliveryQueueItem {
+        uid,
+        act_key,
+        request_id,
+        blind_recipients: None,
+        inboxes: None,
+    };
+    queue_delivery(config, &client, item).await?;
+    Ok((StatusCode::CREATED, [(header::LOCATION, act_iri)]).into_response())
+}
+
+/// The stored `object` of an activity posted to the outbox of `actor_iri`.
+///
+/// Unknown objects are rejected with 422, deleted ones with 410 and those
+/// of someone else with 403, users can only change their own objects.
+async fn find_own_object(
+    config: &RuntimeConfig,
+    activity: &Object<'_>,
+    actor_iri: &str,
+) -> Result<(ObjectKey, Object<'static>), StatusCode> {
+    let Some(iri) = activity.get_node_iri("object").map(str::to_string) else {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    };
+    let keyspace = config.keyspace.clone();
+    let stored = spawn_blocking(move || -> Result<Option<(ObjectKey, Object<'static>)>> {
+        let Some(key) = IriIndex::new(keyspace.clone())?.find_one(&iri)? else {
+            return Ok(None);
+        };
+        let obj_key = ObjectKey::try_from(key.as_ref())?;
+        let object = ObjectRepo::new(keyspace)?.find_one(obj_key)?;
+        Ok(object.map(|object| (obj_key, object)))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+    .map_err(ise)?;
+    let Some((obj_key, stored)) = stored else {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    };
+    if stored.type_is("Tombstone") {
+        return Err(StatusCode::GONE);
+    }
+    if !stored.is_attributed_to(actor_iri) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok((obj_key, stored))
+}
+
+/// Objects the user pinned, all on one page as there are only a few.
+async fn get_featured(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+) -> Result<ActivityStreamsJson<Value>, StatusCode> {
+    info!(%uid, "handle get featured request");
+    spawn_blocking(move || {
+        let index = OutboxIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let featured_iri = format!(
+            "{}/collections/featured",
+            config.init.activity_pub.user_iri(&uid)
+        );
+        let items = index.find_featured(&uid).map_err(ise)?;
+        let featured = Collection::ordered()
+            .id(featured_iri)
+            .total_items(items.len() as u64)
+            .with_items(items.iter().map(Object::to_value).collect());
+        Ok(ActivityStreamsJson(Json(featured.into())))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
 }
 
 async fn post_inbox(
     State(config): State<RuntimeConfig>,
     Path(uid): Path<String>,
-    Json(value): Json<Value>,
-) -> Result<(), StatusCode> {
+    Extension(request_id): Extension<RequestId>,
+    Extension(resolver): Extension<ActorResolver>,
+    Extension(limiter): Extension<InboxLimiter>,
+    ObjectJson(value): ObjectJson,
+) -> Result<Response, StatusCode> {
     info!(%uid, "handle post inbox request");
     let object = Object::from(value);
-    if object.is_inbox_activity() {
-        let client = get_raft_local_client().map_err(ise)?;
-        let obj_type = object.get_first_type();
-        let obj_type = obj_type.as_deref();
-        let scoped_cmd = S2sCommand {
-            uid: uid.clone(),
+    if let Some(response) = admit_sender(&config, &limiter, &object)? {
+        return Ok(response);
+    }
+    receive(&config, uid, &request_id, &resolver, object).await
+}
+
+/// Refuse a delivery from a server we do not federate with, or tell its
+/// sender to slow down. Checked once per delivery, however many local
+/// users it is for. Activities without an actor are refused, there would
+/// be no sender to check.
+fn admit_sender(
+    config: &RuntimeConfig,
+    limiter: &InboxLimiter,
+    object: &Object<'_>,
+) -> Result<Option<Response>, StatusCode> {
+    let Some(actor) = object.get_node_iri("actor") else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    if !config.init.federation.check(actor, "inbound") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Err(retry_after) = limiter.check(actor) {
+        info!(%actor, "rate limiting inbox deliveries");
+        return Ok(Some(
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response(),
+        ));
+    }
+    Ok(None)
+}
+
+/// Hand an admitted activity to the state machine for the local user `uid`.
+async fn receive(
+    config: &RuntimeConfig,
+    uid: String,
+    request_id: &RequestId,
+    resolver: &ActorResolver,
+    object: Object<'static>,
+) -> Result<Response, StatusCode> {
+    if is_disabled(config, &uid).await? {
+        return Err(StatusCode::GONE);
+    }
+    if let Some(actor) = object.get_node_iri("actor") {
+        let moderation = ModerationRepo::new(config.keyspace.clone()).map_err(ise)?;
+        let actor = actor.to_string();
+        let user_id = uid.clone();
+        let blocked = spawn_blocking(move || moderation.is_blocked(&user_id, &actor))
+            .await
+            .context("task failed")
+            .map_err(ise)?
+            .map_err(ise)?;
+        if blocked {
+            info!(%uid, "dropping activity from blocked actor");
+            return Ok(StatusCode::ACCEPTED.into_response());
+        }
+    }
+    if object.is_inbox_activity() {
+        let client = get_raft_local_client().map_err(ise)?;
+        let obj_type = object.get_first_type();
+        let obj_type = obj_type.as_deref();
+        let scoped_cmd = S2sCommand {
+            uid: uid.clone(),
             obj_key: ObjectKey::new(),
             object: object.clone(),
+            request_id: request_id::to_string(request_id),
         };
         let command = match obj_type {
             Some("Create") => ActivityPubCommand::S2sCreate(scoped_cmd),
@@ -528,62 +1284,222 @@ async fn post_inbox(
             Some("Undo") => ActivityPubCommand::S2sUndo(scoped_cmd),
             Some("Update") => ActivityPubCommand::S2sUpdate(scoped_cmd),
             Some("Announce") => ActivityPubCommand::S2sAnnounce(scoped_cmd),
-            _ => return Ok(()),
+            Some("Flag") => ActivityPubCommand::S2sFlag(scoped_cmd),
+            Some("Reject") => ActivityPubCommand::S2sReject(scoped_cmd),
+            Some("Move") => {
+                if let Err(error) = verify_move(resolver, &object).await {
+                    warn!(?error, "ignoring Move");
+                    return Ok(StatusCode::ACCEPTED.into_response());
+                }
+                ActivityPubCommand::S2sMove(scoped_cmd)
+            }
+            _ => {
+                report_unhandled(&uid, &object);
+                return Ok(StatusCode::ACCEPTED.into_response());
+            }
         };
-        ractor::call!(
-            client,
-            RaftClientMsg::ClientRequest,
-            LogEntryValue::from(command)
-        )
-        .context("RPC call failed")
-        .map_err(ise)?;
-        // FIXME move to state machine effect
-        if obj_type == Some("Follow") {
-            let follow_id = object.id().ok_or(StatusCode::BAD_REQUEST)?;
-            let req_actor = object
-                .get_node_iri("actor")
-                .ok_or(StatusCode::BAD_REQUEST)?;
-            let act_key = ObjectKey::new();
-            let accept = Object::from(json!({
-                "@context": "https://www.w3.org/ns/activitystreams",
-                "type": "Accept",
-                "actor": format!("{}/users/{uid}", config.init.activity_pub.base_url),
-                "object": follow_id,
-                "to": req_actor
-            }));
-            let accept = accept.ensure_id(format!(
-                "{}/as/objects/{act_key}",
-                config.init.activity_pub.base_url
-            ));
-            let accept_cmd = C2sCommand {
-                uid: uid.clone(),
-                act_key,
-                obj_key: ObjectKey::new(), // not used
-                object: accept,
-            };
-            let command = ActivityPubCommand::C2sAccept(accept_cmd);
-            ractor::call!(
-                client,
-                RaftClientMsg::ClientRequest,
-                LogEntryValue::from(command)
-            )
-            .context("RPC call failed")
-            .map_err(ise)?;
-            let command =
-                ActivityPubCommand::QueueDelivery(uuidgen(), DeliveryQueueItem { uid, act_key });
-            ractor::call!(
-                client,
-                RaftClientMsg::ClientRequest,
-                LogEntryValue::from(command)
-            )
-            .context("RPC call failed")
+        let stored = submit(&client, command).await?;
+        if obj_type == Some("Move") {
+            // The stored record is our Follow of the new account.
+            if let Some(act_key) = stored {
+                let item = DeliveryQueueItem {
+                    uid,
+                    act_key,
+                    request_id: request_id::to_string(request_id),
+                    blind_recipients: None,
+                    inboxes: None,
+                };
+                queue_delivery(config, &client, item).await?;
+            }
+            return Ok(StatusCode::ACCEPTED.into_response());
+        }
+        let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let (user_id, activity) = (uid.clone(), object.clone());
+        let answer =
+            spawn_blocking(move || immediate_answer(&user_index, &user_id, &activity, stored))
+                .await
+                .context("task failed")
+                .map_err(ise)?
+                .map_err(ise)?;
+        if let Some(follow_key) = answer {
+            let request_id = request_id::to_string(request_id);
+            answer_follow(config, &uid, follow_key, &object, true, request_id).await?;
+        }
+        if let Some(obj_key) = stored {
+            let iri = config.init.activity_pub.object_iri(obj_key);
+            return Ok((StatusCode::CREATED, [(header::LOCATION, iri)]).into_response());
+        }
+    } else {
+        report_unhandled(&uid, &object);
+    }
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+/// Log and count an inbox activity that is accepted but has no handler, so
+/// operators see which federation messages are dropped.
+fn report_unhandled(uid: &str, object: &Object<'_>) {
+    let obj_type = object.get_first_type();
+    let actor = object.get_node_iri("actor");
+    info!(%uid, ?obj_type, ?actor, "ignoring inbox activity of unhandled type");
+    counter!("pinka_inbox_unhandled_total", "type" => object.activity_type_label()).increment(1);
+}
+
+/// Hand an activity delivered to the shared inbox to the inbox of every
+/// local user it is for: those it names and those following its sender.
+/// The sender is admitted once for the whole delivery.
+async fn post_shared_inbox(
+    State(config): State<RuntimeConfig>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(resolver): Extension<ActorResolver>,
+    Extension(limiter): Extension<InboxLimiter>,
+    ObjectJson(value): ObjectJson,
+) -> Result<Response, StatusCode> {
+    if !config.init.activity_pub.shared_inbox {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let object = Object::from(value);
+    if let Some(response) = admit_sender(&config, &limiter, &object)? {
+        return Ok(response);
+    }
+    let mut uids = local_recipients(&config.init.activity_pub.base_url, &object);
+    let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+    // Posts to the followers of the sender name no local user, they are
+    // for whoever here follows it.
+    if let Some(actor) = object.get_node_iri("actor") {
+        let user_index = user_index.clone();
+        let actor = actor.to_string();
+        let followers = spawn_blocking(move || user_index.local_followers_of(&actor))
+            .await
+            .context("task failed")
+            .map_err(ise)?
             .map_err(ise)?;
+        uids.extend(followers);
+        uids.sort_unstable();
+        uids.dedup();
+    }
+    info!(?uids, "handle post shared inbox request");
+    for uid in uids {
+        let user_index = user_index.clone();
+        let user_id = uid.clone();
+        let exists = spawn_blocking(move || user_index.find_one(&user_id))
+            .await
+            .context("task failed")
+            .map_err(ise)?
+            .map_err(ise)?
+            .is_some();
+        if !exists {
+            continue;
+        }
+        match receive(&config, uid, &request_id, &resolver, object.clone()).await {
+            Ok(_) | Err(StatusCode::GONE) => {}
+            Err(status) => return Err(status),
         }
+    }
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+/// Accept or Reject the Follow stored at `follow_key` and deliver the answer
+/// to the requesting actor.
+async fn answer_follow(
+    config: &RuntimeConfig,
+    uid: &str,
+    follow_key: ObjectKey,
+    follow: &Object<'_>,
+    accept: bool,
+    request_id: Option<String>,
+) -> Result<(), StatusCode> {
+    let apub = &config.init.activity_pub;
+    let (Some(follow_id), Some(req_actor)) = (follow.id(), follow.get_node_iri("actor")) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let act_key = ObjectKey::new();
+    let answer = Object::from(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "type": if accept { "Accept" } else { "Reject" },
+        "actor": apub.user_iri(uid),
+        "object": follow_id,
+        "to": req_actor,
+        "published": timestamp_now(),
+    }));
+    let answer_cmd = C2sCommand {
+        uid: uid.to_string(),
+        act_key,
+        obj_key: follow_key,
+        object: answer.ensure_id(apub.object_iri(act_key)),
+        request_id: request_id.clone(),
+    };
+    let command = if accept {
+        ActivityPubCommand::C2sAccept(answer_cmd)
+    } else {
+        ActivityPubCommand::C2sReject(answer_cmd)
+    };
+    let client = get_raft_local_client().map_err(ise)?;
+    submit(&client, command).await?;
+    let item = DeliveryQueueItem {
+        uid: uid.to_string(),
+        act_key,
+        request_id,
+        blind_recipients: None,
+        inboxes: None,
+    };
+    queue_delivery(config, &client, item).await?;
+    Ok(())
+}
+
+/// Refetch both accounts of a Move and check that they agree on it.
+async fn verify_move(resolver: &ActorResolver, object: &Object<'_>) -> Result<()> {
+    let activity = Move::try_from(object.clone())?;
+    let mut accounts = vec![];
+    for iri in [activity.origin(), activity.target()] {
+        // Aliases are usually added right before moving, skip the cache.
+        resolver.invalidate(iri).await?;
+        let account = resolver.resolve(iri).await?;
+        accounts.push(account.with_context(|| format!("{iri} is gone"))?);
+    }
+    activity.verify(&accounts[0], &accounts[1])
+}
+
+/// Queue `item` for delivery, unless federation is disabled.
+async fn queue_delivery(
+    config: &RuntimeConfig,
+    client: &DerivedActorRef<RaftClientMsg>,
+    item: DeliveryQueueItem,
+) -> Result<(), StatusCode> {
+    if !config.init.activity_pub.federation_enabled {
         return Ok(());
     }
+    let command = ActivityPubCommand::QueueDelivery(uuidgen(), item);
+    submit(client, command).await?;
     Ok(())
 }
 
+/// Submit `command` to the raft log and wait until it is applied.
+///
+/// Returns the key of the record the command stored, `None` if it stored
+/// nothing. The log index is reported to the client, see [`consistency`].
+async fn submit(
+    client: &DerivedActorRef<RaftClientMsg>,
+    command: ActivityPubCommand,
+) -> Result<Option<ObjectKey>, StatusCode> {
+    let (bytes, index) = ractor::call!(
+        client,
+        RaftClientMsg::ClientRequest,
+        LogEntryValue::from(command)
+    )
+    .context("RPC call failed")
+    .map_err(ise)?
+    .into_result()
+    .map_err(client_error)?;
+    record_write(index);
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    ObjectKey::try_from(bytes.as_slice())
+        .map(Some)
+        .context("invalid object key")
+        .map_err(ise)
+}
+
 async fn get_followers(
     State(config): State<RuntimeConfig>,
     Path(uid): Path<String>,
@@ -591,78 +1507,51 @@ async fn get_followers(
 ) -> Result<ActivityStreamsJson<Value>, StatusCode> {
     info!(%uid, "handle get followers request");
     spawn_blocking(move || {
+        let followers_iri = format!("{}/followers", config.init.activity_pub.user_iri(&uid));
         let index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
         // TODO generic collections handling
         if params.has_page() {
             let query = params.to_query();
+            let (first, last) = params.limits(&config.server.http);
             let PageParams { before, after, .. } = params;
-            let first = params
-                .first
-                .or_else(|| after.as_ref().map(|_| 10))
-                .map(|first| first.clamp(0, 50));
-            let last = params
-                .last
-                .or_else(|| before.as_ref().map(|_| 10))
-                .map(|last| last.clamp(0, 50));
             let items: Vec<(ObjectKey, String)> = index
                 .find_followers(&uid, before, after, first, last)
                 .map_err(invalid)?;
-            let (next, prev) = if !items.is_empty() {
-                (Some(items[0].0), Some(items.last().unwrap().0))
-            } else {
-                (None, None)
-            };
+            let keys: Vec<ObjectKey> = items.iter().map(|it| it.0).collect();
+            let size = last
+                .or(first)
+                .unwrap_or(config.server.http.collection_page_default);
+            let (next, prev) = page_links(&keys, size, |params| {
+                let PageParams {
+                    before,
+                    after,
+                    first,
+                    last,
+                } = params;
+                Ok(!index
+                    .find_followers(&uid, before, after, first, last)?
+                    .is_empty())
+            })?;
             let items = items.into_iter().rev().map(|it| it.1).collect();
-            let mut followers = OrderedCollection::new()
-                .id(format!(
-                    "{}/users/{uid}/followers?{query}",
-                    config.init.activity_pub.base_url,
-                ))
-                .part_of(format!(
-                    "{}/users/{uid}/followers",
-                    config.init.activity_pub.base_url
-                ))
-                .last(format!(
-                    "{}/users/{uid}/followers?after={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::nil().simple()
-                ))
-                .first(format!(
-                    "{}/users/{uid}/followers?before={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::max().simple()
-                ))
-                .with_ordered_items(items);
-            if let Some(id) = next {
-                followers = followers.prev(format!(
-                    "{}/users/{uid}/followers?before={id}",
-                    config.init.activity_pub.base_url
-                ));
+            let mut followers = Collection::ordered()
+                .id(format!("{followers_iri}?{query}"))
+                .part_of(followers_iri.clone())
+                .last(format!("{followers_iri}?after={}", Uuid::nil().simple()))
+                .first(format!("{followers_iri}?before={}", Uuid::max().simple()))
+                .with_items(items);
+            if let Some(query) = next {
+                followers = followers.next(format!("{followers_iri}?{query}"));
             }
-            if let Some(id) = prev {
-                followers = followers.next(format!(
-                    "{}/users/{uid}/followers?after={id}",
-                    config.init.activity_pub.base_url
-                ));
+            if let Some(query) = prev {
+                followers = followers.prev(format!("{followers_iri}?{query}"));
             }
             Ok(ActivityStreamsJson(Json(followers.into_page().into())))
         } else {
-            let followers = OrderedCollection::new()
-                .id(format!(
-                    "{}/users/{uid}/followers",
-                    config.init.activity_pub.base_url
-                ))
-                .last(format!(
-                    "{}/users/{uid}/followers?after={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::nil().simple()
-                ))
-                .first(format!(
-                    "{}/users/{uid}/followers?before={}",
-                    config.init.activity_pub.base_url,
-                    Uuid::max().simple()
-                ))
-                .total_items(index.count_followers(&uid));
+            let followers = Collection::ordered()
+                .id(followers_iri.clone())
+                .last(format!("{followers_iri}?after={}", Uuid::nil().simple()))
+                .first(format!("{followers_iri}?before={}", Uuid::max().simple()))
+                .total_items(index.count_followers(&uid).map_err(ise)?);
             Ok(ActivityStreamsJson(Json(followers.into())))
         }
     })
@@ -699,6 +1588,435 @@ async fn post_ingest_feed(Json(ingest_feed): Json<IngestFeed>) -> Result<(), Sta
     Ok(())
 }
 
+/// Trigger blob garbage collection of the raft log and object partitions.
+///
+/// The raft log is never truncated, so this is the only way to reclaim its
+/// space on demand. It is not a raft snapshot or log compaction: neither is
+/// implemented, there is no `last_included_index` to report, and a trigger
+/// for them has to wait for snapshotting itself.
+async fn post_gc(
+    State(config): State<RuntimeConfig>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    info!("handle manual garbage collection request");
+    let keyspace = config.keyspace.clone();
+    match spawn_blocking(move || gc_keyspace(&keyspace))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?
+    {
+        Some(report) => Ok((StatusCode::OK, Json(json!(report)))),
+        None => Ok((
+            StatusCode::CONFLICT,
+            Json(json!({"message": "garbage collection is already in progress"})),
+        )),
+    }
+}
+
+/// Move raft leadership away from this server, e.g. before maintenance.
+///
+/// Answers with the server leadership was handed to, or 409 when this
+/// server does not lead.
+/// Which raft servers answer and how fast, 503 unless a majority does and a
+/// leader is known. Health gates can script on the status alone.
+async fn get_quorum(State(config): State<RuntimeConfig>) -> (StatusCode, Json<Value>) {
+    info!("handle get quorum request");
+    let report = check_quorum(&config.init.cluster.servers).await;
+    let status = if report.healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(json!(report)))
+}
+
+async fn post_step_down() -> Result<(StatusCode, Json<Value>), StatusCode> {
+    info!("handle step down request");
+    let client = get_raft_local_client().map_err(ise)?;
+    let result = ractor::call!(client, RaftClientMsg::StepDown)
+        .context("RPC call failed")
+        .map_err(ise)?
+        .into_result();
+    match result {
+        Ok((successor, _)) => {
+            let successor = Some(String::from_utf8_lossy(&successor)).filter(|s| !s.is_empty());
+            Ok((StatusCode::OK, Json(json!({"successor": successor}))))
+        }
+        Err(ClientError::NotLeader { leader }) => Ok((
+            StatusCode::CONFLICT,
+            Json(json!({"message": "not leader", "leader": leader})),
+        )),
+        Err(error) => Err(client_error(error)),
+    }
+}
+
+/// Blocks of all local users.
+async fn get_blocks(State(config): State<RuntimeConfig>) -> Result<Json<Value>, StatusCode> {
+    info!("handle get blocklist request");
+    spawn_blocking(move || {
+        let moderation = ModerationRepo::new(config.keyspace.clone()).map_err(ise)?;
+        let apub = &config.init.activity_pub;
+        let blocks: Vec<Value> = moderation
+            .find_blocks()
+            .map_err(ise)?
+            .into_iter()
+            .map(
+                |BlockEntry {
+                     uid,
+                     actor,
+                     act_key,
+                 }| {
+                    json!({
+                        "uid": uid,
+                        "actor": actor,
+                        "activity": apub.object_iri(act_key),
+                    })
+                },
+            )
+            .collect();
+        Ok(Json(json!(blocks)))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+}
+
+/// Received Flag activities, oldest first.
+async fn get_reports(State(config): State<RuntimeConfig>) -> Result<Json<Value>, StatusCode> {
+    info!("handle get reports request");
+    spawn_blocking(move || {
+        let moderation = ModerationRepo::new(config.keyspace.clone()).map_err(ise)?;
+        let reports: Vec<Value> = moderation
+            .find_reports()
+            .map_err(ise)?
+            .into_iter()
+            .map(|(obj_key, report)| json!({"key": obj_key.to_string(), "report": report.to_value()}))
+            .collect();
+        Ok(Json(json!(reports)))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct UserListParams {
+    after: Option<String>,
+    first: Option<u64>,
+}
+
+/// Local users in uid order, `next` is the `after` of the following page.
+async fn get_users(
+    State(config): State<RuntimeConfig>,
+    Query(params): Query<UserListParams>,
+) -> Result<Json<Value>, StatusCode> {
+    info!("handle get users request");
+    spawn_blocking(move || {
+        let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let http = &config.server.http;
+        let first = params
+            .first
+            .unwrap_or(http.collection_page_default)
+            .clamp(1, http.collection_page_max);
+        let uids = user_index
+            .list_users(params.after.as_deref(), first)
+            .map_err(ise)?;
+        let mut users = vec![];
+        for uid in &uids {
+            users.push(json!({
+                "uid": uid,
+                "id": config.init.activity_pub.user_iri(uid),
+                "disabled": user_index.is_disabled(uid).map_err(ise)?,
+            }));
+        }
+        let next = uids.last().filter(|_| uids.len() as u64 == first);
+        Ok(Json(json!({"users": users, "next": next})))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    uid: String,
+    name: Option<String>,
+}
+
+/// Create a local user with a new signing key.
+async fn post_users(
+    State(config): State<RuntimeConfig>,
+    Json(new_user): Json<NewUser>,
+) -> Result<Response, StatusCode> {
+    let NewUser { uid, name } = new_user;
+    info!(%uid, "handle post users request");
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+    if uid.is_empty() || !uid.chars().all(valid) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let keyspace = config.keyspace.clone();
+    let user_id = uid.clone();
+    let exists = spawn_blocking(move || UserIndex::new(keyspace)?.find_one(&user_id))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?
+        .is_some();
+    if exists {
+        return Err(StatusCode::CONFLICT);
+    }
+    let object = Object::from(json!({
+        "type": "Person",
+        "id": uid,
+        "preferredUsername": uid,
+        "name": name.as_deref().unwrap_or(&uid),
+    }));
+    let key_material = generate_key().map_err(ise)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::UpdateUser(uid.clone(), object, Some(key_material));
+    submit(&client, command).await?;
+    let iri = config.init.activity_pub.user_iri(&uid);
+    Ok((StatusCode::CREATED, [(header::LOCATION, iri)]).into_response())
+}
+
+/// Disable a local user, `disabled` false enables it again.
+async fn set_user_disabled(
+    config: &RuntimeConfig,
+    uid: String,
+    disabled: bool,
+) -> Result<(), StatusCode> {
+    info!(%uid, disabled, "handle user disable request");
+    let keyspace = config.keyspace.clone();
+    let user_id = uid.clone();
+    spawn_blocking(move || UserIndex::new(keyspace)?.find_one(&user_id))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    submit(&client, ActivityPubCommand::SetUserDisabled(uid, disabled)).await?;
+    Ok(())
+}
+
+async fn post_user_disable(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+) -> Result<(), StatusCode> {
+    set_user_disabled(&config, uid, true).await
+}
+
+async fn post_user_enable(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+) -> Result<(), StatusCode> {
+    set_user_disabled(&config, uid, false).await
+}
+
+#[derive(Deserialize)]
+struct UserVisibility {
+    visibility: Visibility,
+}
+
+/// Pick the audience of posts by a local user that name none, instead of
+/// `activity_pub.default_visibility`.
+async fn post_user_visibility(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+    Json(body): Json<UserVisibility>,
+) -> Result<(), StatusCode> {
+    info!(%uid, visibility = ?body.visibility, "handle user visibility request");
+    let keyspace = config.keyspace.clone();
+    let user_id = uid.clone();
+    spawn_blocking(move || UserIndex::new(keyspace)?.find_one(&user_id))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    let command = ActivityPubCommand::SetUserVisibility(uid, body.visibility);
+    submit(&client, command).await?;
+    Ok(())
+}
+
+/// Replace the signing key of a local user. Remote servers fetch the actor
+/// again when a signature no longer matches the key they cached.
+async fn post_user_rotate_key(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+) -> Result<(), StatusCode> {
+    info!(%uid, "handle rotate key request");
+    let crypto_repo = CryptoRepo::new(config.keyspace.clone()).map_err(ise)?;
+    let user_id = uid.clone();
+    spawn_blocking(move || crypto_repo.find_one(&user_id))
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let key_material = generate_key().map_err(ise)?;
+    let client = get_raft_local_client().map_err(ise)?;
+    submit(
+        &client,
+        ActivityPubCommand::RotateUserKey(uid, key_material),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Follows waiting for the user to approve them, oldest first.
+async fn get_follow_requests(
+    State(config): State<RuntimeConfig>,
+    Path(uid): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    info!(%uid, "handle get follow requests request");
+    spawn_blocking(move || {
+        let user_index = UserIndex::new(config.keyspace.clone()).map_err(ise)?;
+        let requests: Vec<Value> = user_index
+            .find_follow_requests(&uid)
+            .map_err(ise)?
+            .into_iter()
+            .map(|(follow_key, actor)| json!({"key": follow_key.to_string(), "actor": actor}))
+            .collect();
+        Ok(Json(json!(requests)))
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+}
+
+/// Accept or Reject a pending Follow of the user.
+async fn answer_follow_request(
+    config: &RuntimeConfig,
+    uid: String,
+    follow_key: String,
+    accept: bool,
+    request_id: Option<String>,
+) -> Result<(), StatusCode> {
+    info!(%uid, %follow_key, accept, "handle answer follow request");
+    let follow_key = ObjectKey::from_str(&follow_key).map_err(|_| StatusCode::NOT_FOUND)?;
+    let keyspace = config.keyspace.clone();
+    let user_id = uid.clone();
+    let follow = spawn_blocking(move || -> Result<Option<Object<'static>>> {
+        if !UserIndex::new(keyspace.clone())?.has_follow_request(&user_id, follow_key)? {
+            return Ok(None);
+        }
+        ObjectRepo::new(keyspace)?.find_one(follow_key)
+    })
+    .await
+    .context("task failed")
+    .map_err(ise)?
+    .map_err(ise)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+    answer_follow(config, &uid, follow_key, &follow, accept, request_id).await
+}
+
+async fn post_follow_request_accept(
+    State(config): State<RuntimeConfig>,
+    Path((uid, follow_key)): Path<(String, String)>,
+    Extension(request_id): Extension<RequestId>,
+) -> Result<(), StatusCode> {
+    let request_id = request_id::to_string(&request_id);
+    answer_follow_request(&config, uid, follow_key, true, request_id).await
+}
+
+async fn post_follow_request_reject(
+    State(config): State<RuntimeConfig>,
+    Path((uid, follow_key)): Path<(String, String)>,
+    Extension(request_id): Extension<RequestId>,
+) -> Result<(), StatusCode> {
+    let request_id = request_id::to_string(&request_id);
+    answer_follow_request(&config, uid, follow_key, false, request_id).await
+}
+
+#[derive(Deserialize)]
+struct Redeliver {
+    activity: String,
+}
+
+/// Deliver an abandoned local activity again to the inboxes that failed.
+async fn post_redeliver(
+    State(config): State<RuntimeConfig>,
+    Json(redeliver): Json<Redeliver>,
+) -> Result<Json<Value>, StatusCode> {
+    info!(%redeliver.activity, "handle redeliver request");
+    let act_key = local_activity_key(&config, &redeliver.activity)?;
+    if !config.init.activity_pub.federation_enabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let Some(delivery_worker) = ActorRef::where_is("delivery_worker".to_string()) else {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let report = ractor::call!(delivery_worker, DeliveryWorkerMsg::Redeliver, act_key)
+        .context("RPC call failed")
+        .map_err(ise)?
+        .map_err(ise)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(json!({
+        "delivered": report.delivered,
+        "failed": report.failed,
+    })))
+}
+
+/// Show the inboxes a local activity goes to and the signed requests that
+/// would be posted to them, without posting anything.
+async fn get_delivery_preview(
+    State(config): State<RuntimeConfig>,
+    Query(redeliver): Query<Redeliver>,
+) -> Result<Json<Value>, StatusCode> {
+    info!(%redeliver.activity, "handle delivery preview request");
+    let act_key = local_activity_key(&config, &redeliver.activity)?;
+    if !config.init.activity_pub.federation_enabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let Some(delivery_worker) = ActorRef::where_is("delivery_worker".to_string()) else {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    let preview = ractor::call!(delivery_worker, DeliveryWorkerMsg::Preview, act_key)
+        .context("RPC call failed")
+        .map_err(ise)?
+        .map_err(invalid)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let requests: Vec<Value> = preview
+        .requests
+        .into_iter()
+        .map(|request| {
+            let headers: Map<String, Value> = request
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name, Value::String(value)))
+                .collect();
+            let last_attempt = request.last_attempt.map(|attempt| {
+                json!({
+                    "at": attempt.at.to_string(),
+                    "ok": attempt.error.is_none(),
+                    "error": attempt.error,
+                })
+            });
+            json!({
+                "inbox": request.inbox,
+                "headers": headers,
+                "last_attempt": last_attempt,
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "activity": redeliver.activity,
+        "body": preview.body,
+        "requests": requests,
+    })))
+}
+
+/// Key of an activity stored on this server, from its IRI.
+fn local_activity_key(config: &RuntimeConfig, iri: &str) -> Result<ObjectKey, StatusCode> {
+    let prefix = format!("{}/as/objects/", config.init.activity_pub.base_url);
+    iri.strip_prefix(&prefix)
+        .and_then(|obj_key| ObjectKey::from_str(obj_key).ok())
+        .ok_or(StatusCode::BAD_REQUEST)
+}
+
 fn ise(_error: anyhow::Error) -> StatusCode {
     StatusCode::INTERNAL_SERVER_ERROR
 }
@@ -706,3 +2024,177 @@ fn ise(_error: anyhow::Error) -> StatusCode {
 fn invalid(_error: anyhow::Error) -> StatusCode {
     StatusCode::UNPROCESSABLE_ENTITY
 }
+
+fn client_error(error: ClientError) -> StatusCode {
+    warn!(%error, "client request failed");
+    match error {
+        ClientError::NotLeader { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        ClientError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ClientError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        ClientError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use reqwest::{Client, StatusCode};
+    use serde_json::{json, Value};
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    use crate::activity_pub::machine::AppliedIndex;
+    use crate::activity_pub::{ActorCache, Batch, ObjectKey, ObjectRepo};
+    use crate::config::{
+        ActivityPubConfig, CacheConfig, Config, HttpConfig, RuntimeConfig, ServerConfig,
+    };
+    use crate::storage_health::StorageHealth;
+
+    use super::{router, PageParams};
+
+    fn params(before: bool, after: bool, first: Option<u64>, last: Option<u64>) -> PageParams {
+        PageParams {
+            before: before.then(|| "b".to_string()),
+            after: after.then(|| "a".to_string()),
+            first,
+            last,
+        }
+    }
+
+    #[test]
+    fn page_limits_follow_config() {
+        let http = HttpConfig {
+            collection_page_default: 5,
+            collection_page_max: 20,
+            ..Default::default()
+        };
+        assert_eq!(
+            params(true, false, None, None).limits(&http),
+            (None, Some(5))
+        );
+        assert_eq!(
+            params(false, true, None, None).limits(&http),
+            (Some(5), None)
+        );
+        assert_eq!(
+            params(true, false, None, Some(100)).limits(&http),
+            (None, Some(20))
+        );
+        assert_eq!(
+            params(false, true, Some(3), None).limits(&http),
+            (Some(3), None)
+        );
+    }
+
+    /// Serve the API without a raft server, for requests turned away
+    /// before they reach it. Returns the base URL.
+    async fn serve(
+        activity_pub: ActivityPubConfig,
+        storage: StorageHealth,
+        dir: &TempDir,
+    ) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let config = RuntimeConfig {
+            init: Config {
+                activity_pub: ActivityPubConfig {
+                    base_url: base_url.clone(),
+                    ..activity_pub
+                },
+                ..Default::default()
+            },
+            server: ServerConfig::default(),
+            keyspace: fjall::Config::new(dir.path()).open()?,
+            actor_cache: ActorCache::new(&CacheConfig::default()),
+            applied_index: AppliedIndex::default(),
+            storage,
+        };
+        let app = router(&config)?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(base_url)
+    }
+
+    #[tokio::test]
+    async fn local_only_refuses_inbox() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let activity_pub = ActivityPubConfig {
+            shared_inbox: true,
+            federation_enabled: false,
+            ..Default::default()
+        };
+        let base_url = serve(activity_pub, StorageHealth::default(), &dir).await?;
+
+        let client = Client::new();
+        let like = json!({"type": "Like", "actor": "https://remote.example/users/bob"});
+        for path in ["/users/alice/inbox", "/inbox"] {
+            let response = client
+                .post(format!("{base_url}{path}"))
+                .json(&like)
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{path}");
+        }
+        let response = client.get(format!("{base_url}/version")).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn degraded_storage_refuses_writes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = StorageHealth::default();
+        let base_url = serve(ActivityPubConfig::default(), storage.clone(), &dir).await?;
+        let error = anyhow::anyhow!("No space left on device");
+        while !storage.is_degraded() {
+            storage.failed(&error);
+        }
+
+        let client = Client::new();
+        let note = json!({"type": "Note", "content": "helo"});
+        let response = client
+            .post(format!("{base_url}/users/alice/outbox"))
+            .json(&note)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key("retry-after"));
+        let response = client.get(format!("{base_url}/version")).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remote_objects_keep_their_context() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let remote_context =
+            json!(["https://www.w3.org/ns/activitystreams", {"ex": "https://remote.example/ns#"}]);
+        let (local_key, remote_key) = (ObjectKey::new(), ObjectKey::new());
+        {
+            let keyspace = fjall::Config::new(dir.path()).open()?;
+            let obj_repo = ObjectRepo::new(keyspace.clone())?;
+            let mut b = Batch::new(&keyspace);
+            let local = json!({"id": "http://127.0.0.1/notes/1", "type": "Note"});
+            obj_repo.insert(&mut b, local_key, local)?;
+            let remote = json!({
+                "@context": remote_context,
+                "id": "https://remote.example/notes/1",
+                "type": "Note",
+            });
+            obj_repo.insert(&mut b, remote_key, remote)?;
+            b.commit()?;
+        }
+        let base_url = serve(ActivityPubConfig::default(), StorageHealth::default(), &dir).await?;
+
+        let client = Client::new();
+        let get = |key: ObjectKey| client.get(format!("{base_url}/as/objects/{key}")).send();
+        let remote: Value = get(remote_key).await?.json().await?;
+        assert_eq!(remote["@context"], remote_context);
+        let local: Value = get(local_key).await?.json().await?;
+        assert_eq!(
+            local["@context"][0],
+            "https://www.w3.org/ns/activitystreams"
+        );
+        assert!(local["@context"][1]["sensitive"].is_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Server metadata for other servers and for operators.
+//!
+//! References:
+//! * <https://github.com/jhass/nodeinfo/blob/main/PROTOCOL.md>
+//! * <https://github.com/jhass/nodeinfo/blob/main/schemas/2.1/schema.json>
+
+use anyhow::Context;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::{json, Map, Value};
+use tokio::task::spawn_blocking;
+
+use super::ise;
+use crate::activity_pub::UserIndex;
+use crate::build_info;
+use crate::config::{InstanceConfig, RuntimeConfig};
+
+const SCHEMA_2_1: &str = "http://nodeinfo.diaspora.software/ns/schema/2.1";
+
+pub(super) async fn get_nodeinfo_links(State(config): State<RuntimeConfig>) -> Json<Value> {
+    Json(json!({
+        "links": [{
+            "rel": SCHEMA_2_1,
+            "href": format!("{}/nodeinfo/2.1", config.init.activity_pub.base_url),
+        }]
+    }))
+}
+
+pub(super) async fn get_nodeinfo(
+    State(config): State<RuntimeConfig>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let keyspace = config.keyspace.clone();
+    let users = spawn_blocking(move || UserIndex::new(keyspace)?.count_users())
+        .await
+        .context("task failed")
+        .map_err(ise)?
+        .map_err(ise)?;
+    let content_type = format!("application/json; profile=\"{SCHEMA_2_1}#\"");
+    let nodeinfo = json!({
+        "version": "2.1",
+        "software": {
+            "name": build_info::NAME,
+            "version": build_info::full_version(),
+        },
+        "protocols": ["activitypub"],
+        "services": {"inbound": [], "outbound": []},
+        "openRegistrations": false,
+        "usage": {"users": {"total": users}},
+        "metadata": metadata(&config.init.instance),
+    });
+    Ok(([(header::CONTENT_TYPE, content_type)], Json(nodeinfo)))
+}
+
+/// The instance description in the keys Misskey and others read.
+fn metadata(instance: &InstanceConfig) -> Value {
+    let mut metadata = Map::new();
+    let insert = |map: &mut Map<String, Value>, key: &str, value: &str| {
+        if !value.is_empty() {
+            map.insert(key.to_string(), Value::String(value.to_string()));
+        }
+    };
+    insert(&mut metadata, "nodeName", &instance.title);
+    insert(&mut metadata, "nodeDescription", &instance.description);
+    let mut maintainer = Map::new();
+    insert(&mut maintainer, "name", &instance.contact_name);
+    insert(&mut maintainer, "email", &instance.contact_email);
+    if !maintainer.is_empty() {
+        metadata.insert("maintainer".to_string(), Value::Object(maintainer));
+    }
+    Value::Object(metadata)
+}
+
+/// Name, version, commit and build time of the running binary.
+pub(super) async fn get_version() -> Json<Value> {
+    Json(build_info::to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::metadata;
+    use crate::config::InstanceConfig;
+
+    #[test]
+    fn metadata_leaves_out_empty_values() {
+        assert_eq!(metadata(&InstanceConfig::default()), json!({}));
+        let instance = InstanceConfig {
+            title: "Pinka".to_string(),
+            contact_email: "admin@example.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            metadata(&instance),
+            json!({"nodeName": "Pinka", "maintainer": {"email": "admin@example.com"}})
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Rate limits on activities delivered to inboxes.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use metrics::counter;
+use moka::sync::Cache;
+use reqwest::Url;
+
+use crate::config::HttpConfig;
+
+/// Token bucket refilled at a fixed rate up to its burst size.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets of one kind of sender, created full on first sight.
+#[derive(Clone)]
+struct Buckets {
+    buckets: Cache<String, Arc<Mutex<TokenBucket>>>,
+    /// Tokens per second.
+    rate: f64,
+    burst: f64,
+}
+
+impl Buckets {
+    fn new(per_minute: u32, burst: u32, capacity: u64) -> Option<Buckets> {
+        if per_minute == 0 {
+            return None;
+        }
+        let rate = f64::from(per_minute) / 60.0;
+        let burst = f64::from(burst.max(1));
+        // A bucket idle this long is full again, forgetting it changes nothing.
+        let refill = Duration::from_secs_f64(burst / rate);
+        let buckets = Cache::builder()
+            .max_capacity(capacity.max(1))
+            .time_to_idle(refill)
+            .build();
+        Some(Buckets {
+            buckets,
+            rate,
+            burst,
+        })
+    }
+    /// Take a token for `key`, or tell how long until the next one.
+    fn take(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let bucket = self.buckets.get_with_by_ref(key, || {
+            Arc::new(Mutex::new(TokenBucket {
+                tokens: self.burst,
+                updated: now,
+            }))
+        });
+        let mut bucket = bucket.lock().expect("rate limit bucket poisoned");
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// Limits how many activities a single remote actor, and all actors of a
+/// single host, get into the inboxes of this server.
+///
+/// Each server keeps its own counts in memory, a peer balanced over several
+/// servers gets the rate of each.
+#[derive(Clone)]
+pub(super) struct InboxLimiter {
+    actors: Option<Buckets>,
+    hosts: Option<Buckets>,
+}
+
+impl InboxLimiter {
+    pub(super) fn new(config: &HttpConfig) -> InboxLimiter {
+        InboxLimiter {
+            actors: Buckets::new(
+                config.inbox_actor_per_minute,
+                config.inbox_actor_burst,
+                config.inbox_rate_tracked,
+            ),
+            hosts: Buckets::new(
+                config.inbox_host_per_minute,
+                config.inbox_host_burst,
+                config.inbox_rate_tracked,
+            ),
+        }
+    }
+    /// Count an activity from `actor`. Returns the seconds to wait before
+    /// trying again if either limit is exceeded.
+    pub(super) fn check(&self, actor: &str) -> Result<(), u64> {
+        self.check_at(actor, Instant::now())
+    }
+    fn check_at(&self, actor: &str, now: Instant) -> Result<(), u64> {
+        let retry_after = |wait: Duration| wait.as_secs_f64().ceil().max(1.0) as u64;
+        if let Some(actors) = &self.actors {
+            if let Err(wait) = actors.take(actor, now) {
+                counter!("pinka_inbox_rate_limited_total", "by" => "actor").increment(1);
+                return Err(retry_after(wait));
+            }
+        }
+        let host = Url::parse(actor)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if let (Some(hosts), Some(host)) = (&self.hosts, host) {
+            if let Err(wait) = hosts.take(&host, now) {
+                counter!("pinka_inbox_rate_limited_total", "by" => "host").increment(1);
+                return Err(retry_after(wait));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::config::HttpConfig;
+
+    use super::InboxLimiter;
+
+    #[test]
+    fn limit_actor_then_host() {
+        let limiter = InboxLimiter::new(&HttpConfig {
+            inbox_actor_per_minute: 60,
+            inbox_actor_burst: 2,
+            inbox_host_per_minute: 60,
+            inbox_host_burst: 3,
+            ..Default::default()
+        });
+        let bob = "https://remote.example/users/bob";
+        let carol = "https://remote.example/users/carol";
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(bob, now), Ok(()));
+        assert_eq!(limiter.check_at(bob, now), Ok(()));
+        assert_eq!(limiter.check_at(bob, now), Err(1));
+
+        // The host has one token left, shared by every actor.
+        assert_eq!(limiter.check_at(carol, now), Ok(()));
+        assert_eq!(limiter.check_at(carol, now), Err(1));
+        assert_eq!(
+            limiter.check_at("https://other.example/users/dave", now),
+            Ok(())
+        );
+
+        // One token per second comes back.
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check_at(bob, later), Ok(()));
+    }
+
+    #[test]
+    fn zero_disables_limit() {
+        let limiter = InboxLimiter::new(&HttpConfig {
+            inbox_actor_per_minute: 0,
+            inbox_host_per_minute: 0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(
+                limiter.check_at("https://remote.example/users/bob", now),
+                Ok(())
+            );
+        }
+    }
+}
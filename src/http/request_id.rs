@@ -0,0 +1,28 @@
+//! Per-request correlation IDs.
+//!
+//! Every request gets an `X-Request-Id` (kept if the client sent one) that
+//! is recorded on the request span, echoed back in the response and carried
+//! into the Raft commands so state machine and delivery logs can be joined.
+
+use axum::body::Body;
+use axum::http::Request;
+use tower_http::request_id::RequestId;
+use tracing::{info_span, Span};
+
+pub(super) fn make_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id
+    )
+}
+
+pub(super) fn to_string(request_id: &RequestId) -> Option<String> {
+    request_id.header_value().to_str().ok().map(str::to_string)
+}
@@ -0,0 +1,61 @@
+//! Log output and its verbosity per tracing target.
+//!
+//! The filter names a level per target, `info,raft=debug,http=warn` logs
+//! raft at debug, http at warn and everything else at info. Targets are
+//! module paths unless an event names its own, a target also matches the
+//! ones it prefixes and `raft` stands for `pinka::raft` as well. The filter
+//! comes from `log.filter` in the config, `RUST_LOG` overrides it. A running
+//! server reads the config file again on SIGHUP and applies the new filter.
+
+use std::env;
+
+use anyhow::{Context, Result};
+use tracing::info;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, Registry};
+
+use crate::config::LogConfig;
+
+/// Handle on the filter of the installed subscriber.
+#[derive(Clone)]
+pub(crate) struct LogFilter {
+    handle: reload::Handle<Targets, Registry>,
+    /// `RUST_LOG` was set, the config does not override it.
+    from_env: bool,
+}
+
+/// Install the global subscriber, logging at info until a config applies
+/// its filter.
+pub(crate) fn init() -> LogFilter {
+    let env_filter = env::var("RUST_LOG").ok().and_then(|filter| {
+        LogConfig { filter }
+            .targets()
+            .inspect_err(|error| eprintln!("Ignoring RUST_LOG: {error:#}"))
+            .ok()
+    });
+    let from_env = env_filter.is_some();
+    let filter = env_filter.unwrap_or_else(|| LogConfig::default().targets().unwrap());
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .init();
+    LogFilter { handle, from_env }
+}
+
+impl LogFilter {
+    /// Log with the filter of `config`, unless `RUST_LOG` set one.
+    pub(crate) fn apply(&self, config: &LogConfig) -> Result<()> {
+        if self.from_env {
+            return Ok(());
+        }
+        let targets = config.targets()?;
+        self.handle
+            .reload(targets)
+            .context("Failed to reload log filter")?;
+        info!(filter = config.filter, "log filter applied");
+        Ok(())
+    }
+}
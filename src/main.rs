@@ -1,35 +1,53 @@
+#![recursion_limit = "256"]
+
 mod activity_pub;
+mod backup;
+mod build_info;
 mod cluster;
 mod config;
+mod dump_log;
 mod feed_slurp;
 mod flags;
 mod http;
+mod logging;
 mod raft;
+mod raft_state;
+mod storage_health;
 mod supervisor;
+mod telemetry;
 
 use std::fs::{self, File};
+use std::io::stdout;
 use std::path::Path;
 use std::process::exit;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
 use fd_lock::RwLock;
 use ractor::Actor;
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{sleep, Instant};
 use tracing::{error, info};
 
+use self::activity_pub::machine::AppliedIndex;
+use self::activity_pub::ActorCache;
 use self::config::{ActivityPubConfig, Config, RuntimeConfig};
 use self::flags::{Pinka, PinkaCmd};
+use self::raft::check_quorum;
+use self::storage_health::StorageHealth;
 use self::supervisor::Supervisor;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let log_filter = logging::init();
+    telemetry::install()?;
     let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
 
     let flags = Pinka::from_env_or_exit();
 
     let config = Config::open(&flags.config)
         .with_context(|| format!("Failed to read config file {}", flags.config.display()))?;
+    log_filter.apply(&config.log)?;
 
     let server_id = flags.server.unwrap_or_default();
     if config.cluster.servers.len() <= server_id {
@@ -43,6 +61,12 @@ async fn main() -> Result<()> {
 
     let keyspace_name = config.database.path.join(&server.name);
     if !keyspace_name.exists() {
+        if matches!(
+            flags.subcommand,
+            PinkaCmd::DumpLog(_) | PinkaCmd::RaftState(_)
+        ) {
+            bail!("database {} does not exist", keyspace_name.display());
+        }
         create_keyspace_folder(&keyspace_name).context("Failed to create database folder")?;
     }
     let lock_file =
@@ -68,14 +92,32 @@ async fn main() -> Result<()> {
         .open()
         .context("Failed to open database")?;
 
+    let actor_cache = ActorCache::new(&config.cache);
     let config = RuntimeConfig {
         init: config,
         server,
         keyspace,
+        actor_cache,
+        applied_index: AppliedIndex::default(),
+        storage: StorageHealth::default(),
     };
 
     match flags.subcommand {
-        PinkaCmd::Serve(_) => serve(config).await?,
+        PinkaCmd::Serve(cmd) => {
+            serve(config, cmd.wait_for_quorum, &flags.config, log_filter).await?
+        }
+        PinkaCmd::Backup(cmd) => backup::backup(&config.keyspace, &cmd.out)?,
+        PinkaCmd::Restore(cmd) => backup::restore(&config.keyspace, &cmd.from)?,
+        PinkaCmd::DumpLog(cmd) => {
+            dump_log::dump_log(&config.keyspace, cmd.from, cmd.to, &mut stdout().lock())?
+        }
+        PinkaCmd::RaftState(cmd) => raft_state::raft_state(
+            &config.keyspace,
+            &config.server,
+            &config.init.cluster.servers,
+            cmd,
+            &mut stdout().lock(),
+        )?,
     }
 
     drop(write_guard);
@@ -95,30 +137,58 @@ fn create_keyspace_folder(keyspace_name: &Path) -> Result<()> {
     Ok(())
 }
 
-async fn serve(config: RuntimeConfig) -> Result<()> {
+async fn serve(
+    config: RuntimeConfig,
+    wait_for_quorum_secs: Option<u64>,
+    config_path: &Path,
+    log_filter: logging::LogFilter,
+) -> Result<()> {
     let (supervisor, mut actor_handle) =
         Actor::spawn(Some("supervisor".into()), Supervisor, config.clone())
             .await
             .context("Failed to spawn supervisor")?;
 
+    if let Some(secs) = wait_for_quorum_secs {
+        if let Err(error) = wait_for_quorum(&config, Duration::from_secs(secs)).await {
+            supervisor.stop(None);
+            actor_handle.await?;
+            return Err(error);
+        }
+    }
+
     let http = http::serve(&config);
+    tokio::pin!(http);
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sigint = signal(SignalKind::interrupt())?;
-
-    tokio::select! {
-        _ = &mut actor_handle => {
-            error!("Supervisor thread crashed");
-            bail!("Supervisor thread crashed");
-        }
-        _ = http => {
-            error!("HTTP thread crashed");
-        }
-        _ = sigterm.recv() => {
-            info!("Received the terminate signal; stopping");
-        }
-        _ = sigint.recv() => {
-            info!("Received the interrupt signal; stopping");
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    loop {
+        tokio::select! {
+            _ = &mut actor_handle => {
+                error!("Supervisor stopped");
+                bail!("Supervisor stopped");
+            }
+            _ = &mut http => {
+                error!("HTTP thread crashed");
+            }
+            _ = sigterm.recv() => {
+                info!("Received the terminate signal; stopping");
+            }
+            _ = sigint.recv() => {
+                info!("Received the interrupt signal; stopping");
+            }
+            _ = sighup.recv() => {
+                // Only the log filter changes, other settings need a restart.
+                info!("Received the hangup signal; reloading the log filter");
+                let reloaded = Config::open(config_path)
+                    .and_then(|config| log_filter.apply(&config.log));
+                if let Err(error) = reloaded {
+                    error!(?error, "Failed to reload the log filter");
+                }
+                continue;
+            }
         }
+        break;
     }
 
     supervisor.stop(None);
@@ -126,3 +196,26 @@ async fn serve(config: RuntimeConfig) -> Result<()> {
 
     Ok(())
 }
+
+/// Poll the raft servers until a majority answers and a leader is known,
+/// then print the last check as JSON.
+async fn wait_for_quorum(config: &RuntimeConfig, wait: Duration) -> Result<()> {
+    let deadline = Instant::now() + wait;
+    loop {
+        let report = check_quorum(&config.init.cluster.servers).await;
+        let healthy = report.healthy();
+        if healthy || Instant::now() >= deadline {
+            println!("{}", serde_json::to_string(&report)?);
+            if !healthy {
+                bail!("no raft quorum with a known leader in {wait:?}");
+            }
+            return Ok(());
+        }
+        info!(
+            visible = report.visible,
+            voters = report.voters,
+            "waiting for raft quorum"
+        );
+        sleep(Duration::from_millis(500)).await;
+    }
+}
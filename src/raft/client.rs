@@ -1,22 +1,33 @@
+use std::fmt::{self, Display};
+
 use anyhow::{bail, Result};
 use minicbor::{Decode, Encode};
-use ractor::{ActorRef, DerivedActorRef, RpcReplyPort};
-use ractor_cluster::RactorClusterMessage;
+use ractor::{ActorRef, DerivedActorRef, Message, RpcReplyPort};
+use serde::{Deserialize, Serialize};
 
-use super::RaftWorker;
+use super::rpc::{PeerId, Wire};
 use super::{LogEntryValue, RaftMsg};
+use super::{RaftWorker, RAFT_SCOPE};
 
-#[derive(RactorClusterMessage)]
+/// Requests to the local raft worker. They reach other nodes as [`RaftMsg`].
 pub(crate) enum RaftClientMsg {
-    // TODO: add status code
-    #[rpc]
     ClientRequest(LogEntryValue, RpcReplyPort<ClientResult>),
+    /// Hand leadership over to another server, the reply names it.
+    StepDown(RpcReplyPort<ClientResult>),
+    /// Replies `Ok` if this server leads, `NotLeader` otherwise.
+    CheckLeader(RpcReplyPort<ClientResult>),
 }
 
+impl Message for RaftClientMsg {}
+
 impl From<RaftClientMsg> for RaftMsg {
     fn from(value: RaftClientMsg) -> Self {
         match value {
-            RaftClientMsg::ClientRequest(value, reply) => RaftMsg::ClientRequest(value, reply),
+            RaftClientMsg::ClientRequest(value, reply) => {
+                RaftMsg::ClientRequest(value.into(), reply)
+            }
+            RaftClientMsg::StepDown(reply) => RaftMsg::StepDown(reply),
+            RaftClientMsg::CheckLeader(reply) => RaftMsg::CheckLeader(reply),
         }
     }
 }
@@ -24,38 +35,132 @@ impl From<RaftClientMsg> for RaftMsg {
 impl From<RaftMsg> for RaftClientMsg {
     fn from(value: RaftMsg) -> Self {
         match value {
-            RaftMsg::ClientRequest(value, reply) => RaftClientMsg::ClientRequest(value, reply),
+            RaftMsg::ClientRequest(Wire::Valid(value), reply) => {
+                RaftClientMsg::ClientRequest(value, reply)
+            }
+            RaftMsg::StepDown(reply) => RaftClientMsg::StepDown(reply),
+            RaftMsg::CheckLeader(reply) => RaftClientMsg::CheckLeader(reply),
             _ => panic!("unsupported RaftClientMsg conversion"),
         }
     }
 }
 
-#[derive(Debug, Encode, Decode)]
+/// Reply to a client request, see [`ClientResult::into_result`].
+///
+/// A successful reply carries what the state machine replied and the index
+/// of the log entry. The state machine leaves the index at 0, the raft
+/// worker fills it in when it replies to the client.
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(crate) enum ClientResult {
     #[n(0)]
-    Ok(#[cbor(n(0), with = "minicbor::bytes")] Vec<u8>),
+    Ok(#[cbor(n(0), with = "minicbor::bytes")] Vec<u8>, #[n(1)] u64),
+    #[n(1)]
+    Err(#[n(0)] ClientError),
+}
+
+/// Why a client request failed.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub(crate) enum ClientError {
+    /// The node does not lead and cannot forward the request. `leader` is
+    /// the leader it last heard from, if any.
+    ///
+    /// A leader that steps down fails its outstanding requests this way, the
+    /// entries may still be committed by the next leader.
+    #[n(0)]
+    NotLeader {
+        #[n(0)]
+        leader: Option<PeerId>,
+    },
+    /// The leader did not answer a forwarded request in time.
     #[n(1)]
-    Err(#[cbor(n(0), with = "minicbor::bytes")] Vec<u8>),
+    Timeout,
+    /// Writing the log or calling the leader failed.
+    #[n(2)]
+    Internal(#[n(0)] String),
+    /// The serialized entry is larger than `raft.max_entry_bytes`.
+    #[n(3)]
+    TooLarge {
+        #[n(0)]
+        size: u64,
+        #[n(1)]
+        max: u64,
+    },
 }
 
+impl Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotLeader {
+                leader: Some(leader),
+            } => {
+                write!(f, "not the leader, the leader is {leader}")
+            }
+            ClientError::NotLeader { leader: None } => write!(f, "not the leader, no leader known"),
+            ClientError::Timeout => write!(f, "the leader did not answer in time"),
+            ClientError::Internal(error) => write!(f, "internal error: {error}"),
+            ClientError::TooLarge { size, max } => {
+                write!(f, "entry of {size} bytes exceeds the limit of {max} bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
 impl ClientResult {
     pub(crate) fn ok() -> ClientResult {
-        ClientResult::Ok(vec![])
+        ClientResult::Ok(vec![], 0)
+    }
+    /// Reply with the key of the record a command stored, if any.
+    pub(crate) fn stored(key: Option<impl AsRef<[u8]>>) -> ClientResult {
+        key.map_or_else(ClientResult::ok, |key| {
+            ClientResult::Ok(key.as_ref().to_vec(), 0)
+        })
+    }
+    /// The reply for the log entry at `index`.
+    pub(crate) fn at_index(self, index: u64) -> ClientResult {
+        match self {
+            ClientResult::Ok(bytes, _) => ClientResult::Ok(bytes, index),
+            error => error,
+        }
+    }
+    /// What the state machine replied and the index of the log entry, or
+    /// why the request failed.
+    pub(crate) fn into_result(self) -> Result<(Vec<u8>, u64), ClientError> {
+        match self {
+            ClientResult::Ok(bytes, index) => Ok((bytes, index)),
+            ClientResult::Err(error) => Err(error),
+        }
     }
 }
 
 impl From<Vec<u8>> for ClientResult {
     fn from(value: Vec<u8>) -> Self {
-        ClientResult::Ok(value)
+        ClientResult::Ok(value, 0)
+    }
+}
+
+impl From<ClientError> for ClientResult {
+    fn from(value: ClientError) -> Self {
+        ClientResult::Err(value)
     }
 }
 
 pub(crate) fn get_raft_local_client() -> Result<DerivedActorRef<RaftClientMsg>> {
     if let Some(cell) =
-        ractor::pg::get_scoped_local_members(&"raft".into(), &RaftWorker::pg_name()).first()
+        ractor::pg::get_scoped_local_members(&RAFT_SCOPE.into(), &RaftWorker::pg_name()).first()
     {
         let worker: ActorRef<RaftMsg> = cell.clone().into();
         return Ok(worker.get_derived());
     }
     bail!("raft service is not available")
 }
+
+/// Names of the raft workers in the process group, on this server and on the
+/// connected ones.
+pub(crate) fn get_raft_members() -> Vec<PeerId> {
+    ractor::pg::get_scoped_members(&RAFT_SCOPE.into(), &RaftWorker::pg_name())
+        .iter()
+        .filter_map(|cell| cell.get_name())
+        .collect()
+}
@@ -3,11 +3,12 @@ use std::ops::RangeBounds;
 use anyhow::{Context, Error, Result};
 use fjall::{Batch, PartitionHandle};
 use minicbor::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
 use super::rpc::RaftSerDe;
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(crate) struct LogEntry {
     #[n(0)]
     pub(crate) index: u64,
@@ -17,7 +18,7 @@ pub(crate) struct LogEntry {
     pub(crate) value: LogEntryValue,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(crate) enum LogEntryValue {
     /// New leader has been elected
     #[n(0)]
@@ -30,12 +31,6 @@ pub(crate) enum LogEntryValue {
     Command(#[cbor(n(0), with = "minicbor::bytes")] Vec<u8>),
 }
 
-#[derive(Debug, Encode, Decode)]
-pub(crate) struct LogEntryList {
-    #[n(0)]
-    pub(crate) items: Vec<LogEntry>,
-}
-
 impl RaftSerDe for LogEntry {}
 
 #[derive(Clone)]
@@ -48,18 +43,6 @@ impl RaftLog {
         RaftLog { log }
     }
 
-    pub(super) async fn insert(&self, mut b: Batch, entry: LogEntry) -> Result<()> {
-        let log = self.log.clone();
-        spawn_blocking(move || {
-            let key = entry.index.to_be_bytes();
-            let value = entry.to_bytes()?;
-            b.insert(&log, key, value);
-            b.commit().context("Failed to write log entry")
-        })
-        .await
-        .context("Failed to insert log entry")?
-    }
-
     pub(super) async fn insert_all(&self, mut b: Batch, entries: Vec<LogEntry>) -> Result<()> {
         let log = self.log.clone();
         spawn_blocking(move || {
@@ -138,3 +121,37 @@ impl RaftLog {
         .context("Failed to remove last log entry")?
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::Config;
+    use tempfile::tempdir;
+
+    use super::{LogEntry, LogEntryValue, RaftLog};
+
+    /// Log keys are big-endian indexes, so the byte order of the partition
+    /// is the numeric order range scans and the last entry rely on.
+    #[tokio::test]
+    async fn scan_log_in_index_order() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Config::new(tmp_dir.path()).temporary(true).open()?;
+        let log = RaftLog::new(keyspace.open_partition("raft_log", Default::default())?);
+        let entries = [2, 65536, 255, 1, 256]
+            .into_iter()
+            .map(|index| LogEntry {
+                index,
+                term: 1,
+                value: LogEntryValue::NewTermStarted,
+            })
+            .collect();
+        log.insert_all(keyspace.batch(), entries).await?;
+
+        let range = log.log_entry_range(2..=256).await?;
+        let indexes: Vec<u64> = range.iter().map(|entry| entry.index).collect();
+        assert_eq!(indexes, [2, 255, 256]);
+        let last = log.get_last_log_entry().await?;
+        assert_eq!(last.map(|entry| entry.index), Some(65536));
+        Ok(())
+    }
+}
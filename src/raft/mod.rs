@@ -1,29 +1,40 @@
 mod client;
 mod log_entry;
+mod quorum;
 mod replicate;
 mod rpc;
 mod state;
 mod state_machine;
+#[cfg(test)]
+mod tests;
+mod watchdog;
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Deref;
 use std::time::Duration;
 
-pub(crate) use self::client::{get_raft_local_client, ClientResult, RaftClientMsg};
+pub(crate) use self::client::{
+    get_raft_local_client, get_raft_members, ClientError, ClientResult, RaftClientMsg,
+};
 use self::log_entry::RaftLog;
-pub(crate) use self::log_entry::{LogEntry, LogEntryList, LogEntryValue};
+pub(crate) use self::log_entry::{LogEntry, LogEntryValue};
+pub(crate) use self::quorum::check_quorum;
 use self::replicate::{ReplicateArgs, ReplicateMsg, ReplicateWorker};
 use self::rpc::RaftSerDe;
+pub(crate) use self::rpc::{set_wire_encoding, wire_cookie};
 use self::rpc::{
-    AdvanceCommitIndexMsg, AppendEntriesAsk, AppendEntriesReply, PeerId, RequestVoteAsk,
-    RequestVoteReply,
+    AdvanceCommitIndexMsg, AppendEntriesAsk, AppendEntriesReply, PeerId, RaftStatus,
+    RequestVoteAsk, RequestVoteReply, Wire,
 };
-use self::state::RaftSaved;
+pub(crate) use self::state::RaftSaved;
 pub(crate) use self::state_machine::{get_raft_applied, RaftAppliedMsg, StateMachineMsg};
+use self::watchdog::{Stall, Watchdog};
 
 use anyhow::{Context, Error, Result};
-use fjall::{KvSeparationOptions, PartitionCreateOptions, PartitionHandle, PersistMode};
-use ractor::{pg, Actor, ActorProcessingErr, ActorRef, RpcReplyPort, SupervisionEvent};
+use fjall::{Keyspace, KvSeparationOptions, PartitionCreateOptions, PartitionHandle, PersistMode};
+use metrics::{counter, gauge};
+use ractor::rpc::CallResult;
+use ractor::{pg, Actor, ActorCell, ActorProcessingErr, ActorRef, RpcReplyPort, SupervisionEvent};
 use ractor_cluster::{RactorClusterMessage, RactorMessage};
 use rand::Rng;
 use tokio::select;
@@ -36,11 +47,20 @@ use crate::config::{RuntimeConfig, ServerConfig};
 
 pub(super) struct RaftServer;
 #[derive(RactorMessage)]
-pub(super) enum RaftServerMsg {}
+pub(super) enum RaftServerMsg {
+    /// Poll the worker for progress, see [`watchdog`].
+    Watchdog,
+}
+
+pub(super) struct RaftServerState {
+    config: RuntimeConfig,
+    /// None if `raft.watchdog_interval_ms` is 0.
+    watchdog: Option<Watchdog>,
+}
 
 impl Actor for RaftServer {
     type Msg = RaftServerMsg;
-    type State = RuntimeConfig;
+    type State = RaftServerState;
     type Arguments = RuntimeConfig;
 
     async fn pre_start(
@@ -51,11 +71,33 @@ impl Actor for RaftServer {
         Actor::spawn_linked(
             Some(args.server.name.clone()),
             RaftWorker,
-            args.clone(),
+            RaftWorkerArgs::from(args.clone()),
             myself.get_cell(),
         )
         .await?;
-        Ok(args)
+        let raft = &args.init.raft;
+        let watchdog = (raft.watchdog_interval_ms > 0).then(|| {
+            let interval = Duration::from_millis(raft.watchdog_interval_ms);
+            myself.send_interval(interval, || RaftServerMsg::Watchdog);
+            let stall = Duration::from_millis(raft.watchdog_stall_ms);
+            Watchdog::new(stall, Instant::now())
+        });
+        Ok(RaftServerState {
+            config: args,
+            watchdog,
+        })
+    }
+
+    async fn handle(
+        &self,
+        myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            RaftServerMsg::Watchdog => state.watch(&myself).await?,
+        }
+        Ok(())
     }
 
     async fn handle_supervisor_evt(
@@ -68,9 +110,9 @@ impl Actor for RaftServer {
             error!("{:?}", error);
             info!("raft worker crashed, restarting...");
             Actor::spawn_linked(
-                Some(state.server.name.clone()),
+                Some(state.config.server.name.clone()),
                 RaftWorker,
-                state.clone(),
+                state.worker_args(),
                 myself.get_cell(),
             )
             .await?;
@@ -79,21 +121,166 @@ impl Actor for RaftServer {
     }
 }
 
+impl RaftServerState {
+    fn worker_args(&self) -> RaftWorkerArgs {
+        self.config.clone().into()
+    }
+    async fn watch(&mut self, myself: &ActorRef<RaftServerMsg>) -> Result<()> {
+        let Some(watchdog) = &mut self.watchdog else {
+            return Ok(());
+        };
+        let raft = &self.config.init.raft;
+        let worker: Option<ActorRef<RaftMsg>> =
+            ractor::registry::where_is(self.config.server.name.clone()).map(Into::into);
+        let timeout = Duration::from_millis(raft.watchdog_interval_ms);
+        let status = match &worker {
+            Some(worker) => match worker.call(RaftMsg::GetStatus, Some(timeout)).await {
+                Ok(CallResult::Success(status)) => status.valid(),
+                _ => None,
+            },
+            None => None,
+        };
+        let Some(stall) = watchdog.check(status.as_ref(), Instant::now()) else {
+            return Ok(());
+        };
+        counter!("pinka_raft_watchdog_stalls_total").increment(1);
+        let stall_ms = raft.watchdog_stall_ms;
+        match stall {
+            Stall::Unresponsive => {
+                error!(stall_ms, "raft worker did not answer status polls");
+            }
+            Stall::Apply {
+                commit_index,
+                last_applied,
+            } => {
+                let term = status.map(|status| status.term);
+                error!(
+                    stall_ms,
+                    ?term,
+                    commit_index,
+                    last_applied,
+                    "raft worker applied no committed entry"
+                );
+            }
+        }
+        if !raft.watchdog_restart {
+            return Ok(());
+        }
+        warn!("restarting stalled raft worker");
+        if let Some(worker) = worker {
+            worker.kill_and_wait(None).await?;
+        }
+        // Not `worker_args`, the watchdog is still borrowed.
+        let args = RaftWorkerArgs::from(self.config.clone());
+        Actor::spawn_linked(
+            Some(self.config.server.name.clone()),
+            RaftWorker,
+            args,
+            myself.get_cell(),
+        )
+        .await?;
+        watchdog.reset(Instant::now());
+        Ok(())
+    }
+}
+
+/// What raft workers saved in `keyspace`, the defaults of a new server if
+/// nothing.
+pub(crate) fn saved_state(keyspace: &Keyspace) -> Result<RaftSaved> {
+    let restore = keyspace
+        .open_partition("raft_restore", PartitionCreateOptions::default())
+        .context("Failed to open raft_restore state")?;
+    match restore.get("raft_saved")? {
+        Some(value) => RaftSaved::from_bytes(&value),
+        None => Ok(RaftSaved::default()),
+    }
+}
+
+/// Replace what raft workers saved in `keyspace`, for the offline repair in
+/// [`crate::raft_state`]. The server must not be running.
+pub(crate) fn overwrite_saved_state(keyspace: &Keyspace, saved: &RaftSaved) -> Result<()> {
+    let restore = keyspace
+        .open_partition("raft_restore", PartitionCreateOptions::default())
+        .context("Failed to open raft_restore state")?;
+    store_saved(keyspace, &restore, saved)
+}
+
+/// Write `saved` and sync it to disk before returning, raft must not answer
+/// an RPC on a term or vote it could forget.
+fn store_saved(keyspace: &Keyspace, restore: &PartitionHandle, saved: &RaftSaved) -> Result<()> {
+    let mut batch = keyspace.batch().durability(Some(PersistMode::SyncAll));
+    batch.insert(restore, "raft_saved", saved.to_bytes()?);
+    batch.commit()?;
+    Ok(())
+}
+
+/// `last_applied` as saved by raft workers before the state machine kept
+/// its own position.
+pub(crate) fn saved_last_applied(keyspace: &Keyspace) -> Result<u64> {
+    Ok(saved_state(keyspace)?.last_applied)
+}
+
+/// Process group scope the raft workers of a server join.
+const RAFT_SCOPE: &str = "raft";
+
+/// Registered name of the state machine raft workers apply entries to.
+const STATE_MACHINE: &str = "state_machine";
+
 struct RaftWorker;
 
+struct RaftWorkerArgs {
+    config: RuntimeConfig,
+    /// Process group scope to find peers in, it picks the transport.
+    /// Servers join [`RAFT_SCOPE`], which `ractor_cluster` spans across
+    /// nodes. Test clusters join a scope of their own and reach each other
+    /// in process only, through the links of `tests::network`.
+    scope: String,
+    /// Registered name of the state machine to apply entries to.
+    state_machine: String,
+}
+
+impl From<RuntimeConfig> for RaftWorkerArgs {
+    fn from(config: RuntimeConfig) -> Self {
+        RaftWorkerArgs {
+            config,
+            scope: RAFT_SCOPE.into(),
+            state_machine: STATE_MACHINE.into(),
+        }
+    }
+}
+
 #[derive(RactorClusterMessage)]
 enum RaftMsg {
     ElectionTimeout,
     UpdateTerm(u32),
-    AdvanceCommitIndex(AdvanceCommitIndexMsg),
+    AdvanceCommitIndex(Wire<AdvanceCommitIndexMsg>),
     #[rpc]
-    AppendEntries(AppendEntriesAsk, RpcReplyPort<AppendEntriesReply>),
-    RequestVote(RequestVoteAsk),
-    RequestVoteResponse(RequestVoteReply),
+    AppendEntries(
+        Wire<AppendEntriesAsk>,
+        RpcReplyPort<Wire<AppendEntriesReply>>,
+    ),
+    RequestVote(Wire<RequestVoteAsk>),
+    RequestVoteResponse(Wire<RequestVoteReply>),
     // TODO: add status code
     #[rpc]
-    ClientRequest(LogEntryValue, RpcReplyPort<ClientResult>),
+    ClientRequest(Wire<LogEntryValue>, RpcReplyPort<ClientResult>),
     AppliedLog(u64, ClientResult),
+    ResumeApply(u64),
+    /// Give up leadership, see [`RaftState::step_down`].
+    #[rpc]
+    StepDown(RpcReplyPort<ClientResult>),
+    /// The drain of a step down in the term is over.
+    DrainTimeout(u32),
+    /// Append the client requests batched in the term, see
+    /// [`RaftState::flush_batch`].
+    FlushBatch(u32),
+    #[rpc]
+    CheckLeader(RpcReplyPort<ClientResult>),
+    #[rpc]
+    GetStatus(RpcReplyPort<Wire<RaftStatus>>),
+    /// A leader stepping down in the term asks the receiver to run for
+    /// election right away. The second field names the leader.
+    TimeoutNow(u32, PeerId),
 }
 
 /// Role played by the worker.
@@ -121,9 +308,18 @@ struct RaftState {
     /// Actor reference
     myself: ActorRef<RaftMsg>,
 
+    /// Our name, the name of our server.
+    id: PeerId,
+
     /// Cluster config
     config: RuntimeConfig,
 
+    /// Process group scope to find peers in.
+    scope: String,
+
+    /// Registered name of the state machine.
+    state_machine: String,
+
     /// State restore partition
     restore: PartitionHandle,
 
@@ -170,17 +366,22 @@ struct RaftState {
     /// Volatile state. Index of the last log entry enqueued for the state
     /// machine to avoid sending duplicated log entries to the state machine.
     /// However the state machine might still receive repeated logs after a
-    /// crash restore.
+    /// crash restore, it skips entries it applied before.
     ///
-    /// Restored from last_applied.
+    /// Reset to last_applied when the state machine resumes.
     last_queued: u64,
 
     /// Index of highest log entry applied to the state machine (initialized to
     /// 0, increases monotonically).
     ///
-    /// Updated on stable storage after state machine has applied an entry.
+    /// A copy of the state machine's own durable position, updated when it
+    /// has applied an entry.
     last_applied: u64,
 
+    /// Whether the state machine reported its position since this worker
+    /// started. Nothing is queued before, see [`RaftState::resume_apply`].
+    apply_resumed: bool,
+
     /// Keeps track of outstanding start election timer.
     election_timer: Option<Sender<Duration>>,
 
@@ -190,6 +391,13 @@ struct RaftState {
     /// Volatile state on leaders. Outstanding client requests mapped by log index.
     /// TODO: add Effect
     pending_responses: BTreeMap<u64, RpcReplyPort<ClientResult>>,
+
+    /// Reply to a step down waiting for `pending_responses` to drain.
+    draining: Option<RpcReplyPort<ClientResult>>,
+
+    /// Volatile state on leaders. Client requests waiting to be appended
+    /// together, see `raft.max_batch_delay_ms`.
+    batch: Vec<(LogEntryValue, RpcReplyPort<ClientResult>)>,
 }
 
 impl Deref for RaftState {
@@ -209,14 +417,22 @@ impl RaftWorker {
 impl Actor for RaftWorker {
     type Msg = RaftMsg;
     type State = RaftState;
-    type Arguments = RuntimeConfig;
+    type Arguments = RaftWorkerArgs;
 
     async fn pre_start(
         &self,
         myself: ActorRef<Self::Msg>,
         args: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let config = args;
+        let RaftWorkerArgs {
+            config,
+            scope,
+            state_machine,
+        } = args;
+        // Peers know each other by the names of their workers.
+        let id = myself
+            .get_name()
+            .context("raft worker must be spawned with the server name")?;
 
         let keyspace = config.keyspace.clone();
         let log = spawn_blocking(move || {
@@ -236,7 +452,7 @@ impl Actor for RaftWorker {
         .await?
         .context("Failed to open raft_restore state")?;
 
-        let mut state = RaftState::new(myself, config, log, restore);
+        let mut state = RaftState::new(myself, id, config, scope, state_machine, log, restore);
         state
             .restore_state()
             .await
@@ -253,14 +469,22 @@ impl Actor for RaftWorker {
         info!("raft_worker started");
 
         pg::join_scoped(
-            "raft".into(),
+            state.scope.clone(),
             RaftWorker::pg_name(),
             vec![myself.get_cell()],
         );
-        pg::monitor_scope("raft".into(), myself.get_cell());
+        pg::monitor_scope(state.scope.clone(), myself.get_cell());
         info!("raft_worker joined raft process group and start monitoring changes");
 
-        if !matches!(state.role, RaftRole::Leader) {
+        // Nothing is applied until the state machine tells where it stopped.
+        // If it is not running yet, it tells us once it starts.
+        if let Some(machine) = ActorRef::<StateMachineMsg>::where_is(state.state_machine.clone()) {
+            ractor::cast!(machine, StateMachineMsg::ReportApplied)?;
+        }
+
+        if state.single_node() {
+            state.lead_alone().await?;
+        } else if !matches!(state.role, RaftRole::Leader) {
             state.set_election_timer();
         }
 
@@ -275,17 +499,33 @@ impl Actor for RaftWorker {
     ) -> Result<(), ActorProcessingErr> {
         use RaftMsg::*;
 
+        #[cfg(test)]
+        if !tests::network::deliver(&state.scope, &state.peer_id(), &message).await {
+            return Ok(());
+        }
+
         match message {
             RequestVote(request) => {
+                let Some(request) = request.valid() else {
+                    return Ok(());
+                };
                 if state.config.server.readonly_replica {
                     return Ok(());
                 }
+                // Like a server that is down, it cannot save a vote.
+                if state.config.storage.is_degraded() {
+                    debug!("ignoring RequestVote while the storage is degraded");
+                    return Ok(());
+                }
                 state
                     .handle_request_vote(request)
                     .await
                     .context("Failed to handle RequestVote")?;
             }
             RequestVoteResponse(reply) => {
+                let Some(reply) = reply.valid() else {
+                    return Ok(());
+                };
                 if state.config.server.readonly_replica {
                     return Ok(());
                 }
@@ -295,6 +535,15 @@ impl Actor for RaftWorker {
                     .context("Failed to handle RequestVoteResponse")?;
             }
             AppendEntries(request, reply) => {
+                // Dropping the reply port fails the leader's call, it
+                // retries with the next heartbeat.
+                let Some(request) = request.valid() else {
+                    return Ok(());
+                };
+                if state.config.storage.is_degraded() {
+                    debug!("ignoring AppendEntries while the storage is degraded");
+                    return Ok(());
+                }
                 state
                     .handle_append_entries(request, reply)
                     .await
@@ -310,6 +559,9 @@ impl Actor for RaftWorker {
                     .context("Failed to start a new election")?;
             }
             AdvanceCommitIndex(peer_info) => {
+                let Some(peer_info) = peer_info.valid() else {
+                    return Ok(());
+                };
                 if state.config.server.readonly_replica {
                     return Ok(());
                 }
@@ -322,6 +574,11 @@ impl Actor for RaftWorker {
                 state.update_term(new_term).await?;
             }
             ClientRequest(request, reply) => {
+                let Some(request) = request.valid() else {
+                    let error = ClientError::Internal("malformed request".to_string());
+                    let _ = reply.send(error.into());
+                    return Ok(());
+                };
                 state
                     .handle_client_request(request, reply)
                     .await
@@ -333,6 +590,62 @@ impl Actor for RaftWorker {
                     .await
                     .context("Failed to handle AppliedLog")?;
             }
+            ResumeApply(last_applied) => {
+                state
+                    .resume_apply(last_applied)
+                    .await
+                    .context("Failed to handle ResumeApply")?;
+            }
+            StepDown(reply) => {
+                state.step_down(reply);
+            }
+            CheckLeader(reply) => {
+                let result = if matches!(state.role, RaftRole::Leader) {
+                    ClientResult::ok()
+                } else {
+                    let leader = state.leader_id.clone();
+                    ClientError::NotLeader { leader }.into()
+                };
+                let _ = reply.send(result);
+            }
+            GetStatus(reply) => {
+                let status = RaftStatus {
+                    term: state.current_term,
+                    commit_index: state.commit_index,
+                    last_applied: state.last_applied,
+                    leader_id: match state.role {
+                        RaftRole::Leader => Some(state.config.server.name.clone()),
+                        _ => state.leader_id.clone(),
+                    },
+                };
+                let _ = reply.send(status.into());
+            }
+            FlushBatch(term) => {
+                if term == state.current_term {
+                    state.flush_batch().await?;
+                }
+            }
+            DrainTimeout(term) => {
+                if term == state.current_term {
+                    if let Some(reply) = state.draining.take() {
+                        info!(
+                            pending = state.pending_responses.len(),
+                            "drain timed out, failing client requests"
+                        );
+                        state.hand_over(reply);
+                    }
+                }
+            }
+            TimeoutNow(term, leader) => {
+                if state.config.server.readonly_replica || term < state.current_term {
+                    return Ok(());
+                }
+                info!(leader, "leader handed over, running for election");
+                state
+                    .start_new_election()
+                    .await
+                    .context("Failed to start a new election")?;
+            }
         }
 
         Ok(())
@@ -350,7 +663,7 @@ impl Actor for RaftWorker {
                 return Err(error);
             }
             SupervisionEvent::ProcessGroupChanged(change) => {
-                if change.get_scope() != "raft" {
+                if change.get_scope() != state.scope {
                     return Ok(());
                 }
                 if !matches!(state.role, RaftRole::Leader) {
@@ -359,12 +672,13 @@ impl Actor for RaftWorker {
                 match change {
                     pg::GroupChangeMessage::Join(_, _, members) => {
                         for server in members {
-                            let server_name =
-                                server.get_name().expect("raft server should have name");
+                            let Some(server_name) = member_name(&server) else {
+                                continue;
+                            };
                             if !state.replicate_workers.contains_key(&server_name) {
                                 info!(peer = server_name, "peer joined, resume replication");
                                 state
-                                    .spawn_one_replicate_worker(server.into())
+                                    .spawn_one_replicate_worker(server_name, server.into())
                                     .await
                                     .context("Failed to spawn replication worker")?;
                             }
@@ -372,8 +686,9 @@ impl Actor for RaftWorker {
                     }
                     pg::GroupChangeMessage::Leave(_, _, members) => {
                         for server in members {
-                            let server_name =
-                                server.get_name().expect("raft server should have name");
+                            let Some(server_name) = member_name(&server) else {
+                                continue;
+                            };
                             if let Some(worker) = state.replicate_workers.remove(&server_name) {
                                 info!(peer = server_name, "peer left, stop replication");
                                 worker.stop(Some("remote server disconnected".into()));
@@ -388,6 +703,22 @@ impl Actor for RaftWorker {
     }
 }
 
+/// Name of a member of the raft process group. Every raft worker has one,
+/// a member without is skipped.
+fn member_name(member: &ActorCell) -> Option<PeerId> {
+    let name = member.get_name();
+    if name.is_none() {
+        warn!(actor = ?member.get_id(), "raft group member has no name, skipped");
+    }
+    name
+}
+
+/// Send `ElectionTimeout` to `myself` once `timeout` elapsed without a new
+/// timeout coming through the returned sender.
+///
+/// Raft only measures time with the tokio clock, tests pause it with
+/// `#[tokio::test(start_paused = true)]` and elections happen exactly when
+/// the configured timeouts elapse.
 fn election_timer(myself: ActorRef<RaftMsg>, timeout: Duration) -> Sender<Duration> {
     let (tx, mut rx) = channel(1);
     let mut sleep = Box::pin(sleep(timeout));
@@ -417,13 +748,19 @@ fn election_timer(myself: ActorRef<RaftMsg>, timeout: Duration) -> Sender<Durati
 impl RaftState {
     fn new(
         myself: ActorRef<RaftMsg>,
+        id: PeerId,
         config: RuntimeConfig,
+        scope: String,
+        state_machine: String,
         log: PartitionHandle,
         restore: PartitionHandle,
     ) -> RaftState {
         Self {
             myself,
+            id,
             config,
+            scope,
+            state_machine,
             restore,
             current_term: 1,
             role: RaftRole::Follower,
@@ -437,15 +774,17 @@ impl RaftState {
             last_log_index: 0,
             last_queued: 0,
             last_applied: 0,
+            apply_resumed: false,
             election_timer: None,
             replicate_workers: BTreeMap::new(),
             pending_responses: BTreeMap::new(),
+            draining: None,
+            batch: vec![],
         }
     }
 
     fn peer_id(&self) -> PeerId {
-        self.get_name()
-            .expect("raft_worker should have name=server_name")
+        self.id.clone()
     }
 
     async fn restore_state(&mut self) -> Result<()> {
@@ -463,11 +802,12 @@ impl RaftState {
             last_applied,
         } = saved;
 
-        info!(voted_for, current_term, last_applied, "restored from state");
+        info!(voted_for, current_term, "restored from state");
 
         self.current_term = current_term;
         self.voted_for = voted_for;
-        self.last_queued = last_applied;
+        // Kept for older binaries, the state machine reports the actual
+        // position before anything is applied.
         self.last_applied = last_applied;
 
         if let Some(last_log) = self.log.get_last_log_entry().await? {
@@ -476,59 +816,83 @@ impl RaftState {
             self.last_log_term = last_log.term;
         }
 
-        if self.last_applied > self.last_log_index {
-            error!(
-                last_applied = self.last_applied,
-                last_log_index = self.last_log_index,
-                "detected inconsistent state, last_applied is greater than last_log_index"
-            );
-        }
-
         Ok(())
     }
 
     async fn persist_state(&mut self) -> Result<()> {
+        let result = self.write_state().await;
+        if result.is_err() {
+            counter!("pinka_raft_persist_failures_total").increment(1);
+        }
+        self.report_write(result)
+    }
+
+    /// Tell the storage health how a write to the keyspace went, see
+    /// [`crate::storage_health::StorageHealth`].
+    fn report_write<T>(&self, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.config.storage.succeeded(),
+            Err(error) => self.config.storage.failed(error),
+        }
+        result
+    }
+
+    /// Whether the storage takes writes again, trying one while it is
+    /// degraded.
+    async fn probe_storage(&self) -> bool {
+        #[cfg(test)]
+        if tests::disk::fails(&self.scope, &self.peer_id()) {
+            self.config
+                .storage
+                .failed(&anyhow::anyhow!("injected disk failure"));
+            return false;
+        }
+        let keyspace = self.config.keyspace.clone();
+        let storage = self.config.storage.clone();
+        spawn_blocking(move || storage.probe(&keyspace))
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn write_state(&mut self) -> Result<()> {
+        #[cfg(test)]
+        if tests::disk::fails(&self.scope, &self.peer_id()) {
+            anyhow::bail!("injected disk failure");
+        }
         let saved = RaftSaved {
             current_term: self.current_term,
             voted_for: self.voted_for.clone(),
             last_applied: self.last_applied,
         };
-        let mut batch = self
-            .config
-            .keyspace
-            .batch()
-            .durability(Some(PersistMode::SyncAll));
+        let keyspace = self.config.keyspace.clone();
         let restore = self.restore.clone();
-        spawn_blocking(move || {
-            saved.to_bytes().and_then(|value| {
-                batch.insert(&restore, "raft_saved", value);
-                batch.commit()?;
-                Ok(())
-            })
-        })
-        .await?
-        .context("Failed to persist raft state")
+        spawn_blocking(move || store_saved(&keyspace, &restore, &saved))
+            .await?
+            .context("Failed to persist raft state")
     }
 
     async fn spawn_replicate_workers(&mut self) -> Result<()> {
         assert!(self.replicate_workers.is_empty());
 
-        for server in pg::get_scoped_members(&"raft".into(), &RaftWorker::pg_name()) {
-            if server.get_name() == self.get_name() {
+        for server in pg::get_scoped_members(&self.scope, &RaftWorker::pg_name()) {
+            let Some(server_name) = member_name(&server) else {
                 continue;
-            }
-            self.spawn_one_replicate_worker(server.into())
+            };
+            self.spawn_one_replicate_worker(server_name, server.into())
                 .await
                 .context("Failed to spawn replication worker")?;
         }
         Ok(())
     }
 
-    async fn spawn_one_replicate_worker(&mut self, server: ActorRef<RaftMsg>) -> Result<()> {
-        if server.get_name() == self.get_name() {
+    async fn spawn_one_replicate_worker(
+        &mut self,
+        server_name: PeerId,
+        server: ActorRef<RaftMsg>,
+    ) -> Result<()> {
+        if server_name == self.id {
             return Ok(());
         }
-        let server_name = server.get_name().unwrap();
         let observer = self
             .server_config_for(&server_name)
             .with_context(|| format!("Server {server_name} is not defined in config"))?
@@ -543,6 +907,7 @@ impl RaftState {
             },
             name: self.peer_id(),
             parent: self.myself.clone(),
+            peer_id: server_name.clone(),
             peer: server,
             log: self.log.clone(),
             last_log_index: self.last_log_index,
@@ -558,19 +923,45 @@ impl RaftState {
         if self.match_index.is_empty() {
             return 0;
         }
-        let mut values = self.match_index.values().collect::<Vec<_>>();
+        // The leader has its whole log.
+        let me = self.peer_id();
+        let mut values = self
+            .match_index
+            .iter()
+            .map(|(peer, &index)| {
+                if *peer == me {
+                    self.last_log_index
+                } else {
+                    index
+                }
+            })
+            .collect::<Vec<_>>();
         assert_eq!(server_count, values.len());
-        values.sort_unstable();
-        // Leader is always in position 0 with value 0 so we can use 1-index
-        // Quorum pos = majority
-        //            = (N / 2 + 1)
-        // For example in 5 server cluster we need to look at index 3
-        //         3 = 5 / 2 + 1
-        // For example in 4 server cluster we need to look at index 3
-        //         3 = 4 / 2 + 1
-        // For example in 3 server cluster we need to look at index 2
-        //         2 = 3 / 2 + 1
-        *values[server_count / 2 + 1]
+        values.sort_unstable_by(|a, b| b.cmp(a));
+        // The highest index a majority has, i.e. the N / 2 + 1 largest. For
+        // example in a 5 server cluster we look at index 2, in a 4 server
+        // cluster also at index 2, and in a 2 server cluster at index 1.
+        values[server_count / 2]
+    }
+
+    /// Whether this is the only server of the cluster. It leads from the
+    /// start and commits what it logged, there is nobody to vote for it or
+    /// to replicate to.
+    fn single_node(&self) -> bool {
+        let servers = &self.config.init.cluster.servers;
+        servers.len() == 1 && !self.config.server.readonly_replica
+    }
+
+    /// Take the lead of a single server cluster without waiting out an
+    /// election timeout.
+    async fn lead_alone(&mut self) -> Result<()> {
+        self.current_term += 1;
+        self.role = RaftRole::Candidate;
+        info!(
+            term = self.current_term,
+            "leading the single server cluster"
+        );
+        self.become_leader().await
     }
 
     fn voted_has_quorum(&self) -> bool {
@@ -609,6 +1000,20 @@ impl RaftState {
         }
     }
 
+    /// Start the election timer unless one is already running, e.g. when
+    /// stepping down. Seeing a higher term alone must not delay our own
+    /// candidacy, or a candidate that cannot win keeps everyone else from
+    /// running.
+    fn keep_election_timer(&mut self) {
+        if self
+            .election_timer
+            .as_ref()
+            .is_none_or(|timer| timer.is_closed())
+        {
+            self.set_election_timer();
+        }
+    }
+
     fn unset_election_timer(&mut self) {
         debug!("unset election timer");
         self.election_timer = None;
@@ -616,7 +1021,18 @@ impl RaftState {
 
     async fn start_new_election(&mut self) -> Result<()> {
         if matches!(self.role, RaftRole::Leader) {
-            warn!("starting a election as a leader");
+            // A timeout from before we won, the timer is gone by now.
+            debug!("ignore election timeout as a leader");
+            return Ok(());
+        }
+        // A term or vote that cannot be saved must not be used. Stay out
+        // until a probe gets through, instead of failing every election.
+        if self.config.storage.is_degraded() && !self.probe_storage().await {
+            self.set_election_timer();
+            return Ok(());
+        }
+        if self.single_node() {
+            return self.lead_alone().await;
         }
         let new_term = self.current_term + 1;
         if let Some(prev_leader_id) = &self.leader_id {
@@ -638,6 +1054,8 @@ impl RaftState {
         self.votes_received.clear();
         self.leader_id = None;
         self.persist_state().await?;
+        // A fresh random timeout each term, so candidates that split the
+        // votes do not keep running at the same time.
         self.set_election_timer();
 
         self.request_vote();
@@ -649,7 +1067,7 @@ impl RaftState {
         assert!(matches!(self.role, RaftRole::Candidate));
 
         info!(term = self.current_term, "requesting votes");
-        for peer in pg::get_scoped_members(&"raft".into(), &RaftWorker::pg_name()) {
+        for peer in pg::get_scoped_members(&self.scope, &RaftWorker::pg_name()) {
             let peer: ActorRef<RaftMsg> = peer.into();
             let Some(peer_name) = peer.get_name() else {
                 error!(remote_actor = ?peer.get_id(), "peer has no name, skipped");
@@ -672,7 +1090,7 @@ impl RaftState {
 
             info!(to = peer_name, term = request.term, "request_vote");
 
-            if let Err(error) = ractor::cast!(peer, RaftMsg::RequestVote(request)) {
+            if let Err(error) = ractor::cast!(peer, RaftMsg::RequestVote(request.into())) {
                 warn!(%error, "request_vote failed");
             }
         }
@@ -727,18 +1145,32 @@ impl RaftState {
             "received request for vote"
         );
         // TODO ignore distrubing request_vote
-        self.update_term(request.term).await?;
+        let new_term = self.adopt_term(request.term);
 
         let log_ok = request.last_log_term > self.last_log_term
             || (request.last_log_term == self.last_log_term
                 && request.last_log_index >= self.last_log_index);
         let grant = request.term == self.current_term && log_ok && self.voted_for.is_none();
+        if grant {
+            self.voted_for = Some(request.candidate_name.clone());
+        }
 
+        // The new term and the vote go to disk in a single write before the
+        // candidate hears about either. A vote forgotten in a crash could be
+        // cast again for another candidate of the same term. If the write
+        // fails the worker stops without replying.
+        if new_term || grant {
+            self.persist_state()
+                .await
+                .context("Failed to persist vote")?;
+        }
         if grant {
             info!(candidate = request.candidate_name, "voted for candidate");
-            self.voted_for = Some(request.candidate_name.clone());
-            self.persist_state().await?;
+            self.set_election_timer();
         } else {
+            if new_term {
+                self.keep_election_timer();
+            }
             info!(
                 candidate = request.candidate_name,
                 term_ok = (request.term == self.current_term),
@@ -748,7 +1180,7 @@ impl RaftState {
             );
         }
 
-        let server = pg::get_scoped_members(&"raft".into(), &RaftWorker::pg_name())
+        let server = pg::get_scoped_members(&self.scope, &RaftWorker::pg_name())
             .into_iter()
             .find(|server| server.get_name().as_ref() == Some(&request.candidate_name));
         if let Some(server) = server {
@@ -758,7 +1190,8 @@ impl RaftState {
                 vote_from: self.peer_id(),
             };
             let server: ActorRef<RaftMsg> = server.into();
-            if let Err(error) = ractor::cast!(server, RaftMsg::RequestVoteResponse(response)) {
+            if let Err(error) = ractor::cast!(server, RaftMsg::RequestVoteResponse(response.into()))
+            {
                 warn!(
                     candidate = request.candidate_name,
                     %error,
@@ -775,7 +1208,7 @@ impl RaftState {
     async fn handle_append_entries(
         &mut self,
         request: AppendEntriesAsk,
-        reply: RpcReplyPort<AppendEntriesReply>,
+        reply: RpcReplyPort<Wire<AppendEntriesReply>>,
     ) -> Result<()> {
         trace!(?request, "received append_entries");
         self.update_term(request.term).await?;
@@ -786,9 +1219,36 @@ impl RaftState {
 
         assert!(request.term <= self.current_term);
 
+        if request.term == self.current_term && matches!(self.role, RaftRole::Candidate) {
+            info!(
+                leader = request.leader_id,
+                term = self.current_term,
+                "another candidate won the election, stepping down"
+            );
+            self.role = RaftRole::Follower;
+            self.set_election_timer();
+        }
+
+        // Even when the logs do not match yet, the request comes from the
+        // leader of this term. A follower far behind must not run for
+        // election while the leader backs up to the end of its log.
+        if request.term == self.current_term {
+            self.recognize_new_leader(&request.leader_id);
+            self.set_election_timer();
+        }
+
+        // Taking entries past the end of the log would leave a hole in it.
+        // Rejecting them makes the leader back up and send what is missing.
+        let beyond_log = request.prev_log_index > self.last_log_index;
+        if beyond_log {
+            debug!(
+                prev_log_index = request.prev_log_index,
+                last_log_index = self.last_log_index,
+                "append_entries starts beyond the end of the log"
+            );
+        }
         let log_ok = request.prev_log_index == 0
-            || (request.prev_log_index > 0
-                && request.prev_log_index <= self.last_log_index
+            || (!beyond_log
                 && request.prev_log_term
                     == self.log.get_log_entry(request.prev_log_index).await?.term);
 
@@ -810,68 +1270,70 @@ impl RaftState {
                 self.current_term
             );
             // reject request
-            if let Err(error) = reply.send(response) {
+            if let Err(error) = reply.send(response.into()) {
                 warn!(%error, "send response to append_entries failed");
             }
             return Ok(());
         }
 
-        self.recognize_new_leader(&request.leader_id);
-
-        let index = request.prev_log_index + 1;
-        if request.entries.is_empty()
-            || (self.last_log_index >= index
-                && self.log.get_log_entry(index).await?.term == request.entries[0].term)
-        {
-            // already done with request
-            self.commit_index = request.commit_index;
-            response.success = true;
-
-            trace!(?response, "done with request");
-            if let Err(error) = reply.send(response) {
-                warn!(%error, "send response to append_entries failed");
+        // The log may already have some of the entries, from an earlier
+        // append_entries that was retried.
+        let last_new_index = request.prev_log_index + request.entries.len() as u64;
+        let mut entries = request.entries;
+        let mut known = 0;
+        for entry in &entries {
+            if entry.index > self.last_log_index {
+                break;
             }
-            self.apply_log_entries().await?;
-            self.set_election_timer();
-            return Ok(());
-        }
-        if !request.entries.is_empty()
-            && self.last_log_index >= index
-            && self.log.get_log_entry(index).await?.term != request.entries[0].term
-        {
-            // conflict: remove 1 entry
-            let batch = self
-                .config
-                .keyspace
-                .batch()
-                .durability(Some(PersistMode::SyncAll));
-            self.log
-                .remove_last_log_entry(batch, self.last_log_index)
-                .await?;
-
-            trace!(?response, "conflict, remove 1 entry from our log");
-            if let Err(error) = reply.send(response) {
-                warn!(%error, "send response to append_entries failed");
+            if self.log.get_log_entry(entry.index).await?.term != entry.term {
+                // conflict: remove 1 entry
+                let batch = self
+                    .config
+                    .keyspace
+                    .batch()
+                    .durability(Some(PersistMode::SyncAll));
+                let result = self
+                    .log
+                    .remove_last_log_entry(batch, self.last_log_index)
+                    .await;
+                self.report_write(result)?;
+                self.last_log_index -= 1;
+                self.last_log_term = match self.last_log_index {
+                    0 => 0,
+                    index => self.log.get_log_entry(index).await?.term,
+                };
+
+                trace!(?response, "conflict, remove 1 entry from our log");
+                if let Err(error) = reply.send(response.into()) {
+                    warn!(%error, "send response to append_entries failed");
+                }
+                self.set_election_timer();
+                return Ok(());
             }
-            self.set_election_timer();
-            return Ok(());
+            known += 1;
         }
-        if !request.entries.is_empty() && self.last_log_index == request.prev_log_index {
+        let entries = entries.split_off(known);
+        if !entries.is_empty() {
             // Is there a better way to handle timeout? Just use Instant and a
             // regular interval to check?
             self.unset_election_timer();
-            self.replicate_log_entries(request.entries).await?;
-            response.success = true;
-
-            trace!(?response, "replicated some log entries");
-            if let Err(error) = reply.send(response) {
-                warn!(%error, "send response to append_entries failed");
-            }
-            self.apply_log_entries().await?;
-            self.set_election_timer();
-            return Ok(());
+            self.replicate_log_entries(entries).await?;
+            trace!("replicated some log entries");
         }
+        // Entries past the ones sent may be left over from an older term, they
+        // are not committed until the leader sent its own.
+        let commit_index = request.commit_index.min(last_new_index);
+        if commit_index > self.commit_index {
+            self.commit_index = commit_index;
+        }
+        response.success = true;
 
+        trace!(?response, "done with request");
+        if let Err(error) = reply.send(response.into()) {
+            warn!(%error, "send response to append_entries failed");
+        }
+        self.apply_log_entries().await?;
+        self.set_election_timer();
         Ok(())
     }
 
@@ -892,13 +1354,13 @@ impl RaftState {
                     "got one vote",
                 );
                 if !matches!(self.role, RaftRole::Candidate) {
-                    if matches!(self.role, RaftRole::Follower) {
-                        warn!(
-                            peer = response.vote_from,
-                            current_role = ?self.role,
-                            "received vote but not a candidate"
-                        );
-                    }
+                    // Late votes for a candidacy that is over, we either
+                    // won already or follow the winner.
+                    debug!(
+                        peer = response.vote_from,
+                        current_role = ?self.role,
+                        "ignore vote, not a candidate"
+                    );
                     return Ok(());
                 }
                 self.votes_received.insert(response.vote_from);
@@ -930,7 +1392,9 @@ impl RaftState {
         self.unset_election_timer();
         self.reset_match_index();
         self.append_log(LogEntryValue::NewTermStarted).await?;
-        self.spawn_replicate_workers().await?;
+        if !self.single_node() {
+            self.spawn_replicate_workers().await?;
+        }
         Ok(())
     }
 
@@ -946,28 +1410,114 @@ impl RaftState {
     }
 
     async fn update_term(&mut self, new_term: u32) -> Result<()> {
-        if new_term <= self.current_term {
+        if !self.adopt_term(new_term) {
             return Ok(());
         }
+        self.persist_state()
+            .await
+            .context("Failed to update current term")?;
+        self.keep_election_timer();
+        Ok(())
+    }
 
-        let was_leader = matches!(self.role, RaftRole::Leader);
-
+    /// Move to a newer term in memory only, the caller persists it. Returns
+    /// whether the term changed.
+    fn adopt_term(&mut self, new_term: u32) -> bool {
+        if new_term <= self.current_term {
+            return false;
+        }
+        if matches!(self.role, RaftRole::Leader) {
+            info!("stepping down");
+        }
         self.current_term = new_term;
         self.voted_for = None;
         self.role = RaftRole::Follower;
+        self.stop_replication();
+        true
+    }
+
+    /// Stop replicating and fail the outstanding client requests, after
+    /// losing leadership.
+    fn stop_replication(&mut self) {
         self.stop_children(None);
         self.replicate_workers.clear();
-        self.pending_responses.clear();
-        self.persist_state()
-            .await
-            .context("Failed to update current term")?;
-        self.set_election_timer();
+        for (_, reply) in std::mem::take(&mut self.pending_responses) {
+            let _ = reply.send(ClientError::NotLeader { leader: None }.into());
+        }
+        self.fail_batch();
+        if let Some(reply) = self.draining.take() {
+            let _ = reply.send(ClientError::NotLeader { leader: None }.into());
+        }
+    }
 
-        if was_leader || self.election_timer.is_none() {
-            info!("stepping down");
+    /// Step down as leader and ask the most up to date voting peer to run
+    /// for election right away, so leadership moves without waiting for an
+    /// election timeout.
+    ///
+    /// Replies with the name of that peer, empty if no peer is connected,
+    /// or `NotLeader` when not leading. The peer may still lose, e.g. when
+    /// it lacks entries, an ordinary election follows then.
+    ///
+    /// Client requests in flight are failed with `NotLeader` so clients
+    /// retry with the next leader right away. With `raft.step_down_drain_ms`
+    /// they get that long to commit first, new ones are refused meanwhile.
+    fn step_down(&mut self, reply: RpcReplyPort<ClientResult>) {
+        if !matches!(self.role, RaftRole::Leader) || self.draining.is_some() {
+            let error = ClientError::NotLeader {
+                leader: self.leader_id.clone(),
+            };
+            let _ = reply.send(error.into());
+            return;
+        }
+        // Batched requests are not in the log yet, they are refused like new
+        // ones.
+        self.fail_batch();
+        let drain = Duration::from_millis(self.config.init.raft.step_down_drain_ms);
+        if !drain.is_zero() && !self.pending_responses.is_empty() {
+            info!(
+                pending = self.pending_responses.len(),
+                "draining client requests before stepping down"
+            );
+            self.draining = Some(reply);
+            let term = self.current_term;
+            self.myself
+                .send_after(drain, move || RaftMsg::DrainTimeout(term));
+            return;
         }
+        self.hand_over(reply);
+    }
 
-        Ok(())
+    /// Step down for [`RaftState::step_down`] once the drain, if any, is over.
+    fn hand_over(&mut self, reply: RpcReplyPort<ClientResult>) {
+        let me = self.peer_id();
+        let successor = self
+            .match_index
+            .iter()
+            .filter(|(peer, _)| **peer != me && self.replicate_workers.contains_key(*peer))
+            .max_by_key(|(_, &index)| index)
+            .map(|(peer, _)| peer.clone());
+        info!(
+            successor,
+            term = self.current_term,
+            "stepping down on request"
+        );
+        self.role = RaftRole::Follower;
+        self.stop_replication();
+        self.set_election_timer();
+        let mut handed_to = vec![];
+        if let Some(successor) = successor {
+            let peer = pg::get_scoped_members(&self.scope, &RaftWorker::pg_name())
+                .into_iter()
+                .find(|server| server.get_name().as_ref() == Some(&successor));
+            if let Some(peer) = peer {
+                let peer: ActorRef<RaftMsg> = peer.into();
+                match ractor::cast!(peer, RaftMsg::TimeoutNow(self.current_term, me)) {
+                    Ok(()) => handed_to = successor.into_bytes(),
+                    Err(error) => warn!(peer = successor, %error, "handing over failed"),
+                }
+            }
+        }
+        let _ = reply.send(ClientResult::from(handed_to));
     }
 
     async fn handle_client_request(
@@ -975,36 +1525,105 @@ impl RaftState {
         request: LogEntryValue,
         reply: RpcReplyPort<ClientResult>,
     ) -> Result<()> {
+        // Every server checks, so oversized entries are not even forwarded.
+        let size = minicbor::to_vec(&request)
+            .context("Unable to serialize client request")?
+            .len();
+        let max = self.config.init.raft.max_entry_bytes;
+        if size > max {
+            warn!(size, max, "rejecting oversized client request");
+            let error = ClientError::TooLarge {
+                size: size as u64,
+                max: max as u64,
+            };
+            if let Err(error) = reply.send(error.into()) {
+                info!(%error, "failed to reply client request");
+            }
+            return Ok(());
+        }
+        if matches!(self.role, RaftRole::Leader) && self.draining.is_some() {
+            info!("received a client request while stepping down");
+            let error = ClientError::NotLeader { leader: None };
+            if let Err(error) = reply.send(error.into()) {
+                info!(%error, "failed to reply client request");
+            }
+            return Ok(());
+        }
+        let raft = &self.config.init.raft;
+        if matches!(self.role, RaftRole::Leader) && raft.max_batch_delay_ms > 0 {
+            debug!("received a new client request, batching");
+            let delay = Duration::from_millis(raft.max_batch_delay_ms);
+            let full = self.batch.len() + 1 >= raft.max_batch_entries;
+            self.batch.push((request, reply));
+            if full {
+                return self.flush_batch().await;
+            }
+            // A timer set for a batch that filled up early flushes the next
+            // one early, which is harmless.
+            if self.batch.len() == 1 {
+                let term = self.current_term;
+                self.myself
+                    .send_after(delay, move || RaftMsg::FlushBatch(term));
+            }
+            return Ok(());
+        }
         if matches!(self.role, RaftRole::Leader) {
             info!("received a new client request");
-            let log_index = self.append_log(request).await?;
+            let log_index = match self.append_log(request).await {
+                Ok(log_index) => log_index,
+                Err(error) => {
+                    let _ = reply.send(ClientError::Internal(format!("{error:#}")).into());
+                    return Err(error);
+                }
+            };
             self.pending_responses.insert(log_index, reply);
             return Ok(());
         }
-        // Forward to leader
-        info!("received a new client request, forwarding to leader");
-        if let Some(leader) = self.get_leader() {
-            // DEADLOCK HAZARD: Leader needs our vote to confirm quorum so we
-            // should not block our actor thread.
-            tokio::spawn(async move {
-                // TODO: add timeout?
-                reply
-                    .send(
-                        ractor::call!(leader, RaftMsg::ClientRequest, request)
-                            .expect("client_request forwarding failed"),
-                    )
-                    .expect("unable to reply to client");
-            });
+        let Some(leader) = self.get_leader() else {
+            info!(
+                leader = self.leader_id,
+                "received a client request, no leader to forward to"
+            );
+            let error = ClientError::NotLeader {
+                leader: self.leader_id.clone(),
+            };
+            if let Err(error) = reply.send(error.into()) {
+                info!(%error, "failed to reply client request");
+            }
             return Ok(());
-        }
+        };
+        info!("received a new client request, forwarding to leader");
+        let timeout = Duration::from_millis(self.config.init.raft.client_timeout_ms);
+        // DEADLOCK HAZARD: Leader needs our vote to confirm quorum so we
+        // should not block our actor thread.
+        tokio::spawn(async move {
+            let result = match leader
+                .call(
+                    |reply| RaftMsg::ClientRequest(request.into(), reply),
+                    Some(timeout),
+                )
+                .await
+            {
+                Ok(CallResult::Success(result)) => result,
+                Ok(CallResult::Timeout) => ClientError::Timeout.into(),
+                Ok(CallResult::SenderError) => {
+                    ClientError::Internal("the leader dropped the request".to_string()).into()
+                }
+                Err(error) => ClientError::Internal(error.to_string()).into(),
+            };
+            if let Err(error) = reply.send(result) {
+                info!(%error, "failed to reply client request");
+            }
+        });
         Ok(())
     }
 
     async fn handle_applied_log(&mut self, last_applied: u64, result: ClientResult) -> Result<()> {
         debug_assert!(self.last_applied <= last_applied);
 
+        // The state machine saved its position with its writes before
+        // replying, there is nothing to persist here.
         self.last_applied = last_applied;
-        self.persist_state().await?;
 
         // Avoid flooded apply message caused election timeout
         if !matches!(self.role, RaftRole::Leader) {
@@ -1019,28 +1638,73 @@ impl RaftState {
 
         if let Some(reply) = self.pending_responses.remove(&self.last_applied) {
             debug!("index {last_applied} applied, reply to client");
-            if let Err(error) = reply.send(result) {
+            if let Err(error) = reply.send(result.at_index(last_applied)) {
                 info!(%error, "failed to reply client request");
             }
         }
-        Ok(())
+        if self.pending_responses.is_empty() {
+            if let Some(reply) = self.draining.take() {
+                info!("client requests drained");
+                self.hand_over(reply);
+            }
+        }
+        self.apply_log_entries().await
+    }
+
+    /// The state machine (re)started or answered `ReportApplied`, queue
+    /// entries after its position from now on.
+    ///
+    /// Either actor may restart on its own. Whichever starts second brings
+    /// the two in sync: the state machine sends its position when it starts
+    /// and the raft worker asks for it when it starts.
+    async fn resume_apply(&mut self, last_applied: u64) -> Result<()> {
+        info!(
+            last_applied,
+            last_queued = self.last_queued,
+            "state machine resumes applying"
+        );
+        if last_applied > self.last_log_index {
+            error!(
+                last_applied,
+                last_log_index = self.last_log_index,
+                "detected inconsistent state, last_applied is greater than last_log_index"
+            );
+        }
+        // Entries queued to a state machine that crashed are lost, queue
+        // them again.
+        self.last_applied = last_applied;
+        self.last_queued = last_applied;
+        self.apply_resumed = true;
+        self.apply_log_entries().await
     }
 
     async fn apply_log_entries(&mut self) -> Result<()> {
+        if !self.apply_resumed {
+            trace!("state machine has not reported its position yet");
+            return Ok(());
+        }
         debug_assert!(self.last_queued >= self.last_applied);
 
-        // TODO configurable machine name
         async {
-            if let Some(machine) = ActorRef::where_is("state_machine".into()) {
-                // TODO avoid message pile up
-                for log_entry in self
-                    .log
-                    .log_entry_range(self.last_queued + 1..=self.commit_index)
-                    .await?
-                {
-                    ractor::cast!(machine, StateMachineMsg::Apply(log_entry))?;
+            if let Some(machine) = ActorRef::where_is(self.state_machine.clone()) {
+                // Queue no more than the limit, the rest follow as entries
+                // are applied.
+                let limit = self.last_applied + self.config.init.raft.max_apply_queue;
+                let last = u64::min(self.commit_index, limit);
+                if last > self.last_queued {
+                    for log_entry in self
+                        .log
+                        .log_entry_range(self.last_queued + 1..=last)
+                        .await?
+                    {
+                        ractor::cast!(machine, StateMachineMsg::Apply(log_entry))?;
+                    }
+                    self.last_queued = last;
                 }
-                self.last_queued = u64::max(self.last_queued, self.commit_index);
+                gauge!("pinka_raft_apply_queue_depth")
+                    .set(self.last_queued.saturating_sub(self.last_applied) as f64);
+                gauge!("pinka_raft_apply_backlog")
+                    .set(self.commit_index.saturating_sub(self.last_applied) as f64);
             } else {
                 warn!("unable to apply log entries because state_machine is not running");
             }
@@ -1069,7 +1733,7 @@ impl RaftState {
             return Some(self.myself.clone());
         }
         if let Some(leader_id) = &self.leader_id {
-            for server in pg::get_scoped_members(&"raft".into(), &RaftWorker::pg_name()) {
+            for server in pg::get_scoped_members(&self.scope, &RaftWorker::pg_name()) {
                 if server.get_name().as_ref() == Some(leader_id) {
                     return Some(server.into());
                 }
@@ -1078,31 +1742,69 @@ impl RaftState {
         None
     }
 
+    /// Append the batched client requests with a single write, a group
+    /// commit. Each is answered once its entry is applied, like a request
+    /// appended on its own.
+    async fn flush_batch(&mut self) -> Result<()> {
+        if self.batch.is_empty() || !matches!(self.role, RaftRole::Leader) {
+            return Ok(());
+        }
+        let (values, replies): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.batch).into_iter().unzip();
+        info!(entries = values.len(), "appending batched client requests");
+        let first_index = self.last_log_index + 1;
+        if let Err(error) = self.append_logs(values).await {
+            for reply in replies {
+                let _ = reply.send(ClientError::Internal(format!("{error:#}")).into());
+            }
+            return Err(error);
+        }
+        self.pending_responses.extend((first_index..).zip(replies));
+        Ok(())
+    }
+
+    /// Refuse the batched client requests, they never reached the log.
+    fn fail_batch(&mut self) {
+        for (_, reply) in std::mem::take(&mut self.batch) {
+            let _ = reply.send(ClientError::NotLeader { leader: None }.into());
+        }
+    }
+
     async fn append_log(&mut self, value: LogEntryValue) -> Result<u64> {
-        let index = self.last_log_index + 1;
-        let new_log_entry = LogEntry {
-            index,
-            term: self.current_term,
-            value,
+        self.append_logs(vec![value]).await
+    }
+
+    /// Append `values` to the log with one write, returns the index of the
+    /// last one.
+    async fn append_logs(&mut self, values: Vec<LogEntryValue>) -> Result<u64> {
+        let first_index = self.last_log_index + 1;
+        let entries: Vec<LogEntry> = (first_index..)
+            .zip(values)
+            .map(|(index, value)| LogEntry {
+                index,
+                term: self.current_term,
+                value,
+            })
+            .collect();
+        let Some(index) = entries.last().map(|entry| entry.index) else {
+            return Ok(self.last_log_index);
         };
         let batch = self
             .config
             .keyspace
             .batch()
             .durability(Some(PersistMode::SyncAll));
-        self.log.insert(batch, new_log_entry).await?;
+        let result = self.log.insert_all(batch, entries).await;
+        self.report_write(result)?;
         self.last_log_index = index;
         self.last_log_term = self.current_term;
 
-        // special case single server mode
-        if self.config.init.cluster.servers.len() == 1 {
+        // The write is durable and there is no quorum to wait for.
+        if self.single_node() {
             debug!("commit immediately for single server cluster");
-            self.advance_commit_index(AdvanceCommitIndexMsg {
-                peer_id: Some(self.peer_id()),
-                match_index: index,
-            })
-            .await
-            .context("Failed to advance commit index (single server mode)")?;
+            self.commit_index = index;
+            self.notify_state_change();
+            self.apply_log_entries().await?;
         }
 
         Ok(self.last_log_index)
@@ -1119,7 +1821,8 @@ impl RaftState {
             .keyspace
             .batch()
             .durability(Some(PersistMode::SyncAll));
-        self.log.insert_all(batch, entries).await?;
+        let result = self.log.insert_all(batch, entries).await;
+        self.report_write(result)?;
         self.last_log_index = last_log_index;
         self.last_log_term = last_log_term;
         Ok(())
@@ -0,0 +1,125 @@
+//! Whether the raft servers of the cluster can currently form a quorum.
+
+use std::time::Duration;
+
+use ractor::rpc::CallResult;
+use ractor::{pg, ActorRef};
+use serde::Serialize;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+use super::rpc::RaftStatus;
+use super::{RaftMsg, RaftWorker, RAFT_SCOPE};
+use crate::config::ServerConfig;
+
+/// How long to wait for each server to answer.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the raft servers of the cluster answered to a status poll.
+#[derive(Debug, Serialize)]
+pub(crate) struct QuorumReport {
+    /// Servers that vote, readonly replicas excluded.
+    pub(crate) voters: usize,
+    /// Voters that answered the poll in time.
+    pub(crate) visible: usize,
+    /// Whether the visible voters are a majority.
+    pub(crate) quorum: bool,
+    /// The leader named by the answer with the latest term.
+    pub(crate) leader: Option<String>,
+    pub(crate) servers: Vec<ServerReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ServerReport {
+    pub(crate) name: String,
+    pub(crate) readonly_replica: bool,
+    /// Whether the worker is a member of the raft process group.
+    pub(crate) member: bool,
+    /// Round trip of the status poll, missing if it did not answer in time.
+    pub(crate) rtt_ms: Option<f64>,
+    pub(crate) term: Option<u32>,
+    pub(crate) leader: Option<String>,
+}
+
+impl QuorumReport {
+    /// Whether the cluster can serve writes: a majority is visible and a
+    /// leader is known.
+    pub(crate) fn healthy(&self) -> bool {
+        self.quorum && self.leader.is_some()
+    }
+}
+
+/// Poll every configured raft server for its status.
+pub(crate) async fn check_quorum(servers: &[ServerConfig]) -> QuorumReport {
+    check_quorum_in(RAFT_SCOPE, servers).await
+}
+
+/// [`check_quorum`] of the workers in the process group `scope`.
+pub(super) async fn check_quorum_in(scope: &str, servers: &[ServerConfig]) -> QuorumReport {
+    let members = pg::get_scoped_members(&scope.into(), &RaftWorker::pg_name());
+    let mut polls = JoinSet::new();
+    for (n, server) in servers.iter().enumerate() {
+        let member = members
+            .iter()
+            .find(|cell| cell.get_name().as_deref() == Some(server.name.as_str()))
+            .map(|cell| ActorRef::<RaftMsg>::from(cell.clone()));
+        polls.spawn(async move {
+            let Some(worker) = member else {
+                return (n, false, None);
+            };
+            let start = Instant::now();
+            let status = match worker.call(RaftMsg::GetStatus, Some(POLL_TIMEOUT)).await {
+                Ok(CallResult::Success(status)) => status.valid(),
+                _ => None,
+            };
+            (n, true, status.map(|status| (start.elapsed(), status)))
+        });
+    }
+    let mut reports: Vec<_> = servers
+        .iter()
+        .map(|server| ServerReport {
+            name: server.name.clone(),
+            readonly_replica: server.readonly_replica,
+            member: false,
+            rtt_ms: None,
+            term: None,
+            leader: None,
+        })
+        .collect();
+    let mut latest: Option<(u32, String)> = None;
+    while let Some(poll) = polls.join_next().await {
+        let Ok((n, member, answer)) = poll else {
+            continue;
+        };
+        let report = &mut reports[n];
+        report.member = member;
+        if let Some((
+            rtt,
+            RaftStatus {
+                term, leader_id, ..
+            },
+        )) = answer
+        {
+            report.rtt_ms = Some(rtt.as_secs_f64() * 1000.0);
+            report.term = Some(term);
+            if let Some(leader) = &leader_id {
+                if latest.as_ref().is_none_or(|(latest, _)| term > *latest) {
+                    latest = Some((term, leader.clone()));
+                }
+            }
+            report.leader = leader_id;
+        }
+    }
+    let voters = servers.iter().filter(|s| !s.readonly_replica).count();
+    let visible = reports
+        .iter()
+        .filter(|r| !r.readonly_replica && r.rtt_ms.is_some())
+        .count();
+    QuorumReport {
+        voters,
+        visible,
+        quorum: visible > voters / 2,
+        leader: latest.map(|(_, leader)| leader),
+        servers: reports,
+    }
+}
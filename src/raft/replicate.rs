@@ -1,16 +1,23 @@
 use std::ops::Deref;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use anyhow::Result;
 use ractor::{Actor, ActorProcessingErr, ActorRef};
 use ractor_cluster::RactorMessage;
+use rand::Rng;
+use tokio::time::Instant;
 use tracing::{info, trace, warn};
 
 use super::log_entry::LogEntry;
-use super::{AdvanceCommitIndexMsg, AppendEntriesAsk, RaftLog, RaftMsg, RaftShared, RuntimeConfig};
+use super::{
+    AdvanceCommitIndexMsg, AppendEntriesAsk, PeerId, RaftLog, RaftMsg, RaftShared, RuntimeConfig,
+};
 
 pub(super) struct ReplicateWorker;
 
+/// Entries per append_entries while the peer keeps up.
+const HEARTBEAT_BATCH: u64 = 10;
+
 #[derive(RactorMessage)]
 pub(super) enum ReplicateMsg {
     RunLoop,
@@ -33,6 +40,9 @@ pub(super) struct ReplicateState {
     /// Parent's name
     name: String,
 
+    /// Remote server's name
+    peer_id: PeerId,
+
     /// Remote server's reference
     peer: ActorRef<RaftMsg>,
 
@@ -54,6 +64,9 @@ pub(super) struct ReplicateState {
 
     /// Timestamp of last append_entries
     anchor: Instant,
+
+    /// Whether the peer lags far behind, see [`ReplicateState::run_loop`].
+    catching_up: bool,
 }
 
 pub(super) struct ReplicateArgs {
@@ -65,6 +78,8 @@ pub(super) struct ReplicateArgs {
     pub(super) name: String,
     /// Parent's reference
     pub(super) parent: ActorRef<RaftMsg>,
+    /// Remote server's name
+    pub(super) peer_id: PeerId,
     /// Remote server's reference
     pub(super) peer: ActorRef<RaftMsg>,
     /// Raft log
@@ -91,12 +106,14 @@ impl Actor for ReplicateWorker {
             parent: args.parent,
             config: args.config,
             raft: args.raft,
+            peer_id: args.peer_id,
             peer: args.peer,
             log: args.log,
             next_index: args.last_log_index + 1,
             match_index: 0,
             observer: args.observer,
             anchor: Instant::now(),
+            catching_up: false,
         })
     }
 
@@ -105,11 +122,13 @@ impl Actor for ReplicateWorker {
         _myself: ActorRef<Self::Msg>,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        info!(
-            "replication worker for {} started",
-            state.peer.get_name().unwrap()
-        );
-        state.run_loop().await?;
+        info!("replication worker for {} started", state.peer_id);
+        // Assert leadership right away, then fall into a random phase so
+        // workers spawned together on election don't heartbeat in lockstep.
+        state.append_entries().await?;
+        let heartbeat_ms = state.config.init.raft.heartbeat_ms;
+        let phase = Duration::from_millis(rand::rng().random_range(0..=heartbeat_ms));
+        state.send_after(phase, || ReplicateMsg::RunLoop);
         Ok(())
     }
 
@@ -147,14 +166,36 @@ impl Deref for ReplicateState {
 }
 
 impl ReplicateState {
+    /// Replicate one batch, then schedule the next one.
+    ///
+    /// A peer whose match_index lags the commit index by more than
+    /// `catch_up_threshold` is in catch-up mode: it gets `catch_up_batch`
+    /// entries at a time, back to back instead of once per heartbeat, until
+    /// it is within the threshold again. Only a peer that answers stays in
+    /// catch-up mode, an unreachable one is retried at the heartbeat pace.
     async fn run_loop(&mut self) -> Result<()> {
-        self.append_entries().await?;
-        let next_heartbeat = Duration::from_millis(self.config.init.raft.heartbeat_ms);
-        self.send_after(next_heartbeat, || ReplicateMsg::RunLoop);
+        let answered = self.append_entries().await?;
+        let lag = self.raft.commit_index.saturating_sub(self.match_index);
+        let catching_up = answered && lag > self.config.init.raft.catch_up_threshold;
+        if catching_up != self.catching_up {
+            info!(
+                peer = self.peer_id,
+                lag, catching_up, "replication mode changed"
+            );
+            self.catching_up = catching_up;
+        }
+        if self.catching_up {
+            ractor::cast!(self.myself, ReplicateMsg::RunLoop)?;
+        } else {
+            let next_heartbeat = Duration::from_millis(self.config.init.raft.heartbeat_ms);
+            self.send_after(next_heartbeat, || ReplicateMsg::RunLoop);
+        }
         Ok(())
     }
 
-    async fn append_entries(&mut self) -> Result<()> {
+    /// Send the next batch, returns whether the peer answered in the
+    /// current term.
+    async fn append_entries(&mut self) -> Result<bool> {
         // NB: Replicate worker only runs when the parent is a Leader
         self.anchor = Instant::now();
 
@@ -179,37 +220,39 @@ impl ReplicateState {
         };
 
         trace!(
-            peer = %self.peer.get_name().unwrap(),
+            peer = %self.peer_id,
             ?request,
             "send append_entries"
         );
         // FIXME when timing out we should either reconnect or kill the worker
-        let call_result = ractor::call_t!(self.peer, RaftMsg::AppendEntries, 1000, request);
+        let call_result = ractor::call_t!(self.peer, RaftMsg::AppendEntries, 1000, request.into());
         if let Err(error) = call_result {
             warn!(%error, "append_entries failed");
-            return Ok(());
+            return Ok(false);
         }
 
-        let response = call_result.unwrap();
+        let Some(response) = call_result.unwrap().valid() else {
+            return Ok(false);
+        };
         if response.term < current_term {
             warn!(
                 term = response.term,
                 "discard stale append_entries response"
             );
-            return Ok(());
+            return Ok(false);
         }
         if response.term > current_term {
             info!(
-                peer = self.peer.get_name().unwrap(),
+                peer = self.peer_id,
                 response_term = response.term,
                 current_term,
                 "received append_entries response from server {} in term {} (this server's term was {})",
-                self.peer.get_name().unwrap(),
+                self.peer_id,
                 response.term,
                 current_term,
             );
             ractor::cast!(self.parent, RaftMsg::UpdateTerm(response.term))?;
-            return Ok(());
+            return Ok(false);
         }
 
         assert_eq!(response.term, current_term);
@@ -218,10 +261,10 @@ impl ReplicateState {
 
             if !self.observer {
                 let msg = AdvanceCommitIndexMsg {
-                    peer_id: Some(self.peer.get_name().unwrap()),
+                    peer_id: Some(self.peer_id.clone()),
                     match_index: self.match_index,
                 };
-                ractor::cast!(self.parent, RaftMsg::AdvanceCommitIndex(msg))?;
+                ractor::cast!(self.parent, RaftMsg::AdvanceCommitIndex(msg.into()))?;
             }
 
             self.next_index = self.match_index + 1;
@@ -230,11 +273,16 @@ impl ReplicateState {
             // TODO optimize for skipping last_log_index
         }
 
-        Ok(())
+        Ok(true)
     }
 
     async fn get_log_entries(&self) -> Result<Vec<LogEntry>> {
         let from = self.next_index;
-        self.log.log_entry_range(from..from + 10).await
+        let batch = if self.catching_up {
+            self.config.init.raft.catch_up_batch.max(HEARTBEAT_BATCH)
+        } else {
+            HEARTBEAT_BATCH
+        };
+        self.log.log_entry_range(from..from + batch).await
     }
 }
@@ -1,9 +1,83 @@
-use anyhow::{Context, Result};
+//! Messages raft workers exchange between nodes.
+//!
+//! On the wire every message starts with [`WIRE_VERSION`] and a byte naming
+//! its encoding, followed by the message in that encoding. Nodes started with
+//! a different `cluster.wire_encoding` or wire version derive a different
+//! cookie with [`wire_cookie`] and fail the handshake, instead of
+//! exchanging messages neither side can read.
+
+use std::any::type_name;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Error, Result};
+use metrics::counter;
 use minicbor::{Decode, Encode};
 use ractor::BytesConvertable;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::client::{ClientError, ClientResult};
+use super::{LogEntry, LogEntryValue};
+use crate::config::WireEncoding;
+
+/// Bumped on incompatible changes to the messages below.
+const WIRE_VERSION: u8 = 1;
+
+static WIRE_ENCODING: OnceLock<WireEncoding> = OnceLock::new();
+
+/// Set the encoding of messages sent by this node, once at startup.
+pub(crate) fn set_wire_encoding(encoding: WireEncoding) {
+    if WIRE_ENCODING.set(encoding).is_err() {
+        assert_eq!(
+            WIRE_ENCODING.get(),
+            Some(&encoding),
+            "wire encoding changed"
+        );
+    }
+}
+
+/// The cluster cookie for nodes speaking `encoding` at this wire version.
+pub(crate) fn wire_cookie(cookie: &str, encoding: WireEncoding) -> String {
+    format!("{cookie}/pinka-wire-{WIRE_VERSION}-{encoding}")
+}
 
-use super::client::ClientResult;
-use super::{LogEntry, LogEntryList, LogEntryValue};
+fn encoding_tag(encoding: WireEncoding) -> u8 {
+    match encoding {
+        WireEncoding::Cbor => 0,
+        WireEncoding::Json => 1,
+    }
+}
+
+fn encode_wire<T>(message: &T, encoding: WireEncoding) -> Result<Vec<u8>>
+where
+    T: Encode<()> + Serialize,
+{
+    let mut bytes = vec![WIRE_VERSION, encoding_tag(encoding)];
+    match encoding {
+        WireEncoding::Cbor => minicbor::encode(message, &mut bytes)?,
+        WireEncoding::Json => serde_json::to_writer(&mut bytes, message)?,
+    }
+    Ok(bytes)
+}
+
+/// Decode a message in whichever encoding its tag names.
+fn decode_wire<T>(bytes: &[u8]) -> Result<T>
+where
+    T: for<'b> Decode<'b, ()> + DeserializeOwned,
+{
+    let [version, tag, payload @ ..] = bytes else {
+        bail!("message of {} bytes has no header", bytes.len());
+    };
+    if *version != WIRE_VERSION {
+        bail!("wire version {version}, this node speaks {WIRE_VERSION}");
+    }
+    match *tag {
+        0 => minicbor::decode(payload).context("invalid CBOR message"),
+        1 => serde_json::from_slice(payload).context("invalid JSON message"),
+        _ => bail!("unknown wire encoding {tag}"),
+    }
+}
 
 pub(super) trait RaftSerDe {
     fn to_bytes(&self) -> Result<Vec<u8>>
@@ -22,7 +96,7 @@ pub(super) trait RaftSerDe {
 
 pub(super) type PeerId = String;
 
-#[derive(Debug, Default, Encode, Decode)]
+#[derive(Debug, Default, Encode, Decode, Serialize, Deserialize)]
 pub(super) struct AdvanceCommitIndexMsg {
     #[n(0)]
     pub(super) peer_id: Option<PeerId>,
@@ -30,7 +104,7 @@ pub(super) struct AdvanceCommitIndexMsg {
     pub(super) match_index: u64,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(super) struct AppendEntriesAsk {
     /// Leader's term
     #[n(0)]
@@ -53,7 +127,7 @@ pub(super) struct AppendEntriesAsk {
     pub(super) commit_index: u64,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(super) struct AppendEntriesReply {
     /// Current term, for leader to update itself
     #[n(0)]
@@ -64,7 +138,7 @@ pub(super) struct AppendEntriesReply {
     pub(super) success: bool,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(super) struct RequestVoteAsk {
     /// Candidate's term
     #[n(0)]
@@ -80,7 +154,7 @@ pub(super) struct RequestVoteAsk {
     pub(super) last_log_term: u32,
 }
 
-#[derive(Debug, Encode, Decode)]
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub(super) struct RequestVoteReply {
     /// Current term, for the candidate to update itself
     #[n(0)]
@@ -93,25 +167,195 @@ pub(super) struct RequestVoteReply {
     pub(super) vote_from: String,
 }
 
-macro_rules! impl_bytes_convertable_for_serde {
-    ($t:ident) => {
-        impl RaftSerDe for $t {}
-        impl BytesConvertable for $t {
-            fn into_bytes(self) -> Vec<u8> {
-                self.to_bytes().unwrap()
-            }
-            fn from_bytes(bytes: Vec<u8>) -> Self {
-                RaftSerDe::from_bytes(&bytes).unwrap()
+/// Progress of a raft worker, polled by the [`super::watchdog`].
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
+pub(super) struct RaftStatus {
+    #[n(0)]
+    pub(super) term: u32,
+    #[n(1)]
+    pub(super) commit_index: u64,
+    #[n(2)]
+    pub(super) last_applied: u64,
+    #[n(3)]
+    pub(super) leader_id: Option<PeerId>,
+}
+
+/// A message received from a peer, or a placeholder for one that could not
+/// be decoded.
+///
+/// [`BytesConvertable::from_bytes`] cannot fail, and ractor stops an actor
+/// whose message fails to deserialize. Raft messages cross nodes in a
+/// `Wire` instead: a malformed one is logged when decoded and dropped by the
+/// receiver, so a bad peer cannot crash the worker.
+#[derive(Debug)]
+pub(super) enum Wire<T> {
+    Valid(T),
+    Malformed,
+}
+
+impl<T> Wire<T> {
+    /// The message, `None` if it was malformed.
+    pub(super) fn valid(self) -> Option<T> {
+        match self {
+            Wire::Valid(message) => Some(message),
+            Wire::Malformed => None,
+        }
+    }
+}
+
+impl<T> From<T> for Wire<T> {
+    fn from(value: T) -> Self {
+        Wire::Valid(value)
+    }
+}
+
+/// Messages raft workers send each other in a [`Wire`].
+pub(super) trait WireMessage:
+    Encode<()> + for<'b> Decode<'b, ()> + Serialize + DeserializeOwned
+{
+}
+
+impl WireMessage for AdvanceCommitIndexMsg {}
+impl WireMessage for AppendEntriesAsk {}
+impl WireMessage for AppendEntriesReply {}
+impl WireMessage for RequestVoteAsk {}
+impl WireMessage for RequestVoteReply {}
+impl WireMessage for LogEntryValue {}
+impl WireMessage for RaftStatus {}
+
+/// Encode a message in the encoding of this node. Encoding a message built
+/// by this node does not fail unless there is a bug.
+fn encode_for_peers<T: Encode<()> + Serialize>(message: &T) -> Vec<u8> {
+    let encoding = WIRE_ENCODING.get().copied().unwrap_or_default();
+    encode_wire(message, encoding)
+        .unwrap_or_else(|error| panic!("unable to encode {}: {error:#}", type_name::<T>()))
+}
+
+fn report_malformed<T>(error: &Error) {
+    counter!("pinka_raft_malformed_messages_total").increment(1);
+    warn!(
+        message = type_name::<T>(),
+        ?error,
+        "dropping malformed raft message"
+    );
+}
+
+impl<T: WireMessage> BytesConvertable for Wire<T> {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Wire::Valid(message) => encode_for_peers(&message),
+            // Decodes as malformed again on the other side.
+            Wire::Malformed => vec![],
+        }
+    }
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        match decode_wire(&bytes) {
+            Ok(message) => Wire::Valid(message),
+            Err(error) => {
+                report_malformed::<T>(&error);
+                Wire::Malformed
             }
         }
-    };
+    }
+}
+
+/// Client results already carry errors, a malformed one turns into
+/// [`ClientError::Internal`] for the client.
+impl BytesConvertable for ClientResult {
+    fn into_bytes(self) -> Vec<u8> {
+        encode_for_peers(&self)
+    }
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        decode_wire(&bytes).unwrap_or_else(|error| {
+            report_malformed::<ClientResult>(&error);
+            ClientError::Internal(format!("malformed reply: {error:#}")).into()
+        })
+    }
 }
 
-impl_bytes_convertable_for_serde!(AdvanceCommitIndexMsg);
-impl_bytes_convertable_for_serde!(AppendEntriesAsk);
-impl_bytes_convertable_for_serde!(AppendEntriesReply);
-impl_bytes_convertable_for_serde!(RequestVoteAsk);
-impl_bytes_convertable_for_serde!(RequestVoteReply);
-impl_bytes_convertable_for_serde!(LogEntryValue);
-impl_bytes_convertable_for_serde!(LogEntryList);
-impl_bytes_convertable_for_serde!(ClientResult);
+#[cfg(test)]
+mod tests {
+    use ractor::BytesConvertable;
+
+    use super::{decode_wire, encode_wire, wire_cookie, AppendEntriesAsk, Wire, WIRE_VERSION};
+    use crate::config::WireEncoding;
+    use crate::raft::{ClientError, ClientResult, LogEntry, LogEntryValue};
+
+    fn ask() -> AppendEntriesAsk {
+        AppendEntriesAsk {
+            term: 3,
+            leader_id: "s1".to_string(),
+            prev_log_index: 7,
+            prev_log_term: 2,
+            entries: vec![LogEntry {
+                index: 8,
+                term: 3,
+                value: LogEntryValue::Command(vec![0, 1, 0xff]),
+            }],
+            commit_index: 6,
+        }
+    }
+
+    #[test]
+    fn round_trip_each_encoding() {
+        for encoding in [WireEncoding::Cbor, WireEncoding::Json] {
+            let bytes = encode_wire(&ask(), encoding).unwrap();
+            let decoded: AppendEntriesAsk = decode_wire(&bytes).unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{:?}", ask()));
+        }
+        let json = encode_wire(&ask(), WireEncoding::Json).unwrap();
+        assert!(serde_json::from_slice::<serde_json::Value>(&json[2..]).is_ok());
+    }
+
+    #[test]
+    fn reject_unknown_header() {
+        let mut bytes = encode_wire(&ask(), WireEncoding::Cbor).unwrap();
+        bytes[0] = WIRE_VERSION + 1;
+        let error = decode_wire::<AppendEntriesAsk>(&bytes).unwrap_err();
+        assert!(error.to_string().contains("wire version"));
+
+        bytes[0] = WIRE_VERSION;
+        bytes[1] = 9;
+        assert!(decode_wire::<AppendEntriesAsk>(&bytes).is_err());
+        assert!(decode_wire::<AppendEntriesAsk>(&[WIRE_VERSION]).is_err());
+    }
+
+    #[test]
+    fn drop_malformed_messages() {
+        let bytes = Wire::from(ask()).into_bytes();
+        assert!(Wire::<AppendEntriesAsk>::from_bytes(bytes.clone())
+            .valid()
+            .is_some());
+        let truncated = bytes[..bytes.len() / 2].to_vec();
+        assert!(Wire::<AppendEntriesAsk>::from_bytes(truncated)
+            .valid()
+            .is_none());
+        assert!(Wire::<AppendEntriesAsk>::from_bytes(vec![])
+            .valid()
+            .is_none());
+        assert!(Wire::<AppendEntriesAsk>::from_bytes(
+            Wire::<AppendEntriesAsk>::Malformed.into_bytes()
+        )
+        .valid()
+        .is_none());
+    }
+
+    #[test]
+    fn malformed_client_result_is_an_error() {
+        let result = ClientResult::from_bytes(vec![WIRE_VERSION, 0, 0xff]);
+        assert!(matches!(
+            result.into_result(),
+            Err(ClientError::Internal(error)) if error.contains("malformed")
+        ));
+        let result = ClientResult::from_bytes(ClientResult::ok().into_bytes());
+        assert_eq!(result.into_result(), Ok((vec![], 0)));
+    }
+
+    #[test]
+    fn cookie_depends_on_encoding() {
+        assert_ne!(
+            wire_cookie("secret", WireEncoding::Cbor),
+            wire_cookie("secret", WireEncoding::Json)
+        );
+    }
+}
@@ -4,23 +4,26 @@ use super::rpc::RaftSerDe;
 use super::PeerId;
 
 #[derive(Debug, Default, Clone, Encode, Decode)]
-pub(super) struct RaftSaved {
+pub(crate) struct RaftSaved {
     /// Latest term this worker has seen (initialized to 0 on first boot,
     /// increases monotonically).
     ///
     /// Updated on stable storage before responding to RPCs.
     #[n(0)]
-    pub(super) current_term: u32,
+    pub(crate) current_term: u32,
 
     /// CandidateId that received vote in current term (or None if none).
     ///
     /// Updated on stable storage before responding to RPCs.
     #[n(1)]
-    pub(super) voted_for: Option<PeerId>,
+    pub(crate) voted_for: Option<PeerId>,
 
-    /// Last applied log entry index
+    /// Last applied log entry index.
+    ///
+    /// Only read once to seed the state machine's own position, which is
+    /// authoritative since.
     #[n(2)]
-    pub(super) last_applied: u64,
+    pub(crate) last_applied: u64,
 }
 
 impl RaftSerDe for RaftSaved {}
@@ -2,22 +2,33 @@ use anyhow::{bail, Result};
 use ractor::{ActorRef, DerivedActorRef};
 use ractor_cluster::RactorMessage;
 
-use super::{ClientResult, LogEntry, RaftMsg, RaftWorker};
+use super::{ClientResult, LogEntry, RaftMsg, RaftWorker, RAFT_SCOPE};
 
+/// Messages from the raft worker to the state machine.
+///
+/// There are deliberately no queries: readers open the repositories on the
+/// keyspace themselves, so they never wait in the mailbox behind an apply.
 #[derive(RactorMessage)]
 pub(crate) enum StateMachineMsg {
     Apply(LogEntry),
+    /// Ask the state machine to send its position with
+    /// [`RaftAppliedMsg::Resume`].
+    ReportApplied,
 }
 
 #[derive(RactorMessage)]
 pub(crate) enum RaftAppliedMsg {
     Applied(u64, ClientResult),
+    /// Index of the last entry the state machine applied, raft queues
+    /// entries after it.
+    Resume(u64),
 }
 
 impl From<RaftAppliedMsg> for RaftMsg {
     fn from(value: RaftAppliedMsg) -> Self {
         match value {
             RaftAppliedMsg::Applied(index, result) => RaftMsg::AppliedLog(index, result),
+            RaftAppliedMsg::Resume(index) => RaftMsg::ResumeApply(index),
         }
     }
 }
@@ -26,6 +37,7 @@ impl From<RaftMsg> for RaftAppliedMsg {
     fn from(value: RaftMsg) -> Self {
         match value {
             RaftMsg::AppliedLog(index, result) => RaftAppliedMsg::Applied(index, result),
+            RaftMsg::ResumeApply(index) => RaftAppliedMsg::Resume(index),
             _ => panic!("unsupported RaftAppliedMsg conversion"),
         }
     }
@@ -33,7 +45,7 @@ impl From<RaftMsg> for RaftAppliedMsg {
 
 pub(crate) fn get_raft_applied() -> Result<DerivedActorRef<RaftAppliedMsg>> {
     if let Some(cell) =
-        ractor::pg::get_scoped_local_members(&"raft".into(), &RaftWorker::pg_name()).first()
+        ractor::pg::get_scoped_local_members(&RAFT_SCOPE.into(), &RaftWorker::pg_name()).first()
     {
         let worker: ActorRef<RaftMsg> = cell.clone().into();
         return Ok(worker.get_derived());
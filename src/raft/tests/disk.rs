@@ -0,0 +1,52 @@
+//! Failing writes of the raft workers of a test cluster.
+//!
+//! Before a worker persists its term and vote it asks [`fails`], which
+//! makes the write fail for the nodes a test broke the disk of.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{LazyLock, Mutex};
+
+/// Nodes with a broken disk in the clusters running, by process group scope.
+static DISKS: LazyLock<Mutex<HashMap<String, BTreeSet<String>>>> = LazyLock::new(Default::default);
+
+/// Whether writes of the worker `node` in `scope` fail.
+pub(in crate::raft) fn fails(scope: &str, node: &str) -> bool {
+    DISKS
+        .lock()
+        .unwrap()
+        .get(scope)
+        .is_some_and(|broken| broken.contains(node))
+}
+
+/// Handle on the disks of one cluster.
+pub(super) struct Disks {
+    scope: String,
+}
+
+impl Disks {
+    pub(super) fn new(scope: &str) -> Disks {
+        DISKS
+            .lock()
+            .unwrap()
+            .insert(scope.to_string(), BTreeSet::new());
+        Disks {
+            scope: scope.to_string(),
+        }
+    }
+    /// Make writes of `node` fail, or succeed again.
+    pub(super) fn set_broken(&self, node: &str, broken: bool) {
+        let mut disks = DISKS.lock().unwrap();
+        let nodes = disks.get_mut(&self.scope).expect("disks are registered");
+        if broken {
+            nodes.insert(node.to_string());
+        } else {
+            nodes.remove(node);
+        }
+    }
+}
+
+impl Drop for Disks {
+    fn drop(&mut self) {
+        DISKS.lock().unwrap().remove(&self.scope);
+    }
+}
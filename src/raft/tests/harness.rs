@@ -0,0 +1,583 @@
+//! A raft cluster running in-process.
+//!
+//! Every node is a [`RaftWorker`] with its own keyspace and a
+//! [`TestMachine`] that records the entries it applied. The workers of one
+//! cluster share a process group scope nobody else uses, so clusters of
+//! tests running in parallel never see each other.
+
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use fjall::{Config as KeyspaceConfig, Keyspace, PartitionCreateOptions, PartitionHandle};
+use ractor::rpc::CallResult;
+use ractor::{Actor, ActorProcessingErr, ActorRef, ActorStatus};
+use tempfile::TempDir;
+use tokio::task::{spawn_blocking, JoinSet};
+use tokio::time::{sleep, Instant};
+
+use super::disk::Disks;
+use super::network::Network;
+use crate::activity_pub::machine::AppliedIndex;
+use crate::activity_pub::ActorCache;
+use crate::config::{CacheConfig, Config, RaftConfig, RuntimeConfig, ServerConfig};
+use crate::raft::log_entry::RaftLog;
+use crate::raft::quorum::{check_quorum_in, QuorumReport};
+use crate::raft::rpc::{AppendEntriesAsk, RaftSerDe};
+use crate::raft::{
+    ClientError, ClientResult, LogEntry, LogEntryValue, RaftMsg, RaftWorker, RaftWorkerArgs,
+    StateMachineMsg,
+};
+use crate::storage_health::StorageHealth;
+
+/// How long helpers wait for the cluster to get where they expect.
+const PATIENCE: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(20);
+
+static CLUSTERS: AtomicUsize = AtomicUsize::new(0);
+
+struct Node {
+    name: String,
+    keyspace: Keyspace,
+    /// Range the election timeouts are drawn from.
+    election_ms: RangeInclusive<u64>,
+    worker: Option<ActorRef<RaftMsg>>,
+    machine: Option<ActorRef<StateMachineMsg>>,
+    /// Kept across restarts, like the runtime config of a server.
+    storage: StorageHealth,
+}
+
+pub(super) struct Cluster {
+    scope: String,
+    config: Config,
+    network: Network,
+    disks: Disks,
+    nodes: Vec<Node>,
+    _dir: TempDir,
+}
+
+/// What a node applied or logged at one index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Entry {
+    pub(super) index: u64,
+    pub(super) term: u32,
+    pub(super) command: Option<Vec<u8>>,
+}
+
+impl From<LogEntry> for Entry {
+    fn from(entry: LogEntry) -> Self {
+        Entry {
+            index: entry.index,
+            term: entry.term,
+            command: match entry.value {
+                LogEntryValue::Command(command) => Some(command),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Cluster {
+    /// Start `size` nodes with short election timeouts, 100ms apart.
+    pub(super) async fn start(size: usize) -> Result<Cluster> {
+        Cluster::start_with_config(size, RaftConfig::default()).await
+    }
+
+    /// Like [`Cluster::start`], with the settings of `raft` other than the
+    /// timeouts.
+    pub(super) async fn start_with_config(size: usize, raft: RaftConfig) -> Result<Cluster> {
+        let election_ms = (0..size as u64).map(|i| 150 + 100 * i);
+        Cluster::launch(election_ms.map(|ms| ms..=ms).collect(), raft).await
+    }
+
+    /// Start a node for each of the fixed election timeouts in `election_ms`.
+    ///
+    /// With the tokio clock paused, elections then play out the same way on
+    /// every run.
+    pub(super) async fn start_with_timeouts(election_ms: &[u64]) -> Result<Cluster> {
+        let election_ms = election_ms.iter().map(|&ms| ms..=ms).collect();
+        Cluster::launch(election_ms, RaftConfig::default()).await
+    }
+
+    /// Start `size` nodes that draw their election timeouts at random, like
+    /// in production.
+    pub(super) async fn start_randomized(size: usize) -> Result<Cluster> {
+        Cluster::launch(vec![150..=300; size], RaftConfig::default()).await
+    }
+
+    async fn launch(election_ms: Vec<RangeInclusive<u64>>, raft: RaftConfig) -> Result<Cluster> {
+        let id = CLUSTERS.fetch_add(1, Ordering::Relaxed);
+        let scope = format!("raft_test_{id}");
+        let dir = tempfile::tempdir()?;
+        let mut config = Config {
+            raft: RaftConfig {
+                heartbeat_ms: 20,
+                ..raft
+            },
+            ..Default::default()
+        };
+        let mut nodes = vec![];
+        for (i, election_ms) in election_ms.into_iter().enumerate() {
+            let name = format!("{scope}_n{i}");
+            let keyspace = KeyspaceConfig::new(dir.path().join(&name))
+                .temporary(true)
+                .open()?;
+            config.cluster.servers.push(ServerConfig {
+                name: name.clone(),
+                ..Default::default()
+            });
+            nodes.push(Node {
+                name,
+                keyspace,
+                election_ms,
+                worker: None,
+                machine: None,
+                storage: StorageHealth::default(),
+            });
+        }
+        let mut cluster = Cluster {
+            network: Network::new(&scope),
+            disks: Disks::new(&scope),
+            scope,
+            config,
+            nodes,
+            _dir: dir,
+        };
+        for i in 0..cluster.nodes.len() {
+            cluster.restart(i).await?;
+        }
+        Ok(cluster)
+    }
+
+    /// Start the worker and state machine of a stopped `node` from what they
+    /// saved in its keyspace.
+    pub(super) async fn restart(&mut self, node: usize) -> Result<()> {
+        let mut init = self.config.clone();
+        init.raft.min_election_ms = *self.nodes[node].election_ms.start();
+        init.raft.max_election_ms = *self.nodes[node].election_ms.end();
+        let config = RuntimeConfig {
+            init,
+            server: self.config.cluster.servers[node].clone(),
+            keyspace: self.nodes[node].keyspace.clone(),
+            actor_cache: ActorCache::new(&CacheConfig::default()),
+            applied_index: AppliedIndex::default(),
+            storage: self.nodes[node].storage.clone(),
+        };
+        let Node { name, keyspace, .. } = &self.nodes[node];
+        let (machine, _) = Actor::spawn(
+            Some(machine_name(name)),
+            TestMachine,
+            (name.clone(), keyspace.clone()),
+        )
+        .await?;
+        let (worker, _) = Actor::spawn(
+            Some(name.clone()),
+            RaftWorker,
+            RaftWorkerArgs {
+                config,
+                scope: self.scope.clone(),
+                state_machine: machine_name(name),
+            },
+        )
+        .await?;
+        self.nodes[node].machine = Some(machine);
+        self.nodes[node].worker = Some(worker);
+        Ok(())
+    }
+
+    /// Kill the worker and state machine of `node`, only what they saved in
+    /// the keyspace survives.
+    pub(super) async fn crash(&mut self, node: usize) -> Result<()> {
+        if let Some(worker) = self.nodes[node].worker.take() {
+            worker.kill_and_wait(None).await?;
+        }
+        if let Some(machine) = self.nodes[node].machine.take() {
+            machine.kill_and_wait(None).await?;
+        }
+        Ok(())
+    }
+
+    /// Make `node` run for election right away.
+    pub(super) fn time_out(&self, node: usize) -> Result<()> {
+        let worker = self.nodes[node].worker.as_ref().context("node is down")?;
+        worker.cast(RaftMsg::ElectionTimeout)?;
+        Ok(())
+    }
+
+    /// Cut `node` off from every other node.
+    pub(super) fn isolate(&self, node: usize) {
+        for other in &self.nodes {
+            if other.name != self.nodes[node].name {
+                self.network.cut(&self.nodes[node].name, &other.name);
+            }
+        }
+    }
+
+    /// Hold messages from `from` to `to` for `delay`.
+    pub(super) fn delay(&self, from: usize, to: usize, delay: Duration) {
+        self.network
+            .delay(&self.nodes[from].name, &self.nodes[to].name, delay);
+    }
+
+    /// What the running nodes answer to a quorum check.
+    pub(super) async fn quorum(&self) -> QuorumReport {
+        check_quorum_in(&self.scope, &self.config.cluster.servers).await
+    }
+
+    /// Name of `node` in the cluster config.
+    pub(super) fn name(&self, node: usize) -> &str {
+        &self.nodes[node].name
+    }
+
+    /// Failed writes of `node` in a row.
+    pub(super) fn write_failures(&self, node: usize) -> u32 {
+        self.nodes[node].storage.failures()
+    }
+
+    /// Whether `node` stopped taking part because its writes keep failing.
+    pub(super) fn degraded(&self, node: usize) -> bool {
+        self.nodes[node].storage.is_degraded()
+    }
+
+    /// Whether the worker of `node` is running.
+    pub(super) fn is_up(&self, node: usize) -> bool {
+        self.nodes[node].worker.as_ref().is_some_and(|worker| {
+            !matches!(
+                worker.get_status(),
+                ActorStatus::Stopping | ActorStatus::Stopped
+            )
+        })
+    }
+
+    /// Make the worker of `node` fail to persist its term and vote and its
+    /// storage probes, or succeed again.
+    pub(super) fn break_disk(&self, node: usize, broken: bool) {
+        self.disks.set_broken(&self.nodes[node].name, broken);
+    }
+
+    /// Restore all links.
+    pub(super) fn heal(&self) {
+        self.network.heal();
+    }
+
+    /// Wait until exactly one of `among` leads and return it.
+    ///
+    /// A leader is the only worker that runs replication workers. A leader
+    /// cut off from the others keeps leading, so pass the nodes on one side
+    /// of a partition.
+    pub(super) async fn leader(&self, among: &[usize]) -> Result<usize> {
+        let deadline = Instant::now() + PATIENCE;
+        loop {
+            let leaders: Vec<usize> = among
+                .iter()
+                .copied()
+                .filter(|&node| {
+                    self.nodes[node]
+                        .worker
+                        .as_ref()
+                        .is_some_and(|worker| !worker.get_children().is_empty())
+                })
+                .collect();
+            if let [leader] = leaders[..] {
+                return Ok(leader);
+            }
+            if Instant::now() > deadline {
+                bail!("no single leader among {among:?}, found {leaders:?}");
+            }
+            sleep(POLL).await;
+        }
+    }
+
+    /// Send `command` to `node` once and wait for the reply.
+    pub(super) async fn request(&self, node: usize, command: &[u8]) -> Result<ClientResult> {
+        let worker = self.nodes[node].worker.as_ref().context("node is down")?;
+        let value = LogEntryValue::Command(command.to_vec());
+        match worker
+            .call(
+                |reply| RaftMsg::ClientRequest(value.into(), reply),
+                Some(PATIENCE),
+            )
+            .await?
+        {
+            CallResult::Success(result) => Ok(result),
+            CallResult::Timeout => bail!("request timed out"),
+            CallResult::SenderError => bail!("node dropped the request"),
+        }
+    }
+
+    /// Ask `node` to give up leadership and return the node it handed over
+    /// to, `Err(_)` with the reply when it did not lead.
+    pub(super) async fn step_down(&self, node: usize) -> Result<Option<usize>> {
+        let worker = self.nodes[node].worker.as_ref().context("node is down")?;
+        match worker.call(RaftMsg::StepDown, Some(PATIENCE)).await? {
+            CallResult::Success(ClientResult::Ok(name, _)) => Ok(self
+                .nodes
+                .iter()
+                .position(|other| other.name.as_bytes() == name)),
+            CallResult::Success(ClientResult::Err(error)) => bail!("step down failed: {error}"),
+            CallResult::Timeout => bail!("step down timed out"),
+            CallResult::SenderError => bail!("node dropped the request"),
+        }
+    }
+
+    /// Send `node` an AppendEntries from `leader` in its current term, with
+    /// `entries` following `prev_log_index`. Returns whether `node` took them.
+    pub(super) async fn append_entries(
+        &self,
+        node: usize,
+        leader: usize,
+        prev_log_index: u64,
+        entries: Vec<LogEntry>,
+    ) -> Result<bool> {
+        let worker = self.nodes[node].worker.as_ref().context("node is down")?;
+        let log = self.log(leader).await?;
+        let term = log.last().context("leader has an empty log")?.term;
+        let request = AppendEntriesAsk {
+            term,
+            leader_id: self.nodes[leader].name.clone(),
+            prev_log_index,
+            prev_log_term: term,
+            entries,
+            commit_index: 0,
+        };
+        match worker
+            .call(
+                |reply| RaftMsg::AppendEntries(request.into(), reply),
+                Some(PATIENCE),
+            )
+            .await?
+        {
+            CallResult::Success(reply) => Ok(reply.valid().context("malformed reply")?.success),
+            CallResult::Timeout => bail!("append_entries timed out"),
+            CallResult::SenderError => bail!("node dropped the request"),
+        }
+    }
+
+    /// Submit `command` through `node` and wait for it to be applied.
+    ///
+    /// Retried while `node` finds no leader to forward to, an entry that may
+    /// have made it into the log is never submitted twice.
+    pub(super) async fn submit(&self, node: usize, command: &[u8]) -> Result<()> {
+        let worker = self.nodes[node].worker.as_ref().context("node is down")?;
+        let deadline = Instant::now() + PATIENCE;
+        loop {
+            let value = LogEntryValue::Command(command.to_vec());
+            match worker
+                .call(
+                    |reply| RaftMsg::ClientRequest(value.into(), reply),
+                    Some(PATIENCE),
+                )
+                .await?
+            {
+                CallResult::Success(ClientResult::Ok(..)) => return Ok(()),
+                CallResult::Success(ClientResult::Err(ClientError::NotLeader { .. }))
+                    if Instant::now() < deadline =>
+                {
+                    sleep(POLL).await
+                }
+                CallResult::Success(ClientResult::Err(error)) => bail!("command failed: {error}"),
+                CallResult::Timeout => bail!("command timed out"),
+                CallResult::SenderError => bail!("node dropped the request"),
+            }
+        }
+    }
+
+    /// Send all `commands` to the leader `node` at once, like concurrent
+    /// clients, and wait for every reply.
+    pub(super) async fn submit_all(&self, node: usize, commands: &[&[u8]]) -> Result<()> {
+        let worker = self.nodes[node].worker.clone().context("node is down")?;
+        let mut replies = JoinSet::new();
+        for command in commands {
+            let value = LogEntryValue::Command(command.to_vec());
+            let worker = worker.clone();
+            replies.spawn(async move {
+                worker
+                    .call(
+                        |reply| RaftMsg::ClientRequest(value.into(), reply),
+                        Some(PATIENCE),
+                    )
+                    .await
+            });
+        }
+        while let Some(reply) = replies.join_next().await {
+            match reply?? {
+                CallResult::Success(ClientResult::Ok(..)) => {}
+                CallResult::Success(ClientResult::Err(error)) => bail!("command failed: {error}"),
+                CallResult::Timeout => bail!("command timed out"),
+                CallResult::SenderError => bail!("node dropped the request"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries the state machine of `node` applied, in order.
+    pub(super) async fn applied(&self, node: usize) -> Result<Vec<Entry>> {
+        let partition = machine_partition(&self.nodes[node].keyspace)?;
+        spawn_blocking(move || {
+            partition
+                .values()
+                .map(|value| Ok(LogEntry::from_bytes(&value?)?.into()))
+                .collect()
+        })
+        .await?
+    }
+
+    /// The raft log of `node`.
+    pub(super) async fn log(&self, node: usize) -> Result<Vec<Entry>> {
+        let log = self.nodes[node]
+            .keyspace
+            .open_partition("raft_log", PartitionCreateOptions::default())?;
+        let entries = RaftLog::new(log).log_entry_range(..).await?;
+        Ok(entries.into_iter().map(Entry::from).collect())
+    }
+
+    /// Wait until every running node applied exactly `commands`, in order,
+    /// from the same log.
+    pub(super) async fn assert_converged(&self, commands: &[&[u8]]) -> Result<()> {
+        let deadline = Instant::now() + PATIENCE;
+        loop {
+            let problem = self.divergence(commands).await?;
+            let Some(problem) = problem else {
+                return Ok(());
+            };
+            if Instant::now() > deadline {
+                bail!("cluster did not converge: {problem}");
+            }
+            sleep(POLL).await;
+        }
+    }
+
+    async fn divergence(&self, commands: &[&[u8]]) -> Result<Option<String>> {
+        let mut reference: Option<(usize, Vec<Entry>)> = None;
+        for (node, state) in self.nodes.iter().enumerate() {
+            if state.worker.is_none() {
+                continue;
+            }
+            let applied = self.applied(node).await?;
+            let applied_commands: Vec<&[u8]> = applied
+                .iter()
+                .filter_map(|e| e.command.as_deref())
+                .collect();
+            if applied_commands != commands {
+                return Ok(Some(format!(
+                    "node {node} applied {} of {} commands",
+                    applied_commands.len(),
+                    commands.len()
+                )));
+            }
+            let log = self.log(node).await?;
+            if !log.starts_with(&applied) {
+                return Ok(Some(format!("node {node} applied entries not in its log")));
+            }
+            match &reference {
+                Some((other, entries)) if *entries != applied => {
+                    return Ok(Some(format!(
+                        "nodes {other} and {node} applied different entries"
+                    )));
+                }
+                Some(_) => {}
+                None => reference = Some((node, applied)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Stop every node.
+    pub(super) async fn shutdown(mut self) -> Result<()> {
+        for node in 0..self.nodes.len() {
+            self.crash(node).await?;
+        }
+        Ok(())
+    }
+}
+
+fn machine_name(node: &str) -> String {
+    format!("{node}_machine")
+}
+
+fn machine_partition(keyspace: &Keyspace) -> Result<PartitionHandle> {
+    keyspace
+        .open_partition("test_machine", PartitionCreateOptions::default())
+        .context("Failed to open test_machine")
+}
+
+/// Records the entries it applies in its keyspace, keyed by index.
+struct TestMachine;
+
+struct TestMachineState {
+    raft: String,
+    applied: PartitionHandle,
+    last_applied: u64,
+}
+
+impl TestMachineState {
+    fn report(&self, message: RaftMsg) -> Result<(), ActorProcessingErr> {
+        if let Some(raft) = ActorRef::<RaftMsg>::where_is(self.raft.clone()) {
+            raft.cast(message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for TestMachine {
+    type Msg = StateMachineMsg;
+    type State = TestMachineState;
+    /// Name of the raft worker and the keyspace of the node.
+    type Arguments = (String, Keyspace);
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        (raft, keyspace): Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let applied = machine_partition(&keyspace)?;
+        let last_applied = match applied.last_key_value()? {
+            Some((key, _)) => u64::from_be_bytes(key.as_ref().try_into()?),
+            None => 0,
+        };
+        Ok(TestMachineState {
+            raft,
+            applied,
+            last_applied,
+        })
+    }
+
+    async fn post_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        state.report(RaftMsg::ResumeApply(state.last_applied))
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            StateMachineMsg::Apply(entry) => {
+                let index = entry.index;
+                if index <= state.last_applied {
+                    return Ok(());
+                }
+                if index != state.last_applied + 1 {
+                    return Err(
+                        format!("expected entry {}, got {index}", state.last_applied + 1).into(),
+                    );
+                }
+                state
+                    .applied
+                    .insert(index.to_be_bytes(), entry.to_bytes()?)?;
+                state.last_applied = index;
+                state.report(RaftMsg::AppliedLog(index, ClientResult::ok()))?;
+            }
+            StateMachineMsg::ReportApplied => {
+                state.report(RaftMsg::ResumeApply(state.last_applied))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,517 @@
+//! Multi-node scenarios on an in-process cluster, see [`harness::Cluster`].
+//!
+//! The tokio clock is paused in every test. It only moves on when all
+//! nodes wait for a timer, and every node has its own fixed election
+//! timeout, so a scenario plays out the same way on every run no matter
+//! how busy the machine is.
+
+pub(super) mod disk;
+mod harness;
+pub(super) mod network;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::time::sleep;
+
+use self::harness::Cluster;
+use super::{ClientError, ClientResult, LogEntry, LogEntryValue};
+use crate::config::RaftConfig;
+
+#[tokio::test(start_paused = true)]
+async fn replicate_to_all_nodes() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+
+    cluster.submit(leader, b"one").await?;
+    // Followers forward to the leader.
+    cluster.submit(follower, b"two").await?;
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn single_node_leads_from_the_start() -> Result<()> {
+    let mut cluster = Cluster::start(1).await?;
+    // No election timeout had to run out.
+    let result = cluster.request(0, b"one").await?;
+    assert!(matches!(result, ClientResult::Ok(..)), "{result:?}");
+    cluster.crash(0).await?;
+    cluster.restart(0).await?;
+    let result = cluster.request(0, b"two").await?;
+    assert!(matches!(result, ClientResult::Ok(..)), "{result:?}");
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn report_quorum_of_running_servers() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    // Followers know the leader once it replicated to them.
+    cluster.submit(leader, b"one").await?;
+    let report = cluster.quorum().await;
+    assert!(report.healthy(), "{report:?}");
+    assert_eq!((report.voters, report.visible), (3, 3));
+    assert_eq!(report.leader.as_deref(), Some(cluster.name(leader)));
+    assert!(report.servers.iter().all(|server| server.rtt_ms.is_some()));
+
+    let followers: Vec<usize> = (0..3).filter(|&node| node != leader).collect();
+    cluster.crash(followers[0]).await?;
+    let report = cluster.quorum().await;
+    assert!(report.healthy(), "{report:?}");
+    assert_eq!(report.visible, 2);
+    assert!(!report.servers[followers[0]].member);
+
+    cluster.crash(followers[1]).await?;
+    let report = cluster.quorum().await;
+    assert!(!report.quorum && !report.healthy(), "{report:?}");
+    assert_eq!(report.visible, 1);
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn catch_up_partitioned_follower() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+
+    cluster.isolate(follower);
+    // The two others are still a majority.
+    cluster.submit(leader, b"one").await?;
+    cluster.submit(leader, b"two").await?;
+    // It may have applied the entry that started the term before it was cut
+    // off, but none of the commands.
+    let applied = cluster.applied(follower).await?;
+    assert!(applied.iter().all(|entry| entry.command.is_none()));
+
+    cluster.heal();
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn catch_up_follower_far_behind() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+
+    cluster.crash(follower).await?;
+    let commands: Vec<Vec<u8>> = (0..20).map(|n| format!("{n}").into_bytes()).collect();
+    for command in &commands {
+        cluster.submit(leader, command).await?;
+    }
+    cluster.restart(follower).await?;
+
+    // Entries past the end of its log would leave a hole.
+    let gap = cluster.log(leader).await?.len() as u64 + 10;
+    let entry = LogEntry {
+        index: gap + 1,
+        term: 1,
+        value: LogEntryValue::Command(b"after the gap".to_vec()),
+    };
+    assert!(
+        !cluster
+            .append_entries(follower, leader, gap, vec![entry])
+            .await?
+    );
+
+    // The leader backs up until the logs match.
+    let commands: Vec<&[u8]> = commands.iter().map(Vec::as_slice).collect();
+    cluster.assert_converged(&commands).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn elect_new_leader_when_leader_is_cut_off() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    let old_leader = cluster.leader(&[0, 1, 2]).await?;
+    cluster.submit(old_leader, b"one").await?;
+    // Either follower can win with the whole log.
+    cluster.assert_converged(&[b"one"]).await?;
+
+    cluster.isolate(old_leader);
+    let others: Vec<usize> = (0..3).filter(|&node| node != old_leader).collect();
+    let new_leader = cluster.leader(&others).await?;
+    cluster.submit(new_leader, b"two").await?;
+
+    cluster.heal();
+    // The old leader learns about the new term and follows.
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, new_leader);
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn restart_crashed_nodes() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+    cluster.submit(leader, b"one").await?;
+    cluster.assert_converged(&[b"one"]).await?;
+
+    cluster.crash(follower).await?;
+    cluster.submit(leader, b"two").await?;
+    cluster.restart(follower).await?;
+    // Entries applied before the crash are not applied again.
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+
+    cluster.crash(leader).await?;
+    let others: Vec<usize> = (0..3).filter(|&node| node != leader).collect();
+    let new_leader = cluster.leader(&others).await?;
+    cluster.submit(new_leader, b"three").await?;
+    cluster.restart(leader).await?;
+    cluster
+        .assert_converged(&[b"one", b"two", b"three"])
+        .await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn fail_over_to_new_leader_with_persisted_state() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    let old_leader = cluster.leader(&[0, 1, 2]).await?;
+    cluster.submit_all(old_leader, &[b"one", b"two"]).await?;
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+
+    // Only the keyspace of the old leader is left.
+    cluster.crash(old_leader).await?;
+    let others: Vec<usize> = (0..3).filter(|&node| node != old_leader).collect();
+    let new_leader = cluster.leader(&others).await?;
+    let applied = cluster.applied(new_leader).await?;
+    let commands: Vec<&[u8]> = applied
+        .iter()
+        .filter_map(|entry| entry.command.as_deref())
+        .collect();
+    assert_eq!(commands, [b"one", b"two"]);
+    cluster.submit(new_leader, b"three").await?;
+
+    cluster.restart(old_leader).await?;
+    cluster
+        .assert_converged(&[b"one", b"two", b"three"])
+        .await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, new_leader);
+    // The old leader forwards to the new one.
+    cluster.submit(old_leader, b"four").await?;
+    cluster
+        .assert_converged(&[b"one", b"two", b"three", b"four"])
+        .await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn withhold_vote_that_was_not_persisted() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+
+    // Node 1 runs first and needs the vote of node 2, which cannot save it.
+    cluster.break_disk(2, true);
+    cluster.isolate(0);
+    assert!(cluster.leader(&[1, 2]).await.is_err());
+    assert!(cluster.write_failures(2) > 0);
+
+    cluster.break_disk(2, false);
+    cluster.crash(2).await?;
+    cluster.restart(2).await?;
+    cluster.leader(&[1, 2]).await?;
+    assert_eq!(cluster.write_failures(2), 0);
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn degraded_node_stays_out_until_storage_recovers() -> Result<()> {
+    let mut cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+
+    // Each election of node 2 fails to save the new term and stops the
+    // worker, until its storage counts as degraded.
+    cluster.break_disk(2, true);
+    while !cluster.degraded(2) {
+        cluster.time_out(2)?;
+        while cluster.is_up(2) {
+            sleep(Duration::from_millis(20)).await;
+        }
+        cluster.crash(2).await?;
+        cluster.restart(2).await?;
+    }
+
+    // Degraded, it neither runs for election nor stops.
+    cluster.time_out(2)?;
+    sleep(Duration::from_secs(1)).await;
+    assert!(cluster.is_up(2));
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    cluster.submit(0, b"one").await?;
+
+    // A probe gets through once the disk works again.
+    cluster.break_disk(2, false);
+    cluster.time_out(2)?;
+    while cluster.degraded(2) {
+        sleep(Duration::from_millis(20)).await;
+    }
+    cluster.leader(&[0, 1, 2]).await?;
+    cluster.assert_converged(&[b"one"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn queue_committed_entries_as_they_are_applied() -> Result<()> {
+    let raft = RaftConfig {
+        max_batch_delay_ms: 5,
+        max_apply_queue: 1,
+        ..Default::default()
+    };
+    let mut cluster = Cluster::start_with_config(3, raft).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+
+    // Batches commit several entries at once, the state machine gets them
+    // one at a time.
+    let commands: Vec<Vec<u8>> = (0..20).map(|n| format!("{n:02}").into_bytes()).collect();
+    let commands: Vec<&[u8]> = commands.iter().map(Vec::as_slice).collect();
+    cluster.crash(follower).await?;
+    cluster.submit_all(leader, &commands).await?;
+    // The restarted follower catches up on the whole log.
+    cluster.restart(follower).await?;
+    let applied: Vec<Vec<u8>> = cluster
+        .applied(leader)
+        .await?
+        .into_iter()
+        .filter_map(|entry| entry.command)
+        .collect();
+    let applied: Vec<&[u8]> = applied.iter().map(Vec::as_slice).collect();
+    let mut sorted = applied.clone();
+    sorted.sort();
+    assert_eq!(sorted, commands);
+    cluster.assert_converged(&applied).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn batch_client_requests() -> Result<()> {
+    let raft = RaftConfig {
+        max_batch_delay_ms: 5,
+        max_batch_entries: 2,
+        ..Default::default()
+    };
+    let cluster = Cluster::start_with_config(3, raft).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+
+    // One full batch, one flushed by the timer.
+    cluster
+        .submit_all(leader, &[b"one", b"two", b"six"])
+        .await?;
+    let mut commands: Vec<Vec<u8>> = cluster
+        .applied(leader)
+        .await?
+        .into_iter()
+        .filter_map(|entry| entry.command)
+        .collect();
+    commands.sort();
+    assert_eq!(commands, [b"one", b"six", b"two"]);
+    // Forwarded requests are batched by the leader too.
+    cluster.submit(follower, b"ten").await?;
+    let applied = cluster.applied(leader).await?;
+    assert_eq!(
+        applied.last().unwrap().command.as_deref(),
+        Some(&b"ten"[..])
+    );
+    cluster.shutdown().await
+}
+
+/// Client requests per second on a single node with and without group
+/// commit, run with `cargo test --release bench_group_commit -- --ignored
+/// --nocapture`.
+#[tokio::test]
+#[ignore = "benchmark"]
+async fn bench_group_commit() -> Result<()> {
+    let commands: Vec<Vec<u8>> = (0..2000)
+        .map(|i| format!("command {i}").into_bytes())
+        .collect();
+    let commands: Vec<&[u8]> = commands.iter().map(Vec::as_slice).collect();
+    for max_batch_delay_ms in [0, 2] {
+        let raft = RaftConfig {
+            max_batch_delay_ms,
+            ..Default::default()
+        };
+        let cluster = Cluster::start_with_config(1, raft).await?;
+        let start = std::time::Instant::now();
+        cluster.submit_all(0, &commands).await?;
+        let rate = commands.len() as f64 / start.elapsed().as_secs_f64();
+        println!("max_batch_delay_ms = {max_batch_delay_ms}: {rate:.0} requests/s");
+        cluster.shutdown().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn replicate_over_slow_link() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    let leader = cluster.leader(&[0, 1, 2]).await?;
+    let follower = (leader + 1) % 3;
+    cluster.delay(leader, follower, Duration::from_millis(50));
+
+    for command in [b"one", b"two", b"six"] {
+        cluster.submit(leader, command).await?;
+    }
+    cluster.assert_converged(&[b"one", b"two", b"six"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn elect_first_node_to_time_out() -> Result<()> {
+    let cluster = Cluster::start_with_timeouts(&[400, 200, 600]).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 1);
+    cluster.submit(0, b"one").await?;
+    cluster.assert_converged(&[b"one"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn recover_from_disruptive_candidate() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    cluster.submit(0, b"one").await?;
+
+    // Cut off, node 2 keeps running for election in ever higher terms.
+    cluster.isolate(2);
+    sleep(Duration::from_secs(3)).await;
+    cluster.submit(0, b"two").await?;
+
+    // Its term makes the leader step down when it is back, node 2 cannot
+    // win with its shorter log and node 0 is first to time out again.
+    cluster.heal();
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn hand_over_leadership() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    cluster.submit(0, b"one").await?;
+    cluster.assert_converged(&[b"one"]).await?;
+
+    // Only the leader can step down.
+    assert!(cluster.step_down(1).await.is_err());
+    // Node 0 would time out first, the successor runs before it does.
+    let successor = cluster.step_down(0).await?.expect("a peer is connected");
+    assert_ne!(successor, 0);
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, successor);
+    cluster.submit(0, b"two").await?;
+    cluster.assert_converged(&[b"one", b"two"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn fail_requests_in_flight_on_step_down() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    // The step down is handled before the entry could commit.
+    let (result, successor) = tokio::join!(cluster.request(0, b"one"), cluster.step_down(0));
+    assert!(matches!(
+        result?,
+        ClientResult::Err(ClientError::NotLeader { leader: None })
+    ));
+    let successor = successor?.expect("a peer is connected");
+    cluster.submit(successor, b"two").await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn drain_requests_before_step_down() -> Result<()> {
+    let raft = RaftConfig {
+        step_down_drain_ms: 1_000,
+        ..Default::default()
+    };
+    let cluster = Cluster::start_with_config(3, raft).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    let (one, successor, two) = tokio::join!(
+        cluster.request(0, b"one"),
+        cluster.step_down(0),
+        cluster.request(0, b"two"),
+    );
+    // The request in flight commits, the one after is refused.
+    assert!(matches!(one?, ClientResult::Ok(..)));
+    assert!(matches!(
+        two?,
+        ClientResult::Err(ClientError::NotLeader { leader: None })
+    ));
+    let successor = successor?.expect("a peer is connected");
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, successor);
+    cluster.submit(0, b"three").await?;
+    cluster.assert_converged(&[b"one", b"three"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn reject_oversized_requests() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    let huge = vec![0; RaftConfig::default().max_entry_bytes];
+    // The leader refuses to append it and a follower to forward it.
+    for node in [0, 1] {
+        assert!(matches!(
+            cluster.request(node, &huge).await?,
+            ClientResult::Err(ClientError::TooLarge { .. })
+        ));
+    }
+    cluster.submit(0, b"one").await?;
+    cluster.assert_converged(&[b"one"]).await?;
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn resolve_split_vote() -> Result<()> {
+    for _ in 0..5 {
+        let cluster = Cluster::start_randomized(2).await?;
+        // Both run at once and vote for themselves, neither has a quorum
+        // until one times out again first.
+        cluster.time_out(0)?;
+        cluster.time_out(1)?;
+        let leader = cluster.leader(&[0, 1]).await?;
+        // The other candidate follows, otherwise nothing commits.
+        cluster.submit(1 - leader, b"one").await?;
+        cluster.assert_converged(&[b"one"]).await?;
+        cluster.shutdown().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn reject_requests_without_leader() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    // Node 2 never hears from the leader, it only runs for election.
+    cluster.isolate(2);
+    cluster.leader(&[0, 1]).await?;
+    assert!(matches!(
+        cluster.request(2, b"one").await?,
+        ClientResult::Err(ClientError::NotLeader { leader: None })
+    ));
+    cluster.shutdown().await
+}
+
+#[tokio::test(start_paused = true)]
+async fn fail_requests_of_deposed_leader() -> Result<()> {
+    let cluster = Cluster::start(3).await?;
+    assert_eq!(cluster.leader(&[0, 1, 2]).await?, 0);
+    cluster.submit(0, b"one").await?;
+    cluster.assert_converged(&[b"one"]).await?;
+
+    cluster.isolate(0);
+    let (result, new_leader) = tokio::join!(cluster.request(0, b"two"), async {
+        let new_leader = cluster.leader(&[1, 2]).await;
+        cluster.heal();
+        new_leader
+    });
+    // The old leader steps down before it could commit the entry.
+    assert!(matches!(
+        result?,
+        ClientResult::Err(ClientError::NotLeader { .. })
+    ));
+    cluster.submit(new_leader?, b"three").await?;
+    cluster.assert_converged(&[b"one", b"three"]).await?;
+    cluster.shutdown().await
+}
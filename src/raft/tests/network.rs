@@ -0,0 +1,111 @@
+//! In-memory transport between the raft workers of a test cluster.
+//!
+//! Workers of a cluster talk to each other directly through their actor
+//! references. Every message from a peer passes [`deliver`] before it is
+//! handled, which drops it if the link is cut or holds it for the delay of
+//! the link.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use tokio::time::sleep;
+
+use crate::raft::rpc::Wire;
+use crate::raft::RaftMsg;
+
+type Link = (String, String);
+
+#[derive(Default)]
+struct Links {
+    cut: BTreeSet<Link>,
+    delays: BTreeMap<Link, Duration>,
+}
+
+/// Links of the clusters running, by process group scope.
+static NETWORKS: LazyLock<Mutex<HashMap<String, Links>>> = LazyLock::new(Default::default);
+
+/// Whether `message` for the worker `to` in `scope` goes through.
+pub(in crate::raft) async fn deliver(scope: &str, to: &str, message: &RaftMsg) -> bool {
+    let Some(from) = sender(message) else {
+        return true;
+    };
+    let link = (from.to_string(), to.to_string());
+    let delay = {
+        let networks = NETWORKS.lock().unwrap();
+        let Some(links) = networks.get(scope) else {
+            return true;
+        };
+        if links.cut.contains(&link) {
+            return false;
+        }
+        links.delays.get(&link).copied()
+    };
+    if let Some(delay) = delay {
+        sleep(delay).await;
+    }
+    true
+}
+
+/// The peer that sent `message`, `None` for local messages.
+fn sender(message: &RaftMsg) -> Option<&str> {
+    match message {
+        RaftMsg::AppendEntries(Wire::Valid(request), _) => Some(&request.leader_id),
+        RaftMsg::RequestVote(Wire::Valid(request)) => Some(&request.candidate_name),
+        RaftMsg::RequestVoteResponse(Wire::Valid(reply)) => Some(&reply.vote_from),
+        RaftMsg::TimeoutNow(_, leader) => Some(leader),
+        _ => None,
+    }
+}
+
+/// Handle on the links of one cluster.
+pub(super) struct Network {
+    scope: String,
+}
+
+impl Network {
+    pub(super) fn new(scope: &str) -> Network {
+        NETWORKS
+            .lock()
+            .unwrap()
+            .insert(scope.to_string(), Links::default());
+        Network {
+            scope: scope.to_string(),
+        }
+    }
+    fn update(&self, f: impl FnOnce(&mut Links)) {
+        f(NETWORKS
+            .lock()
+            .unwrap()
+            .get_mut(&self.scope)
+            .expect("network is registered"));
+    }
+    /// Drop messages between `a` and `b`, in both directions.
+    pub(super) fn cut(&self, a: &str, b: &str) {
+        self.update(|links| {
+            links.cut.insert((a.to_string(), b.to_string()));
+            links.cut.insert((b.to_string(), a.to_string()));
+        });
+    }
+    /// Hold messages from `from` to `to` for `delay` before they are handled.
+    ///
+    /// The receiver handles nothing else meanwhile, like a worker on a slow
+    /// link that is busy reading.
+    pub(super) fn delay(&self, from: &str, to: &str, delay: Duration) {
+        self.update(|links| {
+            links
+                .delays
+                .insert((from.to_string(), to.to_string()), delay);
+        });
+    }
+    /// Restore all links.
+    pub(super) fn heal(&self) {
+        self.update(|links| *links = Links::default());
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        NETWORKS.lock().unwrap().remove(&self.scope);
+    }
+}
@@ -0,0 +1,122 @@
+//! Detection of a raft worker that stopped making progress.
+//!
+//! Supervision restarts a worker that crashed, the watchdog catches one
+//! that hangs: it no longer answers, or leaves committed entries unapplied.
+//! The raft server polls the status of its worker every
+//! `raft.watchdog_interval_ms`, without progress for `raft.watchdog_stall_ms`
+//! it reports the worker and, with `raft.watchdog_restart`, restarts it.
+
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::rpc::RaftStatus;
+
+/// Why the worker looks wedged.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Stall {
+    /// The worker did not answer the status polls.
+    Unresponsive,
+    /// Committed entries wait and the state machine applied none.
+    Apply {
+        commit_index: u64,
+        last_applied: u64,
+    },
+}
+
+pub(super) struct Watchdog {
+    stall: Duration,
+    last_applied: Option<u64>,
+    /// When the worker last answered with some progress.
+    progressed: Instant,
+}
+
+impl Watchdog {
+    pub(super) fn new(stall: Duration, now: Instant) -> Watchdog {
+        Watchdog {
+            stall,
+            last_applied: None,
+            progressed: now,
+        }
+    }
+
+    /// Record the status polled at `now`, `None` if the worker did not
+    /// answer. Returns the stall once the worker went a whole window without
+    /// progress, then again after every further window.
+    pub(super) fn check(&mut self, status: Option<&RaftStatus>, now: Instant) -> Option<Stall> {
+        let stall = match status {
+            None => Stall::Unresponsive,
+            Some(status) => {
+                let applied = self.last_applied.replace(status.last_applied);
+                if status.last_applied >= status.commit_index
+                    || applied != Some(status.last_applied)
+                {
+                    self.progressed = now;
+                    return None;
+                }
+                Stall::Apply {
+                    commit_index: status.commit_index,
+                    last_applied: status.last_applied,
+                }
+            }
+        };
+        if now.duration_since(self.progressed) < self.stall {
+            return None;
+        }
+        self.progressed = now;
+        Some(stall)
+    }
+
+    /// Start a new window, after the worker was restarted.
+    pub(super) fn reset(&mut self, now: Instant) {
+        self.last_applied = None;
+        self.progressed = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{Stall, Watchdog};
+    use crate::raft::rpc::RaftStatus;
+
+    fn status(commit_index: u64, last_applied: u64) -> RaftStatus {
+        RaftStatus {
+            term: 1,
+            commit_index,
+            last_applied,
+            leader_id: None,
+        }
+    }
+
+    #[test]
+    fn report_stalls_after_window() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut watchdog = Watchdog::new(Duration::from_secs(60), start);
+
+        // Nothing to apply is no stall, however long it lasts.
+        assert_eq!(watchdog.check(Some(&status(5, 5)), at(100)), None);
+        // Applying slowly is progress.
+        assert_eq!(watchdog.check(Some(&status(9, 6)), at(130)), None);
+        assert_eq!(watchdog.check(Some(&status(9, 7)), at(160)), None);
+        // Stuck at 7 with 9 committed.
+        assert_eq!(watchdog.check(Some(&status(9, 7)), at(190)), None);
+        assert_eq!(
+            watchdog.check(Some(&status(9, 7)), at(220)),
+            Some(Stall::Apply {
+                commit_index: 9,
+                last_applied: 7
+            })
+        );
+        // Reported once per window.
+        assert_eq!(watchdog.check(Some(&status(9, 7)), at(250)), None);
+
+        watchdog.reset(at(250));
+        assert_eq!(watchdog.check(None, at(280)), None);
+        assert_eq!(watchdog.check(None, at(310)), Some(Stall::Unresponsive));
+    }
+}
@@ -0,0 +1,164 @@
+//! Offline inspection and repair of the term and vote a server's Raft saved.
+//!
+//! Like dump-log, the command runs while `main` holds the database lock, so
+//! the server is stopped and takes no part in elections while its state is
+//! rewritten. Every overwrite is logged with the state it replaced, see the
+//! `raft-state` help for why it is dangerous.
+
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+use fjall::Keyspace;
+use serde_json::json;
+use tracing::warn;
+
+use crate::config::ServerConfig;
+use crate::flags::RaftState;
+use crate::raft::{overwrite_saved_state, saved_state, LogEntry, RaftSaved};
+
+/// Print the saved state of `server`, or overwrite it as `cmd` asks.
+pub(crate) fn raft_state(
+    keyspace: &Keyspace,
+    server: &ServerConfig,
+    servers: &[ServerConfig],
+    cmd: RaftState,
+    out: &mut impl Write,
+) -> Result<()> {
+    let saved = saved_state(keyspace)?;
+    let overwrite = cmd.set_term.is_some() || cmd.set_voted_for.is_some() || cmd.clear_vote;
+    if !overwrite {
+        return print_state(&saved, out);
+    }
+    let name = &server.name;
+    if cmd.confirm.as_ref() != Some(name) {
+        bail!("refusing to overwrite the raft state without --confirm {name}");
+    }
+    if cmd.set_voted_for.is_some() && cmd.clear_vote {
+        bail!("--set-voted-for and --clear-vote contradict each other");
+    }
+    let current_term = cmd.set_term.unwrap_or(saved.current_term);
+    let last_log_term = last_log_term(keyspace)?;
+    if current_term < last_log_term {
+        bail!("term {current_term} is below the term {last_log_term} of the last log entry");
+    }
+    let voted_for = match (cmd.set_voted_for, cmd.clear_vote) {
+        (Some(candidate), _) => {
+            if !servers
+                .iter()
+                .any(|s| s.name == candidate && !s.readonly_replica)
+            {
+                bail!("{candidate} is not a voting server of the cluster");
+            }
+            Some(candidate)
+        }
+        (None, true) => None,
+        // A vote belongs to its term.
+        (None, false) if current_term != saved.current_term => None,
+        (None, false) => saved.voted_for.clone(),
+    };
+    let new = RaftSaved {
+        current_term,
+        voted_for,
+        last_applied: saved.last_applied,
+    };
+    warn!(
+        server = name,
+        old_term = saved.current_term,
+        old_voted_for = saved.voted_for,
+        new_term = new.current_term,
+        new_voted_for = new.voted_for,
+        "overwriting the saved raft state"
+    );
+    overwrite_saved_state(keyspace, &new)?;
+    print_state(&new, out)
+}
+
+fn print_state(saved: &RaftSaved, out: &mut impl Write) -> Result<()> {
+    let state = json!({
+        "current_term": saved.current_term,
+        "voted_for": saved.voted_for,
+    });
+    writeln!(out, "{state}")?;
+    Ok(())
+}
+
+/// Term of the last entry in the log, 0 for an empty log.
+fn last_log_term(keyspace: &Keyspace) -> Result<u32> {
+    if !keyspace.partition_exists("raft_log") {
+        return Ok(0);
+    }
+    let log = keyspace.open_partition("raft_log", Default::default())?;
+    let Some((_, value)) = log.last_key_value()? else {
+        return Ok(0);
+    };
+    let entry: LogEntry = minicbor::decode(&value).context("Failed to decode last log entry")?;
+    Ok(entry.term)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use fjall::Config;
+    use serde_json::{json, Value};
+    use tempfile::tempdir;
+
+    use super::raft_state;
+    use crate::config::ServerConfig;
+    use crate::flags::RaftState;
+    use crate::raft::{overwrite_saved_state, saved_state, LogEntry, LogEntryValue, RaftSaved};
+
+    #[test]
+    fn overwrite_only_when_confirmed_and_safe() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Config::new(tmp_dir.path()).open()?;
+        let server = |name: &str| ServerConfig {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let servers = [server("s1"), server("s2"), server("s3")];
+        let saved = RaftSaved {
+            current_term: 5,
+            voted_for: Some("s2".to_string()),
+            last_applied: 7,
+        };
+        overwrite_saved_state(&keyspace, &saved)?;
+        let log = keyspace.open_partition("raft_log", Default::default())?;
+        let entry = LogEntry {
+            index: 7,
+            term: 4,
+            value: LogEntryValue::NewTermStarted,
+        };
+        log.insert(7u64.to_be_bytes(), minicbor::to_vec(&entry)?)?;
+        let cmd = |term, voted_for: Option<&str>, clear_vote, confirm: Option<&str>| RaftState {
+            set_term: term,
+            set_voted_for: voted_for.map(str::to_string),
+            clear_vote,
+            confirm: confirm.map(str::to_string),
+        };
+        let run = |cmd| -> Result<Value> {
+            let mut out = vec![];
+            raft_state(&keyspace, &servers[0], &servers, cmd, &mut out)?;
+            Ok(serde_json::from_slice(&out)?)
+        };
+
+        let state = run(cmd(None, None, false, None))?;
+        assert_eq!(state, json!({"current_term": 5, "voted_for": "s2"}));
+        // Overwrites need the name of this server, and a sound state.
+        assert!(run(cmd(None, None, true, None)).is_err());
+        assert!(run(cmd(None, None, true, Some("s2"))).is_err());
+        assert!(run(cmd(Some(3), None, false, Some("s1"))).is_err());
+        assert!(run(cmd(None, Some("s9"), false, Some("s1"))).is_err());
+        assert!(run(cmd(None, Some("s3"), true, Some("s1"))).is_err());
+        assert_eq!(run(cmd(None, None, false, None))?["voted_for"], "s2");
+
+        let state = run(cmd(None, None, true, Some("s1")))?;
+        assert_eq!(state, json!({"current_term": 5, "voted_for": null}));
+        let state = run(cmd(None, Some("s3"), false, Some("s1")))?;
+        assert_eq!(state, json!({"current_term": 5, "voted_for": "s3"}));
+        // A new term starts without a vote, unless one is given.
+        let state = run(cmd(Some(9), None, false, Some("s1")))?;
+        assert_eq!(state, json!({"current_term": 9, "voted_for": null}));
+        assert_eq!(saved_state(&keyspace)?.last_applied, 7);
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Whether the keyspace of this server takes writes.
+//!
+//! The raft worker and the state machine report how their writes went. On a
+//! full or read-only disk every write fails, and crashing and restarting on
+//! each one would only loop. After [`FAILURE_LIMIT`] failures in a row the
+//! server is degraded instead: the HTTP API refuses writes with 503, the
+//! raft worker neither votes nor runs for leader, and the state machine
+//! tries its apply again later. The first write that succeeds ends it,
+//! including the probes the raft worker makes while degraded.
+//!
+//! A keyspace that failed to flush is poisoned by fjall and refuses writes
+//! until the process restarts, freeing space does not bring it back.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use fjall::{Keyspace, PartitionCreateOptions, PersistMode};
+use metrics::{counter, gauge};
+use tracing::{debug, error, info, warn};
+
+/// Failed writes in a row after which the server is degraded.
+///
+/// A failed write stops the actor that made it and its supervisor restarts
+/// it, which gives a passing storage hiccup another chance. Writes failing
+/// again and again mean the storage is broken, the restarts will not fix
+/// it.
+pub(crate) const FAILURE_LIMIT: u32 = 3;
+
+/// Failed writes to the keyspace in a row, shared by every actor writing to
+/// it and kept across their restarts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StorageHealth {
+    failures: Arc<AtomicU32>,
+}
+
+impl StorageHealth {
+    /// Count a failed write, reporting the server degraded when it reaches
+    /// the limit.
+    pub(crate) fn failed(&self, error: &anyhow::Error) {
+        counter!("pinka_storage_write_failures_total").increment(1);
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < FAILURE_LIMIT {
+            warn!(failures, ?error, "write to the keyspace failed");
+        } else if failures == FAILURE_LIMIT {
+            gauge!("pinka_storage_degraded").set(1.0);
+            error!(
+                failures,
+                poisoned = is_poisoned(error),
+                ?error,
+                "STORAGE DEGRADED: writes to the keyspace keep failing, check for a full disk \
+                 or wrong permissions. Refusing writes and leadership until a write succeeds"
+            );
+        } else {
+            debug!(failures, ?error, "storage is still degraded");
+        }
+    }
+
+    /// Reset the count after a write went through.
+    pub(crate) fn succeeded(&self) {
+        let failures = self.failures.swap(0, Ordering::Relaxed);
+        if failures >= FAILURE_LIMIT {
+            gauge!("pinka_storage_degraded").set(0.0);
+            info!(
+                failures,
+                "writes to the keyspace succeed again, storage recovered"
+            );
+        }
+    }
+
+    pub(crate) fn failures(&self) -> u32 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.failures() >= FAILURE_LIMIT
+    }
+
+    /// Make a small synced write to see whether the keyspace takes writes
+    /// again, and report how it went. Blocks on the disk.
+    pub(crate) fn probe(&self, keyspace: &Keyspace) -> bool {
+        let result = (|| -> Result<()> {
+            let probe =
+                keyspace.open_partition("storage_probe", PartitionCreateOptions::default())?;
+            let mut batch = keyspace.batch().durability(Some(PersistMode::SyncAll));
+            batch.insert(&probe, "probe", []);
+            batch.commit()?;
+            Ok(())
+        })();
+        match result {
+            Ok(()) => {
+                self.succeeded();
+                true
+            }
+            Err(error) => {
+                self.failed(&error);
+                false
+            }
+        }
+    }
+}
+
+/// Whether `error` came from the keyspace or the disk under it, rather than
+/// from a command that cannot be applied.
+pub(crate) fn is_storage_error(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.is::<fjall::Error>() || cause.is::<std::io::Error>())
+}
+
+fn is_poisoned(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| matches!(cause.downcast_ref(), Some(fjall::Error::Poisoned)))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::{is_storage_error, StorageHealth, FAILURE_LIMIT};
+
+    #[test]
+    fn degrade_after_failures_in_a_row() {
+        let health = StorageHealth::default();
+        let error = anyhow!("disk full");
+        for _ in 1..FAILURE_LIMIT {
+            health.failed(&error);
+        }
+        assert!(!health.is_degraded());
+        health.succeeded();
+        assert_eq!(health.failures(), 0);
+
+        for _ in 0..FAILURE_LIMIT {
+            health.clone().failed(&error);
+        }
+        assert!(health.is_degraded());
+
+        let dir = tempfile::tempdir().unwrap();
+        let keyspace = fjall::Config::new(dir.path()).open().unwrap();
+        assert!(health.probe(&keyspace));
+        assert!(!health.is_degraded());
+    }
+
+    #[test]
+    fn storage_errors_are_told_apart() {
+        let io = std::io::Error::other("no space left on device");
+        let error = anyhow::Error::from(fjall::Error::Io(io)).context("Failed to persist");
+        assert!(is_storage_error(&error));
+        assert!(!is_storage_error(&anyhow!("invalid object")));
+    }
+}
@@ -1,29 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
-use fjall::GarbageCollection;
-use ractor::{Actor, ActorProcessingErr, ActorRef, SupervisionEvent};
+use anyhow::{Context, Result};
+use fjall::{GarbageCollection, Keyspace, KvSeparationOptions, PartitionCreateOptions};
+use ractor::rpc::CallResult;
+use ractor::{registry, Actor, ActorProcessingErr, ActorRef, SupervisionEvent};
 use ractor_cluster::RactorMessage;
-use tracing::{error, info};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
 
 use crate::activity_pub::delivery::{DeliveryWorker, DeliveryWorkerInit, DeliveryWorkerMsg};
-use crate::activity_pub::machine::{ActivityPubMachine, ActivityPubMachineInit};
-use crate::cluster::{ClusterMaint, ClusterMaintMsg};
+use crate::activity_pub::machine::{
+    ActivityPubCommand, ActivityPubMachine, ActivityPubMachineInit,
+};
+use crate::activity_pub::{object_options, ObjectKey};
+use crate::cluster::{BootstrapFailed, ClusterMaint, ClusterMaintMsg};
 use crate::config::RuntimeConfig;
 use crate::feed_slurp::{FeedSlurpMsg, FeedSlurpWorker, FeedSlurpWorkerInit};
-use crate::raft::{RaftServer, RaftServerMsg, StateMachineMsg};
+use crate::raft::{
+    get_raft_local_client, LogEntryValue, RaftClientMsg, RaftServer, RaftServerMsg, StateMachineMsg,
+};
 
 pub(crate) struct Supervisor;
 
+static GC_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Held by tests that spawn the actors registered under fixed names, like
+/// `state_machine` and the raft workers, which one test at a time can have.
+#[cfg(test)]
+pub(crate) static NAMED_ACTORS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// How long a stopping supervisor waits for the delivery worker to hand its
+/// work back to the queue.
+const DELIVERY_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize)]
+pub(crate) struct GcReport {
+    raft_log_freed_bytes: u64,
+    objects_freed_bytes: u64,
+}
+
+/// Rewrite stale blobs of the key-value separated partitions.
+///
+/// Returns `None` without doing anything if another collection, periodic or
+/// manual, is still running.
+pub(crate) fn gc_keyspace(keyspace: &Keyspace) -> Result<Option<GcReport>> {
+    struct Running;
+    impl Drop for Running {
+        fn drop(&mut self) {
+            GC_RUNNING.store(false, Ordering::Release);
+        }
+    }
+    if GC_RUNNING.swap(true, Ordering::AcqRel) {
+        return Ok(None);
+    }
+    let _running = Running;
+
+    info!("garbage collect blobs in the keyspace...");
+    let raft_log = keyspace
+        .open_partition(
+            "raft_log",
+            PartitionCreateOptions::default().with_kv_separation(KvSeparationOptions::default()),
+        )
+        .context("Failed to open raft_log partition")?;
+    let raft_log_freed_bytes = raft_log
+        .gc_with_staleness_threshold(0.5)
+        .context("Failed to garbage collect raft_log")?;
+    let objects = keyspace
+        .open_partition("objects", object_options())
+        .context("Failed to open objects partition")?;
+    let objects_freed_bytes = objects
+        .gc_with_staleness_threshold(0.5)
+        .context("Failed to garbage collect objects")?;
+    Ok(Some(GcReport {
+        raft_log_freed_bytes,
+        objects_freed_bytes,
+    }))
+}
+
 #[derive(RactorMessage)]
 pub(crate) enum SupervisorMsg {
     KeyspaceMaint,
+    PruneRemoteObjects,
 }
 
 pub(crate) struct SupervisorState {
     config: RuntimeConfig,
     myself: ActorRef<SupervisorMsg>,
+    delivery_shutdown: watch::Sender<bool>,
 }
 
 impl Actor for Supervisor {
@@ -36,12 +102,20 @@ impl Actor for Supervisor {
         myself: ActorRef<Self::Msg>,
         config: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let state = SupervisorState { config, myself };
+        let state = SupervisorState {
+            config,
+            myself,
+            delivery_shutdown: watch::Sender::new(false),
+        };
 
         state.spawn_cluster_maint().await?;
         state.spawn_raft_server().await?;
         state.spawn_state_machine().await?;
-        state.spawn_delivery_worker().await?;
+        if state.config.init.activity_pub.federation_enabled {
+            state.spawn_delivery_worker().await?;
+        } else {
+            warn!("federation is disabled, nothing is delivered and inbox requests are refused");
+        }
         state.spawn_feed_slurp().await?;
 
         Ok(state)
@@ -58,24 +132,43 @@ impl Actor for Supervisor {
             SupervisorMsg::KeyspaceMaint
         });
         state.gc_keyspace();
+        let retention = &state.config.init.activity_pub.retention;
+        if retention.remote_object_ttl_secs > 0 {
+            myself.send_interval(Duration::from_secs(retention.prune_interval_secs), || {
+                SupervisorMsg::PruneRemoteObjects
+            });
+        }
 
         Ok(())
     }
 
+    async fn post_stop(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        // Children are killed once this returns.
+        state.quiesce_delivery_worker().await;
+        Ok(())
+    }
+
     async fn handle(
         &self,
         _myself: ActorRef<Self::Msg>,
-        _message: Self::Msg,
+        message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
-        // TODO move to module scope
-        state.gc_keyspace();
+        match message {
+            // TODO move to module scope
+            SupervisorMsg::KeyspaceMaint => state.gc_keyspace(),
+            SupervisorMsg::PruneRemoteObjects => state.prune_remote_objects(),
+        }
         Ok(())
     }
 
     async fn handle_supervisor_evt(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: SupervisionEvent,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
@@ -86,6 +179,11 @@ impl Actor for Supervisor {
         match message {
             ActorStarted(_) => {}
             ActorTerminated(_, _, _) => {}
+            ActorFailed(_, error) if error.is::<BootstrapFailed>() => {
+                // Stopping runs post_stop and lets main flush the keyspace.
+                error!(%error, "stopping because cluster.exit_on_bootstrap_timeout is set");
+                myself.stop(Some(error.to_string()));
+            }
             ActorFailed(actor_cell, error) => {
                 error!("{error:?}");
                 if actor_cell
@@ -135,20 +233,10 @@ impl Actor for Supervisor {
 impl SupervisorState {
     fn gc_keyspace(&self) {
         let keyspace = self.config.keyspace.clone();
-        info!("garbage collect blobs in the keyspace...");
-        thread::spawn(move || {
-            let raft_log = keyspace
-                .open_partition("raft_log", Default::default())
-                .expect("failed to open raft_log partition");
-            raft_log
-                .gc_with_staleness_threshold(0.5)
-                .expect("failed to garbage collect raft_log");
-            let objects = keyspace
-                .open_partition("objects", Default::default())
-                .expect("failed to open objects partition");
-            objects
-                .gc_with_staleness_threshold(0.5)
-                .expect("failed to garbage collect objects");
+        thread::spawn(move || match gc_keyspace(&keyspace) {
+            Ok(Some(report)) => info!(?report, "garbage collected blobs in the keyspace"),
+            Ok(None) => info!("garbage collection is already in progress, skipping"),
+            Err(error) => error!(?error, "failed to garbage collect keyspace"),
         });
     }
     async fn spawn_cluster_maint(&self) -> Result<()> {
@@ -178,6 +266,9 @@ impl SupervisorState {
             ActivityPubMachineInit {
                 apub: self.config.init.activity_pub.clone(),
                 keyspace: self.config.keyspace.clone(),
+                actor_cache: self.config.actor_cache.clone(),
+                applied_index: self.config.applied_index.clone(),
+                storage: self.config.storage.clone(),
             },
             self.myself.get_cell(),
         )
@@ -186,22 +277,84 @@ impl SupervisorState {
     }
     async fn spawn_delivery_worker(&self) -> Result<()> {
         Actor::spawn_linked(
-            None,
+            Some("delivery_worker".into()),
             DeliveryWorker,
             DeliveryWorkerInit {
                 config: self.config.clone(),
+                shutdown: self.delivery_shutdown.subscribe(),
             },
             self.myself.get_cell(),
         )
         .await?;
         Ok(())
     }
+    /// Ask the cluster to prune remote objects older than the retention
+    /// TTL, one pass after the other until all were looked at. Every
+    /// server asks, a later run only finds what expired since.
+    fn prune_remote_objects(&self) {
+        let ttl = self
+            .config
+            .init
+            .activity_pub
+            .retention
+            .remote_object_ttl_secs;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time must be after unix epoch")
+            .as_secs();
+        let cutoff = now.saturating_sub(ttl);
+        tokio::spawn(async move {
+            let result = async {
+                let client = get_raft_local_client()?;
+                let mut after = None;
+                loop {
+                    let command = ActivityPubCommand::PruneRemoteObjects(cutoff, after);
+                    let (resume, _) = ractor::call!(
+                        client,
+                        RaftClientMsg::ClientRequest,
+                        LogEntryValue::from(command)
+                    )?
+                    .into_result()?;
+                    if resume.is_empty() {
+                        break;
+                    }
+                    after = Some(ObjectKey::try_from(resume.as_slice())?);
+                }
+                anyhow::Ok(())
+            }
+            .await;
+            if let Err(error) = result {
+                warn!(?error, "failed to prune remote objects");
+            }
+        });
+    }
+    /// Stop the delivery worker from pulling new work and wait for the
+    /// delivery in flight to be acknowledged or handed back to the queue.
+    async fn quiesce_delivery_worker(&self) {
+        self.delivery_shutdown.send_replace(true);
+        let Some(cell) = registry::where_is("delivery_worker".into()) else {
+            return;
+        };
+        let worker = ActorRef::<DeliveryWorkerMsg>::from(cell);
+        match worker
+            .call(DeliveryWorkerMsg::Shutdown, Some(DELIVERY_SHUTDOWN_TIMEOUT))
+            .await
+        {
+            Ok(CallResult::Success(())) => {}
+            Ok(CallResult::Timeout) => warn!(
+                "delivery worker did not quiesce in {DELIVERY_SHUTDOWN_TIMEOUT:?}, \
+                 its delivery in flight is retried after the visibility timeout"
+            ),
+            Ok(CallResult::SenderError) | Err(_) => {}
+        }
+    }
     async fn spawn_feed_slurp(&self) -> Result<()> {
         Actor::spawn_linked(
             Some("feed_slurp".to_string()),
             FeedSlurpWorker,
             FeedSlurpWorkerInit {
                 apub: self.config.init.activity_pub.clone(),
+                feed_slurp: self.config.init.feed_slurp.clone(),
             },
             self.myself.get_cell(),
         )
@@ -209,3 +362,257 @@ impl SupervisorState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::{ensure, Result};
+    use aws_lc_rs::encoding::AsDer;
+    use aws_lc_rs::rsa::{KeySize, PrivateDecryptingKey};
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use fjall::{Config, Keyspace};
+    use ractor::{registry, Actor, ActorRef};
+    use serde_json::json;
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::{sleep, timeout};
+
+    use super::{gc_keyspace, Supervisor, SupervisorMsg, GC_RUNNING, NAMED_ACTORS};
+    use crate::activity_pub::delivery::{DeliveryQueueItem, DeliveryWorkerMsg};
+    use crate::activity_pub::machine::{ActivityPubCommand, AppliedIndex};
+    use crate::activity_pub::{
+        uuidgen, ActorCache, Batch, CryptoRepo, KeyMaterial, ObjectKey, ObjectRepo,
+    };
+    use crate::config::{
+        ActivityPubConfig, CacheConfig, ClusterConfig, Config as PinkaConfig, RaftConfig,
+        RuntimeConfig, ServerConfig,
+    };
+    use crate::raft::{get_raft_local_client, LogEntryValue, RaftClientMsg};
+    use crate::storage_health::StorageHealth;
+
+    #[test]
+    fn gc_skips_while_running() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let keyspace = Keyspace::open(Config::new(tmp_dir.path()).temporary(true))?;
+
+        GC_RUNNING.store(true, Ordering::Release);
+        assert!(gc_keyspace(&keyspace)?.is_none());
+        GC_RUNNING.store(false, Ordering::Release);
+
+        let report = gc_keyspace(&keyspace)?.expect("should run");
+        assert_eq!(report.objects_freed_bytes, 0);
+        assert!(!GC_RUNNING.load(Ordering::Acquire));
+        Ok(())
+    }
+
+    /// Supervise the servers of a one server cluster and wait for it to lead.
+    async fn start(config: &RuntimeConfig) -> Result<ActorRef<SupervisorMsg>> {
+        let (supervisor, _) = Actor::spawn(None, Supervisor, config.clone()).await?;
+        // A new leader appends an entry before it takes requests.
+        ensure!(
+            config
+                .applied_index
+                .wait_for(1, Duration::from_secs(10))
+                .await,
+            "no leader was elected"
+        );
+        Ok(supervisor)
+    }
+
+    /// Stop `supervisor` and wait for its children to free their names.
+    async fn stop(supervisor: ActorRef<SupervisorMsg>) -> Result<()> {
+        supervisor
+            .stop_and_wait(None, Some(Duration::from_secs(30)))
+            .await?;
+        wait_for_children().await;
+        Ok(())
+    }
+
+    /// Wait until the children of a stopped supervisor freed their names.
+    async fn wait_for_children() {
+        let names = [
+            "cluster_maint",
+            "node_server",
+            "state_machine",
+            "delivery_worker",
+        ];
+        while names
+            .iter()
+            .any(|name| registry::where_is(name.to_string()).is_some())
+            || get_raft_local_client().is_ok()
+        {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Poke the delivery worker until `posts` sees a delivery, instead of
+    /// waiting out its idle timer.
+    async fn next_post(posts: &mut mpsc::UnboundedReceiver<()>) -> Result<()> {
+        let poke = async {
+            loop {
+                if let Some(worker) = registry::where_is("delivery_worker".into()) {
+                    let worker = ActorRef::<DeliveryWorkerMsg>::from(worker);
+                    let _ = worker.cast(DeliveryWorkerMsg::RunLoop);
+                }
+                sleep(Duration::from_millis(200)).await;
+            }
+        };
+        // Well below the visibility timeout of a leased delivery.
+        let wait = Duration::from_secs(10);
+        tokio::select! {
+            post = timeout(wait, posts.recv()) => ensure!(post?.is_some(), "inbox closed"),
+            () = poke => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redeliver_the_delivery_in_flight_after_restart() -> Result<()> {
+        let _actors = NAMED_ACTORS.lock().await;
+        let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        // The first delivery never gets an answer, the next ones do.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let inbox = format!("http://{}/inbox", listener.local_addr()?);
+        let (posted, mut posts) = mpsc::unbounded_channel();
+        let answered = Arc::new(AtomicBool::new(false));
+        let app = Router::new().route(
+            "/inbox",
+            post(move || async move {
+                let _ = posted.send(());
+                if !answered.swap(true, Ordering::AcqRel) {
+                    std::future::pending::<()>().await;
+                }
+                StatusCode::ACCEPTED
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+        let dir = tempdir()?;
+        let server = ServerConfig {
+            name: "single".to_string(),
+            hostname: "127.0.0.1".to_string(),
+            port,
+            ..Default::default()
+        };
+        let init = PinkaConfig {
+            raft: RaftConfig {
+                heartbeat_ms: 20,
+                min_election_ms: 50,
+                max_election_ms: 100,
+                ..Default::default()
+            },
+            cluster: ClusterConfig {
+                servers: vec![server.clone()],
+                ..Default::default()
+            },
+            activity_pub: ActivityPubConfig {
+                base_url: "http://127.0.0.1".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let keyspace = fjall::Config::new(dir.path().join("single")).open()?;
+        let config = RuntimeConfig {
+            init,
+            server,
+            keyspace: keyspace.clone(),
+            actor_cache: ActorCache::new(&CacheConfig::default()),
+            applied_index: AppliedIndex::default(),
+            storage: StorageHealth::default(),
+        };
+
+        let key = PrivateDecryptingKey::generate(KeySize::Rsa2048)?;
+        let act_key = ObjectKey::new();
+        let create = json!({"type": "Create", "actor": "http://127.0.0.1/users/alice"});
+        let mut b = Batch::new(&keyspace);
+        CryptoRepo::new(keyspace.clone())?.insert(
+            &mut b,
+            "alice",
+            &KeyMaterial::from(key.as_der()?.as_ref().to_vec()),
+        );
+        ObjectRepo::new(keyspace.clone())?.insert(&mut b, act_key, create)?;
+        b.commit()?;
+
+        let supervisor = start(&config).await?;
+        let item = DeliveryQueueItem {
+            uid: "alice".to_string(),
+            act_key,
+            request_id: None,
+            blind_recipients: None,
+            inboxes: Some(vec![inbox]),
+        };
+        let command = ActivityPubCommand::QueueDelivery(uuidgen(), item);
+        ractor::call!(
+            get_raft_local_client()?,
+            RaftClientMsg::ClientRequest,
+            LogEntryValue::from(command)
+        )?
+        .into_result()?;
+        next_post(&mut posts).await?;
+
+        // Stopped while the delivery is leased, the worker hands it back
+        // and a restarted one delivers it without waiting for the lease to
+        // run out.
+        stop(supervisor).await?;
+        let config = RuntimeConfig {
+            applied_index: AppliedIndex::default(),
+            ..config
+        };
+        let supervisor = start(&config).await?;
+        next_post(&mut posts).await?;
+        stop(supervisor).await
+    }
+
+    #[tokio::test]
+    async fn stop_when_no_quorum_is_reachable() -> Result<()> {
+        let _actors = NAMED_ACTORS.lock().await;
+        let _ = tokio_rustls::rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+        // The second server never runs, a quorum of two is out of reach.
+        let mut servers = vec![];
+        for name in ["s1", "s2"] {
+            let port = TcpListener::bind("127.0.0.1:0").await?.local_addr()?.port();
+            servers.push(ServerConfig {
+                name: name.to_string(),
+                hostname: "127.0.0.1".to_string(),
+                port,
+                ..Default::default()
+            });
+        }
+        let dir = tempdir()?;
+        let init = PinkaConfig {
+            cluster: ClusterConfig {
+                servers: servers.clone(),
+                bootstrap_timeout_ms: 100,
+                exit_on_bootstrap_timeout: true,
+                ..Default::default()
+            },
+            activity_pub: ActivityPubConfig {
+                base_url: "http://127.0.0.1".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let config = RuntimeConfig {
+            init,
+            server: servers[0].clone(),
+            keyspace: fjall::Config::new(dir.path().join("s1")).open()?,
+            actor_cache: ActorCache::new(&CacheConfig::default()),
+            applied_index: AppliedIndex::default(),
+            storage: StorageHealth::default(),
+        };
+
+        let (_supervisor, handle) = Actor::spawn(None, Supervisor, config).await?;
+        timeout(Duration::from_secs(10), handle).await??;
+        wait_for_children().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+//! Process wide metrics recorder.
+//!
+//! Code records through the `metrics` facade; this module installs the
+//! Prometheus exporter behind it and renders the text exposition format for
+//! the admin endpoint.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+static PROMETHEUS: OnceLock<PrometheusHandle> = OnceLock::new();
+
+const SECONDS_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Install the Prometheus recorder as the global `metrics` recorder.
+///
+/// Must be called from within the tokio runtime, it spawns the upkeep task
+/// that drains histogram buffers.
+pub(crate) fn install() -> Result<()> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), SECONDS_BUCKETS)?
+        .install_recorder()
+        .context("Failed to install metrics recorder")?;
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+    let _ = PROMETHEUS.set(handle);
+    Ok(())
+}
+
+/// Render all metrics, `None` if no recorder was installed.
+pub(crate) fn render() -> Option<String> {
+    PROMETHEUS.get().map(PrometheusHandle::render)
+}